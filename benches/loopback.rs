@@ -0,0 +1,124 @@
+// Benchmark de vazão no loopback: compara send_to/recv_from (um datagrama por syscall) com
+// sendmmsg/recvmmsg e com GSO/GRO. Execute com `cargo bench --bench loopback`.
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rawsocket_udp::batch::{BatchOptions, BatchReceiver, BatchSender};
+use rawsocket_udp::protocol::{UdpPacket, CHUNK_SIZE, END_OF_TRANSMISSION_SEQ_NUM};
+use socket2::SockRef;
+
+// Tamanho do "arquivo" transferido em cada rodada.
+const FILE_SIZE: usize = 32 * 1024 * 1024;
+const RECV_BUFFER: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy)]
+enum Mode {
+  PerDatagram,
+  Batched(BatchOptions),
+}
+
+struct Received {
+  datagrams: usize,
+  bytes: usize,
+  finished: Instant,
+}
+
+fn main() -> io::Result<()> {
+  let data: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 251) as u8).collect();
+  let mut datagrams: Vec<Vec<u8>> = UdpPacket::prepare_packets(8083, 0, data, CHUNK_SIZE, 0)
+    .iter()
+    .map(UdpPacket::serialize)
+    .collect();
+  datagrams.push(UdpPacket::end_of_transmission(8083, 0).serialize());
+
+  let modes = [
+    ("send_to/recv_from", Mode::PerDatagram),
+    ("sendmmsg/recvmmsg", Mode::Batched(BatchOptions { gso: false, gro: false })),
+    ("sendmmsg+GSO/recvmmsg+GRO", Mode::Batched(BatchOptions { gso: true, gro: true })),
+  ];
+
+  println!("{} datagramas, {} MiB por rodada", datagrams.len(), FILE_SIZE / (1024 * 1024));
+  for (name, mode) in modes {
+    let (elapsed, received) = run(mode, &datagrams)?;
+    let mbits = received.bytes as f64 * 8.0 / elapsed.as_secs_f64() / 1_000_000.0;
+    println!(
+      "{:<28} {:>8.1} ms {:>9.1} Mbit/s  recebidos {}/{}",
+      name,
+      elapsed.as_secs_f64() * 1000.0,
+      mbits,
+      received.datagrams,
+      datagrams.len(),
+    );
+  }
+  Ok(())
+}
+
+fn run(mode: Mode, datagrams: &[Vec<u8>]) -> io::Result<(Duration, Received)> {
+  let receiver_socket = UdpSocket::bind("127.0.0.1:0")?;
+  SockRef::from(&receiver_socket).set_recv_buffer_size(RECV_BUFFER)?;
+  receiver_socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+  let destination: SocketAddr = receiver_socket.local_addr()?;
+
+  let receiver = thread::spawn(move || receive(mode, &receiver_socket));
+
+  let sender_socket = UdpSocket::bind("127.0.0.1:0")?;
+  let start = Instant::now();
+  match mode {
+    Mode::PerDatagram => {
+      for datagram in datagrams {
+        sender_socket.send_to(datagram, destination)?;
+      }
+    },
+    Mode::Batched(options) => BatchSender::new(options).send_all(&sender_socket, datagrams, destination)?,
+  }
+
+  let received = receiver.join().expect("thread de recepção falhou")?;
+  Ok((received.finished.duration_since(start), received))
+}
+
+fn receive(mode: Mode, socket: &UdpSocket) -> io::Result<Received> {
+  let mut received = Received { datagrams: 0, bytes: 0, finished: Instant::now() };
+  let mut count = |datagram: &[u8]| {
+    received.datagrams += 1;
+    received.bytes += datagram.len();
+    received.finished = Instant::now();
+    datagram.len() >= 4 && datagram[..4] == END_OF_TRANSMISSION_SEQ_NUM.to_be_bytes()
+  };
+
+  let result = match mode {
+    Mode::PerDatagram => {
+      let mut buf = [0u8; 2048];
+      loop {
+        let (size, _) = match socket.recv_from(&mut buf) {
+          Ok(received) => received,
+          Err(e) => break Err(e),
+        };
+        if count(&buf[..size]) {
+          break Ok(());
+        }
+      }
+    },
+    Mode::Batched(options) => {
+      let mut batch = BatchReceiver::new(socket, options, 2048);
+      'receive: loop {
+        if let Err(e) = batch.recv(socket) {
+          break Err(e);
+        }
+        for (datagram, _) in batch.datagrams() {
+          if count(datagram) {
+            break 'receive Ok(());
+          }
+        }
+      }
+    },
+  };
+
+  match result {
+    Ok(()) => Ok(received),
+    // Fim de transmissão perdido: mede até o último datagrama recebido.
+    Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => Ok(received),
+    Err(e) => Err(e),
+  }
+}
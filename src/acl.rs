@@ -0,0 +1,261 @@
+// Lista de controle de acesso aos caminhos servidos, consultada antes do gancho de autorização em
+// cada GET, STAT, LIST, PUT e DELTA. As regras são avaliadas em ordem e a primeira que casa com a
+// operação, o endereço de origem, a identidade do cliente e o caminho decide; sem regra que case,
+// vale a política padrão.
+//
+// Formato do arquivo, uma regra por linha (`#` inicia um comentário):
+//   allow read,list path=/publico
+//   allow write from=10.0.0.0/8 identity=alice path=/uploads/alice
+//   deny * from=192.168.7.0/24
+//   default deny
+// As operações são `read` (GET, STAT e DELTA), `list` (LIST) e `write` (PUT), ou `*` para todas.
+// `from` restringe a regra a uma sub-rede, `identity` a uma identidade autenticada (`*` para
+// qualquer uma; ver `auth` e `ServerBuilder::identify`) e `path` a um prefixo, comparado por componentes:
+// `/docs` vale para `docs` e `docs/a.txt`, mas não para `docs2`. Sem `default`, o que nenhuma
+// regra permite é negado.
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Component, Path};
+
+use crate::shaping::Subnet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+  Read,
+  List,
+  Write,
+}
+
+impl Operation {
+  // Operação de um comando da requisição.
+  pub fn of_command(command: &str) -> Option<Operation> {
+    match command {
+      "GET" | "STAT" | "DELTA" => Some(Operation::Read),
+      "LIST" => Some(Operation::List),
+      "PUT" => Some(Operation::Write),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Operation::Read => "read",
+      Operation::List => "list",
+      Operation::Write => "write",
+    }
+  }
+
+  fn parse(value: &str) -> Option<Operation> {
+    match value {
+      "read" => Some(Operation::Read),
+      "list" => Some(Operation::List),
+      "write" => Some(Operation::Write),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for Operation {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum IdentityMatch {
+  // Qualquer identidade autenticada.
+  Any,
+  Named(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Rule {
+  pub allow: bool,
+  operations: Vec<Operation>,
+  from: Option<Subnet>,
+  identity: Option<IdentityMatch>,
+  // Prefixo sem barras nas pontas; vazio vale para todos os caminhos.
+  prefix: String,
+  // Linha do arquivo, para os logs.
+  pub line: usize,
+}
+
+impl Rule {
+  fn matches(&self, operation: Operation, peer: IpAddr, identity: Option<&str>, path: &str) -> bool {
+    let identity_matches = match (&self.identity, identity) {
+      (None, _) => true,
+      (Some(IdentityMatch::Any), Some(_)) => true,
+      (Some(IdentityMatch::Named(name)), Some(identity)) => name == identity,
+      (Some(_), None) => false,
+    };
+    self.operations.contains(&operation)
+      && self.from.is_none_or(|subnet| subnet.contains(peer))
+      && identity_matches
+      && under_prefix(path, &self.prefix)
+  }
+}
+
+// Decisão da ACL para uma requisição.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decision {
+  pub allowed: bool,
+  // Linha da regra que decidiu; `None` quando valeu a política padrão.
+  pub line: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Acl {
+  rules: Vec<Rule>,
+  default_allow: bool,
+}
+
+impl Acl {
+  pub fn load(path: &Path) -> io::Result<Acl> {
+    Acl::parse(&fs::read_to_string(path)?)
+  }
+
+  pub fn parse(text: &str) -> io::Result<Acl> {
+    let mut acl = Acl::default();
+    for (index, line) in text.lines().enumerate() {
+      let line_number = index + 1;
+      let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("ACL, linha {}: {}", line_number, message));
+      let mut words = line.split('#').next().unwrap_or_default().split_whitespace();
+      let allow = match words.next() {
+        None => continue,
+        Some("default") => {
+          acl.default_allow = match (words.next(), words.next()) {
+            (Some("allow"), None) => true,
+            (Some("deny"), None) => false,
+            _ => return Err(invalid("use `default allow` ou `default deny`")),
+          };
+          continue;
+        },
+        Some("allow") => true,
+        Some("deny") => false,
+        Some(word) => return Err(invalid(&format!("esperado allow, deny ou default, encontrado `{}`", word))),
+      };
+      let operations = match words.next() {
+        Some("*") => vec![Operation::Read, Operation::List, Operation::Write],
+        Some(list) => list
+          .split(',')
+          .map(|name| Operation::parse(name).ok_or_else(|| invalid(&format!("operação desconhecida `{}`", name))))
+          .collect::<io::Result<Vec<_>>>()?,
+        None => return Err(invalid("faltam as operações")),
+      };
+      let mut rule = Rule { allow, operations, from: None, identity: None, prefix: String::new(), line: line_number };
+      for word in words {
+        match word.split_once('=') {
+          Some(("from", subnet)) => rule.from = Some(subnet.parse().map_err(|e: io::Error| invalid(&e.to_string()))?),
+          Some(("identity", "*")) => rule.identity = Some(IdentityMatch::Any),
+          Some(("identity", name)) if !name.is_empty() => rule.identity = Some(IdentityMatch::Named(name.to_string())),
+          Some(("path", prefix)) => {
+            rule.prefix = prefix.split('/').filter(|name| !name.is_empty() && *name != ".").collect::<Vec<_>>().join("/")
+          },
+          _ => return Err(invalid(&format!("opção desconhecida `{}`", word))),
+        }
+      }
+      acl.rules.push(rule);
+    }
+    Ok(acl)
+  }
+
+  // Decide uma requisição pelo caminho pedido, normalizado como a origem dos arquivos o interpreta
+  // (`a//b` e `a/./b` são `a/b`). Caminhos com `..` são negados.
+  pub fn decide(&self, operation: Operation, peer: IpAddr, identity: Option<&str>, path: &str) -> Decision {
+    let mut components = Vec::new();
+    for component in Path::new(path.trim_start_matches('/')).components() {
+      match component {
+        Component::Normal(name) => components.push(name.to_string_lossy()),
+        Component::CurDir => {},
+        _ => return Decision { allowed: false, line: None },
+      }
+    }
+    let path = components.join("/");
+    match self.rules.iter().find(|rule| rule.matches(operation, peer, identity, &path)) {
+      Some(rule) => Decision { allowed: rule.allow, line: Some(rule.line) },
+      None => Decision { allowed: self.default_allow, line: None },
+    }
+  }
+
+  pub fn rules(&self) -> &[Rule] {
+    &self.rules
+  }
+}
+
+fn under_prefix(path: &str, prefix: &str) -> bool {
+  prefix.is_empty()
+    || path == prefix
+    || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const RULES: &str = "
+    # comentários e linhas vazias são ignorados
+    allow read,list path=/publico
+    allow write from=10.0.0.0/8 identity=alice path=/uploads/alice
+    allow read identity=* path=docs
+    deny * from=192.168.7.0/24
+    default deny
+  ";
+
+  fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+  }
+
+  fn allowed(acl: &Acl, operation: Operation, peer: &str, identity: Option<&str>, path: &str) -> bool {
+    acl.decide(operation, ip(peer), identity, path).allowed
+  }
+
+  #[test]
+  fn first_matching_rule_decides() {
+    let acl = Acl::parse(RULES).unwrap();
+    assert_eq!(acl.decide(Operation::Read, ip("1.2.3.4"), None, "publico/a.txt"), Decision { allowed: true, line: Some(3) });
+    assert_eq!(acl.decide(Operation::Write, ip("1.2.3.4"), None, "publico/a.txt"), Decision { allowed: false, line: None });
+    assert!(allowed(&acl, Operation::Write, "10.1.2.3", Some("alice"), "/uploads/alice/x.bin"));
+    assert!(!allowed(&acl, Operation::Write, "10.1.2.3", Some("bob"), "/uploads/alice/x.bin"));
+    assert!(!allowed(&acl, Operation::Write, "11.1.2.3", Some("alice"), "/uploads/alice/x.bin"));
+    assert!(allowed(&acl, Operation::Read, "1.2.3.4", Some("bob"), "docs/manual.pdf"));
+    assert!(!allowed(&acl, Operation::Read, "1.2.3.4", None, "docs/manual.pdf"));
+  }
+
+  #[test]
+  fn prefixes_match_whole_components() {
+    let acl = Acl::parse(RULES).unwrap();
+    assert!(allowed(&acl, Operation::List, "1.2.3.4", None, "publico"));
+    assert!(allowed(&acl, Operation::List, "1.2.3.4", None, "/publico/"));
+    assert!(!allowed(&acl, Operation::Read, "1.2.3.4", None, "publico2/a.txt"));
+    assert!(!allowed(&acl, Operation::Read, "1.2.3.4", None, "publicoa.txt"));
+  }
+
+  #[test]
+  fn paths_are_normalized_and_parent_components_denied() {
+    let acl = Acl::parse("allow * path=/publico\ndefault allow").unwrap();
+    assert!(allowed(&acl, Operation::Read, "1.2.3.4", None, "publico//./a.txt"));
+    assert_eq!(acl.decide(Operation::Read, ip("1.2.3.4"), None, "publico/../segredo"), Decision { allowed: false, line: None });
+    assert!(!allowed(&acl, Operation::Read, "1.2.3.4", None, "../etc/passwd"));
+    assert!(!allowed(&acl, Operation::Read, "1.2.3.4", None, "outro/.."));
+  }
+
+  #[test]
+  fn subnets_match_mapped_peers() {
+    let acl = Acl::parse(RULES).unwrap();
+    // A regra `deny` da sub-rede decide, e não a política padrão.
+    let denied = Decision { allowed: false, line: Some(6) };
+    assert_eq!(acl.decide(Operation::Read, ip("::ffff:192.168.7.20"), None, "outro"), denied);
+    assert_eq!(acl.decide(Operation::Read, ip("192.168.7.20"), None, "outro"), denied);
+    assert!(allowed(&acl, Operation::Write, "::ffff:10.0.0.1", Some("alice"), "uploads/alice"));
+  }
+
+  #[test]
+  fn invalid_rules_report_the_line() {
+    for text in ["allow", "permit read", "allow read,delete", "allow read owner=x", "default maybe", "deny * from=10.0.0.0/40"] {
+      let error = Acl::parse(&format!("# regra inválida\n{}", text)).unwrap_err();
+      assert!(error.to_string().contains("linha 2"), "{}: {}", text, error);
+    }
+  }
+}
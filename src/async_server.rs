@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tracing::{debug, info, info_span, warn, Instrument, Span};
+
+use crate::batch::{BatchOptions, BatchSender};
+use crate::capture::{self, Direction};
+use crate::discovery::DISCOVERY_GROUP;
+use crate::handler::{error_message, Handler};
+use crate::limits::Refusal;
+use crate::protocol::{ErrorCode, MAX_UDP_PAYLOAD};
+use crate::shaping::{self, Ticket};
+
+// Quantidade de requisições que podem ficar enfileiradas para uma mesma sessão.
+const SESSION_QUEUE_LEN: usize = 1024;
+
+// Configuração do servidor assíncrono.
+#[derive(Clone, Debug)]
+pub struct AsyncServerConfig {
+  // Número máximo de sessões atendidas ao mesmo tempo.
+  pub max_sessions: usize,
+  // Tempo sem requisições depois do qual a tarefa da sessão é encerrada.
+  pub session_idle_timeout: Duration,
+  // Opções de envio em lote (sendmmsg/GSO).
+  pub batch: BatchOptions,
+}
+
+impl Default for AsyncServerConfig {
+  fn default() -> Self {
+    AsyncServerConfig {
+      max_sessions: 256,
+      session_idle_timeout: Duration::from_secs(30),
+      batch: BatchOptions::default(),
+    }
+  }
+}
+
+type Sessions = Mutex<HashMap<SocketAddr, mpsc::Sender<Vec<u8>>>>;
+
+// Estado compartilhado pelo laço principal e pelas tarefas de sessão.
+struct Shared {
+  socket: UdpSocket,
+  local_address: SocketAddr,
+  handler: Handler,
+  batch_sender: BatchSender,
+  sessions: Sessions,
+  session_idle_timeout: Duration,
+}
+
+// Laço principal do servidor assíncrono. Cada cliente (endereço de origem) ganha uma tarefa de
+// sessão própria, que trata suas requisições em ordem; o número de sessões simultâneas é limitado
+// por um semáforo, e novos clientes além do limite recebem BUSY em vez de criar tarefas sem limite.
+pub async fn run(socket: UdpSocket, config: AsyncServerConfig, handler: Handler) -> io::Result<()> {
+  if let Some(discovery) = handler.discovery().filter(|_| socket.local_addr().is_ok_and(|address| address.is_ipv4())) {
+    match socket.join_multicast_v4(DISCOVERY_GROUP, discovery.interface) {
+      Ok(()) => info!(group = %DISCOVERY_GROUP, interface = %discovery.interface, "discovery group joined"),
+      Err(e) => warn!(group = %DISCOVERY_GROUP, interface = %discovery.interface, error = %e, "could not join discovery group"),
+    }
+  }
+  let shared = Arc::new(Shared {
+    local_address: socket.local_addr()?,
+    socket,
+    handler,
+    batch_sender: BatchSender::new(config.batch),
+    sessions: Mutex::new(HashMap::new()),
+    session_idle_timeout: config.session_idle_timeout,
+  });
+  let permits = Arc::new(Semaphore::new(config.max_sessions));
+  let mut buf = vec![0u8; MAX_UDP_PAYLOAD];
+
+  loop {
+    let (size, client_address) = shared.socket.recv_from(&mut buf).await?;
+    let request = buf[..size].to_vec();
+    capture::record(shared.local_address, client_address, Direction::Received, &request);
+
+    let request = match dispatch(&shared.sessions, client_address, request) {
+      Some(request) => request,
+      None => continue,
+    };
+
+    // Ainda não existe sessão para este cliente: cria a tarefa se houver vaga. Sem vaga, responde
+    // BUSY sem esperar, para que o laço continue lendo o socket das sessões em andamento.
+    let permit = match Arc::clone(&permits).try_acquire_owned() {
+      Ok(permit) => permit,
+      Err(TryAcquireError::NoPermits) => {
+        warn!(peer = %client_address, max_sessions = config.max_sessions, "session limit reached, answering BUSY");
+        let busy = error_message(ErrorCode::Busy, Refusal::Sessions.message(), client_address);
+        match shared.socket.send_to(&busy, client_address).await {
+          Ok(_) => capture::record(shared.local_address, client_address, Direction::Sent, &busy),
+          Err(e) => warn!(peer = %client_address, error = %e, "send failed"),
+        }
+        continue;
+      },
+      Err(TryAcquireError::Closed) => return Err(io::Error::other("semáforo de sessões fechado")),
+    };
+    let (sender, receiver) = mpsc::channel(SESSION_QUEUE_LEN);
+    sender.try_send(request).expect("fila da sessão recém-criada está vazia");
+    shared.sessions.lock().unwrap().insert(client_address, sender);
+
+    let span = info_span!("client_session", peer = %client_address);
+    tokio::spawn(run_session(Arc::clone(&shared), client_address, receiver, permit).instrument(span));
+  }
+}
+
+// Entrega o datagrama à sessão já existente do cliente. Devolve o datagrama de volta quando
+// não há sessão ativa para ele.
+fn dispatch(sessions: &Sessions, client_address: SocketAddr, request: Vec<u8>) -> Option<Vec<u8>> {
+  let mut sessions = sessions.lock().unwrap();
+  let sender = match sessions.get(&client_address) {
+    Some(sender) => sender,
+    None => return Some(request),
+  };
+  match sender.try_send(request) {
+    Ok(()) => None,
+    Err(mpsc::error::TrySendError::Full(_)) => {
+      warn!(peer = %client_address, "session queue full, dropping request");
+      None
+    },
+    Err(mpsc::error::TrySendError::Closed(request)) => {
+      sessions.remove(&client_address);
+      Some(request)
+    }
+  }
+}
+
+async fn run_session(
+  shared: Arc<Shared>,
+  client_address: SocketAddr,
+  mut receiver: mpsc::Receiver<Vec<u8>>,
+  _permit: OwnedSemaphorePermit,
+) {
+  debug!("session started");
+  loop {
+    let request = match tokio::time::timeout(shared.session_idle_timeout, receiver.recv()).await {
+      Ok(Some(request)) => request,
+      Ok(None) => break,
+      Err(_) => {
+        // A remoção acontece sob o mesmo lock usado em `dispatch`, então nenhuma requisição
+        // pode ser enfileirada entre a verificação e o encerramento da sessão.
+        let mut sessions = shared.sessions.lock().unwrap();
+        if receiver.is_empty() {
+          sessions.remove(&client_address);
+          debug!("session idle, closing");
+          break;
+        }
+        continue;
+      }
+    };
+
+    // O tratamento lê arquivos do disco, então roda fora das threads do runtime.
+    let handler = shared.handler.clone();
+    let span = Span::current();
+    let datagrams = match tokio::task::spawn_blocking(move || span.in_scope(|| handler.handle(&request, client_address))).await {
+      Ok(datagrams) => datagrams,
+      Err(e) => {
+        warn!(error = %e, "request handling failed");
+        continue;
+      }
+    };
+
+    let ticket = shared.handler.shaper().ticket(client_address);
+    match send_shaped(&shared, &ticket, &datagrams, client_address).await {
+      Ok(()) => {
+        capture::record_all(shared.local_address, client_address, Direction::Sent, datagrams.iter().map(Vec::as_slice));
+        shared.handler.sent(client_address, &datagrams);
+      },
+      Err(e) => warn!(error = %e, "failed to send response"),
+    }
+  }
+}
+
+// Envia a resposta bloco a bloco, esperando sem bloquear o runtime quando um limite de taxa exige.
+async fn send_shaped(shared: &Shared, ticket: &Ticket, datagrams: &[Vec<u8>], destination: SocketAddr) -> io::Result<()> {
+  for chunk in ticket.chunks(datagrams) {
+    while let Some(delay) = ticket.poll(shaping::chunk_len(chunk)) {
+      tokio::time::sleep(delay).await;
+    }
+    send_datagrams(&shared.socket, &shared.batch_sender, chunk, destination).await?;
+  }
+  Ok(())
+}
+
+// Envia os datagramas em lote, esperando o socket ficar gravável quando o kernel não aceita mais.
+#[cfg(target_os = "linux")]
+async fn send_datagrams(socket: &UdpSocket, sender: &BatchSender, datagrams: &[Vec<u8>], destination: SocketAddr) -> io::Result<()> {
+  let mut offset = 0;
+  while offset < datagrams.len() {
+    offset += socket
+      .async_io(tokio::io::Interest::WRITABLE, || sender.send_some(socket, &datagrams[offset..], destination))
+      .await?;
+  }
+  Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn send_datagrams(socket: &UdpSocket, _sender: &BatchSender, datagrams: &[Vec<u8>], destination: SocketAddr) -> io::Result<()> {
+  for datagram in datagrams {
+    socket.send_to(datagram, destination).await?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashSet;
+  use std::thread;
+
+  use super::*;
+  use crate::error::ClientError;
+  use crate::source::MemorySource;
+  use crate::transfer::{Client, ClientConfig, GetOptions};
+
+  fn sample(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+  }
+
+  // Servidor assíncrono em um runtime próprio, que vive até o fim do processo de testes.
+  fn start(config: AsyncServerConfig, data: &[u8]) -> SocketAddr {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let socket = runtime.block_on(UdpSocket::bind("127.0.0.1:0")).unwrap();
+    let address = socket.local_addr().unwrap();
+    let handler = Handler::new(Arc::new(MemorySource::new().with_file("a.bin", data.to_vec())));
+    thread::spawn(move || runtime.block_on(run(socket, config, handler)));
+    address
+  }
+
+  fn client(address: SocketAddr) -> Client {
+    Client::new(address, ClientConfig { chunk_size: Some(1000), timeout: Duration::from_millis(500), ..ClientConfig::default() })
+  }
+
+  #[test]
+  fn serves_gets_with_retransmission() {
+    let data = sample(30_000);
+    let address = start(AsyncServerConfig::default(), &data);
+    let lost: HashSet<u32> = [1, 7, 30].into_iter().collect();
+    let download = client(address).get_with("a.bin", GetOptions::default().simulate_loss(lost)).expect("GET falhou");
+    assert_eq!(download.data.as_deref(), Some(data.as_slice()));
+    assert!(download.retransmitted >= 3);
+  }
+
+  #[test]
+  fn answers_busy_beyond_the_session_limit() {
+    let config = AsyncServerConfig { max_sessions: 1, session_idle_timeout: Duration::from_millis(300), ..AsyncServerConfig::default() };
+    let data = sample(5_000);
+    let address = start(config, &data);
+    client(address).get("a.bin").expect("primeiro GET falhou");
+    match client(address).get("a.bin") {
+      Err(ClientError::Server { code, .. }) => assert_eq!(code, ErrorCode::Busy),
+      other => panic!("esperava BUSY, obtido {:?}", other.map(|download| download.path)),
+    }
+    // A sessão inativa é encerrada e libera a vaga.
+    thread::sleep(Duration::from_millis(600));
+    assert_eq!(client(address).get("a.bin").expect("GET depois da vaga liberada falhou").data, Some(data));
+  }
+}
@@ -0,0 +1,343 @@
+// Autenticação dos clientes por chaves compartilhadas, sem handshake: cada GET, STAT, LIST, PUT e
+// DELTA leva as credenciais na query string, em uma de duas formas.
+//
+//   token:  `GET /arquivo?token=SEGREDO`, o segredo de uma das chaves em texto claro.
+//   HMAC:   `GET /arquivo?start=1&key=ID&ts=UNIX&nonce=HEX&sig=HEX`, com `sig` por último: o
+//           HMAC-SHA256, com o segredo da chave `ID`, de toda a requisição antes de `&sig=`
+//           (comando, caminho e demais parâmetros).
+//
+// A identidade do cliente é o identificador da chave usada, consultado pela ACL e pelos limites de
+// taxa. Uma requisição assinada só vale dentro da janela de tempo em torno de `ts`, e cada `nonce`
+// só pode ser usado uma vez nessa janela. O mesmo datagrama reenviado pelo mesmo endereço (o
+// cliente repete o pedido quando a resposta se perde) não é uma repetição: a resposta volta para
+// quem o assinou. As credenciais são retiradas da requisição antes do tratamento, de modo que não
+// aparecem nos logs nem no diário de sessões.
+//
+// Arquivo de chaves, uma por linha (`#` inicia um comentário):
+//   ci-agent-1 9f2c4e...
+//   deploy     s3nh4-l0ng4
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use digest::Digest;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// Janela de tempo padrão das requisições assinadas, para mais ou para menos.
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+// Parâmetros da query string que carregam credenciais.
+const CREDENTIAL_PARAMS: [&str; 5] = ["token", "key", "ts", "nonce", "sig"];
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Chaves conhecidas pelo servidor: identificador e segredo.
+#[derive(Clone, Debug, Default)]
+pub struct KeySet {
+  keys: HashMap<String, Vec<u8>>,
+}
+
+impl KeySet {
+  pub fn load(path: &Path) -> io::Result<KeySet> {
+    KeySet::parse(&fs::read_to_string(path)?)
+  }
+
+  pub fn parse(text: &str) -> io::Result<KeySet> {
+    let mut keys = KeySet::default();
+    for (index, line) in text.lines().enumerate() {
+      let mut words = line.split('#').next().unwrap_or_default().split_whitespace();
+      match (words.next(), words.next(), words.next()) {
+        (None, _, _) => {},
+        (Some(id), Some(secret), None) => keys.insert(id, secret),
+        _ => {
+          let message = format!("chaves, linha {}: use `identificador segredo`", index + 1);
+          return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        },
+      }
+    }
+    Ok(keys)
+  }
+
+  pub fn insert(&mut self, id: &str, secret: &str) {
+    self.keys.insert(id.to_string(), secret.as_bytes().to_vec());
+  }
+
+  pub fn len(&self) -> usize {
+    self.keys.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.keys.is_empty()
+  }
+}
+
+// Requisição assinada já aceita, guardada até sair da janela de tempo.
+struct SeenNonce {
+  client_address: SocketAddr,
+  expires: u64,
+}
+
+// Verificação das credenciais no servidor.
+pub struct Authenticator {
+  keys: KeySet,
+  window: Duration,
+  // Aceita requisições sem credenciais, que seguem sem identidade.
+  allow_anonymous: bool,
+  nonces: Mutex<HashMap<(String, String), SeenNonce>>,
+}
+
+impl Authenticator {
+  pub fn new(keys: KeySet) -> Authenticator {
+    Authenticator { keys, window: DEFAULT_WINDOW, allow_anonymous: false, nonces: Mutex::new(HashMap::new()) }
+  }
+
+  pub fn window(mut self, window: Duration) -> Authenticator {
+    self.window = window;
+    self
+  }
+
+  pub fn allow_anonymous(mut self, allow: bool) -> Authenticator {
+    self.allow_anonymous = allow;
+    self
+  }
+
+  pub fn allows_anonymous(&self) -> bool {
+    self.allow_anonymous
+  }
+
+  // Confere as credenciais de uma requisição e devolve a identidade do cliente (`None` para um
+  // cliente anônimo aceito). O erro é o motivo da recusa, enviado ao cliente.
+  pub fn verify(&self, request: &str, client_address: SocketAddr) -> Result<Option<String>, &'static str> {
+    let request = request.trim_end_matches('\0');
+    let params = query(request);
+    if let Some(token) = param(&params, "token") {
+      return self
+        .keys
+        .keys
+        .iter()
+        .find(|(_, secret)| constant_time_eq(secret, token.as_bytes()))
+        .map(|(id, _)| Some(id.clone()))
+        .ok_or("Token inválido");
+    }
+    let key_id = match param(&params, "key") {
+      Some(key_id) => key_id,
+      None if self.allow_anonymous => return Ok(None),
+      None => return Err("Credenciais ausentes"),
+    };
+
+    let (signed, signature) = match request.rsplit_once("sig=") {
+      Some((signed, signature)) if signed.ends_with('&') || signed.ends_with('?') => (&signed[..signed.len() - 1], signature),
+      _ => return Err("Assinatura ausente"),
+    };
+    let (ts, nonce) = match (param(&params, "ts").and_then(|ts| ts.parse::<u64>().ok()), param(&params, "nonce")) {
+      (Some(ts), Some(nonce)) if !nonce.is_empty() => (ts, nonce),
+      _ => return Err("Requisição assinada requer ts e nonce"),
+    };
+    let secret = self.keys.keys.get(key_id).ok_or("Chave desconhecida")?;
+    let signature = decode_hex(signature).ok_or("Assinatura inválida")?;
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC aceita chaves de qualquer tamanho");
+    mac.update(signed.as_bytes());
+    mac.verify_slice(&signature).map_err(|_| "Assinatura inválida")?;
+
+    let now = unix_time();
+    let window = self.window.as_secs();
+    if ts.abs_diff(now) > window {
+      return Err("Requisição fora da janela de tempo");
+    }
+    let mut nonces = self.nonces.lock().unwrap();
+    nonces.retain(|_, seen| seen.expires >= now);
+    match nonces.entry((key_id.to_string(), nonce.to_string())) {
+      Entry::Occupied(seen) if seen.get().client_address != client_address => return Err("Requisição repetida"),
+      Entry::Occupied(_) => {},
+      Entry::Vacant(vacant) => {
+        vacant.insert(SeenNonce { client_address, expires: ts + window });
+      },
+    }
+    Ok(Some(key_id.to_string()))
+  }
+}
+
+// Credenciais do cliente, acrescentadas a cada requisição que acessa arquivos.
+#[derive(Clone, Debug)]
+pub enum Credentials {
+  Token(String),
+  Key { id: String, secret: String },
+}
+
+impl Credentials {
+  // Chave no formato `identificador:segredo`.
+  pub fn parse_key(value: &str) -> Option<Credentials> {
+    match value.split_once(':') {
+      Some((id, secret)) if !id.is_empty() && !secret.is_empty() => {
+        Some(Credentials::Key { id: id.to_string(), secret: secret.to_string() })
+      },
+      _ => None,
+    }
+  }
+
+  // Requisição com as credenciais; as assinadas ganham um nonce novo a cada chamada.
+  pub fn apply(&self, request: &str) -> String {
+    let separator = if request.contains('?') { '&' } else { '?' };
+    match self {
+      Credentials::Token(token) => format!("{}{}token={}", request, separator, token),
+      Credentials::Key { id, secret } => {
+        let signed = format!("{}{}key={}&ts={}&nonce={}", request, separator, id, unix_time(), new_nonce());
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC aceita chaves de qualquer tamanho");
+        mac.update(signed.as_bytes());
+        format!("{}&sig={}", signed, encode_hex(&mac.finalize().into_bytes()))
+      },
+    }
+  }
+}
+
+// Requisição sem os parâmetros de credenciais, para o tratamento e os logs.
+pub fn strip_credentials(request: &str) -> Cow<'_, str> {
+  let (head, query) = match request.split_once('?') {
+    Some(parts) => parts,
+    None => return Cow::Borrowed(request),
+  };
+  let is_credential = |pair: &&str| CREDENTIAL_PARAMS.contains(&pair.split('=').next().unwrap_or_default());
+  if !query.split('&').any(|pair| is_credential(&pair)) {
+    return Cow::Borrowed(request);
+  }
+  let kept: Vec<&str> = query.split('&').filter(|pair| !is_credential(pair)).collect();
+  if kept.is_empty() {
+    Cow::Owned(head.to_string())
+  } else {
+    Cow::Owned(format!("{}?{}", head, kept.join("&")))
+  }
+}
+
+fn query(request: &str) -> Vec<(&str, &str)> {
+  let target = request.split_whitespace().nth(1).unwrap_or_default();
+  let query = target.split_once('?').map(|(_, query)| query).unwrap_or_default();
+  query.split('&').filter_map(|pair| pair.split_once('=')).collect()
+}
+
+fn param<'a>(params: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+  params.iter().find(|(key, _)| *key == name).map(|(_, value)| *value)
+}
+
+fn unix_time() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+// Nonce único por processo: o hash do PID, do relógio e de um contador.
+fn new_nonce() -> String {
+  static COUNTER: AtomicU64 = AtomicU64::new(0);
+  let mut hasher = Sha256::new();
+  hasher.update(process::id().to_be_bytes());
+  hasher.update(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_be_bytes());
+  hasher.update(COUNTER.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+  encode_hex(&hasher.finalize()[..16])
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+  if !value.len().is_multiple_of(2) {
+    return None;
+  }
+  (0..value.len()).step_by(2).map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn authenticator() -> Authenticator {
+    Authenticator::new(KeySet::parse("# chaves de teste\nci-agent s3gredo\ndeploy outro-segredo\n").unwrap())
+  }
+
+  fn peer(port: u16) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, 1], port))
+  }
+
+  // Requisição assinada com `ts` e `nonce` escolhidos.
+  fn signed(secret: &str, ts: u64, nonce: &str) -> String {
+    let signed = format!("GET /a.txt?start=1&key=ci-agent&ts={}&nonce={}", ts, nonce);
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(signed.as_bytes());
+    format!("{}&sig={}", signed, encode_hex(&mac.finalize().into_bytes()))
+  }
+
+  #[test]
+  fn signed_requests_identify_the_key() {
+    let request = Credentials::parse_key("ci-agent:s3gredo").unwrap().apply("GET /a.txt?start=1");
+    assert_eq!(authenticator().verify(&request, peer(1)), Ok(Some("ci-agent".to_string())));
+  }
+
+  #[test]
+  fn tokens_identify_the_key() {
+    let authenticator = authenticator();
+    let request = Credentials::Token("outro-segredo".to_string()).apply("LIST /docs");
+    assert_eq!(authenticator.verify(&request, peer(1)), Ok(Some("deploy".to_string())));
+    assert_eq!(authenticator.verify("LIST /docs?token=errado", peer(1)), Err("Token inválido"));
+  }
+
+  #[test]
+  fn replayed_nonces_are_refused_from_other_addresses() {
+    let authenticator = authenticator();
+    let request = signed("s3gredo", unix_time(), "abc123");
+    assert!(authenticator.verify(&request, peer(1)).is_ok());
+    // O cliente repete o pedido quando a resposta se perde: do mesmo endereço, vale de novo.
+    assert!(authenticator.verify(&request, peer(1)).is_ok());
+    assert_eq!(authenticator.verify(&request, peer(2)), Err("Requisição repetida"));
+  }
+
+  #[test]
+  fn requests_outside_the_window_are_refused() {
+    let authenticator = authenticator().window(Duration::from_secs(30));
+    let now = unix_time();
+    assert!(authenticator.verify(&signed("s3gredo", now - 20, "n1"), peer(1)).is_ok());
+    assert_eq!(authenticator.verify(&signed("s3gredo", now - 40, "n2"), peer(1)), Err("Requisição fora da janela de tempo"));
+    assert_eq!(authenticator.verify(&signed("s3gredo", now + 40, "n3"), peer(1)), Err("Requisição fora da janela de tempo"));
+    assert_eq!(authenticator.verify(&signed("s3gredo", u64::MAX, "n4"), peer(1)), Err("Requisição fora da janela de tempo"));
+  }
+
+  #[test]
+  fn bad_signatures_are_refused() {
+    let authenticator = authenticator();
+    let now = unix_time();
+    assert_eq!(authenticator.verify(&signed("errado", now, "n1"), peer(1)), Err("Assinatura inválida"));
+    // Parâmetro alterado depois da assinatura.
+    let tampered = signed("s3gredo", now, "n2").replace("start=1", "start=2");
+    assert_eq!(authenticator.verify(&tampered, peer(1)), Err("Assinatura inválida"));
+    let truncated = signed("s3gredo", now, "n3");
+    assert_eq!(authenticator.verify(&truncated[..truncated.len() - 1], peer(1)), Err("Assinatura inválida"));
+    assert_eq!(authenticator.verify("GET /a.txt?key=ci-agent&ts=1&nonce=x", peer(1)), Err("Assinatura ausente"));
+    assert_eq!(authenticator.verify(&signed("s3gredo", now, "n4").replace("ci-agent", "outra"), peer(1)), Err("Chave desconhecida"));
+  }
+
+  #[test]
+  fn anonymous_requests_need_permission() {
+    assert_eq!(authenticator().verify("GET /a.txt", peer(1)), Err("Credenciais ausentes"));
+    assert_eq!(authenticator().allow_anonymous(true).verify("GET /a.txt", peer(1)), Ok(None));
+  }
+
+  #[test]
+  fn strip_credentials_keeps_the_other_parameters() {
+    assert_eq!(strip_credentials("GET /a.txt"), "GET /a.txt");
+    assert!(matches!(strip_credentials("GET /a.txt?start=1&chunk=1400"), Cow::Borrowed(_)));
+    assert_eq!(strip_credentials("GET /a.txt?token=segredo"), "GET /a.txt");
+    assert_eq!(strip_credentials(&signed("s3gredo", 1, "n")), "GET /a.txt?start=1");
+    assert_eq!(strip_credentials("GET /a.txt?token=x&chunk=1400&sig=00"), "GET /a.txt?chunk=1400");
+    // Parâmetros que apenas começam com o nome de uma credencial não são retirados.
+    assert_eq!(strip_credentials("GET /a.txt?tokens=2&keyframe=1"), "GET /a.txt?tokens=2&keyframe=1");
+  }
+}
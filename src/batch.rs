@@ -0,0 +1,405 @@
+// Envio e recebimento de datagramas em lote. No Linux usa sendmmsg/recvmmsg, com segmentação
+// pelo kernel (UDP_SEGMENT, GSO) e coalescência na recepção (UDP_GRO) opcionais; nos demais
+// sistemas cai para um send_to/recv_from por datagrama.
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(target_os = "linux")]
+use tracing::warn;
+
+#[cfg(target_os = "linux")]
+use crate::pmtud;
+
+// Número máximo de mensagens por chamada de sendmmsg/recvmmsg.
+pub const BATCH_SIZE: usize = 32;
+// Maior datagrama UDP possível; tamanho dos buffers de recepção quando o GRO está ativo.
+pub const MAX_DATAGRAM: usize = 65_535;
+
+// Opções de E/S em lote escolhidas pelo usuário.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BatchOptions {
+  // Agrupa datagramas de mesmo tamanho em um único envio segmentado pelo kernel (UDP_SEGMENT).
+  pub gso: bool,
+  // Pede ao kernel que entregue datagramas coalescidos (UDP_GRO).
+  pub gro: bool,
+}
+
+// Envia listas de datagramas para um destino usando o menor número possível de syscalls.
+pub struct BatchSender {
+  gso: AtomicBool,
+}
+
+impl BatchSender {
+  pub fn new(options: BatchOptions) -> BatchSender {
+    BatchSender { gso: AtomicBool::new(options.gso && cfg!(target_os = "linux")) }
+  }
+
+  // Indica se o GSO continua ativo (ele é desligado na primeira recusa do kernel).
+  pub fn gso_enabled(&self) -> bool {
+    self.gso.load(Ordering::Relaxed)
+  }
+
+  // Envia todos os datagramas por um socket bloqueante.
+  pub fn send_all(&self, socket: &UdpSocket, datagrams: &[Vec<u8>], destination: SocketAddr) -> io::Result<()> {
+    let mut offset = 0;
+    while offset < datagrams.len() {
+      offset += self.send_some(socket, &datagrams[offset..], destination)?;
+    }
+    Ok(())
+  }
+
+  // Envia uma parte dos datagramas em uma única syscall e devolve quantos foram enviados.
+  // Em sockets não bloqueantes pode devolver `WouldBlock`, sem ter enviado nada.
+  #[cfg(target_os = "linux")]
+  pub fn send_some(&self, socket: &impl AsRawFd, datagrams: &[Vec<u8>], destination: SocketAddr) -> io::Result<usize> {
+    // A confirmação de uma sonda de PMTU não pode ser fragmentada no caminho de volta.
+    if let [datagram] = datagrams {
+      if pmtud::is_probe_ack(datagram) {
+        let _dont_fragment = pmtud::DontFragment::force(socket.as_raw_fd(), destination.is_ipv6())?;
+        return linux::send_batch(socket.as_raw_fd(), datagrams, destination, false);
+      }
+    }
+    let _sending = pmtud::sending();
+    let gso = self.gso_enabled();
+    match linux::send_batch(socket.as_raw_fd(), datagrams, destination, gso) {
+      Err(e) if gso && linux::is_gso_unsupported(&e) => {
+        warn!(error = %e, "UDP_SEGMENT not supported, disabling GSO");
+        self.gso.store(false, Ordering::Relaxed);
+        linux::send_batch(socket.as_raw_fd(), datagrams, destination, false)
+      },
+      result => result,
+    }
+  }
+
+  #[cfg(not(target_os = "linux"))]
+  pub fn send_some(&self, socket: &UdpSocket, datagrams: &[Vec<u8>], destination: SocketAddr) -> io::Result<usize> {
+    match datagrams.first() {
+      Some(datagram) => {
+        socket.send_to(datagram, destination)?;
+        Ok(1)
+      },
+      None => Ok(0),
+    }
+  }
+}
+
+// Recebe datagramas em lote, reaproveitando os buffers entre chamadas.
+pub struct BatchReceiver {
+  gro: bool,
+  bufs: Vec<Vec<u8>>,
+  // (buffer, início, tamanho, origem) de cada datagrama recebido na última chamada.
+  received: Vec<(usize, usize, usize, SocketAddr)>,
+}
+
+impl BatchReceiver {
+  // Cria o receptor para datagramas de até `datagram_size` bytes, ativando o GRO no socket
+  // quando pedido e suportado pelo kernel.
+  pub fn new(socket: &UdpSocket, options: BatchOptions, datagram_size: usize) -> BatchReceiver {
+    let gro = options.gro && enable_gro(socket);
+    let buf_size = if gro { MAX_DATAGRAM } else { datagram_size };
+    let batch = if cfg!(target_os = "linux") { BATCH_SIZE } else { 1 };
+    BatchReceiver {
+      gro,
+      bufs: vec![vec![0u8; buf_size]; batch],
+      received: Vec::with_capacity(batch),
+    }
+  }
+
+  pub fn gro_enabled(&self) -> bool {
+    self.gro
+  }
+
+  // Aumenta os buffers para datagramas de até `datagram_size` bytes (com GRO eles já têm o máximo).
+  pub fn reserve(&mut self, datagram_size: usize) {
+    for buf in &mut self.bufs {
+      if buf.len() < datagram_size {
+        buf.resize(datagram_size, 0);
+      }
+    }
+  }
+
+  // Bloqueia até chegar pelo menos um datagrama (respeitando o timeout de leitura do socket) e
+  // devolve quantos datagramas ficaram disponíveis em `datagrams`.
+  #[cfg(target_os = "linux")]
+  pub fn recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+    self.received.clear();
+    linux::recv_batch(socket.as_raw_fd(), &mut self.bufs, &mut self.received)?;
+    Ok(self.received.len())
+  }
+
+  #[cfg(not(target_os = "linux"))]
+  pub fn recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+    self.received.clear();
+    let (size, origin) = socket.recv_from(&mut self.bufs[0])?;
+    self.received.push((0, 0, size, origin));
+    Ok(1)
+  }
+
+  // Datagramas recebidos na última chamada de `recv`, já separados quando vieram coalescidos.
+  pub fn datagrams(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> + '_ {
+    self.received.iter().map(|&(buf, start, len, origin)| (&self.bufs[buf][start..start + len], origin))
+  }
+}
+
+#[cfg(target_os = "linux")]
+fn enable_gro(socket: &UdpSocket) -> bool {
+  match linux::set_udp_option(socket.as_raw_fd(), libc::UDP_GRO, 1) {
+    Ok(()) => true,
+    Err(e) => {
+      warn!(error = %e, "UDP_GRO not supported, receiving without coalescing");
+      false
+    }
+  }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn enable_gro(_socket: &UdpSocket) -> bool {
+  false
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+  use std::io;
+  use std::mem;
+  use std::net::SocketAddr;
+  use std::os::fd::RawFd;
+  use std::ptr;
+
+  use socket2::SockAddr;
+
+  // Limites do kernel para um único envio com UDP_SEGMENT.
+  const GSO_MAX_SEGMENTS: usize = 64;
+  const GSO_MAX_BYTES: usize = 65_000;
+  // Espaço para uma mensagem de controle com um inteiro, alinhado para cmsghdr.
+  type ControlBuf = [u64; 4];
+
+  pub fn set_udp_option(fd: RawFd, option: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    let result = unsafe {
+      libc::setsockopt(
+        fd,
+        libc::SOL_UDP,
+        option,
+        &value as *const libc::c_int as *const libc::c_void,
+        mem::size_of::<libc::c_int>() as libc::socklen_t,
+      )
+    };
+    if result < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+
+  // Erros com que o kernel (ou a interface de saída) recusa um envio com UDP_SEGMENT.
+  pub fn is_gso_unsupported(e: &io::Error) -> bool {
+    matches!(
+      e.raw_os_error(),
+      Some(libc::EIO) | Some(libc::EINVAL) | Some(libc::ENOPROTOOPT) | Some(libc::EOPNOTSUPP)
+    )
+  }
+
+  // Agrupa datagramas consecutivos de mesmo tamanho (mais um último menor, se houver), que é o
+  // formato aceito pelo UDP_SEGMENT. Sem GSO cada datagrama vira um grupo.
+  fn gso_groups(datagrams: &[Vec<u8>], gso: bool) -> Vec<(usize, usize)> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    while start < datagrams.len() && groups.len() < super::BATCH_SIZE {
+      let segment = datagrams[start].len();
+      let mut end = start + 1;
+      if gso && segment > 0 {
+        let mut bytes = segment;
+        while end < datagrams.len()
+          && end - start < GSO_MAX_SEGMENTS
+          && bytes + datagrams[end].len() <= GSO_MAX_BYTES
+          && datagrams[end].len() <= segment
+          && !datagrams[end].is_empty()
+        {
+          bytes += datagrams[end].len();
+          end += 1;
+          if datagrams[end - 1].len() < segment {
+            break;
+          }
+        }
+      }
+      groups.push((start, end));
+      start = end;
+    }
+    groups
+  }
+
+  pub fn send_batch(fd: RawFd, datagrams: &[Vec<u8>], destination: SocketAddr, gso: bool) -> io::Result<usize> {
+    if datagrams.is_empty() {
+      return Ok(0);
+    }
+    let destination = SockAddr::from(destination);
+    let groups = gso_groups(datagrams, gso);
+    let used = groups.last().map(|&(_, end)| end).unwrap_or(0);
+
+    let mut iovecs: Vec<libc::iovec> = datagrams[..used]
+      .iter()
+      .map(|datagram| libc::iovec {
+        iov_base: datagram.as_ptr() as *mut libc::c_void,
+        iov_len: datagram.len(),
+      })
+      .collect();
+    let mut controls: Vec<ControlBuf> = vec![[0; 4]; groups.len()];
+    let mut msgs: Vec<libc::mmsghdr> = Vec::with_capacity(groups.len());
+
+    for (&(start, end), control) in groups.iter().zip(controls.iter_mut()) {
+      let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+      hdr.msg_name = destination.as_ptr() as *mut libc::c_void;
+      hdr.msg_namelen = destination.len();
+      hdr.msg_iov = unsafe { iovecs.as_mut_ptr().add(start) };
+      hdr.msg_iovlen = (end - start) as _;
+
+      if end - start > 1 {
+        let segment_size = datagrams[start].len() as u16;
+        hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        hdr.msg_controllen = unsafe { libc::CMSG_SPACE(mem::size_of::<u16>() as u32) } as _;
+        unsafe {
+          let cmsg = libc::CMSG_FIRSTHDR(&hdr);
+          (*cmsg).cmsg_level = libc::SOL_UDP;
+          (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+          (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as u32) as _;
+          ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment_size);
+        }
+      }
+      msgs.push(libc::mmsghdr { msg_hdr: hdr, msg_len: 0 });
+    }
+
+    let sent = unsafe { libc::sendmmsg(fd, msgs.as_mut_ptr(), msgs.len() as libc::c_uint, 0) };
+    if sent < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(groups[..sent as usize].iter().map(|&(start, end)| end - start).sum())
+  }
+
+  pub fn recv_batch(
+    fd: RawFd,
+    bufs: &mut [Vec<u8>],
+    received: &mut Vec<(usize, usize, usize, SocketAddr)>,
+  ) -> io::Result<()> {
+    let mut iovecs: Vec<libc::iovec> = bufs
+      .iter_mut()
+      .map(|buf| libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+      })
+      .collect();
+    let mut addrs: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; bufs.len()];
+    let mut controls: Vec<ControlBuf> = vec![[0; 4]; bufs.len()];
+    let mut msgs: Vec<libc::mmsghdr> = Vec::with_capacity(bufs.len());
+
+    for i in 0..bufs.len() {
+      let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+      hdr.msg_name = &mut addrs[i] as *mut libc::sockaddr_storage as *mut libc::c_void;
+      hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+      hdr.msg_iov = &mut iovecs[i];
+      hdr.msg_iovlen = 1;
+      hdr.msg_control = controls[i].as_mut_ptr() as *mut libc::c_void;
+      hdr.msg_controllen = mem::size_of::<ControlBuf>() as _;
+      msgs.push(libc::mmsghdr { msg_hdr: hdr, msg_len: 0 });
+    }
+
+    let count = unsafe {
+      libc::recvmmsg(fd, msgs.as_mut_ptr(), msgs.len() as libc::c_uint, libc::MSG_WAITFORONE, ptr::null_mut())
+    };
+    if count < 0 {
+      return Err(io::Error::last_os_error());
+    }
+
+    for (i, msg) in msgs.iter().take(count as usize).enumerate() {
+      let origin = unsafe { SockAddr::new(addrs[i], msg.msg_hdr.msg_namelen) };
+      let origin = match origin.as_socket() {
+        Some(origin) => origin,
+        None => continue,
+      };
+      let len = msg.msg_len as usize;
+      let segment = gro_segment_size(&msg.msg_hdr).unwrap_or(len).max(1);
+      let mut start = 0;
+      while start < len {
+        let size = segment.min(len - start);
+        received.push((i, start, size, origin));
+        start += size;
+      }
+    }
+    Ok(())
+  }
+
+  // Tamanho dos segmentos de um datagrama coalescido pelo GRO, informado em mensagem de controle.
+  fn gro_segment_size(hdr: &libc::msghdr) -> Option<usize> {
+    unsafe {
+      let mut cmsg = libc::CMSG_FIRSTHDR(hdr);
+      while !cmsg.is_null() {
+        if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+          let size = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+          return Some(size as usize);
+        }
+        cmsg = libc::CMSG_NXTHDR(hdr, cmsg);
+      }
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+
+  fn socket() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    socket
+  }
+
+  // Datagramas de tamanhos variados, cada um com o seu índice no início.
+  fn datagrams() -> Vec<Vec<u8>> {
+    let sizes = (0..70).map(|_| 1000).chain([300, 1000, 1000, 999, 1000, 1]);
+    sizes.enumerate().map(|(i, size)| (0..size).map(|j| if j == 0 { i as u8 } else { (j % 251) as u8 }).collect()).collect()
+  }
+
+  fn receive(receiver: &mut BatchReceiver, socket: &UdpSocket, expected: usize) -> Vec<Vec<u8>> {
+    let mut received = Vec::new();
+    while received.len() < expected {
+      receiver.recv(socket).expect("datagramas não chegaram");
+      received.extend(receiver.datagrams().map(|(datagram, _)| datagram.to_vec()));
+    }
+    received
+  }
+
+  #[test]
+  fn datagram_boundaries_survive_batching() {
+    for (gso, gro) in [(false, false), (true, false), (true, true)] {
+      let options = BatchOptions { gso, gro };
+      let (sender_socket, receiver_socket) = (socket(), socket());
+      let sender = BatchSender::new(options);
+      let mut receiver = BatchReceiver::new(&receiver_socket, options, 1000);
+      let sent = datagrams();
+      sender.send_all(&sender_socket, &sent, receiver_socket.local_addr().unwrap()).unwrap();
+      let received = receive(&mut receiver, &receiver_socket, sent.len());
+      assert_eq!(received, sent, "gso={} gro={}", gso, gro);
+    }
+  }
+
+  #[test]
+  fn reserve_grows_the_buffers() {
+    let (sender_socket, receiver_socket) = (socket(), socket());
+    let mut receiver = BatchReceiver::new(&receiver_socket, BatchOptions::default(), 100);
+    receiver.reserve(1500);
+    let sent = vec![vec![7u8; 1500]];
+    BatchSender::new(BatchOptions::default()).send_all(&sender_socket, &sent, receiver_socket.local_addr().unwrap()).unwrap();
+    assert_eq!(receive(&mut receiver, &receiver_socket, 1), sent);
+  }
+
+  #[test]
+  fn empty_lists_send_nothing() {
+    let sender_socket = socket();
+    let sender = BatchSender::new(BatchOptions { gso: true, gro: false });
+    assert_eq!(sender.send_some(&sender_socket, &[], sender_socket.local_addr().unwrap()).unwrap(), 0);
+    sender.send_all(&sender_socket, &[], sender_socket.local_addr().unwrap()).unwrap();
+  }
+}
@@ -0,0 +1,695 @@
+// Captura do tráfego do protocolo em um arquivo pcap, sem ferramenta externa. Cada datagrama
+// enviado ou recebido pelo processo é gravado com o horário em que passou pelo socket e com
+// cabeçalhos IP e UDP sintéticos (LINKTYPE_RAW), de modo que o arquivo abre no Wireshark e no
+// tcpdump. A captura é global ao processo: `start` a liga e os pontos de envio e recepção chamam
+// `record`, que não faz nada enquanto ela está desligada.
+//
+// No sentido inverso, `PcapReader` lê capturas (desta ou de outras ferramentas) e devolve os
+// datagramas UDP com endereços e horário, e `Sniffer` escuta passivamente o tráfego de uma porta;
+// ambos alimentam o inspetor (`rsudp-inspect`).
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Cabeçalho global do pcap: magic em microssegundos, versão 2.4, snaplen e LINKTYPE_RAW (pacotes
+// começam no cabeçalho IP, v4 ou v6).
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const SNAPLEN: u32 = 262_144;
+const LINKTYPE_RAW: u32 = 101;
+// Variantes aceitas na leitura: timestamps em nanossegundos, pcapng (apenas para a mensagem de
+// erro) e os tipos de enlace mais comuns em capturas de tcpdump.
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_MAGIC: u32 = 0x0a0d_0d0a;
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: [u16; 2] = [0x8100, 0x88a8];
+// Registros maiores que isso indicam um arquivo corrompido.
+const MAX_RECORD_LEN: usize = 16 * 1024 * 1024;
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+const IP_PROTOCOL_UDP: u8 = 17;
+const TTL: u8 = 64;
+
+// Sentido do datagrama em relação ao processo.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+  Sent,
+  Received,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// Identificação dos cabeçalhos IPv4 sintéticos.
+static NEXT_IP_ID: AtomicU16 = AtomicU16::new(0);
+
+lazy_static! {
+  static ref CAPTURE: Mutex<Option<BufWriter<File>>> = Mutex::new(None);
+}
+
+// Liga a captura, gravando em `path` (o arquivo é recriado).
+pub fn start(path: &Path) -> io::Result<()> {
+  let mut writer = BufWriter::new(File::create(path)?);
+  writer.write_all(&PCAP_MAGIC.to_le_bytes())?;
+  writer.write_all(&2u16.to_le_bytes())?;
+  writer.write_all(&4u16.to_le_bytes())?;
+  writer.write_all(&0i32.to_le_bytes())?;
+  writer.write_all(&0u32.to_le_bytes())?;
+  writer.write_all(&SNAPLEN.to_le_bytes())?;
+  writer.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+  writer.flush()?;
+  *CAPTURE.lock().unwrap() = Some(writer);
+  ENABLED.store(true, Ordering::Release);
+  Ok(())
+}
+
+// Desliga a captura e grava o que estiver pendente.
+pub fn stop() -> io::Result<()> {
+  ENABLED.store(false, Ordering::Release);
+  match CAPTURE.lock().unwrap().take() {
+    Some(mut writer) => writer.flush(),
+    None => Ok(()),
+  }
+}
+
+pub fn enabled() -> bool {
+  ENABLED.load(Ordering::Acquire)
+}
+
+// Grava um datagrama trocado entre `local` (o socket do processo) e `peer`.
+pub fn record(local: SocketAddr, peer: SocketAddr, direction: Direction, datagram: &[u8]) {
+  record_all(local, peer, direction, std::iter::once(datagram));
+}
+
+// Grava vários datagramas de uma vez, com um único flush no final.
+pub fn record_all<'a>(local: SocketAddr, peer: SocketAddr, direction: Direction, datagrams: impl IntoIterator<Item = &'a [u8]>) {
+  if !enabled() {
+    return;
+  }
+  let (source, destination) = match direction {
+    Direction::Sent => (local, peer),
+    Direction::Received => (peer, local),
+  };
+  let (source, destination) = synthetic_addresses(source, destination);
+
+  let mut capture = CAPTURE.lock().unwrap();
+  let writer = match capture.as_mut() {
+    Some(writer) => writer,
+    None => return,
+  };
+  let result = datagrams
+    .into_iter()
+    .try_for_each(|datagram| write_record(writer, &ip_packet(source, destination, datagram)))
+    .and_then(|_| writer.flush());
+  if let Err(e) = result {
+    tracing::warn!(error = %e, "failed to write capture, stopping it");
+    ENABLED.store(false, Ordering::Release);
+    *capture = None;
+  }
+}
+
+// Como `record_all`, com o endereço local tirado do socket (consultado apenas com a captura ligada).
+pub fn record_socket<'a>(socket: &UdpSocket, peer: SocketAddr, direction: Direction, datagrams: impl IntoIterator<Item = &'a [u8]>) {
+  if let (true, Ok(local)) = (enabled(), socket.local_addr()) {
+    record_all(local, peer, direction, datagrams);
+  }
+}
+
+fn write_record(writer: &mut impl Write, packet: &[u8]) -> io::Result<()> {
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+  let captured = packet.len().min(SNAPLEN as usize);
+  writer.write_all(&(now.as_secs() as u32).to_le_bytes())?;
+  writer.write_all(&now.subsec_micros().to_le_bytes())?;
+  writer.write_all(&(captured as u32).to_le_bytes())?;
+  writer.write_all(&(packet.len() as u32).to_le_bytes())?;
+  writer.write_all(&packet[..captured])
+}
+
+// Endereços dos cabeçalhos sintéticos. Um socket ligado ao endereço coringa não sabe por qual
+// interface o datagrama passou; nesse caso usa o loopback quando o outro lado é local. Endereços
+// de famílias diferentes (IPv4 em um socket IPv6) são representados em IPv6.
+fn synthetic_addresses(source: SocketAddr, destination: SocketAddr) -> (SocketAddr, SocketAddr) {
+  let resolve = |address: SocketAddr, other: SocketAddr| {
+    let mut address = address;
+    if address.ip().is_unspecified() && other.ip().is_loopback() {
+      address.set_ip(match other.ip() {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+      });
+    }
+    address
+  };
+  let (source, destination) = (resolve(source, destination), resolve(destination, source));
+  match (source.ip(), destination.ip()) {
+    (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => (source, destination),
+    _ => (to_ipv6(source), to_ipv6(destination)),
+  }
+}
+
+fn to_ipv6(address: SocketAddr) -> SocketAddr {
+  match address.ip() {
+    IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), address.port()),
+    IpAddr::V6(_) => address,
+  }
+}
+
+// Cabeçalhos IP e UDP, com checksums, seguidos do datagrama.
+fn ip_packet(source: SocketAddr, destination: SocketAddr, datagram: &[u8]) -> Vec<u8> {
+  let udp_len = UDP_HEADER_LEN + datagram.len();
+  let mut udp = Vec::with_capacity(udp_len);
+  udp.extend_from_slice(&source.port().to_be_bytes());
+  udp.extend_from_slice(&destination.port().to_be_bytes());
+  udp.extend_from_slice(&(udp_len as u16).to_be_bytes());
+  udp.extend_from_slice(&[0, 0]);
+  udp.extend_from_slice(datagram);
+
+  match (source.ip(), destination.ip()) {
+    (IpAddr::V4(src), IpAddr::V4(dst)) => {
+      let mut pseudo = Vec::with_capacity(12);
+      pseudo.extend_from_slice(&src.octets());
+      pseudo.extend_from_slice(&dst.octets());
+      pseudo.extend_from_slice(&[0, IP_PROTOCOL_UDP]);
+      pseudo.extend_from_slice(&(udp_len as u16).to_be_bytes());
+      set_udp_checksum(&mut udp, &pseudo);
+
+      let mut packet = Vec::with_capacity(IPV4_HEADER_LEN + udp_len);
+      packet.extend_from_slice(&[0x45, 0]);
+      packet.extend_from_slice(&((IPV4_HEADER_LEN + udp_len) as u16).to_be_bytes());
+      packet.extend_from_slice(&NEXT_IP_ID.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+      // Don't Fragment, sem deslocamento.
+      packet.extend_from_slice(&[0x40, 0, TTL, IP_PROTOCOL_UDP, 0, 0]);
+      packet.extend_from_slice(&src.octets());
+      packet.extend_from_slice(&dst.octets());
+      let checksum = internet_checksum(&[&packet]);
+      packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+      packet.extend_from_slice(&udp);
+      packet
+    },
+    (src, dst) => {
+      let (src, dst) = (ipv6_octets(src), ipv6_octets(dst));
+      let mut pseudo = Vec::with_capacity(40);
+      pseudo.extend_from_slice(&src);
+      pseudo.extend_from_slice(&dst);
+      pseudo.extend_from_slice(&(udp_len as u32).to_be_bytes());
+      pseudo.extend_from_slice(&[0, 0, 0, IP_PROTOCOL_UDP]);
+      set_udp_checksum(&mut udp, &pseudo);
+
+      let mut packet = Vec::with_capacity(IPV6_HEADER_LEN + udp_len);
+      packet.extend_from_slice(&[0x60, 0, 0, 0]);
+      packet.extend_from_slice(&(udp_len as u16).to_be_bytes());
+      packet.extend_from_slice(&[IP_PROTOCOL_UDP, TTL]);
+      packet.extend_from_slice(&src);
+      packet.extend_from_slice(&dst);
+      packet.extend_from_slice(&udp);
+      packet
+    },
+  }
+}
+
+fn ipv6_octets(ip: IpAddr) -> [u8; 16] {
+  match ip {
+    IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+    IpAddr::V6(ip) => ip.octets(),
+  }
+}
+
+// Checksum UDP sobre o pseudo-cabeçalho e o segmento; zero é transmitido como 0xffff.
+fn set_udp_checksum(udp: &mut [u8], pseudo_header: &[u8]) {
+  let checksum = match internet_checksum(&[pseudo_header, udp]) {
+    0 => 0xffff,
+    checksum => checksum,
+  };
+  udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+}
+
+// Checksum da Internet (RFC 1071) sobre a concatenação das partes, todas de tamanho par exceto a
+// última.
+fn internet_checksum(parts: &[&[u8]]) -> u16 {
+  let mut sum: u32 = 0;
+  for part in parts {
+    let mut chunks = part.chunks_exact(2);
+    for pair in &mut chunks {
+      sum += u16::from_be_bytes([pair[0], pair[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+      sum += (*last as u32) << 8;
+    }
+  }
+  while sum > 0xffff {
+    sum = (sum & 0xffff) + (sum >> 16);
+  }
+  !(sum as u16)
+}
+
+// Datagrama UDP lido de uma captura.
+#[derive(Clone, Debug)]
+pub struct CapturedDatagram {
+  // Horário da captura, desde a época Unix.
+  pub timestamp: Duration,
+  pub source: SocketAddr,
+  pub destination: SocketAddr,
+  pub payload: Vec<u8>,
+}
+
+// Leitor de arquivos pcap clássicos, em qualquer ordem de bytes e com timestamps em micro ou
+// nanossegundos. Pacotes que não são UDP (ARP, TCP, fragmentos IP) são ignorados.
+pub struct PcapReader<R> {
+  reader: R,
+  big_endian: bool,
+  nanos: bool,
+  link_type: u32,
+}
+
+impl PcapReader<BufReader<File>> {
+  pub fn open(path: &Path) -> io::Result<PcapReader<BufReader<File>>> {
+    PcapReader::new(BufReader::new(File::open(path)?))
+  }
+}
+
+impl<R: Read> PcapReader<R> {
+  pub fn new(mut reader: R) -> io::Result<PcapReader<R>> {
+    let mut header = [0u8; 24];
+    if !read_or_eof(&mut reader, &mut header)? {
+      return Err(invalid_data("não é um arquivo pcap"));
+    }
+    let magic = [header[0], header[1], header[2], header[3]];
+    let (big_endian, nanos) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+      (PCAP_MAGIC, _) => (false, false),
+      (PCAP_MAGIC_NANOS, _) => (false, true),
+      (_, PCAP_MAGIC) => (true, false),
+      (_, PCAP_MAGIC_NANOS) => (true, true),
+      (PCAPNG_MAGIC, _) => {
+        return Err(invalid_data("formato pcapng não suportado (converta com `editcap -F pcap`)"));
+      },
+      _ => return Err(invalid_data("não é um arquivo pcap")),
+    };
+    let mut pcap = PcapReader { reader, big_endian, nanos, link_type: 0 };
+    // Os bits altos do tipo de enlace trazem informações de FCS, irrelevantes aqui.
+    pcap.link_type = pcap.u32_at(&header, 20) & 0x0fff_ffff;
+    match pcap.link_type {
+      LINKTYPE_NULL | LINKTYPE_ETHERNET | LINKTYPE_RAW | LINKTYPE_LOOP | LINKTYPE_LINUX_SLL | LINKTYPE_IPV4
+      | LINKTYPE_IPV6 | LINKTYPE_LINUX_SLL2 => Ok(pcap),
+      other => Err(invalid_data(&format!("tipo de enlace {} não suportado", other))),
+    }
+  }
+
+  // Próximo datagrama UDP da captura, ou `None` no fim do arquivo. Um último registro truncado
+  // (captura interrompida no meio da escrita) também encerra a leitura.
+  pub fn next_datagram(&mut self) -> io::Result<Option<CapturedDatagram>> {
+    loop {
+      let mut header = [0u8; 16];
+      if !read_or_eof(&mut self.reader, &mut header)? {
+        return Ok(None);
+      }
+      // O tamanho é conferido antes de reservar o buffer: um cabeçalho corrompido pode anunciar 4 GiB.
+      let record_len = self.u32_at(&header, 8) as usize;
+      if record_len > MAX_RECORD_LEN {
+        return Err(invalid_data("registro pcap com tamanho inválido"));
+      }
+      let mut packet = vec![0u8; record_len];
+      if !read_or_eof(&mut self.reader, &mut packet)? {
+        return Ok(None);
+      }
+      let fraction = self.u32_at(&header, 4);
+      let nanos = if self.nanos { fraction } else { fraction.saturating_mul(1000) };
+      let timestamp = Duration::new(self.u32_at(&header, 0) as u64, 0) + Duration::from_nanos(nanos as u64);
+
+      let datagram = strip_link_layer(self.link_type, &packet)
+        .and_then(parse_ip_packet)
+        .map(|(source, destination, payload)| CapturedDatagram { timestamp, source, destination, payload: payload.to_vec() });
+      if datagram.is_some() {
+        return Ok(datagram);
+      }
+    }
+  }
+
+  fn u32_at(&self, bytes: &[u8], offset: usize) -> u32 {
+    let field = [bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]];
+    if self.big_endian {
+      u32::from_be_bytes(field)
+    } else {
+      u32::from_le_bytes(field)
+    }
+  }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+  type Item = io::Result<CapturedDatagram>;
+
+  fn next(&mut self) -> Option<io::Result<CapturedDatagram>> {
+    self.next_datagram().transpose()
+  }
+}
+
+// Preenche `buf` inteiro; devolve `false` se o arquivo terminar antes.
+fn read_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+  match reader.read_exact(buf) {
+    Ok(()) => Ok(true),
+    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+    Err(e) => Err(e),
+  }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Pacote IP dentro do quadro do enlace informado; `None` para outros protocolos.
+fn strip_link_layer(link_type: u32, frame: &[u8]) -> Option<&[u8]> {
+  let is_ip = |ethertype: u16| ethertype == ETHERTYPE_IPV4 || ethertype == ETHERTYPE_IPV6;
+  let u16_at = |offset: usize| frame.get(offset..offset + 2).map(|field| u16::from_be_bytes([field[0], field[1]]));
+  match link_type {
+    LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(frame),
+    // Família do protocolo em 4 bytes, que o próprio cabeçalho IP torna dispensável.
+    LINKTYPE_NULL | LINKTYPE_LOOP => frame.get(4..),
+    LINKTYPE_ETHERNET => {
+      let mut offset = 12;
+      while ETHERTYPE_VLAN.contains(&u16_at(offset)?) {
+        offset += 4;
+      }
+      is_ip(u16_at(offset)?).then(|| frame.get(offset + 2..)).flatten()
+    },
+    LINKTYPE_LINUX_SLL => is_ip(u16_at(14)?).then(|| frame.get(16..)).flatten(),
+    LINKTYPE_LINUX_SLL2 => is_ip(u16_at(0)?).then(|| frame.get(20..)).flatten(),
+    _ => None,
+  }
+}
+
+// Origem, destino e dados de um datagrama UDP em um pacote IPv4 ou IPv6. Fragmentos e pacotes de
+// outros protocolos devolvem `None`.
+pub fn parse_ip_packet(packet: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+  let (source, destination, segment): (IpAddr, IpAddr, &[u8]) = match packet.first()? >> 4 {
+    4 => {
+      let header_len = ((packet[0] & 0x0f) as usize) * 4;
+      let total_len = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]) as usize;
+      // Mais fragmentos (MF) ou deslocamento diferente de zero.
+      let fragmented = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]) & 0x3fff != 0;
+      if header_len < IPV4_HEADER_LEN || packet.len() < header_len || packet[9] != IP_PROTOCOL_UDP || fragmented {
+        return None;
+      }
+      let source = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+      let destination = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+      let end = total_len.clamp(header_len, packet.len());
+      (source.into(), destination.into(), &packet[header_len..end])
+    },
+    6 => {
+      if packet.len() < IPV6_HEADER_LEN {
+        return None;
+      }
+      let payload_len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+      let end = (IPV6_HEADER_LEN + payload_len).min(packet.len());
+      let source: [u8; 16] = packet[8..24].try_into().unwrap();
+      let destination: [u8; 16] = packet[24..40].try_into().unwrap();
+      // Cabeçalhos de extensão sem efeito no datagrama (hop-by-hop, roteamento, opções de destino).
+      let (mut next_header, mut offset) = (packet[6], IPV6_HEADER_LEN);
+      while matches!(next_header, 0 | 43 | 60) {
+        next_header = *packet.get(offset)?;
+        offset += (*packet.get(offset + 1)? as usize + 1) * 8;
+      }
+      if next_header != IP_PROTOCOL_UDP || offset > end {
+        return None;
+      }
+      (Ipv6Addr::from(source).into(), Ipv6Addr::from(destination).into(), &packet[offset..end])
+    },
+    _ => return None,
+  };
+  if segment.len() < UDP_HEADER_LEN {
+    return None;
+  }
+  let source_port = u16::from_be_bytes([segment[0], segment[1]]);
+  let destination_port = u16::from_be_bytes([segment[2], segment[3]]);
+  let udp_len = (u16::from_be_bytes([segment[4], segment[5]]) as usize).clamp(UDP_HEADER_LEN, segment.len());
+  Some((
+    SocketAddr::new(source, source_port),
+    SocketAddr::new(destination, destination_port),
+    &segment[UDP_HEADER_LEN..udp_len],
+  ))
+}
+
+// Escuta passiva do tráfego UDP de uma porta em todas as interfaces, com um socket AF_PACKET
+// (requer CAP_NET_RAW). Diferente de um socket UDP comum, não consome os datagramas: o servidor e
+// os clientes continuam recebendo tudo normalmente.
+#[cfg(target_os = "linux")]
+pub struct Sniffer {
+  socket: std::os::fd::OwnedFd,
+  port: u16,
+  buf: Vec<u8>,
+}
+
+// Definições de <linux/if_packet.h> ausentes da libc: o tipo de pacote de saída e as estatísticas
+// do socket (pacotes entregues e descartados por falta de espaço no buffer).
+#[cfg(target_os = "linux")]
+const PACKET_OUTGOING: u8 = 4;
+#[cfg(target_os = "linux")]
+const SOL_PACKET: libc::c_int = 263;
+#[cfg(target_os = "linux")]
+const PACKET_STATISTICS: libc::c_int = 6;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct TpacketStats {
+  tp_packets: libc::c_uint,
+  tp_drops: libc::c_uint,
+}
+
+// Buffer de recepção pedido para a escuta, que precisa acompanhar as rajadas de uma transferência.
+#[cfg(target_os = "linux")]
+const SNIFFER_BUFFER_SIZE: libc::c_int = 32 * 1024 * 1024;
+
+#[cfg(target_os = "linux")]
+impl Sniffer {
+  pub fn open(port: u16) -> io::Result<Sniffer> {
+    use std::os::fd::FromRawFd;
+
+    let protocol = (libc::ETH_P_ALL as u16).to_be() as libc::c_int;
+    let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, protocol) };
+    if fd < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    let sniffer = Sniffer { socket: unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) }, port, buf: vec![0u8; 65_536] };
+    // SO_RCVBUFFORCE ignora o limite net.core.rmem_max, mas só com CAP_NET_ADMIN.
+    let size = &SNIFFER_BUFFER_SIZE as *const libc::c_int as *const libc::c_void;
+    let len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    unsafe {
+      if libc::setsockopt(fd, libc::SOL_SOCKET, libc::SO_RCVBUFFORCE, size, len) != 0 {
+        libc::setsockopt(fd, libc::SOL_SOCKET, libc::SO_RCVBUF, size, len);
+      }
+    }
+    Ok(sniffer)
+  }
+
+  // Datagramas descartados pelo kernel porque a leitura não acompanhou o tráfego, desde a consulta
+  // anterior (o kernel zera a contagem a cada leitura).
+  pub fn dropped(&self) -> io::Result<u32> {
+    use std::os::fd::AsRawFd;
+
+    let mut stats = TpacketStats::default();
+    let mut len = std::mem::size_of::<TpacketStats>() as libc::socklen_t;
+    let result = unsafe {
+      libc::getsockopt(
+        self.socket.as_raw_fd(),
+        SOL_PACKET,
+        PACKET_STATISTICS,
+        &mut stats as *mut TpacketStats as *mut libc::c_void,
+        &mut len,
+      )
+    };
+    if result != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(stats.tp_drops)
+  }
+
+  // Próximo datagrama enviado ou recebido pela porta escutada. Uma espera interrompida por um sinal
+  // devolve `ErrorKind::Interrupted`, para que o chamador decida se continua.
+  pub fn next_datagram(&mut self) -> io::Result<CapturedDatagram> {
+    use std::os::fd::AsRawFd;
+
+    loop {
+      let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+      let mut address_len = std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+      let received = unsafe {
+        libc::recvfrom(
+          self.socket.as_raw_fd(),
+          self.buf.as_mut_ptr() as *mut libc::c_void,
+          self.buf.len(),
+          0,
+          &mut address as *mut libc::sockaddr_ll as *mut libc::sockaddr,
+          &mut address_len,
+        )
+      };
+      if received < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      // No loopback cada pacote aparece duas vezes, na saída e na entrada; fica só a entrada.
+      if address.sll_pkttype == PACKET_OUTGOING && address.sll_hatype == libc::ARPHRD_LOOPBACK {
+        continue;
+      }
+      let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+      match parse_ip_packet(&self.buf[..received as usize]) {
+        Some((source, destination, payload)) if source.port() == self.port || destination.port() == self.port => {
+          return Ok(CapturedDatagram { timestamp, source, destination, payload: payload.to_vec() });
+        },
+        _ => {},
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn address(value: &str) -> SocketAddr {
+    value.parse().unwrap()
+  }
+
+  // Cabeçalho global de uma captura na ordem de bytes pedida.
+  fn pcap_header(big_endian: bool, magic: u32, link_type: u32) -> Vec<u8> {
+    let fields = [magic, 0, 0, 0, SNAPLEN, link_type];
+    let mut header: Vec<u8> = fields.iter().flat_map(|field| if big_endian { field.to_be_bytes() } else { field.to_le_bytes() }).collect();
+    // Versão em dois campos de 16 bits: 2.4 na ordem do arquivo.
+    header[4..8].copy_from_slice(&if big_endian { [0, 2, 0, 4] } else { [2, 0, 4, 0] });
+    header
+  }
+
+  #[test]
+  fn synthetic_headers_have_valid_checksums() {
+    let packet = ip_packet(address("10.0.0.1:5000"), address("10.0.0.2:8080"), b"dados impares");
+    assert_eq!(internet_checksum(&[&packet[..IPV4_HEADER_LEN]]), 0);
+    assert_eq!(u16::from_be_bytes([packet[2], packet[3]]) as usize, packet.len());
+    let mut pseudo = packet[12..20].to_vec();
+    pseudo.extend_from_slice(&[0, IP_PROTOCOL_UDP]);
+    pseudo.extend_from_slice(&packet[IPV4_HEADER_LEN + 4..IPV4_HEADER_LEN + 6]);
+    assert_eq!(internet_checksum(&[&pseudo, &packet[IPV4_HEADER_LEN..]]), 0);
+
+    let packet = ip_packet(address("[::1]:5000"), address("[::1]:8080"), b"dados");
+    let mut pseudo = packet[8..40].to_vec();
+    pseudo.extend_from_slice(&(packet.len() as u32 - IPV6_HEADER_LEN as u32).to_be_bytes());
+    pseudo.extend_from_slice(&[0, 0, 0, IP_PROTOCOL_UDP]);
+    assert_eq!(internet_checksum(&[&pseudo, &packet[IPV6_HEADER_LEN..]]), 0);
+  }
+
+  #[test]
+  fn synthetic_addresses_resolve_wildcards_and_families() {
+    let wildcard = address("0.0.0.0:8080");
+    let client = address("127.0.0.1:40000");
+    assert_eq!(synthetic_addresses(wildcard, client), (address("127.0.0.1:8080"), client));
+    // Para um cliente remoto não há como saber a interface.
+    let remote = address("203.0.113.9:40000");
+    assert_eq!(synthetic_addresses(wildcard, remote), (wildcard, remote));
+    // Cliente IPv4 de um socket IPv6.
+    let dual = address("[::]:8080");
+    assert_eq!(synthetic_addresses(dual, remote), (dual, address("[::ffff:203.0.113.9]:40000")));
+  }
+
+  #[test]
+  fn parses_udp_over_ipv4_and_ipv6() {
+    for (source, destination) in [("10.0.0.1:5000", "10.0.0.2:8080"), ("[2001:db8::1]:5000", "[2001:db8::2]:8080")] {
+      let (source, destination) = (address(source), address(destination));
+      let packet = ip_packet(source, destination, b"GET /a.bin");
+      assert_eq!(parse_ip_packet(&packet), Some((source, destination, &b"GET /a.bin"[..])));
+      // Preenchimento do quadro depois do pacote IP não faz parte do datagrama.
+      let mut padded = packet.clone();
+      padded.extend_from_slice(&[0; 6]);
+      assert_eq!(parse_ip_packet(&padded).map(|(_, _, payload)| payload), Some(&b"GET /a.bin"[..]));
+    }
+  }
+
+  #[test]
+  fn skips_ipv6_extension_headers() {
+    let packet = ip_packet(address("[::1]:1"), address("[::1]:2"), b"dados");
+    // Hop-by-hop de 8 bytes entre o cabeçalho fixo e o UDP.
+    let mut extended = packet[..IPV6_HEADER_LEN].to_vec();
+    extended[6] = 0;
+    let payload_len = u16::from_be_bytes([packet[4], packet[5]]) + 8;
+    extended[4..6].copy_from_slice(&payload_len.to_be_bytes());
+    extended.extend_from_slice(&[IP_PROTOCOL_UDP, 0, 0, 0, 0, 0, 0, 0]);
+    extended.extend_from_slice(&packet[IPV6_HEADER_LEN..]);
+    assert_eq!(parse_ip_packet(&extended).map(|(_, _, payload)| payload), Some(&b"dados"[..]));
+  }
+
+  #[test]
+  fn ignores_other_packets() {
+    let packet = ip_packet(address("10.0.0.1:5000"), address("10.0.0.2:8080"), b"dados");
+    let mut tcp = packet.clone();
+    tcp[9] = 6;
+    let mut fragment = packet.clone();
+    fragment[6] = 0x20;
+    let mut later_fragment = packet.clone();
+    later_fragment[7] = 1;
+    let mut short_header = packet.clone();
+    short_header[0] = 0x44;
+    for invalid in [&[][..], &[0x45], &packet[..IPV4_HEADER_LEN + 4], &tcp, &fragment, &later_fragment, &short_header, &[0x70; 40]] {
+      assert_eq!(parse_ip_packet(invalid), None, "{:?}", invalid);
+    }
+    let v6 = ip_packet(address("[::1]:1"), address("[::1]:2"), b"dados");
+    assert_eq!(parse_ip_packet(&v6[..IPV6_HEADER_LEN - 1]), None);
+  }
+
+  #[test]
+  fn reads_datagrams_from_a_capture() {
+    let (source, destination) = (address("127.0.0.1:40000"), address("127.0.0.1:8080"));
+    let mut file = pcap_header(false, PCAP_MAGIC, LINKTYPE_RAW);
+    write_record(&mut file, &ip_packet(source, destination, b"GET /a.bin")).unwrap();
+    // Um registro que não é UDP é ignorado.
+    write_record(&mut file, &[0x45; 28]).unwrap();
+    write_record(&mut file, &ip_packet(destination, source, b"resposta")).unwrap();
+    // Registro interrompido no meio da escrita.
+    write_record(&mut file, &ip_packet(source, destination, b"cortado")).unwrap();
+    file.truncate(file.len() - 3);
+
+    let datagrams: Vec<CapturedDatagram> = PcapReader::new(file.as_slice()).unwrap().collect::<io::Result<_>>().unwrap();
+    assert_eq!(datagrams.len(), 2);
+    assert_eq!((datagrams[0].source, datagrams[0].destination), (source, destination));
+    assert_eq!(datagrams[0].payload, b"GET /a.bin");
+    assert_eq!(datagrams[1].payload, b"resposta");
+    assert!(datagrams[0].timestamp > Duration::ZERO);
+  }
+
+  #[test]
+  fn reads_big_endian_ethernet_captures() {
+    let (source, destination) = (address("192.168.0.10:5353"), address("192.168.0.20:8080"));
+    let packet = ip_packet(source, destination, b"STAT /a.bin");
+    // Quadro Ethernet com uma etiqueta de VLAN.
+    let mut frame = vec![0xaa; 12];
+    frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x07, 0x08, 0x00]);
+    frame.extend_from_slice(&packet);
+    let mut file = pcap_header(true, PCAP_MAGIC_NANOS, LINKTYPE_ETHERNET);
+    for field in [1_700_000_000u32, 500, frame.len() as u32, frame.len() as u32] {
+      file.extend_from_slice(&field.to_be_bytes());
+    }
+    file.extend_from_slice(&frame);
+
+    let datagram = PcapReader::new(file.as_slice()).unwrap().next_datagram().unwrap().unwrap();
+    assert_eq!((datagram.source, datagram.destination), (source, destination));
+    assert_eq!(datagram.payload, b"STAT /a.bin");
+    assert_eq!(datagram.timestamp, Duration::new(1_700_000_000, 500));
+  }
+
+  #[test]
+  fn rejects_invalid_captures() {
+    assert!(PcapReader::new(&[][..]).is_err());
+    assert!(PcapReader::new(pcap_header(false, PCAPNG_MAGIC, LINKTYPE_RAW).as_slice()).is_err());
+    assert!(PcapReader::new(pcap_header(false, PCAP_MAGIC, 147).as_slice()).is_err());
+
+    // Tamanho de registro absurdo: erro, sem reservar memória para ele.
+    let mut file = pcap_header(false, PCAP_MAGIC, LINKTYPE_RAW);
+    for field in [0, 0, u32::MAX, u32::MAX] {
+      file.extend_from_slice(&field.to_le_bytes());
+    }
+    let mut reader = PcapReader::new(file.as_slice()).unwrap();
+    assert_eq!(reader.next_datagram().unwrap_err().kind(), io::ErrorKind::InvalidData);
+  }
+}
@@ -1,14 +1,80 @@
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::{self, stdin, Write};
-use std::net::UdpSocket;
-use std::time::Duration;
-use std::{env, fs, str};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, stdin, IsTerminal, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+use std::env;
+
+use serde_json::json;
+use tracing::{info, warn};
+
+use rawsocket_udp::auth::Credentials;
+use rawsocket_udp::batch::BatchOptions;
+use rawsocket_udp::capture;
+use rawsocket_udp::compression::Compression;
+use rawsocket_udp::discovery::{self, DiscoveryOptions};
+use rawsocket_udp::error::ClientError;
+use rawsocket_udp::listing::{DirEntry, EntryKind};
+use rawsocket_udp::logging::{self, LogFormat};
+use rawsocket_udp::multicast::{Receiver, ReceiverConfig};
+use rawsocket_udp::pmtud::{self, MAX_PLPMTU};
+use rawsocket_udp::protocol::{chunk_size_for_mtu, MAX_CHUNK_SIZE, MIN_CHUNK_SIZE};
+use rawsocket_udp::transfer::{
+    Client, ClientConfig, Destination, Download, GetOptions, MirrorOptions, Progress, DEFAULT_MIRROR_PARALLELISM,
+};
+use rawsocket_udp::upload::OverwritePolicy;
+
+// Endereço do servidor usado pelos comandos sem `--server`.
+const SERVER_ADDR: &str = "127.0.0.1:8083";
+// Intervalo mínimo entre redesenhos da linha de progresso.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// Formato da saída dos comandos: `Normal` mostra o progresso no terminal e um resumo, `--quiet`
+// mostra apenas erros e `--json` imprime um único objeto JSON por comando, para scripts. Nos dois
+// últimos modos, uma falha termina o processo com código 1.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Normal,
+    Quiet,
+    Json,
+}
+
+impl Output {
+    fn from_args(args: &[String]) -> Output {
+        if args.iter().any(|arg| arg == "--json") {
+            Output::Json
+        } else if args.iter().any(|arg| arg == "--quiet") {
+            Output::Quiet
+        } else {
+            Output::Normal
+        }
+    }
+}
 
 fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    init_logging(&args)?;
+    // `--pcap arquivo`: grava todos os datagramas enviados e recebidos em um arquivo pcap.
+    if let Some(path) = flag_value(&args, "--pcap") {
+        capture::start(Path::new(path))?;
+    }
+
+    match args.first().map(String::as_str) {
+        Some("get") => return get_command(&args),
+        Some("put") => return put_command(&args),
+        Some("ls") => return ls_command(&args),
+        Some("stat") => return stat_command(&args),
+        Some("mirror") => return mirror_command(&args),
+        Some("delta") => return delta_command(&args),
+        Some("join") => return join_command(&args),
+        Some("discover") => return discover_command(&args),
+        _ => {}
+    }
+
     // Solicitando inputs do usuário
     println!("Enter the server IP address and port (e.g., '127.0.0.1:8083'):");
-    let server_addr = "127.0.0.1:8083";
     println!("Enter the name of the file to retrieve from the server:");
     let filename = read_input()?;
     println!("Você gostaria de simular perda de pacote? (sim/não)");
@@ -25,83 +91,210 @@ fn main() -> io::Result<()> {
             .collect();
     }
 
-    // Ligando o socket UDP a uma porta disponível aleatória.
-    let socket = UdpSocket::bind("0.0.0.0:0")?;
-    // Definindo um tempo limite de leitura de 5 segundos para o socket UDP.
-    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
-
-    // Estrutura de dados para armazenar pacotes recebidos e números de sequência.
-    let mut packets: HashMap<u32, Vec<u8>> = HashMap::new();
-    let mut received_seq_numbers = HashSet::new();
-    let mut last_received_packet = 0;
-    let mut is_retransmitting = false;  // Estado para controlar a retransmissão
-    let mut total_packets = 0;
-    let mut attempts = 0;
-    let max_attempts = 5;
-
-    // Loop principal para receber todos os pacotes.
-    while attempts < max_attempts {
-        if !is_retransmitting {
-            println!("last_received_packet: {}", last_received_packet);
-            match send_request(&socket, &server_addr, &filename, last_received_packet + 1) {
-                Ok(_) => println!("Request sent successfully."),
-                Err(e) => {
-                    println!("Failed to send request: {:?}", e);
-                    attempts += 1;
-                    continue;
-                }
+    let target = client_files_dir()?.join(&filename);
+    download(&args, &filename, target, loss_packets)
+}
+
+// Subcomando `get <caminho remoto> [arquivo local]`: baixa um arquivo sem as perguntas do modo
+// interativo (por padrão, para client_files).
+fn get_command(args: &[String]) -> io::Result<()> {
+    let remote = match args.get(1).filter(|arg| !arg.starts_with("--")) {
+        Some(remote) => remote.trim_start_matches('/'),
+        None => {
+            println!("Uso: client get <caminho remoto> [arquivo local] [--quiet | --json] [--verbose]");
+            return Ok(());
+        }
+    };
+    let local = match args.get(2).filter(|arg| !arg.starts_with("--")) {
+        Some(local) => PathBuf::from(local),
+        None => client_files_dir()?.join(remote),
+    };
+    download(args, remote, local, HashSet::new())
+}
+
+// Função para baixar o arquivo com a linha de progresso e exibir o resumo no formato pedido.
+fn download(args: &[String], remote: &str, local: PathBuf, loss_packets: HashSet<u32>) -> io::Result<()> {
+    let output = Output::from_args(args);
+    let client = client(args)?;
+    let mut progress = ProgressLine::new(output);
+    let options = GetOptions::default()
+        .destination(Destination::Path(local.clone()))
+        .simulate_loss(loss_packets)
+        .on_progress(|current| progress.update(current));
+    let result = client.get_with(remote, options);
+    progress.finish();
+
+    match result {
+        Ok(download) => match output {
+            Output::Normal => {
+                print_summary(&download);
+                println!("File '{}' saved successfully.", remote);
             }
+            Output::Quiet => {}
+            Output::Json => print_json(download_json(&download, &local)),
+        },
+        Err(err) => return report_error(output, "Error fetching file", err),
+    }
+    Ok(())
+}
+
+// Linha de progresso redesenhada na saída de erro, apenas quando ela é um terminal e a saída é a
+// normal: percentual, bytes recebidos, vazão, tempo restante, perda e retransmissões.
+struct ProgressLine {
+    enabled: bool,
+    last_draw: Option<Instant>,
+}
+
+impl ProgressLine {
+    fn new(output: Output) -> ProgressLine {
+        ProgressLine { enabled: output == Output::Normal && io::stderr().is_terminal(), last_draw: None }
+    }
+
+    fn update(&mut self, progress: &Progress) {
+        if !self.enabled || self.last_draw.is_some_and(|last| last.elapsed() < PROGRESS_INTERVAL) {
+            return;
         }
+        self.last_draw = Some(Instant::now());
+
+        let percent = match progress.fraction() {
+            Some(fraction) => format!("{:5.1}%", fraction * 100.0),
+            None => "  ...%".to_string(),
+        };
+        let received = match progress.size {
+            Some(size) => format!("{} / {}", format_bytes(progress.bytes), format_bytes(size)),
+            None => format_bytes(progress.bytes),
+        };
+        let eta = progress.eta().map(format_duration).unwrap_or_else(|| "--:--".to_string());
+        let loss = progress.loss_rate().unwrap_or(0.0) * 100.0;
+        let mut stderr = io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r{}  {}  {}/s  ETA {}  perda {:.1}%  retx {}\x1b[K",
+            percent,
+            received,
+            format_bytes(progress.throughput() as u64),
+            eta,
+            loss,
+            progress.retransmitted
+        );
+        let _ = stderr.flush();
+    }
 
-        match receive_response(&socket, simulate_loss, &mut loss_packets) {
-            Ok((new_data, new_seqs, received_total_packets, last_received_packet, maybe_error)) => {
-                if let Some(err) = maybe_error {
-                    println!("Error from server: {}", err);
-                    return Ok(()); // Stop processing if an error is received
-                }
-
-                for (data, &seq_number) in new_data.iter().zip(new_seqs.iter()) {
-                    packets.insert(seq_number, data.clone());
-                    received_seq_numbers.insert(seq_number);
-                }
-
-                total_packets = calculate_expected_number_of_packets(&received_seq_numbers) - 1;        
-                let mut missing_packets = identify_missing_packets(&received_seq_numbers, received_total_packets); // 6888 para teste
-                missing_packets.retain(|&x| x != 0);
-                println!("Missing packets: {:?}", missing_packets);
-                println!("Total packets expected: {}", total_packets);
-                println!("received_seq_numbers: {:?}", received_seq_numbers.len());
-                if missing_packets.is_empty() {
-                    println!("All packets received. Proceeding to file writing.");
-                    break; // The loop will only end when all packets have been received.
-                } else {
-                    println!("Missing packets detected: {:?}", missing_packets);
-                    request_retransmission(&socket, &server_addr, &missing_packets)?;
-                    is_retransmitting = true;  // Activate retransmission mode
-                }
-            },
-            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
-                println!("Timeout detected. Retrying...");
-                attempts += 1;
-                continue;
-            },
-            Err(e) => println!("Error: {:?}", e)
+    // Apaga a linha para que o resumo comece em uma linha limpa.
+    fn finish(&mut self) {
+        if self.enabled && self.last_draw.is_some() {
+            let _ = write!(io::stderr(), "\r\x1b[K");
         }
     }
+}
 
-    if attempts >= max_attempts {
-        println!("Failed to complete file transfer after {} attempts.", max_attempts);
+// Função para exibir o resumo do download: metadados, tempo, vazão, perda e, com compressão,
+// quantos bytes atravessaram a rede.
+fn print_summary(download: &Download) {
+    let meta = &download.meta;
+    println!("Tamanho de bloco usado pelo servidor: {}", meta.chunk_size);
+    println!("Tamanho: {} bytes, SHA-256: {}", meta.size, meta.sha256_hex());
+    if meta.compression == Compression::None {
+        println!("Transfer: {} bytes in {} packets.", meta.size, download.packets);
     } else {
-        // Writing all received packets to file in order.
-        match write_to_file(&filename, &packets, total_packets) {
-            Ok(_) => println!("File '{}' saved successfully.", filename),
-            Err(err) => println!("Error saving file: {}", err),
+        let ratio = if meta.size > 0 { download.wire_bytes as f64 * 100.0 / meta.size as f64 } else { 100.0 };
+        println!(
+            "Transfer: {} bytes in {} packets, {} bytes compressed with {} ({:.1}%).",
+            meta.size,
+            download.packets,
+            download.wire_bytes,
+            meta.compression.as_str(),
+            ratio
+        );
+    }
+    println!(
+        "Tempo: {:.2} s, {}/s, {} retransmitted packets ({:.1}% loss).",
+        download.elapsed.as_secs_f64(),
+        format_bytes(download.throughput() as u64),
+        download.retransmitted,
+        download.loss_rate() * 100.0
+    );
+}
+
+fn download_json(download: &Download, local: &Path) -> serde_json::Value {
+    let meta = &download.meta;
+    json!({
+        "path": download.path,
+        "local": local.display().to_string(),
+        "size": meta.size,
+        "sha256": meta.sha256_hex(),
+        "chunk_size": meta.chunk_size,
+        "packets": download.packets,
+        "wire_bytes": download.wire_bytes,
+        "compression": meta.compression.as_str(),
+        "retransmitted": download.retransmitted,
+        "loss_rate": download.loss_rate(),
+        "elapsed_ms": download.elapsed.as_millis() as u64,
+        "throughput": download.throughput(),
+    })
+}
+
+fn print_json(value: serde_json::Value) {
+    println!("{}", value);
+}
+
+// Função para reportar a falha de um comando. Na saída normal a mensagem vai para a saída padrão,
+// como antes (erros de E/S locais continuam encerrando com erro). Com `--quiet` ela vai para a
+// saída de erro e, com `--json`, vira `{"error": ...}`; nos dois casos o processo termina com 1.
+fn report_error(output: Output, context: &str, err: ClientError) -> io::Result<()> {
+    match output {
+        Output::Normal => match err {
+            ClientError::Io(e) => Err(e),
+            err => {
+                println!("{}: {}", context, err);
+                Ok(())
+            }
+        },
+        Output::Quiet => {
+            eprintln!("{}: {}", context, err);
+            process::exit(1);
+        }
+        Output::Json => {
+            print_json(json!({ "error": err.to_string(), "code": err.code().map(|code| code.as_u16()) }));
+            process::exit(1);
         }
     }
+}
 
-    Ok(())
+// Função para formatar bytes com unidades binárias (B, KiB, MiB, GiB).
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
 }
 
+// Função para formatar uma duração como `M:SS`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+// Função para configurar os logs na saída de erro: `--log-format human|json` e `--log-level` com
+// um filtro no formato de RUST_LOG. Por padrão só aparecem avisos; `--verbose` mostra as
+// tentativas, retransmissões e demais mensagens de diagnóstico.
+fn init_logging(args: &[String]) -> io::Result<()> {
+    let format = match flag_value(args, "--log-format") {
+        Some(value) => LogFormat::parse(value)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--log-format inválido"))?,
+        None => LogFormat::Human,
+    };
+    let default_level = if args.iter().any(|arg| arg == "--verbose") { "debug" } else { "warn" };
+    logging::init(format, flag_value(args, "--log-level"), default_level)
+}
 
 // Função para ler a entrada do usuário e tratar erros.
 fn read_input() -> io::Result<String> {
@@ -110,180 +303,466 @@ fn read_input() -> io::Result<String> {
     Ok(input.trim().to_string())
 }
 
-// Função para enviar requisição para o servidor UDP.
-fn send_request(
-    socket: &UdpSocket,
-    server_addr: &str,
-    filename: &str,
-    start_packet: u32,
-) -> io::Result<()> {
-    let message = if start_packet == 0 {
-        format!("GET /{}", filename)
-    } else {
-        format!("GET /{}?start={}", filename, start_packet)
-    };
-    socket.send_to(message.as_bytes(), server_addr)?;
+// Subcomando `ls [diretório remoto] [--hash]`: lista um diretório do servidor.
+fn ls_command(args: &[String]) -> io::Result<()> {
+    let dir = args.get(1).filter(|arg| !arg.starts_with("--")).map(String::as_str).unwrap_or("/");
+    let with_hash = args.iter().any(|arg| arg == "--hash");
+
+    let output = Output::from_args(args);
+    match client(args)?.list(dir, with_hash) {
+        Ok(entries) if output == Output::Json => print_json(json!({ "entries": entries })),
+        Ok(entries) => print_listing(&entries),
+        Err(err) => return report_error(output, "Error listing directory", err),
+    }
     Ok(())
 }
 
-// Função para receber respostas de um servidor, que tambem pode simular perda de pacotes.
-fn receive_response(
-    socket: &UdpSocket,
-    simulate_loss: bool,
-    loss_packets: &mut HashSet<u32>,
-) -> io::Result<(Vec<Vec<u8>>, HashSet<u32>, u32, u32, Option<String>)> {
-    let mut packets = Vec::new();
-    let mut seq_numbers = HashSet::new(); // Utiliza um HashSet para garantir entradas únicas.
-    let mut buf = [0; 1500]; // Buffer para os dados recebidos.
-    let mut error_message: Option<String> = None;
-    let mut total_packets = 0; // Para armazenar o número total de pacotes após receber o primeiro pacote.
-    let mut last_received_packet: u32 = 0;
-
-    loop {
-        match socket.recv_from(&mut buf) {
-            Ok((size, _)) => {
-                if size < 12 {
-                    // Tamanho mínimo para conter seq_number.
-                    continue;
-                }
-                
-                let seq_number = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
-                if seq_number == u32::MAX {
-                    // Sinal de fim de transmissão.
-                    break;
-                }
-
-                if seq_number == 0 {
-                    // Checa se é o primeiro pacote contendo o total de pacotes.
-                    total_packets = u32::from_be_bytes([buf[12], buf[13], buf[14], buf[15]]);
-                    println!("Total de pacotes esperados: {}", total_packets);
-                    continue; // Não processar como um pacote de dados.
-                }
-
-                let message = std::str::from_utf8(&buf[..size]).unwrap_or("");
-                if message.starts_with("ERROR") {
-                    error_message = Some(message.to_owned());
-                    break;
-                }
-
-                if simulate_loss && loss_packets.contains(&seq_number) {
-                    println!("Pacote com número de sequência {} foi artificialmente descartado para simular perda.", seq_number);
-                    loss_packets.remove(&seq_number); // Remover do conjunto para permitir a retransmissão
-                    continue; // Não adicionar aos seq_numbers ou pacotes
-                }
-
-                let received_checksum = u16::from_be_bytes([buf[10], buf[11]]);
-                let packet_data = &buf[12..size];
-                let calculated_checksum = calculate_checksum(packet_data);
-                if calculated_checksum == received_checksum {
-                    packets.push(packet_data.to_vec());
-                    seq_numbers.insert(seq_number); // Armazenar número de sequência válido e não descartado
-                    last_received_packet = seq_number; // Atualizar o último pacote recebido corretamente
-                    println!(
-                        "Pacote {} recebido com checksum correto: esperado {}, obtido: {}",
-                        seq_number, calculated_checksum, received_checksum
-                    );
-                } else {
-                    println!(
-                        "Incompatibilidade de checksum para o pacote {}: esperado {}, obtido {}",
-                        seq_number, calculated_checksum, received_checksum
-                    );
-                }
+// Subcomando `mirror <diretório remoto> [diretório local] [--parallel N] [--delta]`: espelha o
+// diretório remoto no diretório local (por padrão, em client_files).
+fn mirror_command(args: &[String]) -> io::Result<()> {
+    let remote_root = match args.get(1).filter(|arg| !arg.starts_with("--")) {
+        Some(dir) => dir.trim_matches('/').to_string(),
+        None => {
+            println!("Uso: client mirror <diretório remoto> [diretório local] [--parallel N] [--delta]");
+            return Ok(());
+        }
+    };
+    let local_root = match args.get(2).filter(|arg| !arg.starts_with("--")) {
+        Some(dir) => PathBuf::from(dir),
+        None => client_files_dir()?.join(&remote_root),
+    };
+    let parallel = match flag_value(args, "--parallel") {
+        Some(value) => value
+            .parse::<usize>()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--parallel inválido"))?,
+        None => DEFAULT_MIRROR_PARALLELISM,
+    };
+    let options = MirrorOptions { parallel, delta: args.iter().any(|arg| arg == "--delta") };
+    let output = Output::from_args(args);
+
+    let report = match client(args)?.mirror(&remote_root, &local_root, options) {
+        Ok(report) => report,
+        Err(ClientError::Io(e)) if output == Output::Normal => return Err(e),
+        Err(err) if output == Output::Normal => return Err(io::Error::other(err.to_string())),
+        Err(err) => return report_error(output, "Error mirroring directory", err),
+    };
+    match output {
+        Output::Normal => {
+            for (remote, err) in &report.failed {
+                println!("Error mirroring '{}': {}", remote, err);
             }
-            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
-                return Ok((packets, seq_numbers, total_packets, last_received_packet, error_message)); // Retornar os dados, números de sequência, o total de pacotes e uma possível mensagem de erro.
+            println!(
+                "Mirror finished: {} downloaded, {} skipped, {} failed.",
+                report.downloaded,
+                report.skipped,
+                report.failed.len()
+            );
+        }
+        Output::Quiet => {
+            for (remote, err) in &report.failed {
+                eprintln!("Error mirroring '{}': {}", remote, err);
             }
-            Err(e) => return Err(e), // Tratar outros erros
+        }
+        Output::Json => {
+            let failed: Vec<serde_json::Value> = report
+                .failed
+                .iter()
+                .map(|(remote, err)| json!({ "path": remote, "error": err.to_string() }))
+                .collect();
+            print_json(json!({
+                "downloaded": report.downloaded,
+                "skipped": report.skipped,
+                "failed": failed,
+            }));
         }
     }
-    Ok((packets, seq_numbers, total_packets, last_received_packet, error_message)) // Retornar os dados, números de sequência, o total de pacotes e uma possível mensagem de erro.
+    if !report.failed.is_empty() {
+        let failed: Vec<&str> = report.failed.iter().map(|(remote, _)| remote.as_str()).collect();
+        return Err(io::Error::other(format!("{} failed: {}", failed.len(), failed.join(", "))));
+    }
+    Ok(())
 }
 
-fn calculate_checksum(data: &[u8]) -> u16 {
-    let sum: u32 = data.chunks(2).fold(0, |acc, chunk| {
-        let word = chunk.iter().enumerate().fold(0u16, |word_acc, (i, &byte)| {
-            word_acc | ((byte as u16) << ((1 - i) * 8))
-        });
-        acc + word as u32
-    });
+// Subcomando `delta <caminho remoto> [arquivo local]`: atualiza a cópia local (por padrão, em
+// client_files) baixando apenas o que mudou. Sem cópia local, o delta traz o arquivo inteiro.
+fn delta_command(args: &[String]) -> io::Result<()> {
+    let remote = match args.get(1).filter(|arg| !arg.starts_with("--")) {
+        Some(remote) => remote.trim_start_matches('/'),
+        None => {
+            println!("Uso: client delta <caminho remoto> [arquivo local]");
+            return Ok(());
+        }
+    };
+    let local = match args.get(2).filter(|arg| !arg.starts_with("--")) {
+        Some(local) => PathBuf::from(local),
+        None => client_files_dir()?.join(remote),
+    };
 
-    let wrapped_sum = (sum & 0xFFFF) + (sum >> 16);
-    let wrapped_sum = (wrapped_sum & 0xFFFF) + (wrapped_sum >> 16);
-    !wrapped_sum as u16
+    let output = Output::from_args(args);
+    match client(args)?.delta(remote, &local) {
+        Ok(stats) => match output {
+            Output::Normal => println!(
+                "File '{}' updated: {} bytes, {} sent as literals, {} bytes of delta transferred.",
+                local.display(),
+                stats.size,
+                stats.literal,
+                stats.transferred
+            ),
+            Output::Quiet => {}
+            Output::Json => print_json(json!({
+                "path": remote,
+                "local": local.display().to_string(),
+                "size": stats.size,
+                "literal": stats.literal,
+                "transferred": stats.transferred,
+            })),
+        },
+        Err(err) => return report_error(output, "Error updating file", err),
+    }
+    Ok(())
 }
 
-// Função para calcular o número esperado de pacotes baseado no tamanho dos dados ou no último número de sequência.
-fn calculate_expected_number_of_packets(received_seq_numbers: &HashSet<u32>) -> u32 {
-    if received_seq_numbers.is_empty() {
-        0
-    } else {
-        let max_seq_num = *received_seq_numbers.iter().max().unwrap();
-        max_seq_num + 1
+// Subcomando `stat <caminho remoto>`: mostra tamanho, data de modificação, SHA-256 e o número de
+// pacotes de um arquivo do servidor, sem transferi-lo.
+fn stat_command(args: &[String]) -> io::Result<()> {
+    let path = match args.get(1).filter(|arg| !arg.starts_with("--")) {
+        Some(path) => path,
+        None => {
+            println!("Uso: client stat <caminho remoto> [--chunk N | --mtu N | --pmtud [MAX]]");
+            return Ok(());
+        }
+    };
+
+    let output = Output::from_args(args);
+    match client(args)?.stat(path) {
+        Ok(meta) if output == Output::Json => print_json(json!({
+            "path": path,
+            "size": meta.size,
+            "mtime": meta.mtime,
+            "sha256": meta.sha256_hex(),
+            "packets": meta.total_packets - 1,
+            "chunk_size": meta.chunk_size,
+        })),
+        Ok(_) if output == Output::Quiet => {}
+        Ok(meta) => {
+            println!("Path:    {}", path);
+            println!("Size:    {} bytes", meta.size);
+            println!("Mtime:   {} UTC", format_mtime(meta.mtime));
+            println!("SHA-256: {}", meta.sha256_hex());
+            println!("Packets: {} data packets of up to {} bytes", meta.total_packets - 1, meta.chunk_size);
+        }
+        Err(err) => return report_error(output, "Error", err),
     }
+    Ok(())
 }
 
-// Função para identificar pacotes que estão faltando com base nos números de sequência recebidos e no número esperado de pacotes.
-fn identify_missing_packets(
-    received_seq_numbers: &HashSet<u32>,
-    expected_num_packets: u32,
-) -> Vec<u32> {
-    let missing_packets = (0..expected_num_packets)
-        .filter(|n| !received_seq_numbers.contains(n))
-        .collect::<Vec<u32>>();
+// Subcomando `join <arquivo local> [--group 239.255.0.83:9083] [--interface IP] [--timeout SECS]
+// [--max-size MiB] [--no-nack]`: entra no grupo multicast e grava o próximo arquivo transmitido pelo
+// servidor (`server --multicast`). Sem `--no-nack`, os pacotes que a FEC não recupera são pedidos ao
+// servidor.
+fn join_command(args: &[String]) -> io::Result<()> {
+    let local = match args.get(1).filter(|arg| !arg.starts_with("--")) {
+        Some(local) => PathBuf::from(local),
+        None => {
+            println!("Uso: client join <arquivo local> [--group ENDEREÇO:PORTA] [--interface IP] [--timeout SECS] [--max-size MiB] [--no-nack] [--quiet | --json]");
+            return Ok(());
+        }
+    };
+    let invalid = |flag: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{} inválido", flag));
+    let mut config = ReceiverConfig { nack: !args.iter().any(|arg| arg == "--no-nack"), ..ReceiverConfig::default() };
+    if let Some(group) = flag_value(args, "--group") {
+        config.group = group.parse().map_err(|_| invalid("--group"))?;
+    }
+    if let Some(interface) = flag_value(args, "--interface") {
+        config.interface = interface.parse().map_err(|_| invalid("--interface"))?;
+    }
+    if let Some(secs) = flag_value(args, "--timeout") {
+        config.timeout = Duration::from_secs(secs.parse().map_err(|_| invalid("--timeout"))?);
+    }
+    if let Some(mib) = flag_value(args, "--max-size") {
+        config.max_size = mib.parse::<u64>().map_err(|_| invalid("--max-size"))?.saturating_mul(1024 * 1024);
+    }
 
-    missing_packets
+    let output = Output::from_args(args);
+    let group = config.group;
+    let receiver = Receiver::join(config)?;
+    if output == Output::Normal {
+        println!("Aguardando transmissão no grupo {}...", group);
+    }
+    let received = match receiver.receive() {
+        Ok(received) => received,
+        Err(err) => return report_error(output, "Error receiving file", err),
+    };
+    if let Some(parent) = local.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&local, &received.data)?;
+
+    let meta = &received.meta;
+    match output {
+        Output::Normal => {
+            println!("Tamanho: {} bytes, SHA-256: {}", meta.size, meta.sha256_hex());
+            println!(
+                "Transfer: {} packets from {}, {} recovered by FEC, {} NACKs sent ({} packets suppressed).",
+                received.packets,
+                received.sender,
+                received.fec_recovered,
+                received.nacks_sent,
+                received.nacks_suppressed
+            );
+            println!("Tempo: {:.2} s.", received.elapsed.as_secs_f64());
+            println!("File saved to '{}'.", local.display());
+        }
+        Output::Quiet => {}
+        Output::Json => print_json(json!({
+            "group": group.to_string(),
+            "sender": received.sender.to_string(),
+            "local": local.display().to_string(),
+            "size": meta.size,
+            "sha256": meta.sha256_hex(),
+            "packets": received.packets,
+            "fec_recovered": received.fec_recovered,
+            "nacks_sent": received.nacks_sent,
+            "nacks_suppressed": received.nacks_suppressed,
+            "elapsed_ms": received.elapsed.as_millis() as u64,
+        })),
+    }
+    Ok(())
 }
 
-// Função para solicitar retransmissão para pacotes faltantes
-fn request_retransmission(
-    socket: &UdpSocket,
-    server_addr: &str,
-    missing_packets: &[u32],
-) -> io::Result<()> {
-    if missing_packets.is_empty() {
-        return Ok(());
+// Subcomando `discover [--timeout SECS] [--target ENDEREÇO:PORTA]... [--interface IP]`: procura
+// servidores na rede local e lista nome, endereço, versão do protocolo, RTT e capacidades.
+fn discover_command(args: &[String]) -> io::Result<()> {
+    let output = Output::from_args(args);
+    let servers = discovery::discover(&discovery_options(args)?)?;
+    match output {
+        Output::Normal if servers.is_empty() => println!("Nenhum servidor encontrado."),
+        Output::Normal => {
+            for server in &servers {
+                let version = if server.compatible() { server.version.to_string() } else { format!("{} (incompatível)", server.version) };
+                println!(
+                    "{:<20} {:<21} v{:<4} {:>8.2} ms  {}",
+                    server.name,
+                    server.address,
+                    version,
+                    server.rtt.as_secs_f64() * 1000.0,
+                    server.capabilities.join(",")
+                );
+            }
+        }
+        Output::Quiet => {}
+        Output::Json => print_json(json!({
+            "servers": servers.iter().map(|server| json!({
+                "name": server.name,
+                "address": server.address.to_string(),
+                "version": server.version,
+                "compatible": server.compatible(),
+                "rtt_ms": server.rtt.as_secs_f64() * 1000.0,
+                "capabilities": server.capabilities,
+            })).collect::<Vec<_>>(),
+        })),
     }
-    // Define um limite máximo para o número de pacotes por solicitação de retransmissão.
-    const MAX_PACKETS_PER_REQUEST: usize = 10;
+    Ok(())
+}
 
-    // Cria várias mensagens se a lista de pacotes perdidos for muito grande.
-    for chunk in missing_packets.chunks(MAX_PACKETS_PER_REQUEST) {
-        let request_string = format!(
-            "RETRANSMIT {}",
-            chunk
-                .iter()
-                .map(|num| num.to_string())
-                .collect::<Vec<_>>()
-                .join(",")
-        );
-        println!("Solicitando retransmissão para pacotes: {}", request_string);
-        socket.send_to(request_string.as_bytes(), server_addr)?;
+// Função para exibir a listagem no formato de `ls -l`.
+fn print_listing(entries: &[DirEntry]) {
+    for entry in entries {
+        let kind = match entry.kind {
+            EntryKind::Dir => 'd',
+            EntryKind::Symlink => 'l',
+            EntryKind::File => '-',
+            EntryKind::Other => '?',
+        };
+        let suffix = if entry.kind == EntryKind::Dir { "/" } else { "" };
+        match &entry.sha256 {
+            Some(hash) => println!("{} {:>12} {} {} {}{}", kind, entry.size, format_mtime(entry.mtime), hash, entry.name, suffix),
+            None => println!("{} {:>12} {} {}{}", kind, entry.size, format_mtime(entry.mtime), entry.name, suffix),
+        }
+    }
+    println!("{} entries", entries.len());
+}
+
+// Função para formatar segundos desde a época Unix como data e hora UTC (`AAAA-MM-DD HH:MM`).
+fn format_mtime(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let minutes = (secs % 86_400) / 60;
+    // Conversão de dias para data civil (algoritmo de Howard Hinnant).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, minutes / 60, minutes % 60)
+}
+
+// Subcomando `put <arquivo local> <caminho remoto> [--overwrite never|always|if-different]`.
+fn put_command(args: &[String]) -> io::Result<()> {
+    let (local, remote) = match (args.get(1), args.get(2)) {
+        (Some(local), Some(remote)) if !local.starts_with("--") && !remote.starts_with("--") => (local, remote),
+        _ => {
+            println!("Uso: client put <arquivo local> <caminho remoto> [--overwrite never|always|if-different]");
+            return Ok(());
+        }
+    };
+    let overwrite = flag_value(args, "--overwrite").unwrap_or("never");
+    let overwrite = OverwritePolicy::parse(overwrite)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--overwrite inválido"))?;
+
+    let output = Output::from_args(args);
+    match client(args)?.put(Path::new(local), remote, overwrite) {
+        Ok(()) => match output {
+            Output::Normal => println!("File '{}' uploaded successfully as '{}'.", local, remote),
+            Output::Quiet => {}
+            Output::Json => print_json(json!({ "uploaded": local, "path": remote })),
+        },
+        Err(ClientError::Io(e)) if output == Output::Normal => println!("Error uploading file: {}", e),
+        Err(err) => return report_error(output, "Error uploading file", err),
     }
-    
     Ok(())
 }
 
+// Função para criar o cliente com as opções da linha de comando: E/S em lote (`--gso`, `--gro`),
+// tamanho de bloco (ver `negotiated_chunk_size`) e compressão (`--compress zstd|deflate`).
+fn client(args: &[String]) -> io::Result<Client> {
+    let server = server_address(args)?;
+    let compression = match flag_value(args, "--compress") {
+        Some(value) => Compression::parse(value)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--compress inválido"))?,
+        None => Compression::None,
+    };
+    let config = ClientConfig {
+        chunk_size: negotiated_chunk_size(args, server)?,
+        compression,
+        batch: BatchOptions {
+            gso: args.iter().any(|arg| arg == "--gso"),
+            gro: args.iter().any(|arg| arg == "--gro"),
+        },
+        ..ClientConfig::default()
+    };
+    let client = Client::new(server, config);
+    Ok(match credentials(args)? {
+        Some(credentials) => client.with_credentials(credentials),
+        None => client,
+    })
+}
 
-// Função para escrever dados recebidos em um arquivo em ordem, com base nos números de sequência.
-fn write_to_file(path: &str, packets: &HashMap<u32, Vec<u8>>, count: u32) -> io::Result<()> {
-    let exe_path = env::current_exe()?;
-    let exe_dir = exe_path.parent().ok_or(io::Error::new(
-        io::ErrorKind::Other,
-        "Falha ao obter diretório executável",
-    ))?;
-    let files_dir = exe_dir.join("../../src/client_files");
+// Endereço do servidor: `--server ENDEREÇO:PORTA`, ou `--server auto` para usar o servidor
+// compatível de menor RTT encontrado pela descoberta (com as opções de `discover`). Sem a flag, vale
+// SERVER_ADDR.
+fn server_address(args: &[String]) -> io::Result<SocketAddr> {
+    match flag_value(args, "--server") {
+        Some("auto") => {
+            let servers = discovery::discover(&discovery_options(args)?)?;
+            let server = discovery::select(&servers)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "nenhum servidor compatível encontrado"))?;
+            info!(name = %server.name, address = %server.address, "server selected by discovery");
+            Ok(server.address)
+        }
+        Some(address) => address
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "--server inválido")),
+        None => SERVER_ADDR
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "endereço do servidor inválido")),
+    }
+}
+
+// Opções da descoberta: `--timeout SECS` de espera pelas respostas, `--target ENDEREÇO:PORTA`,
+// repetível, no lugar do broadcast e do grupo de descoberta, e `--interface IP` para os pedidos ao
+// grupo.
+fn discovery_options(args: &[String]) -> io::Result<DiscoveryOptions> {
+    let invalid = |flag: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{} inválido", flag));
+    let mut options = DiscoveryOptions::default();
+    if let Some(secs) = flag_value(args, "--timeout") {
+        let secs = secs.parse().map_err(|_| invalid("--timeout"))?;
+        options.timeout = Duration::try_from_secs_f64(secs).map_err(|_| invalid("--timeout"))?;
+    }
+    let targets = flag_values(args, "--target")
+        .map(|target| target.parse().map_err(|_| invalid("--target")))
+        .collect::<io::Result<Vec<SocketAddr>>>()?;
+    if !targets.is_empty() {
+        options.targets = targets;
+    }
+    if let Some(interface) = flag_value(args, "--interface") {
+        options.interface = interface.parse().map_err(|_| invalid("--interface"))?;
+    }
+    Ok(options)
+}
 
-    fs::create_dir_all(&files_dir)?;
-    let file_path = files_dir.join(path);
-    let mut file = File::create(&file_path)?;
+// Credenciais para servidores que exigem autenticação: `--token SEGREDO` ou `--key ID:SEGREDO`
+// (requisições assinadas com HMAC), ou as variáveis de ambiente RSUDP_TOKEN e RSUDP_KEY, que
+// evitam expor o segredo na linha de comando.
+fn credentials(args: &[String]) -> io::Result<Option<Credentials>> {
+    let token = flag_value(args, "--token").map(str::to_string).or_else(|| env::var("RSUDP_TOKEN").ok());
+    let key = flag_value(args, "--key").map(str::to_string).or_else(|| env::var("RSUDP_KEY").ok());
+    match (token, key) {
+        (Some(_), Some(_)) => Err(io::Error::new(io::ErrorKind::InvalidInput, "use --token ou --key, não ambos")),
+        (Some(token), None) => Ok(Some(Credentials::Token(token))),
+        (None, Some(key)) => Credentials::parse_key(&key)
+            .map(Some)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--key inválido, use ID:SEGREDO")),
+        (None, None) => Ok(None),
+    }
+}
 
-    println!("count: {:?}", count + 1);
+// Valor do argumento que segue a flag informada, por exemplo `--mtu 9000`.
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == flag).and_then(|idx| args.get(idx + 1)).map(String::as_str)
+}
 
-    for i in 0..count + 1 {
-        if let Some(data) = packets.get(&i) {
-            file.write_all(data)?;
-        }
+// Valores de todas as ocorrências de uma flag repetível.
+fn flag_values<'a>(args: &'a [String], flag: &'a str) -> impl Iterator<Item = &'a str> {
+    args.windows(2).filter(move |pair| pair[0] == flag).map(|pair| pair[1].as_str())
+}
+
+// Função para escolher o tamanho de bloco pedido no GET. `--chunk N` fixa o tamanho, `--mtu N`
+// o deriva do MTU informado e `--pmtud [MAX]` sonda o caminho até o servidor. Sem nenhuma delas o
+// cliente não negocia e o servidor usa o tamanho padrão.
+fn negotiated_chunk_size(args: &[String], server: SocketAddr) -> io::Result<Option<usize>> {
+    let invalid = |flag: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{} inválido", flag));
+
+    if let Some(chunk) = flag_value(args, "--chunk") {
+        return match chunk.parse() {
+            Ok(chunk) if (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk) => Ok(Some(chunk)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("--chunk inválido, use de {} a {} bytes", MIN_CHUNK_SIZE, MAX_CHUNK_SIZE),
+            )),
+        };
+    }
+    if let Some(mtu) = flag_value(args, "--mtu") {
+        let mtu = mtu.parse().map_err(|_| invalid("--mtu"))?;
+        return Ok(Some(chunk_size_for_mtu(mtu, server.is_ipv6())));
+    }
+    if args.iter().any(|arg| arg == "--pmtud") {
+        let max_mtu = match flag_value(args, "--pmtud").filter(|value| !value.starts_with("--")) {
+            Some(max) => max.parse().map_err(|_| invalid("--pmtud"))?,
+            None => MAX_PLPMTU,
+        };
+        let socket = UdpSocket::bind(if server.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" })?;
+        return match pmtud::discover_path_mtu(&socket, server, max_mtu) {
+            Ok(mtu) => Ok(Some(chunk_size_for_mtu(mtu, server.is_ipv6()))),
+            Err(e) => {
+                warn!(error = %e, "path MTU discovery failed, using the default chunk size");
+                Ok(None)
+            }
+        };
     }
+    Ok(None)
+}
 
-    Ok(())
+// Diretório onde o cliente grava os arquivos baixados, relativo ao executável.
+fn client_files_dir() -> io::Result<PathBuf> {
+    let exe_path = env::current_exe()?;
+    let exe_dir = exe_path
+        .parent()
+        .ok_or(io::Error::other("Falha ao obter diretório executável"))?;
+    Ok(exe_dir.join("../../src/client_files"))
 }
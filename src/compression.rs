@@ -0,0 +1,219 @@
+// Compressão por pacote, negociada no GET e no LIST com `?compress=zstd|deflate`. Cada pacote de
+// dados é comprimido isoladamente e começa com um byte que indica o formato, para que a perda de
+// um pacote não impeça a descompressão dos demais. O servidor enche cada pacote com tantos bytes
+// originais quantos couberem, depois de comprimidos, no tamanho de bloco negociado.
+use std::io::{self, Read, Write};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use crate::protocol::{FileMeta, UdpPacket};
+
+// Nível do zstd: o padrão da biblioteca, rápido o bastante para comprimir a cada requisição.
+const ZSTD_LEVEL: i32 = 3;
+// Limite de bytes originais por pacote, em múltiplos do tamanho de bloco. Também limita a saída da
+// descompressão de um pacote.
+const MAX_WINDOW_FACTOR: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+  None,
+  Zstd,
+  Deflate,
+}
+
+impl Compression {
+  pub fn parse(value: &str) -> Option<Compression> {
+    match value {
+      "none" => Some(Compression::None),
+      "zstd" => Some(Compression::Zstd),
+      "deflate" => Some(Compression::Deflate),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Compression::None => "none",
+      Compression::Zstd => "zstd",
+      Compression::Deflate => "deflate",
+    }
+  }
+
+  // Identificador usado no byte de formato de cada pacote e nos metadados do cabeçalho.
+  pub fn id(&self) -> u8 {
+    match self {
+      Compression::None => 0,
+      Compression::Zstd => 1,
+      Compression::Deflate => 2,
+    }
+  }
+
+  pub fn from_id(id: u8) -> Option<Compression> {
+    match id {
+      0 => Some(Compression::None),
+      1 => Some(Compression::Zstd),
+      2 => Some(Compression::Deflate),
+      _ => None,
+    }
+  }
+}
+
+// Prepara os pacotes de uma transferência comprimida: cabeçalho com os metadados do conteúdo
+// original e pacotes de dados com até `chunk_size` bytes comprimidos cada.
+pub fn prepare_packets(
+  src_port: u16,
+  dst_port: u16,
+  data: &[u8],
+  chunk_size: usize,
+  mtime: u64,
+  codec: Compression,
+) -> io::Result<Vec<UdpPacket>> {
+  let payloads = compress_chunks(data, chunk_size, codec)?;
+  let mut meta = FileMeta::new(data, chunk_size, mtime);
+  meta.total_packets = payloads.len() as u32 + 1;
+  meta.compression = codec;
+
+  let mut packets = vec![meta.header_packet(src_port, dst_port)];
+  for (index, payload) in payloads.into_iter().enumerate() {
+    packets.push(UdpPacket::data_packet(index as u32 + 1, src_port, dst_port, payload));
+  }
+  Ok(packets)
+}
+
+// Divide `data` em payloads de no máximo `capacity` bytes, incluindo o byte de formato. Pacotes que
+// não diminuem com a compressão vão sem ela (formato `None`).
+pub fn compress_chunks(data: &[u8], capacity: usize, codec: Compression) -> io::Result<Vec<Vec<u8>>> {
+  let room = capacity - 1;
+  let mut payloads = Vec::new();
+  let mut pos = 0;
+  // Bytes originais a tentar no próximo pacote, estimados pela razão de compressão do anterior.
+  let mut window = room;
+
+  while pos < data.len() {
+    let mut take = window.clamp(room, room * MAX_WINDOW_FACTOR).min(data.len() - pos);
+    let payload = loop {
+      let raw = &data[pos..pos + take];
+      let compressed = compress(raw, codec)?;
+      if compressed.len() <= room && (compressed.len() < take || take > room) {
+        window = take * room / compressed.len().max(1) * 9 / 10;
+        break with_format(codec, &compressed);
+      }
+      if take <= room {
+        // Nem a compressão ajudou: o trecho vai como está.
+        window = room;
+        break with_format(Compression::None, raw);
+      }
+      // Não coube: diminui a janela na proporção do excesso, com 10% de folga.
+      take = (take * room / compressed.len() * 9 / 10).max(room);
+    };
+    payloads.push(payload);
+    pos += take;
+  }
+  Ok(payloads)
+}
+
+fn with_format(codec: Compression, payload: &[u8]) -> Vec<u8> {
+  let mut bytes = Vec::with_capacity(payload.len() + 1);
+  bytes.push(codec.id());
+  bytes.extend_from_slice(payload);
+  bytes
+}
+
+fn compress(data: &[u8], codec: Compression) -> io::Result<Vec<u8>> {
+  match codec {
+    Compression::None => Ok(data.to_vec()),
+    Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL),
+    Compression::Deflate => {
+      let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+      encoder.write_all(data)?;
+      encoder.finish()
+    },
+  }
+}
+
+// Recupera os dados originais de um pacote comprimido com blocos de `chunk_size` bytes.
+pub fn decompress_payload(payload: &[u8], chunk_size: usize) -> io::Result<Vec<u8>> {
+  let limit = chunk_size * MAX_WINDOW_FACTOR;
+  let (&format, data) = payload
+    .split_first()
+    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Pacote comprimido vazio"))?;
+  match Compression::from_id(format) {
+    Some(Compression::None) => Ok(data.to_vec()),
+    Some(Compression::Zstd) => zstd::bulk::decompress(data, limit),
+    Some(Compression::Deflate) => {
+      let mut decoded = Vec::new();
+      DeflateDecoder::new(data).take(limit as u64 + 1).read_to_end(&mut decoded)?;
+      if decoded.len() > limit {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Pacote descomprimido grande demais"));
+      }
+      Ok(decoded)
+    },
+    None => Err(io::Error::new(io::ErrorKind::InvalidData, "Formato de compressão desconhecido")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CHUNK: usize = 1400;
+
+  // Texto repetitivo, que comprime bem, seguido de bytes pseudoaleatórios, que não comprimem.
+  fn sample() -> Vec<u8> {
+    let mut data: Vec<u8> = b"linha de log repetida com alguma variacao ".iter().copied().cycle().take(200_000).collect();
+    let mut state: u32 = 0x1234_5678;
+    data.extend((0..20_000).map(|_| {
+      state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+      (state >> 16) as u8
+    }));
+    data
+  }
+
+  fn round_trip(codec: Compression) {
+    let data = sample();
+    let payloads = compress_chunks(&data, CHUNK, codec).unwrap();
+    assert!(payloads.iter().all(|payload| payload.len() <= CHUNK));
+    assert!(payloads.len() < data.len().div_ceil(CHUNK), "a compressão não reduziu o número de pacotes");
+    let restored: Vec<u8> = payloads.iter().flat_map(|payload| decompress_payload(payload, CHUNK).unwrap()).collect();
+    assert_eq!(restored, data);
+  }
+
+  #[test]
+  fn zstd_round_trip() {
+    round_trip(Compression::Zstd);
+  }
+
+  #[test]
+  fn deflate_round_trip() {
+    round_trip(Compression::Deflate);
+  }
+
+  #[test]
+  fn incompressible_chunks_are_sent_as_is() {
+    let data: Vec<u8> = sample().split_off(200_000);
+    let payloads = compress_chunks(&data, CHUNK, Compression::Zstd).unwrap();
+    assert!(payloads.iter().any(|payload| payload[0] == Compression::None.id()));
+    let restored: Vec<u8> = payloads.iter().flat_map(|payload| decompress_payload(payload, CHUNK).unwrap()).collect();
+    assert_eq!(restored, data);
+  }
+
+  #[test]
+  fn decompression_is_limited() {
+    // Zeros comprimem a quase nada: um pacote pequeno que descomprime além do limite.
+    let zeros = vec![0u8; CHUNK * MAX_WINDOW_FACTOR + 1];
+    for codec in [Compression::Zstd, Compression::Deflate] {
+      let payload = with_format(codec, &compress(&zeros, codec).unwrap());
+      assert!(payload.len() < CHUNK);
+      assert!(decompress_payload(&payload, CHUNK).is_err(), "{} sem limite", codec.as_str());
+      assert_eq!(decompress_payload(&payload, CHUNK * 2).unwrap(), zeros);
+    }
+  }
+
+  #[test]
+  fn unknown_formats_are_rejected() {
+    assert!(decompress_payload(&[], CHUNK).is_err());
+    assert!(decompress_payload(&[9, 1, 2, 3], CHUNK).is_err());
+    assert_eq!(Compression::parse("brotli"), None);
+  }
+}
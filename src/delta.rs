@@ -0,0 +1,323 @@
+// Transferência por diferença, no estilo do rsync. O cliente calcula assinaturas dos blocos da
+// sua cópia local (checksum rolante e hash forte) e as envia ao servidor; o servidor percorre a
+// versão atual do arquivo procurando esses blocos e responde com um delta: instruções de cópia
+// para os blocos que o cliente já tem e dados literais para o resto. O cliente reconstrói o arquivo
+// e confere o SHA-256 anunciado no delta.
+use std::collections::HashMap;
+use std::io;
+
+use digest::Digest;
+use sha2::Sha256;
+
+// Limites do tamanho de bloco. O cliente escolhe um valor proporcional à raiz quadrada do tamanho
+// da cópia local, como o rsync.
+pub const MIN_BLOCK_SIZE: usize = 2048;
+pub const MAX_BLOCK_SIZE: usize = 128 * 1024;
+// Bytes do SHA-256 guardados em cada assinatura.
+const STRONG_LEN: usize = 16;
+const SIGNATURE_LEN: usize = 4 + STRONG_LEN;
+
+const OP_COPY: u8 = 0;
+const OP_LITERAL: u8 = 1;
+
+// Assinatura de um bloco da cópia local.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockSignature {
+  pub weak: u32,
+  pub strong: [u8; STRONG_LEN],
+}
+
+// Instrução do delta.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeltaOp {
+  // Copia `count` blocos da cópia local, a partir do bloco `block`.
+  Copy { block: u32, count: u32 },
+  Literal(Vec<u8>),
+}
+
+// Delta completo: tamanho e hash do arquivo resultante, mais as instruções para montá-lo.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delta {
+  pub size: u64,
+  pub sha256: [u8; 32],
+  pub ops: Vec<DeltaOp>,
+}
+
+// Checksum rolante do rsync: a soma dos bytes e a soma ponderada pela posição, 16 bits cada.
+struct RollingChecksum {
+  a: u32,
+  b: u32,
+  len: u32,
+}
+
+impl RollingChecksum {
+  fn new(block: &[u8]) -> RollingChecksum {
+    let len = block.len() as u32;
+    let mut a: u32 = 0;
+    let mut b: u32 = 0;
+    for (i, &byte) in block.iter().enumerate() {
+      a = a.wrapping_add(byte as u32);
+      b = b.wrapping_add((len - i as u32).wrapping_mul(byte as u32));
+    }
+    RollingChecksum { a: a & 0xffff, b: b & 0xffff, len }
+  }
+
+  // Desliza a janela um byte: `out` sai pelo início e `incoming` entra pelo fim.
+  fn roll(&mut self, out: u8, incoming: u8) {
+    self.a = self.a.wrapping_sub(out as u32).wrapping_add(incoming as u32) & 0xffff;
+    self.b = self.b.wrapping_sub(self.len.wrapping_mul(out as u32)).wrapping_add(self.a) & 0xffff;
+  }
+
+  fn value(&self) -> u32 {
+    self.a | (self.b << 16)
+  }
+}
+
+fn strong_hash(block: &[u8]) -> [u8; STRONG_LEN] {
+  Sha256::digest(block)[..STRONG_LEN].try_into().unwrap()
+}
+
+// Tamanho de bloco para uma cópia local de `len` bytes.
+pub fn block_size_for(len: usize) -> usize {
+  ((len as f64).sqrt() as usize).next_multiple_of(8).clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
+}
+
+// Assinaturas dos blocos completos de `data`. O resto final, menor que um bloco, não é assinado:
+// o servidor o envia como literal se ele ainda fizer parte do arquivo.
+pub fn signatures(data: &[u8], block_size: usize) -> Vec<BlockSignature> {
+  data
+    .chunks_exact(block_size)
+    .map(|block| BlockSignature { weak: RollingChecksum::new(block).value(), strong: strong_hash(block) })
+    .collect()
+}
+
+pub fn encode_signatures(signatures: &[BlockSignature]) -> Vec<u8> {
+  let mut bytes = Vec::with_capacity(signatures.len() * SIGNATURE_LEN);
+  for signature in signatures {
+    bytes.extend_from_slice(&signature.weak.to_be_bytes());
+    bytes.extend_from_slice(&signature.strong);
+  }
+  bytes
+}
+
+pub fn decode_signatures(data: &[u8]) -> io::Result<Vec<BlockSignature>> {
+  if !data.len().is_multiple_of(SIGNATURE_LEN) {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "Assinaturas com tamanho inválido"));
+  }
+  Ok(
+    data
+      .chunks_exact(SIGNATURE_LEN)
+      .map(|chunk| BlockSignature {
+        weak: u32::from_be_bytes(chunk[..4].try_into().unwrap()),
+        strong: chunk[4..].try_into().unwrap(),
+      })
+      .collect(),
+  )
+}
+
+// Calcula o delta que transforma a cópia descrita por `signatures` em `data`.
+pub fn compute_delta(signatures: &[BlockSignature], block_size: usize, data: &[u8]) -> Delta {
+  let mut by_weak: HashMap<u32, Vec<usize>> = HashMap::new();
+  for (index, signature) in signatures.iter().enumerate() {
+    by_weak.entry(signature.weak).or_default().push(index);
+  }
+
+  let mut ops = Vec::new();
+  let mut literal = Vec::new();
+  let mut pos = 0;
+  let mut rolling = (data.len() >= block_size).then(|| RollingChecksum::new(&data[..block_size]));
+
+  while let Some(checksum) = rolling.as_mut() {
+    let window = &data[pos..pos + block_size];
+    let matched = by_weak.get(&checksum.value()).and_then(|candidates| {
+      let strong = strong_hash(window);
+      candidates.iter().copied().find(|&index| signatures[index].strong == strong)
+    });
+
+    if let Some(index) = matched {
+      if !literal.is_empty() {
+        ops.push(DeltaOp::Literal(std::mem::take(&mut literal)));
+      }
+      push_copy(&mut ops, index as u32);
+      pos += block_size;
+      rolling = (pos + block_size <= data.len()).then(|| RollingChecksum::new(&data[pos..pos + block_size]));
+    } else {
+      literal.push(data[pos]);
+      if pos + block_size < data.len() {
+        checksum.roll(data[pos], data[pos + block_size]);
+      } else {
+        rolling = None;
+      }
+      pos += 1;
+    }
+  }
+
+  literal.extend_from_slice(&data[pos..]);
+  if !literal.is_empty() {
+    ops.push(DeltaOp::Literal(literal));
+  }
+  Delta { size: data.len() as u64, sha256: Sha256::digest(data).into(), ops }
+}
+
+// Junta cópias de blocos consecutivos em uma única instrução.
+fn push_copy(ops: &mut Vec<DeltaOp>, block: u32) {
+  if let Some(DeltaOp::Copy { block: start, count }) = ops.last_mut() {
+    if *start + *count == block {
+      *count += 1;
+      return;
+    }
+  }
+  ops.push(DeltaOp::Copy { block, count: 1 });
+}
+
+impl Delta {
+  // Monta o arquivo novo a partir da cópia local (`basis`) assinada com `block_size`.
+  pub fn apply(&self, basis: &[u8], block_size: usize) -> io::Result<Vec<u8>> {
+    // O tamanho anunciado vem da rede: a reserva não passa do que a cópia local e os literais podem
+    // produzir.
+    let capacity = self.size.min(basis.len().saturating_add(self.literal_len()) as u64);
+    let mut output = Vec::with_capacity(capacity as usize);
+    for op in &self.ops {
+      match op {
+        DeltaOp::Copy { block, count } => {
+          let start = *block as usize * block_size;
+          let end = start + *count as usize * block_size;
+          let blocks = basis
+            .get(start..end)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Delta referencia blocos inexistentes"))?;
+          output.extend_from_slice(blocks);
+        },
+        DeltaOp::Literal(data) => output.extend_from_slice(data),
+      }
+    }
+    Ok(output)
+  }
+
+  // Bytes enviados como literais, para estatísticas.
+  pub fn literal_len(&self) -> usize {
+    self
+      .ops
+      .iter()
+      .map(|op| match op {
+        DeltaOp::Literal(data) => data.len(),
+        DeltaOp::Copy { .. } => 0,
+      })
+      .sum()
+  }
+
+  pub fn encode(&self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(40 + self.literal_len() + self.ops.len() * 9);
+    bytes.extend_from_slice(&self.size.to_be_bytes());
+    bytes.extend_from_slice(&self.sha256);
+    for op in &self.ops {
+      match op {
+        DeltaOp::Copy { block, count } => {
+          bytes.push(OP_COPY);
+          bytes.extend_from_slice(&block.to_be_bytes());
+          bytes.extend_from_slice(&count.to_be_bytes());
+        },
+        DeltaOp::Literal(data) => {
+          bytes.push(OP_LITERAL);
+          bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+          bytes.extend_from_slice(data);
+        },
+      }
+    }
+    bytes
+  }
+
+  pub fn decode(data: &[u8]) -> io::Result<Delta> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Delta mal formado");
+    let u32_at = |pos: usize| data.get(pos..pos + 4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()));
+
+    let size = u64::from_be_bytes(data.get(..8).ok_or_else(invalid)?.try_into().unwrap());
+    let sha256 = data.get(8..40).ok_or_else(invalid)?.try_into().unwrap();
+    let mut ops = Vec::new();
+    let mut pos = 40;
+    while pos < data.len() {
+      match data[pos] {
+        OP_COPY => {
+          let block = u32_at(pos + 1).ok_or_else(invalid)?;
+          let count = u32_at(pos + 5).ok_or_else(invalid)?;
+          ops.push(DeltaOp::Copy { block, count });
+          pos += 9;
+        },
+        OP_LITERAL => {
+          let len = u32_at(pos + 1).ok_or_else(invalid)? as usize;
+          let literal = data.get(pos + 5..pos + 5 + len).ok_or_else(invalid)?;
+          ops.push(DeltaOp::Literal(literal.to_vec()));
+          pos += 5 + len;
+        },
+        _ => return Err(invalid()),
+      }
+    }
+    Ok(Delta { size, sha256, ops })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Bytes pseudoaleatórios, para que blocos diferentes tenham assinaturas diferentes.
+  fn sample(len: usize) -> Vec<u8> {
+    let mut state: u32 = 0x2545_f491;
+    (0..len)
+      .map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+      })
+      .collect()
+  }
+
+  #[test]
+  fn delta_rebuilds_the_new_version_from_the_basis() {
+    let block_size = MIN_BLOCK_SIZE;
+    let basis = sample(10 * block_size + 100);
+    // Um byte alterado no bloco 3, bytes inseridos no bloco 6 e o resto final removido.
+    let mut data = basis.clone();
+    data[3 * block_size + 10] ^= 0xff;
+    data.splice(6 * block_size + 5..6 * block_size + 5, b"inserted".iter().copied());
+    data.truncate(data.len() - 50);
+
+    let delta = compute_delta(&signatures(&basis, block_size), block_size, &data);
+    let decoded = Delta::decode(&delta.encode()).unwrap();
+    assert_eq!(decoded, delta);
+    assert_eq!(decoded.apply(&basis, block_size).unwrap(), data);
+    // Só os dois blocos alterados e o resto final vão como literais.
+    assert!(delta.literal_len() <= 2 * block_size + 100, "literais demais: {}", delta.literal_len());
+    assert_eq!(delta.sha256, <[u8; 32]>::from(Sha256::digest(&data)));
+  }
+
+  #[test]
+  fn delta_without_basis_is_all_literal() {
+    let data = sample(3 * MIN_BLOCK_SIZE);
+    let delta = compute_delta(&[], MIN_BLOCK_SIZE, &data);
+    assert_eq!(delta.ops, vec![DeltaOp::Literal(data.clone())]);
+    assert_eq!(delta.apply(&[], MIN_BLOCK_SIZE).unwrap(), data);
+  }
+
+  #[test]
+  fn signatures_round_trip() {
+    let signatures = signatures(&sample(4 * MIN_BLOCK_SIZE + 1), MIN_BLOCK_SIZE);
+    assert_eq!(signatures.len(), 4);
+    assert_eq!(decode_signatures(&encode_signatures(&signatures)).unwrap(), signatures);
+    assert!(decode_signatures(&[0; SIGNATURE_LEN + 1]).is_err());
+  }
+
+  #[test]
+  fn apply_rejects_copies_outside_the_basis() {
+    let delta = Delta { size: u64::MAX, sha256: [0; 32], ops: vec![DeltaOp::Copy { block: 2, count: 1 }] };
+    let error = delta.apply(&sample(2 * MIN_BLOCK_SIZE), MIN_BLOCK_SIZE).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn decode_rejects_truncated_deltas() {
+    let encoded = compute_delta(&[], MIN_BLOCK_SIZE, b"literal").encode();
+    assert!(Delta::decode(&encoded[..encoded.len() - 1]).is_err());
+    assert!(Delta::decode(&encoded[..39]).is_err());
+  }
+}
@@ -0,0 +1,254 @@
+// Descoberta de servidores na rede local. O cliente envia `DISCOVER` por broadcast e para o grupo
+// multicast `DISCOVERY_GROUP`, na porta do servidor; cada servidor com a descoberta ligada responde,
+// por unicast, `SERVER {...}` com um JSON contendo o seu nome, a versão do protocolo, as
+// capacidades e, opcionalmente, o endereço anunciado (sem ele, o cliente usa a origem da resposta).
+//
+// Como nas sondas PROBE, o pedido é completado com zeros até `DISCOVER_LEN` bytes e o servidor só
+// responde se a resposta não for maior que o pedido, para que um endereço de origem forjado não
+// sirva para amplificar tráfego.
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use tracing::{debug, info, warn};
+
+use crate::capture::{self, Direction};
+use crate::protocol::{Datagram, PROTOCOL_VERSION, SERVER_PORT};
+
+// Grupo em que os servidores escutam pedidos de descoberta (escopo administrativo local, RFC 2365).
+pub const DISCOVERY_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 0, 83);
+// Tamanho mínimo de um pedido de descoberta.
+pub const DISCOVER_LEN: usize = 512;
+// Pedidos enviados por descoberta, espaçados ao longo da espera, para tolerar perdas.
+const DISCOVER_ATTEMPTS: u32 = 2;
+
+// Configuração da descoberta no servidor.
+#[derive(Clone, Debug)]
+pub struct DiscoveryConfig {
+  pub name: String,
+  // Endereço anunciado aos clientes, quando a origem das respostas não serve (NAT, proxy).
+  pub advertise: Option<SocketAddr>,
+  // Interface em que o servidor entra no grupo de descoberta (`0.0.0.0` deixa a escolha para o
+  // sistema).
+  pub interface: Ipv4Addr,
+}
+
+impl DiscoveryConfig {
+  pub fn new(name: impl Into<String>) -> DiscoveryConfig {
+    DiscoveryConfig { name: name.into(), advertise: None, interface: Ipv4Addr::UNSPECIFIED }
+  }
+}
+
+// Conteúdo da resposta `SERVER`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Announcement {
+  pub name: String,
+  pub version: u32,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub address: Option<SocketAddr>,
+  pub capabilities: Vec<String>,
+}
+
+// Entra no grupo de descoberta com o socket do servidor. Sem o grupo, o servidor ainda responde aos
+// pedidos por broadcast e por unicast.
+pub fn join_group(socket: &UdpSocket, interface: Ipv4Addr) {
+  if !socket.local_addr().is_ok_and(|address| address.is_ipv4()) {
+    return;
+  }
+  match socket.join_multicast_v4(&DISCOVERY_GROUP, &interface) {
+    Ok(()) => info!(group = %DISCOVERY_GROUP, %interface, "discovery group joined"),
+    Err(e) => warn!(group = %DISCOVERY_GROUP, %interface, error = %e, "could not join discovery group"),
+  }
+}
+
+// Resposta a um pedido de descoberta; `None` se o pedido for curto demais para ela.
+pub(crate) fn reply(announcement: &Announcement, request_len: usize) -> Option<Vec<u8>> {
+  let json = serde_json::to_string(announcement).ok()?;
+  let reply = format!("SERVER {}", json).into_bytes();
+  if request_len < DISCOVER_LEN || reply.len() > request_len {
+    debug!(request_len, reply_len = reply.len(), "discovery request too short, not answering");
+    return None;
+  }
+  Some(reply)
+}
+
+// Servidor que respondeu a uma descoberta.
+#[derive(Clone, Debug)]
+pub struct DiscoveredServer {
+  pub address: SocketAddr,
+  pub name: String,
+  pub version: u32,
+  pub capabilities: Vec<String>,
+  // Tempo entre o último pedido enviado e a resposta.
+  pub rtt: Duration,
+}
+
+impl DiscoveredServer {
+  // Fala a mesma versão do protocolo que este cliente.
+  pub fn compatible(&self) -> bool {
+    self.version == PROTOCOL_VERSION
+  }
+
+  pub fn supports(&self, capability: &str) -> bool {
+    self.capabilities.iter().any(|known| known == capability)
+  }
+}
+
+// Opções da descoberta no cliente.
+#[derive(Clone, Debug)]
+pub struct DiscoveryOptions {
+  // Espera total pelas respostas.
+  pub timeout: Duration,
+  // Destinos dos pedidos: por padrão, o broadcast e o grupo de descoberta na porta do servidor.
+  pub targets: Vec<SocketAddr>,
+  // Interface de saída dos pedidos para o grupo.
+  pub interface: Ipv4Addr,
+}
+
+impl Default for DiscoveryOptions {
+  fn default() -> DiscoveryOptions {
+    DiscoveryOptions {
+      timeout: Duration::from_secs(1),
+      targets: vec![
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::BROADCAST, SERVER_PORT)),
+        SocketAddr::V4(SocketAddrV4::new(DISCOVERY_GROUP, SERVER_PORT)),
+      ],
+      interface: Ipv4Addr::UNSPECIFIED,
+    }
+  }
+}
+
+// Procura servidores e devolve os que responderam, um por endereço, em ordem de RTT.
+pub fn discover(options: &DiscoveryOptions) -> io::Result<Vec<DiscoveredServer>> {
+  let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+  socket.set_broadcast(true)?;
+  socket.set_multicast_if_v4(&options.interface)?;
+  socket.set_multicast_ttl_v4(1)?;
+  socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)).into())?;
+  let socket: UdpSocket = socket.into();
+
+  let mut request = b"DISCOVER".to_vec();
+  request.resize(DISCOVER_LEN, 0);
+  let started = Instant::now();
+  let deadline = started + options.timeout;
+  let interval = options.timeout / DISCOVER_ATTEMPTS;
+  let mut servers: HashMap<SocketAddr, DiscoveredServer> = HashMap::new();
+  let mut buf = [0u8; 2048];
+
+  for attempt in 0..DISCOVER_ATTEMPTS {
+    let sent = Instant::now();
+    let mut last_error = None;
+    let mut delivered = 0;
+    for &target in &options.targets {
+      match socket.send_to(&request, target) {
+        Ok(_) => {
+          capture::record_socket(&socket, target, Direction::Sent, [request.as_slice()]);
+          delivered += 1;
+        },
+        Err(e) => {
+          debug!(%target, error = %e, "discovery request not sent");
+          last_error = Some(e);
+        },
+      }
+    }
+    if let (0, Some(e)) = (delivered, last_error) {
+      return Err(e);
+    }
+
+    let round_end = if attempt + 1 == DISCOVER_ATTEMPTS { deadline } else { sent + interval };
+    loop {
+      let now = Instant::now();
+      if now >= round_end {
+        break;
+      }
+      socket.set_read_timeout(Some(round_end - now))?;
+      let (len, origin) = match socket.recv_from(&mut buf) {
+        Ok(received) => received,
+        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+        Err(e) => return Err(e),
+      };
+      capture::record_socket(&socket, origin, Direction::Received, [&buf[..len]]);
+      let announcement = match Datagram::decode(&buf[..len]) {
+        Datagram::Text { command: "SERVER", argument } => match serde_json::from_str::<Announcement>(argument) {
+          Ok(announcement) => announcement,
+          Err(e) => {
+            debug!(server = %origin, error = %e, "invalid discovery reply");
+            continue;
+          },
+        },
+        _ => continue,
+      };
+      let server = DiscoveredServer {
+        address: announcement.address.unwrap_or(origin),
+        name: announcement.name,
+        version: announcement.version,
+        capabilities: announcement.capabilities,
+        rtt: sent.elapsed(),
+      };
+      // Servidores com vários sockets na porta (workers) podem responder mais de uma vez.
+      servers.entry(server.address).and_modify(|known| known.rtt = known.rtt.min(server.rtt)).or_insert(server);
+    }
+  }
+
+  let mut servers: Vec<DiscoveredServer> = servers.into_values().collect();
+  servers.sort_by_key(|server| server.rtt);
+  info!(servers = servers.len(), elapsed_ms = started.elapsed().as_millis() as u64, "discovery finished");
+  Ok(servers)
+}
+
+// Servidor escolhido automaticamente: o compatível de menor RTT.
+pub fn select(servers: &[DiscoveredServer]) -> Option<&DiscoveredServer> {
+  servers.iter().filter(|server| server.compatible()).min_by_key(|server| server.rtt)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn announcement(name: &str) -> Announcement {
+    Announcement { name: name.to_string(), version: PROTOCOL_VERSION, address: None, capabilities: vec!["delta".to_string()] }
+  }
+
+  fn server(name: &str, version: u32, rtt_ms: u64) -> DiscoveredServer {
+    DiscoveredServer {
+      address: SocketAddr::from(([10, 0, 0, 1], SERVER_PORT)),
+      name: name.to_string(),
+      version,
+      capabilities: Vec::new(),
+      rtt: Duration::from_millis(rtt_ms),
+    }
+  }
+
+  #[test]
+  fn replies_only_to_padded_requests() {
+    assert_eq!(reply(&announcement("lab"), "DISCOVER".len()), None);
+    assert_eq!(reply(&announcement("lab"), DISCOVER_LEN - 1), None);
+    let answer = reply(&announcement("lab"), DISCOVER_LEN).expect("pedido do tamanho mínimo sem resposta");
+    assert!(answer.len() <= DISCOVER_LEN);
+    match Datagram::decode(&answer) {
+      Datagram::Text { command: "SERVER", argument } => {
+        assert_eq!(serde_json::from_str::<Announcement>(argument).unwrap(), announcement("lab"));
+      },
+      other => panic!("resposta inesperada: {:?}", other),
+    }
+  }
+
+  #[test]
+  fn reply_is_never_larger_than_the_request() {
+    let long = announcement(&"x".repeat(DISCOVER_LEN));
+    assert_eq!(reply(&long, DISCOVER_LEN), None);
+    let answer = reply(&long, 2 * DISCOVER_LEN).expect("pedido grande o bastante sem resposta");
+    assert!(answer.len() <= 2 * DISCOVER_LEN);
+  }
+
+  #[test]
+  fn selects_the_nearest_compatible_server() {
+    let servers = [server("lento", PROTOCOL_VERSION, 30), server("antigo", PROTOCOL_VERSION + 1, 1), server("perto", PROTOCOL_VERSION, 5)];
+    assert_eq!(select(&servers).map(|server| server.name.as_str()), Some("perto"));
+    assert!(select(&servers[1..2]).is_none());
+    assert!(servers[0].compatible() && !servers[1].compatible());
+  }
+}
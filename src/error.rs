@@ -0,0 +1,61 @@
+// Erros vistos pelo lado do cliente: respostas de erro do servidor, com o código tipado, e falhas
+// locais (tempo esgotado, resposta fora do protocolo, E/S).
+use std::{error, fmt, io};
+
+use crate::protocol::{decode_error, ErrorCode};
+
+#[derive(Debug)]
+pub enum ClientError {
+  // O servidor recusou a requisição.
+  Server { code: ErrorCode, message: String },
+  // O servidor não respondeu dentro do número máximo de tentativas.
+  Timeout,
+  // A transferência foi cancelada pelo chamador (`CancelToken`).
+  Cancelled,
+  // Resposta que não segue o protocolo.
+  InvalidResponse(String),
+  Io(io::Error),
+}
+
+impl ClientError {
+  // Erro do servidor contido no datagrama, se ele for um pacote de erro.
+  pub fn from_datagram(datagram: &[u8]) -> Option<ClientError> {
+    decode_error(datagram).map(|(code, message)| ClientError::Server { code, message })
+  }
+
+  // Código enviado pelo servidor, quando o erro veio dele.
+  pub fn code(&self) -> Option<ErrorCode> {
+    match self {
+      ClientError::Server { code, .. } => Some(*code),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for ClientError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ClientError::Server { code, message } if message.is_empty() => write!(f, "server error {}", code),
+      ClientError::Server { code, message } => write!(f, "server error {}: {}", code, message),
+      ClientError::Timeout => write!(f, "server did not respond"),
+      ClientError::Cancelled => write!(f, "transfer cancelled"),
+      ClientError::InvalidResponse(reason) => write!(f, "invalid response: {}", reason),
+      ClientError::Io(e) => write!(f, "{}", e),
+    }
+  }
+}
+
+impl error::Error for ClientError {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match self {
+      ClientError::Io(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for ClientError {
+  fn from(e: io::Error) -> ClientError {
+    ClientError::Io(e)
+  }
+}
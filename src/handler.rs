@@ -0,0 +1,943 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, field, info, info_span, trace, warn, Span};
+
+use crate::acl::{Acl, Operation};
+use crate::auth::{self, Authenticator};
+use crate::compression::{self, Compression};
+use crate::delta;
+use crate::discovery::{self, Announcement, DiscoveryConfig};
+use crate::journal::{ContentKind, Journal, RecoveredSession, SessionRecord};
+use crate::limits::{self, Occupant, SessionLimits};
+use crate::listing::{self, EntryKind};
+use crate::metrics::{Handled, Metrics, SessionEvent};
+use crate::pmtud;
+use crate::shaping::{Shaper, ShapingConfig};
+use crate::source::{validate_path, FileSource};
+use crate::upload::{self, Uploads};
+use crate::protocol::{
+  decode_error, ErrorCode, FileMeta, UdpPacket, CHUNK_SIZE, MAX_CHUNK_SIZE, MIN_CHUNK_SIZE, PROTOCOL_VERSION, SERVER_PORT,
+};
+
+// Intervalo mínimo entre duas varreduras das sessões expiradas.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+// Pacotes da última resposta enviada a um cliente, indexados pelo número de sequência, e a sessão a
+// que pertencem. Os pacotes que o cliente já confirmou são descartados.
+struct StoredSession {
+  id: u64,
+  packets: HashMap<u32, UdpPacket>,
+  // Maior número de sequência da resposta; pedidos além dele não correspondem a nenhum pacote.
+  last_seq: u32,
+  // Bytes de dados dos pacotes guardados, para o limite de memória.
+  bytes: usize,
+  created: Instant,
+  last_activity: Instant,
+}
+
+impl StoredSession {
+  fn new(id: u64, packets: HashMap<u32, UdpPacket>) -> StoredSession {
+    let bytes = packets.values().map(|packet| packet.data.len()).sum();
+    let last_seq = packets.keys().copied().max().unwrap_or(0);
+    let now = Instant::now();
+    StoredSession { id, packets, last_seq, bytes, created: now, last_activity: now }
+  }
+
+  // Descarta os pacotes de dados em `first..=last`, limitado aos números da resposta. Percorre os
+  // pacotes guardados, e não o intervalo, que vem do cliente e pode ter bilhões de números. Devolve o
+  // intervalo limitado se algum pacote foi descartado.
+  fn remove_range(&mut self, first: u32, last: u32) -> Option<(u32, u32)> {
+    let (first, last) = (first.max(1), last.min(self.last_seq));
+    if first > last {
+      return None;
+    }
+    let before = self.packets.len();
+    let mut freed = 0;
+    self.packets.retain(|seq, packet| {
+      let acked = (first..=last).contains(seq);
+      if acked {
+        freed += packet.data.len();
+      }
+      !acked
+    });
+    self.bytes -= freed;
+    (self.packets.len() < before).then_some((first, last))
+  }
+}
+
+// Sessão nas tabelas do servidor: respostas guardadas para retransmissão e uploads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SessionKey {
+  Response(SocketAddr),
+  Upload(SocketAddr),
+}
+
+// Sessões de um `Handler`, compartilhadas pelas cópias dele: as respostas guardadas para
+// retransmissão, por cliente, e os uploads em andamento.
+struct Sessions {
+  responses: Mutex<HashMap<SocketAddr, StoredSession>>,
+  uploads: Uploads,
+  last_sweep: Mutex<Instant>,
+}
+
+impl Sessions {
+  fn new() -> Sessions {
+    Sessions { responses: Mutex::new(HashMap::new()), uploads: Mutex::new(HashMap::new()), last_sweep: Mutex::new(Instant::now()) }
+  }
+}
+
+// Identificador da próxima sessão (resposta de GET/LIST ou upload), registrado nos logs.
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+pub(crate) fn next_session_id() -> u64 {
+  NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// Requisição de um cliente, como vista pelo gancho de autorização.
+pub struct Request<'a> {
+  pub client: SocketAddr,
+  // Comando: GET, STAT, LIST, PUT ou DELTA.
+  pub command: &'a str,
+  // Caminho pedido, sem a barra inicial e sem a query string.
+  pub path: &'a str,
+  // Caminho como enviado pelo cliente, com a query string.
+  pub target: &'a str,
+}
+
+impl Request<'_> {
+  // Valor de um parâmetro da query string.
+  pub fn param(&self, name: &str) -> Option<&str> {
+    query_param(self.target, name)
+  }
+}
+
+// Registro de uma requisição tratada, entregue ao gancho de log.
+pub struct RequestLog<'a> {
+  pub client: SocketAddr,
+  pub request: &'a str,
+  // Datagramas enviados em resposta e o código do erro, quando a resposta é um pacote de erro.
+  pub datagrams: usize,
+  pub error: Option<ErrorCode>,
+}
+
+// Gancho de autorização: devolve o motivo da recusa, enviado ao cliente como erro FORBIDDEN.
+pub type Authorizer = Arc<dyn Fn(&Request) -> Result<(), String> + Send + Sync>;
+// Gancho de log, chamado depois de cada requisição tratada.
+pub type RequestLogger = Arc<dyn Fn(&RequestLog) + Send + Sync>;
+// Gancho de identificação: a identidade autenticada do cliente, usada nos limites de taxa.
+pub type Identifier = Arc<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+// Tratamento das requisições: a origem dos arquivos servidos, a autenticação, a ACL, os ganchos de
+// autorização, de identificação e de log, as métricas, os limites de taxa e de sessões, o diário e
+// as sessões em andamento. Cópias compartilham tudo isso; `Handler`s criados separadamente, mesmo no
+// mesmo processo, não compartilham sessões.
+#[derive(Clone)]
+pub struct Handler {
+  source: Arc<dyn FileSource>,
+  authenticator: Option<Arc<Authenticator>>,
+  acl: Option<Arc<Acl>>,
+  authorizer: Option<Authorizer>,
+  identifier: Option<Identifier>,
+  logger: Option<RequestLogger>,
+  metrics: Option<Arc<Metrics>>,
+  shaper: Arc<Shaper>,
+  discovery: Option<Arc<DiscoveryConfig>>,
+  limits: SessionLimits,
+  journal: Arc<Journal>,
+  sessions: Arc<Sessions>,
+}
+
+impl Handler {
+  pub fn new(source: Arc<dyn FileSource>) -> Handler {
+    Handler {
+      source,
+      authenticator: None,
+      acl: None,
+      authorizer: None,
+      identifier: None,
+      logger: None,
+      metrics: None,
+      shaper: Arc::new(Shaper::new(ShapingConfig::default())),
+      discovery: None,
+      limits: SessionLimits::default(),
+      journal: Arc::new(Journal::default()),
+      sessions: Arc::new(Sessions::new()),
+    }
+  }
+
+  pub fn with_authenticator(mut self, authenticator: Arc<Authenticator>) -> Handler {
+    self.authenticator = Some(authenticator);
+    self
+  }
+
+  pub fn with_acl(mut self, acl: Arc<Acl>) -> Handler {
+    self.acl = Some(acl);
+    self
+  }
+
+  pub fn with_authorizer(mut self, authorizer: Authorizer) -> Handler {
+    self.authorizer = Some(authorizer);
+    self
+  }
+
+  pub fn with_identifier(mut self, identifier: Identifier) -> Handler {
+    self.identifier = Some(identifier);
+    self
+  }
+
+  pub fn with_logger(mut self, logger: RequestLogger) -> Handler {
+    self.logger = Some(logger);
+    self
+  }
+
+  // Métricas atualizadas a cada datagrama; sem elas, nada é contado.
+  pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Handler {
+    self.metrics = Some(metrics);
+    self
+  }
+
+  pub fn with_shaping(mut self, config: ShapingConfig) -> Handler {
+    self.shaper = Arc::new(Shaper::new(config));
+    self
+  }
+
+  pub fn with_discovery(mut self, discovery: DiscoveryConfig) -> Handler {
+    self.discovery = Some(Arc::new(discovery));
+    self
+  }
+
+  pub fn with_limits(mut self, limits: SessionLimits) -> Handler {
+    self.limits = limits;
+    self
+  }
+
+  // Diário das sessões de retransmissão; sem ele, as sessões não sobrevivem a um reinício.
+  pub fn with_journal(mut self, journal: Journal) -> Handler {
+    self.journal = Arc::new(journal);
+    self
+  }
+
+  pub fn source(&self) -> &dyn FileSource {
+    self.source.as_ref()
+  }
+
+  pub fn limits(&self) -> &SessionLimits {
+    &self.limits
+  }
+
+  pub(crate) fn uploads(&self) -> &Uploads {
+    &self.sessions.uploads
+  }
+
+  pub fn metrics(&self) -> Option<&Arc<Metrics>> {
+    self.metrics.as_ref()
+  }
+
+  // Limites de taxa que quem envia as respostas deve respeitar (ver `shaping`).
+  pub fn shaper(&self) -> &Arc<Shaper> {
+    &self.shaper
+  }
+
+  // Descoberta configurada; os modos de servidor entram no grupo de descoberta quando ela existe.
+  pub fn discovery(&self) -> Option<&DiscoveryConfig> {
+    self.discovery.as_deref()
+  }
+
+  // Registra nas métricas a resposta devolvida por `handle` depois de enviada ao cliente. Quem
+  // envia as respostas (os modos de servidor ou um transporte próprio) deve chamá-lo.
+  pub fn sent(&self, client_address: SocketAddr, datagrams: &[Vec<u8>]) {
+    if let Some(metrics) = &self.metrics {
+      metrics.sent(client_address, datagrams);
+    }
+  }
+
+  // Recupera as sessões de retransmissão registradas no diário por uma execução anterior (ver
+  // `journal`), gerando os pacotes de novo a partir da origem. Sessões cujo conteúdo mudou desde a
+  // entrega são descartadas; o cliente recebe SESSION_UNKNOWN e recomeça a transferência.
+  pub(crate) fn recover_sessions(&self) {
+    let mut recovered = 0;
+    for RecoveredSession { record, acked } in self.journal.recover() {
+      NEXT_SESSION_ID.fetch_max(record.session + 1, Ordering::Relaxed);
+      let packets: HashMap<u32, UdpPacket> = match rebuild_packets(self.source(), &record) {
+        Some(packets) => packets.into_iter().map(|packet| (packet.seq_number, packet)).collect(),
+        None => {
+          debug!(session = record.session, target = %record.target, "content changed, discarding session");
+          self.journal.end(record.session);
+          continue;
+        },
+      };
+      let mut session = StoredSession::new(record.session, packets);
+      for (first, last) in acked {
+        session.remove_range(first, last);
+      }
+      self.sessions.responses.lock().unwrap().insert(record.client, session);
+      recovered += 1;
+    }
+    if recovered > 0 {
+      info!(sessions = recovered, "retransmission sessions recovered");
+    }
+  }
+
+  // Trata um datagrama do cliente e devolve, em ordem, os datagramas que devem ser enviados a ele.
+  // O envio fica a cargo de quem chama, o que permite usar o mesmo tratamento no servidor
+  // bloqueante, com workers e no servidor assíncrono. Cada requisição é registrada em um span
+  // `request` com o cliente, o comando, o caminho, a decisão da ACL e a sessão.
+  pub fn handle(&self, datagram: &[u8], client_address: SocketAddr) -> Vec<Vec<u8>> {
+    let started = Instant::now();
+    self.expire_sessions(started);
+    // Pacotes de dados de um upload em andamento são binários e vão direto para a sessão de upload.
+    if let Some(response) = upload::handle_upload_packet(self, datagram, client_address) {
+      if let Some(metrics) = &self.metrics {
+        metrics.received(client_address, datagram.len(), true);
+        let error = response.first().and_then(|datagram| decode_error(datagram)).map(|(code, _)| code);
+        let finished = error.is_some() || response.first().is_some_and(|reply| reply.starts_with(b"PUT-OK"));
+        metrics.handled(client_address, Handled {
+          command: None,
+          error,
+          retransmitted: 0,
+          event: if finished { SessionEvent::Finish } else { SessionEvent::Continue },
+          reply_expected: response.first().is_some_and(|reply| reply.starts_with(b"RETRANSMIT ")),
+          started,
+        });
+      }
+      return response;
+    }
+
+    let raw_request = std::str::from_utf8(datagram).unwrap_or_default();
+    let request = &*auth::strip_credentials(raw_request);
+    let mut parts = request.split_whitespace();
+    // Pedidos DISCOVER são completados com zeros logo depois do comando.
+    let command = parts.next().unwrap_or_default().trim_end_matches('\0');
+    if let Some(metrics) = &self.metrics {
+      metrics.received(client_address, datagram.len(), command == "RETRANSMIT");
+    }
+    let span = info_span!(
+      "request",
+      peer = %client_address,
+      command,
+      path = field::Empty,
+      acl = field::Empty,
+      session = field::Empty
+    );
+    let _entered = span.enter();
+    if let ("GET" | "STAT" | "LIST" | "PUT" | "DELTA", Some(target)) = (command, parts.next()) {
+      span.record("path", request_path(target));
+    }
+
+    let response = match self.admit(raw_request, request, client_address) {
+      Ok(()) => self.dispatch(request, client_address),
+      Err(denied) => vec![denied],
+    };
+    let error = response.first().and_then(|datagram| decode_error(datagram)).map(|(code, _)| code);
+    if let Some(metrics) = &self.metrics {
+      metrics.handled(client_address, handled(command, error, &response, started));
+    }
+
+    // Sondas de PMTU chegam em rajadas e, como os pedidos de descoberta, não interessam fora da
+    // depuração.
+    if command == "PROBE" || command == "DISCOVER" {
+      debug!(datagrams = response.len(), "request handled");
+    } else {
+      info!(datagrams = response.len(), error = error.map(|code| code.as_u16()), "request handled");
+    }
+    if let Some(logger) = &self.logger {
+      logger(&RequestLog {
+        client: client_address,
+        request: request.trim_end_matches('\0'),
+        datagrams: response.len(),
+        error,
+      });
+    }
+    response
+  }
+
+  fn dispatch(&self, request: &str, client_address: SocketAddr) -> Vec<Vec<u8>> {
+    let source = self.source();
+    if request.starts_with("GET /") {
+      self.handle_get_request(request, client_address)
+    } else if request.starts_with("STAT /") {
+      handle_stat_request(source, request, client_address)
+    } else if request.starts_with("LIST /") {
+      self.handle_list_request(request, client_address)
+    } else if request.starts_with("PUT /") {
+      upload::handle_put_request(self, request, client_address)
+    } else if request.starts_with("DELTA /") {
+      upload::handle_delta_request(self, request, client_address)
+    } else if request.starts_with("PROBE ") {
+      handle_probe_request(request)
+    } else if request.starts_with("RETRANSMIT ") {
+      self.handle_retransmission_request(request, client_address)
+    } else if request.trim_end_matches('\0') == "DISCOVER" {
+      self.handle_discover_request(request)
+    } else {
+      warn!(request = request.trim_end_matches('\0'), "invalid request");
+      Vec::new()
+    }
+  }
+
+  // Pedido de descoberta: nome, versão do protocolo e capacidades do servidor (ver `discovery`).
+  // Sem a descoberta configurada, o servidor não responde.
+  fn handle_discover_request(&self, request: &str) -> Vec<Vec<u8>> {
+    let discovery = match &self.discovery {
+      Some(discovery) => discovery,
+      None => return Vec::new(),
+    };
+    let mut capabilities: Vec<String> =
+      ["get", "stat", "list", "put", "delta", "pmtud", "zstd", "deflate"].iter().map(|name| name.to_string()).collect();
+    if self.authenticator.as_ref().is_some_and(|authenticator| !authenticator.allows_anonymous()) {
+      capabilities.push("auth".to_string());
+    }
+    let announcement = Announcement {
+      name: discovery.name.clone(),
+      version: PROTOCOL_VERSION,
+      address: discovery.advertise,
+      capabilities,
+    };
+    discovery::reply(&announcement, request.len()).into_iter().collect()
+  }
+
+  // Controle de acesso dos comandos que acessam arquivos: as credenciais (conferidas em
+  // `raw_request`, antes de serem retiradas de `request`), a ACL e o gancho de autorização.
+  // Retransmissões, sondas e pacotes de upload pertencem a uma transferência já autorizada. O
+  // cliente admitido fica associado à sua identidade (a chave autenticada ou a devolvida pelo gancho
+  // de identificação), para as respostas seguintes, incluindo as retransmissões, contarem no limite
+  // de taxa dela.
+  fn admit(&self, raw_request: &str, request: &str, client_address: SocketAddr) -> Result<(), Vec<u8>> {
+    let request = match file_request(request, client_address) {
+      Some(request) => request,
+      None => return Ok(()),
+    };
+    let authenticated = match &self.authenticator {
+      Some(authenticator) => match authenticator.verify(raw_request, client_address) {
+        Ok(identity) => identity,
+        Err(reason) => {
+          info!(reason, "request not authenticated");
+          return Err(error_message(ErrorCode::Unauthorized, reason, client_address));
+        },
+      },
+      None => None,
+    };
+    let identity = authenticated.or_else(|| self.identifier.as_ref().and_then(|identifier| identifier(&request)));
+
+    if let (Some(acl), Some(operation)) = (&self.acl, Operation::of_command(request.command)) {
+      let decision = acl.decide(operation, client_address.ip(), identity.as_deref(), request.path);
+      Span::current().record("acl", if decision.allowed { "allow" } else { "deny" });
+      if !decision.allowed {
+        info!(%operation, identity, rule = decision.line, "request denied by ACL");
+        return Err(error_message(ErrorCode::Forbidden, "Acesso negado", client_address));
+      }
+      debug!(%operation, identity, rule = decision.line, "request allowed by ACL");
+    }
+
+    if let Some(authorizer) = &self.authorizer {
+      if let Err(reason) = authorizer(&request) {
+        info!(%reason, "request denied by authorizer");
+        return Err(error_message(ErrorCode::Forbidden, &reason, client_address));
+      }
+    }
+    if let Some(identity) = identity {
+      self.shaper.identify(client_address, identity);
+    }
+    Ok(())
+  }
+}
+
+// Requisição de um comando que acessa arquivos, como vista pelos ganchos.
+fn file_request(request: &str, client_address: SocketAddr) -> Option<Request<'_>> {
+  let mut parts = request.split_whitespace();
+  match (parts.next(), parts.next()) {
+    (Some(command @ ("GET" | "STAT" | "LIST" | "PUT" | "DELTA")), Some(target)) => {
+      Some(Request { client: client_address, command, path: request_path(target), target })
+    },
+    _ => None,
+  }
+}
+
+// Papel da resposta na sessão do cliente, para as métricas.
+fn handled<'a>(command: &'a str, error: Option<ErrorCode>, response: &[Vec<u8>], started: Instant) -> Handled<'a> {
+  let ready = response.first().is_some_and(|reply| reply.starts_with(b"PUT-READY"));
+  let (event, retransmitted) = match command {
+    _ if error.is_some() || response.is_empty() => (SessionEvent::None, 0),
+    "GET" | "LIST" => (SessionEvent::Start, 0),
+    "PUT" | "DELTA" if ready => (SessionEvent::Start, 0),
+    // Os pacotes pedidos, sem o fim de transmissão.
+    "RETRANSMIT" => (SessionEvent::Continue, response.len() - 1),
+    _ => (SessionEvent::None, 0),
+  };
+  let reply_expected = matches!(event, SessionEvent::Start | SessionEvent::Continue);
+  Handled { command: (!command.is_empty()).then_some(command), error, retransmitted, event, reply_expected, started }
+}
+
+impl Handler {
+  fn handle_get_request(&self, request: &str, client_address: SocketAddr) -> Vec<Vec<u8>> {
+    // Dividindo a requisição em partes para análise
+    let parts: Vec<&str> = request.split_whitespace().collect();
+    if parts.len() < 2 {
+      return vec![error_message(ErrorCode::BadRequest, "Requisição mal formatada", client_address)];
+    }
+    let path = parts[1];
+
+    // Extraindo o nome do arquivo da URL, considerando que pode haver uma query string
+    let filename = request_path(path);
+
+    // Verificando se o nome do arquivo não está vazio
+    if filename.is_empty() {
+      return vec![error_message(ErrorCode::BadRequest, "Nome do arquivo não especificado", client_address)];
+    }
+
+    if let Err(e) = validate_path(filename) {
+      return vec![error_message(ErrorCode::Forbidden, &e.to_string(), client_address)];
+    }
+
+    match self.source().read(filename) {
+      Ok(file) if query_param(path, "delta") == Some("1") => {
+        self.delta_response(filename, file.data, file.mtime, path, client_address)
+      },
+      Ok(file) => self.deliver(file.data, path, file.mtime, client_address, Some(ContentKind::File)),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+        vec![error_message(ErrorCode::NotFound, "Arquivo não encontrado", client_address)]
+      },
+      Err(e) => {
+        warn!(error = %e, "failed to read file");
+        vec![error_message(io_error_code(&e), &format!("Erro ao ler o arquivo: {}", e), client_address)]
+      }
+    }
+  }
+
+  // GET com `?delta=1`: entrega o delta entre a cópia do cliente, descrita pelas assinaturas enviadas
+  // antes com DELTA, e a versão atual do arquivo. Como as assinaturas ficam só na memória, a sessão
+  // não vai para o diário.
+  fn delta_response(&self, filename: &str, data: Vec<u8>, mtime: u64, path: &str, client_address: SocketAddr) -> Vec<Vec<u8>> {
+    let (block_size, signatures) = match upload::delta_basis(self.uploads(), client_address, filename) {
+      Some(basis) => basis,
+      None => return vec![error_message(ErrorCode::SessionUnknown, "Assinaturas do delta não recebidas", client_address)],
+    };
+    let delta = delta::compute_delta(&signatures, block_size, &data);
+    debug!(literal = delta.literal_len(), size = data.len(), "delta computed");
+    self.deliver(delta.encode(), path, mtime, client_address, None)
+  }
+
+  // Lista um diretório do servidor e entrega o JSON resultante como se fosse um arquivo.
+  fn handle_list_request(&self, request: &str, client_address: SocketAddr) -> Vec<Vec<u8>> {
+    let path = match request.split_whitespace().nth(1) {
+      Some(path) => path,
+      None => return vec![error_message(ErrorCode::BadRequest, "Requisição mal formatada", client_address)],
+    };
+    match list_directory(self.source(), path) {
+      Ok(data) => self.deliver(data, path, 0, client_address, Some(ContentKind::Listing)),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => vec![error_message(ErrorCode::NotFound, "Diretório não encontrado", client_address)],
+      Err(e) => {
+        warn!(error = %e, "failed to list directory");
+        vec![error_message(io_error_code(&e), &format!("Erro ao listar o diretório: {}", e), client_address)]
+      }
+    }
+  }
+
+  // Divide os dados em pacotes (cabeçalho, dados e fim de transmissão), guardando-os para retransmissão.
+  // Tamanho de bloco e compressão vêm da query string da requisição (`path`). Com `kind`, a sessão
+  // é registrada no diário e pode ser recuperada depois de um reinício.
+  fn deliver(&self, data: Vec<u8>, path: &str, mtime: u64, client_address: SocketAddr, kind: Option<ContentKind>) -> Vec<Vec<u8>> {
+    let packets = match prepare_response(data, path, mtime, client_address) {
+      Ok(packets) => packets,
+      Err(e) => return vec![error_message(ErrorCode::Internal, &format!("Erro ao comprimir: {}", e), client_address)],
+    };
+    let bytes = packets.iter().map(|packet| packet.data.len()).sum();
+    if let Err(busy) = self.admit_session(SessionKey::Response(client_address), bytes) {
+      return vec![busy];
+    }
+    let session = next_session_id();
+    Span::current().record("session", session);
+    debug!(bytes = data_len(&packets), packets = packets.len() - 1, "response prepared");
+    let mut datagrams: Vec<Vec<u8>> = packets.iter().map(UdpPacket::serialize).collect();
+    if let (Some(kind), Some(meta)) = (kind, FileMeta::decode(&packets[0].data)) {
+      self.journal.begin(&SessionRecord {
+        session,
+        client: client_address,
+        kind,
+        target: path.to_string(),
+        size: meta.size,
+        mtime: meta.mtime,
+        sha256: meta.sha256_hex(),
+        packets: meta.total_packets,
+      });
+    }
+    self.store_packets(client_address, session, packets);
+    datagrams.push(end_of_transmission(client_address));
+    datagrams
+  }
+
+  // Guarda os pacotes da resposta para retransmissão, substituindo os da resposta anterior ao mesmo
+  // cliente. Separar por cliente permite transferências simultâneas (de clientes diferentes ou de
+  // vários sockets do mesmo cliente) sem que uma retransmissão entregue pacotes de outro arquivo.
+  fn store_packets(&self, client_address: SocketAddr, session: u64, new_packets: Vec<UdpPacket>) {
+    let packets = new_packets.into_iter().map(|packet| (packet.seq_number, packet)).collect();
+    let previous = self.sessions.responses.lock().unwrap().insert(client_address, StoredSession::new(session, packets));
+    if let Some(previous) = previous {
+      self.journal.end(previous.id);
+    }
+  }
+
+  // Verifica os limites antes de abrir uma sessão, encerrando sessões quietas se preciso (ver
+  // `limits`). A sessão anterior do cliente na mesma tabela vai ser substituída e não conta. Devolve
+  // o erro BUSY para o cliente quando a sessão não pode ser aberta.
+  pub(crate) fn admit_session(&self, new: SessionKey, bytes: usize) -> Result<(), Vec<u8>> {
+    let (SessionKey::Response(client_address) | SessionKey::Upload(client_address)) = new;
+    let now = Instant::now();
+    let mut sessions = self.sessions.responses.lock().unwrap();
+    let mut occupants: Vec<Occupant<SessionKey>> = sessions
+      .iter()
+      .map(|(address, session)| Occupant {
+        key: SessionKey::Response(*address),
+        peer: address.ip(),
+        last_activity: session.last_activity,
+        bytes: session.bytes,
+      })
+      .collect();
+    occupants.extend(upload::active_sessions(self.uploads()).into_iter().map(|(address, last_activity, bytes)| Occupant {
+      key: SessionKey::Upload(address),
+      peer: address.ip(),
+      last_activity,
+      bytes,
+    }));
+    occupants.retain(|occupant| occupant.key != new);
+
+    let evicted = match limits::make_room(&self.limits, occupants, client_address.ip(), bytes, now) {
+      Ok(evicted) => evicted,
+      Err(refusal) => {
+        info!(limit = refusal.name(), "session refused, server busy");
+        return Err(error_message(ErrorCode::Busy, refusal.message(), client_address));
+      },
+    };
+    let mut ended = Vec::new();
+    for key in evicted {
+      match key {
+        SessionKey::Response(address) => {
+          if let Some(session) = sessions.remove(&address) {
+            info!(session = session.id, peer = %address, "quiet session evicted to make room");
+            ended.push(session.id);
+          }
+        },
+        SessionKey::Upload(address) => upload::evict(self.uploads(), address),
+      }
+    }
+    drop(sessions);
+    ended.into_iter().for_each(|session| self.journal.end(session));
+    Ok(())
+  }
+
+  // Encerra as sessões expiradas por inatividade ou pelo tempo máximo (ver `limits`). A varredura
+  // acontece no tratamento das requisições, no máximo uma vez por `SWEEP_INTERVAL`.
+  fn expire_sessions(&self, now: Instant) {
+    {
+      let mut last_sweep = self.sessions.last_sweep.lock().unwrap();
+      if now.saturating_duration_since(*last_sweep) < SWEEP_INTERVAL {
+        return;
+      }
+      *last_sweep = now;
+    }
+    let mut ended = Vec::new();
+    self.sessions.responses.lock().unwrap().retain(|address, session| match self.limits.expired(session.created, session.last_activity, now) {
+      Some(reason) => {
+        info!(session = session.id, peer = %address, reason, "session expired");
+        ended.push(session.id);
+        false
+      },
+      None => true,
+    });
+    ended.into_iter().for_each(|session| self.journal.end(session));
+    upload::expire_sessions(self.uploads(), &self.limits, now);
+  }
+
+  fn handle_retransmission_request(&self, request: &str, client_address: SocketAddr) -> Vec<Vec<u8>> {
+    let mut sequences: Vec<u32> = request.trim_start_matches("RETRANSMIT ")
+                                        .split(',')
+                                        .filter_map(|s| s.parse::<u32>().ok())
+                                        .collect();
+    sequences.sort_unstable();
+    sequences.dedup();
+
+    let mut datagrams = Vec::new();
+    let mut acked = Vec::new();
+    let mut sessions = self.sessions.responses.lock().unwrap();
+    let session = sessions.get_mut(&client_address).map(|session| {
+      session.last_activity = Instant::now();
+      Span::current().record("session", session.id);
+      debug!(requested = sequences.len(), "retransmission requested");
+      // Os pacotes entre dois pedidos consecutivos já chegaram e não serão pedidos de novo.
+      acked.extend(acked_ranges(&sequences).into_iter().filter_map(|(first, last)| session.remove_range(first, last)));
+      for &seq_number in &sequences {
+        if let Some(packet) = session.packets.get(&seq_number) {
+          trace!(seq = seq_number, bytes = packet.data.len(), "retransmitting packet");
+          datagrams.push(packet.serialize());
+        } else {
+          debug!(seq = seq_number, "packet not stored, cannot retransmit");
+        }
+      }
+      session.id
+    });
+    drop(sessions);
+    if let Some(session) = session {
+      self.journal.acked(session, &acked);
+    }
+
+    if datagrams.is_empty() {
+      // Nenhum dos pacotes pedidos está guardado: a transferência não existe mais no servidor.
+      return vec![error_message(ErrorCode::SessionUnknown, "Nenhum dos pacotes pedidos está disponível", client_address)];
+    }
+    datagrams.push(end_of_transmission(client_address));
+    datagrams
+  }
+}
+
+// Listagem em JSON do diretório pedido (`?hash=1` inclui o SHA-256 dos arquivos).
+fn list_directory(source: &dyn FileSource, path: &str) -> io::Result<Vec<u8>> {
+  let with_hash = query_param(path, "hash").is_some_and(|value| value == "1");
+  validate_path(request_path(path))
+    .and_then(|dir| source.list(dir, with_hash))
+    .and_then(|entries| listing::encode(&entries))
+}
+
+// Metadados de um arquivo (STAT): responde com um único pacote no formato do cabeçalho de um GET,
+// sem transferir o conteúdo. O número de pacotes considera o tamanho de bloco pedido (`?chunk=`).
+fn handle_stat_request(source: &dyn FileSource, request: &str, client_address: SocketAddr) -> Vec<Vec<u8>> {
+  let path = match request.split_whitespace().nth(1) {
+    Some(path) => path,
+    None => return vec![error_message(ErrorCode::BadRequest, "Requisição mal formatada", client_address)],
+  };
+  let filename = request_path(path);
+  if filename.is_empty() {
+    return vec![error_message(ErrorCode::BadRequest, "Nome do arquivo não especificado", client_address)];
+  }
+
+  if let Err(e) = validate_path(filename) {
+    return vec![error_message(ErrorCode::Forbidden, &e.to_string(), client_address)];
+  }
+  if source.kind(filename).is_ok_and(|kind| kind == EntryKind::Dir) {
+    return vec![error_message(ErrorCode::BadRequest, "O caminho é um diretório", client_address)];
+  }
+  match source.read(filename) {
+    Ok(file) => {
+      let meta = FileMeta::new(&file.data, requested_chunk_size(path), file.mtime);
+      vec![meta.header_packet(SERVER_PORT, client_address.port()).serialize()]
+    },
+    Err(e) if e.kind() == io::ErrorKind::NotFound => vec![error_message(ErrorCode::NotFound, "Arquivo não encontrado", client_address)],
+    Err(e) => {
+      warn!(error = %e, "failed to read file");
+      vec![error_message(io_error_code(&e), &format!("Erro ao ler o arquivo: {}", e), client_address)]
+    }
+  }
+}
+
+// Pacotes da resposta: o cabeçalho e os dados, comprimidos se o cliente pediu.
+fn prepare_response(data: Vec<u8>, path: &str, mtime: u64, client_address: SocketAddr) -> io::Result<Vec<UdpPacket>> {
+  let chunk_size = requested_chunk_size(path);
+  match requested_compression(path) {
+    Compression::None => Ok(UdpPacket::prepare_packets(SERVER_PORT, client_address.port(), data, chunk_size, mtime)),
+    codec => {
+      let packets = compression::prepare_packets(SERVER_PORT, client_address.port(), &data, chunk_size, mtime, codec)?;
+      let sent: usize = packets.iter().skip(1).map(|packet| packet.data.len()).sum();
+      debug!(codec = codec.as_str(), bytes = data.len(), compressed = sent, packets = packets.len() - 1, "response compressed");
+      Ok(packets)
+    },
+  }
+}
+
+// Gera de novo os pacotes de uma sessão do diário, desde que o conteúdo seja o mesmo da entrega.
+fn rebuild_packets(source: &dyn FileSource, record: &SessionRecord) -> Option<Vec<UdpPacket>> {
+  let path = record.target.as_str();
+  let (data, mtime) = match record.kind {
+    ContentKind::File => source.read(request_path(path)).map(|file| (file.data, file.mtime)).ok()?,
+    ContentKind::Listing => (list_directory(source, path).ok()?, 0),
+  };
+  let packets = prepare_response(data, path, mtime, record.client).ok()?;
+  let meta = FileMeta::decode(&packets.first()?.data)?;
+  let unchanged = meta.size == record.size
+    && meta.mtime == record.mtime
+    && meta.total_packets == record.packets
+    && meta.sha256_hex() == record.sha256;
+  unchanged.then_some(packets)
+}
+
+// Tamanho de bloco negociado pelo cliente (`?chunk=`), limitado ao intervalo aceito pelo servidor.
+pub(crate) fn requested_chunk_size(path: &str) -> usize {
+  query_param(path, "chunk")
+    .and_then(|value| value.parse::<usize>().ok())
+    .map(|value| value.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE))
+    .unwrap_or(CHUNK_SIZE)
+}
+
+// Compressão pedida pelo cliente (`?compress=`). Formatos desconhecidos são ignorados: o cabeçalho
+// informa ao cliente que os dados foram enviados sem compressão.
+fn requested_compression(path: &str) -> Compression {
+  query_param(path, "compress").and_then(Compression::parse).unwrap_or(Compression::None)
+}
+
+// Caminho pedido, sem a barra inicial e sem a query string.
+pub(crate) fn request_path(path: &str) -> &str {
+  let path = path.strip_prefix('/').unwrap_or(path);
+  match path.find('?') {
+    Some(idx) => &path[..idx],
+    None => path,
+  }
+}
+
+// Valor de um parâmetro da query string, por exemplo `chunk` em `/arquivo?start=1&chunk=1400`.
+pub fn query_param<'a>(path: &'a str, name: &str) -> Option<&'a str> {
+  let (_, query) = path.split_once('?')?;
+  query
+    .split('&')
+    .filter_map(|pair| pair.split_once('='))
+    .find(|(key, _)| *key == name)
+    .map(|(_, value)| value)
+}
+
+// Sonda de PMTU: o cliente envia `PROBE <tamanho>` completado com zeros até o tamanho anunciado;
+// a confirmação tem o mesmo tamanho e sai com o bit DF forçado (ver `BatchSender::send_some`), para
+// sondar também o caminho de volta.
+fn handle_probe_request(request: &str) -> Vec<Vec<u8>> {
+  let announced = request
+    .trim_start_matches("PROBE ")
+    .split(|c: char| !c.is_ascii_digit())
+    .next()
+    .and_then(|size| size.parse::<usize>().ok());
+  match announced {
+    // Sondas menores que a própria confirmação não são respondidas.
+    Some(size) if size == request.len() => Some(pmtud::probe_ack(size)).filter(|ack| ack.len() == size).into_iter().collect(),
+    _ => Vec::new(),
+  }
+}
+
+// Bytes de dados dos pacotes, sem o cabeçalho (seq 0).
+fn data_len(packets: &[UdpPacket]) -> usize {
+  packets.iter().skip(1).map(|packet| packet.data.len()).sum()
+}
+
+fn end_of_transmission(destination: SocketAddr) -> Vec<u8> {
+  UdpPacket::end_of_transmission(SERVER_PORT, destination.port()).serialize()
+}
+
+// Intervalos confirmados implicitamente por um RETRANSMIT. O cliente pede, em ordem crescente e em
+// mensagens de tamanho limitado, todos os pacotes que lhe faltam; dentro de uma mensagem, os números
+// que ficam entre dois pedidos consecutivos já chegaram. Nada se conclui fora desses limites, pois
+// outras mensagens da mesma rodada podem ter se perdido ou chegado fora de ordem.
+fn acked_ranges(sorted_sequences: &[u32]) -> Vec<(u32, u32)> {
+  sorted_sequences
+    .windows(2)
+    .filter(|pair| pair[1] > pair[0] + 1)
+    .map(|pair| (pair[0] + 1, pair[1] - 1))
+    .collect()
+}
+
+// Pacote de erro para o cliente, com o código tipado e uma mensagem para humanos.
+pub(crate) fn error_message(code: ErrorCode, message: &str, destination: SocketAddr) -> Vec<u8> {
+  UdpPacket::error(SERVER_PORT, destination.port(), code, message).serialize()
+}
+
+// Código de erro correspondente a uma falha de E/S no servidor.
+pub(crate) fn io_error_code(e: &io::Error) -> ErrorCode {
+  match e.kind() {
+    io::ErrorKind::NotFound => ErrorCode::NotFound,
+    io::ErrorKind::PermissionDenied => ErrorCode::Forbidden,
+    io::ErrorKind::AlreadyExists => ErrorCode::AlreadyExists,
+    io::ErrorKind::InvalidData => ErrorCode::IntegrityFailure,
+    io::ErrorKind::InvalidInput => ErrorCode::BadRequest,
+    _ => ErrorCode::Internal,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, fs, process};
+
+  use super::*;
+  use crate::{protocol::Datagram, source::MemorySource};
+
+  fn stored(packets: u32) -> StoredSession {
+    let packets = (0..=packets).map(|seq| (seq, UdpPacket::data_packet(seq, SERVER_PORT, 4000, vec![0; 100]))).collect();
+    StoredSession::new(1, packets)
+  }
+
+  fn handler_with(data: Vec<u8>) -> Handler {
+    Handler::new(Arc::new(MemorySource::new().with_file("a.bin", data)))
+  }
+
+  fn client() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 4000))
+  }
+
+  #[test]
+  fn acked_ranges_are_the_gaps_between_requests() {
+    assert_eq!(acked_ranges(&[]), vec![]);
+    assert_eq!(acked_ranges(&[7]), vec![]);
+    assert_eq!(acked_ranges(&[2, 3, 4]), vec![]);
+    assert_eq!(acked_ranges(&[2, 5, 6, 10]), vec![(3, 4), (7, 9)]);
+    assert_eq!(acked_ranges(&[0, u32::MAX]), vec![(1, u32::MAX - 1)]);
+  }
+
+  #[test]
+  fn remove_range_is_clamped_to_the_stored_packets() {
+    let mut session = stored(10);
+    assert_eq!(session.remove_range(0, u32::MAX), Some((1, 10)));
+    // O cabeçalho (seq 0) fica guardado.
+    assert_eq!(session.packets.len(), 1);
+    assert_eq!(session.bytes, 100);
+    assert_eq!(session.remove_range(1, 10), None);
+
+    let mut session = stored(10);
+    assert_eq!(session.remove_range(11, 20), None);
+    assert_eq!(session.remove_range(4, 6), Some((4, 6)));
+    assert_eq!(session.packets.len(), 8);
+  }
+
+  #[test]
+  fn retransmission_sends_the_requested_packets_and_forgets_acked_ones() {
+    let handler = handler_with(vec![7; 10_000]);
+    let response = handler.handle(b"GET /a.bin?chunk=1000", client());
+    // Cabeçalho, 10 pacotes de dados e o fim de transmissão.
+    assert_eq!(response.len(), 12);
+
+    let retransmitted = handler.handle(b"RETRANSMIT 2,9", client());
+    assert_eq!(retransmitted.len(), 3);
+    assert!(matches!(Datagram::decode(&retransmitted[0]), Datagram::Data { seq_number: 2, .. }));
+    // Os pacotes entre 2 e 9 foram confirmados e não são mais reenviados.
+    let forgotten = handler.handle(b"RETRANSMIT 5", client());
+    assert_eq!(decode_error(&forgotten[0]).map(|(code, _)| code), Some(ErrorCode::SessionUnknown));
+  }
+
+  #[test]
+  fn huge_retransmit_ranges_answer_immediately() {
+    let handler = handler_with(vec![7; 10_000]);
+    handler.handle(b"GET /a.bin?chunk=1000", client());
+    let started = Instant::now();
+    let response = handler.handle(b"RETRANSMIT 1,4294967295", client());
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(response.len(), 2);
+  }
+
+  #[test]
+  fn sessions_are_recovered_from_the_journal() {
+    let dir = env::temp_dir().join(format!("rsudp-handler-journal-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let data = vec![3; 10_000];
+    let before = handler_with(data.clone()).with_journal(Journal::open(&dir).unwrap());
+    before.handle(b"GET /a.bin?chunk=1000", client());
+    before.handle(b"RETRANSMIT 2,9", client());
+
+    // Um novo `Handler` (o servidor reiniciado) continua a transferência.
+    let after = handler_with(data).with_journal(Journal::open(&dir).unwrap());
+    after.recover_sessions();
+    let response = after.handle(b"RETRANSMIT 1,10", client());
+    assert_eq!(response.len(), 3);
+    let forgotten = after.handle(b"RETRANSMIT 5", client());
+    assert_eq!(decode_error(&forgotten[0]).map(|(code, _)| code), Some(ErrorCode::SessionUnknown));
+    // `Handler`s independentes não compartilham sessões.
+    let other = handler_with(vec![3; 10_000]);
+    assert_eq!(decode_error(&other.handle(b"RETRANSMIT 1", client())[0]).map(|(code, _)| code), Some(ErrorCode::SessionUnknown));
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,409 @@
+// Inspetor do protocolo: decodifica os datagramas de uma captura pcap, ou escutados passivamente em
+// uma porta, com o mesmo codec do cliente e do servidor (`protocol::Datagram`), uma linha por
+// datagrama. No final resume cada transferência: pacotes esperados e recebidos, retransmissões,
+// lacunas e falhas de checksum.
+//
+// Uso:
+//   rsudp-inspect captura.pcap [--port 8083] [--summary]
+//   rsudp-inspect --listen 8083 [--count N] [--summary]    (Linux, requer CAP_NET_RAW; Ctrl+C encerra)
+//
+// Uma transferência começa em cada GET, LIST, PUT ou DELTA e reúne os datagramas trocados entre o
+// mesmo par cliente/servidor até a próxima requisição desse par. STAT e PROBE não abrem
+// transferência.
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+use rawsocket_udp::capture::{CapturedDatagram, PcapReader};
+use rawsocket_udp::handler::query_param;
+use rawsocket_udp::protocol::{Datagram, CHUNK_SIZE, MAX_CHUNK_SIZE, MIN_CHUNK_SIZE, SERVER_PORT};
+
+// Flags seguidas de um valor, para localizar o arquivo entre os argumentos.
+const VALUE_FLAGS: [&str; 3] = ["--port", "--listen", "--count"];
+// Quantidade máxima de lacunas listadas por transferência.
+const MAX_GAPS_SHOWN: usize = 20;
+
+fn main() -> io::Result<()> {
+  let args: Vec<String> = env::args().skip(1).collect();
+  let mut inspector = Inspector::new(!args.iter().any(|arg| arg == "--summary"));
+
+  if let Some(port) = flag_value(&args, "--listen") {
+    let count = flag_value(&args, "--count").map(|count| parse_flag(count, "--count")).transpose()?;
+    listen(parse_flag(port, "--listen")?, count, &mut inspector)?;
+  } else {
+    let path = match capture_path(&args) {
+      Some(path) => path,
+      None => {
+        eprintln!("uso: rsudp-inspect <captura.pcap> [--port N] [--summary]");
+        eprintln!("     rsudp-inspect --listen PORTA [--count N] [--summary]");
+        process::exit(2);
+      },
+    };
+    let port = match flag_value(&args, "--port") {
+      Some(port) => parse_flag(port, "--port")?,
+      None => SERVER_PORT,
+    };
+    for datagram in PcapReader::open(Path::new(path))? {
+      let datagram = datagram?;
+      if datagram.source.port() == port || datagram.destination.port() == port {
+        inspector.inspect(&datagram);
+      }
+    }
+  }
+  inspector.report();
+  Ok(())
+}
+
+// Transferência observada: uma requisição e os datagramas que a atendem.
+struct Transfer {
+  id: usize,
+  client: SocketAddr,
+  server: SocketAddr,
+  request: String,
+  // Os dados vão do cliente para o servidor (PUT e DELTA) em vez do contrário.
+  upload: bool,
+  // Primeiro número de sequência dos dados: o cabeçalho (0) nos downloads, 1 nos uploads.
+  first_seq: u32,
+  // Pacotes anunciados pelo cabeçalho ou calculados a partir do tamanho do PUT.
+  expected: Option<u32>,
+  // Cópias recebidas de cada pacote com checksum válido.
+  packets: BTreeMap<u32, u32>,
+  bytes: u64,
+  bad_checksums: u32,
+  retransmit_requests: u32,
+  retransmit_seqs: u32,
+  // PUT-OK ou o erro do servidor.
+  outcome: Option<String>,
+  started: Duration,
+  last: Duration,
+}
+
+impl Transfer {
+  fn new(id: usize, client: SocketAddr, server: SocketAddr, command: &str, argument: &str, now: Duration) -> Transfer {
+    let upload = matches!(command, "PUT" | "DELTA");
+    // O PUT informa o tamanho do arquivo; o DELTA, o das assinaturas que ainda vão ser geradas.
+    let expected = (command == "PUT")
+      .then(|| query_param(argument, "size").and_then(|size| size.parse::<u64>().ok()))
+      .flatten()
+      .map(|size| u32::try_from(size.div_ceil(upload_chunk_size(argument) as u64)).unwrap_or(u32::MAX));
+    Transfer {
+      id,
+      client,
+      server,
+      request: format!("{} {}", command, argument),
+      upload,
+      first_seq: if upload { 1 } else { 0 },
+      expected,
+      packets: BTreeMap::new(),
+      bytes: 0,
+      bad_checksums: 0,
+      retransmit_requests: 0,
+      retransmit_seqs: 0,
+      outcome: None,
+      started: now,
+      last: now,
+    }
+  }
+
+  // Registra um datagrama da transferência. Devolve `true` para pacotes de dados já recebidos antes
+  // (retransmissões ou duplicatas).
+  fn record(&mut self, datagram: &Datagram, from_client: bool, now: Duration) -> bool {
+    self.last = now;
+    match datagram {
+      Datagram::Header(Some(meta)) if !from_client && !self.upload => {
+        self.expected = Some(meta.total_packets);
+        self.insert(0, 0)
+      },
+      Datagram::Data { checksum_valid: false, .. } => {
+        self.bad_checksums += 1;
+        false
+      },
+      Datagram::Data { seq_number, payload, .. } if from_client == self.upload => {
+        self.insert(*seq_number, payload.len())
+      },
+      Datagram::Text { command: "RETRANSMIT", argument } => {
+        self.retransmit_requests += 1;
+        self.retransmit_seqs += argument.split(',').filter(|seq| !seq.is_empty()).count() as u32;
+        false
+      },
+      Datagram::Text { command: "PUT-OK", argument } => {
+        self.outcome = Some(format!("PUT-OK {}", argument));
+        false
+      },
+      Datagram::Error { code, message, .. } if !from_client => {
+        self.outcome = Some(format!("ERROR {} {}", code, message));
+        false
+      },
+      _ => false,
+    }
+  }
+
+  fn insert(&mut self, seq_number: u32, len: usize) -> bool {
+    self.bytes += len as u64;
+    let copies = self.packets.entry(seq_number).or_insert(0);
+    *copies += 1;
+    *copies > 1
+  }
+
+  // Sequências faltantes, agrupadas em intervalos. Sem total anunciado, considera até o maior
+  // número de sequência visto. O total vem da captura e pode ser absurdo, então as lacunas são
+  // calculadas entre os pacotes vistos, sem percorrer cada número.
+  fn gaps(&self) -> Vec<(u32, u32)> {
+    let end = match self.expected {
+      Some(expected) => self.first_seq.saturating_add(expected),
+      None => self.packets.keys().next_back().map_or(self.first_seq, |last| last.saturating_add(1)),
+    };
+    let mut gaps: Vec<(u32, u32)> = Vec::new();
+    let mut next = self.first_seq;
+    for &seq in self.packets.range(self.first_seq..end).map(|(seq, _)| seq) {
+      if seq > next {
+        gaps.push((next, seq - 1));
+      }
+      next = seq + 1;
+    }
+    if next < end {
+      gaps.push((next, end - 1));
+    }
+    gaps
+  }
+
+  // Terminou: confirmada ou recusada pelo servidor, ou um download com todos os pacotes.
+  fn finished(&self) -> bool {
+    self.outcome.is_some() || (!self.upload && self.expected.is_some() && self.gaps().is_empty())
+  }
+
+  fn report(&self) {
+    let elapsed = self.last.saturating_sub(self.started).as_secs_f64();
+    println!("#{} {}  {} <-> {}  {:.3} s", self.id, self.request, self.client, self.server, elapsed);
+    match self.expected {
+      Some(expected) => println!("  pacotes: {}/{} recebidos, {} bytes", self.packets.len(), expected, self.bytes),
+      None => println!("  pacotes: {} recebidos (total não anunciado), {} bytes", self.packets.len(), self.bytes),
+    }
+    let repeated: u32 = self.packets.values().map(|copies| copies - 1).sum();
+    println!(
+      "  retransmissões: {} pacote(s) repetido(s), {} RETRANSMIT pedindo {} pacote(s)",
+      repeated, self.retransmit_requests, self.retransmit_seqs
+    );
+    if self.bad_checksums > 0 {
+      println!("  checksum inválido: {} pacote(s)", self.bad_checksums);
+    }
+    let gaps = self.gaps();
+    if !gaps.is_empty() {
+      let missing: u64 = gaps.iter().map(|(first, last)| u64::from(last - first) + 1).sum();
+      let mut shown: Vec<String> = gaps
+        .iter()
+        .take(MAX_GAPS_SHOWN)
+        .map(|(first, last)| if first == last { first.to_string() } else { format!("{}-{}", first, last) })
+        .collect();
+      if gaps.len() > MAX_GAPS_SHOWN {
+        shown.push("…".to_string());
+      }
+      println!("  lacunas: {} ({} pacote(s))", shown.join(", "), missing);
+    }
+    let outcome = match &self.outcome {
+      Some(outcome) => outcome.as_str(),
+      None if self.finished() => "completo",
+      None => "incompleto",
+    };
+    println!("  resultado: {}", outcome);
+  }
+}
+
+struct Inspector {
+  // Imprime cada datagrama; sem isso, apenas o resumo final.
+  print_datagrams: bool,
+  first_timestamp: Option<Duration>,
+  datagrams: u64,
+  transfers: Vec<Transfer>,
+  // Transferência em andamento de cada par (cliente, servidor).
+  current: HashMap<(SocketAddr, SocketAddr), usize>,
+}
+
+impl Inspector {
+  fn new(print_datagrams: bool) -> Inspector {
+    Inspector { print_datagrams, first_timestamp: None, datagrams: 0, transfers: Vec::new(), current: HashMap::new() }
+  }
+
+  fn inspect(&mut self, captured: &CapturedDatagram) {
+    let first_timestamp = *self.first_timestamp.get_or_insert(captured.timestamp);
+    self.datagrams += 1;
+    let datagram = Datagram::decode(&captured.payload);
+    let (session, repeated) = self.track(captured.source, captured.destination, &datagram, captured.timestamp);
+    if !self.print_datagrams {
+      return;
+    }
+    println!(
+      "{:>11.6}  {} -> {}  {:>4}  len={:<5}  {}{}",
+      captured.timestamp.saturating_sub(first_timestamp).as_secs_f64(),
+      captured.source,
+      captured.destination,
+      session.map_or("-".to_string(), |id| format!("#{}", id)),
+      captured.payload.len(),
+      describe(&datagram),
+      if repeated { "  (repetido)" } else { "" }
+    );
+  }
+
+  // Associa o datagrama a uma transferência, abrindo uma nova a cada requisição. Devolve o número
+  // da transferência e se o pacote de dados é repetido.
+  fn track(&mut self, source: SocketAddr, destination: SocketAddr, datagram: &Datagram, now: Duration) -> (Option<usize>, bool) {
+    match datagram {
+      Datagram::Text { command: command @ ("GET" | "LIST" | "PUT" | "DELTA"), argument } => {
+        // Uma requisição repetida (a resposta se perdeu) continua a mesma transferência.
+        let request = format!("{} {}", command, argument);
+        let repeated_request = self
+          .current
+          .get(&(source, destination))
+          .map(|&index| &self.transfers[index])
+          .is_some_and(|transfer| transfer.request == request && !transfer.finished());
+        if !repeated_request {
+          let id = self.transfers.len() + 1;
+          self.transfers.push(Transfer::new(id, source, destination, command, argument, now));
+          self.current.insert((source, destination), id - 1);
+        }
+      },
+      Datagram::Text { command: "STAT" | "PROBE" | "DISCOVER", .. } => {
+        self.current.remove(&(source, destination));
+      },
+      _ => {},
+    }
+
+    let (index, from_client) = match (self.current.get(&(source, destination)), self.current.get(&(destination, source))) {
+      (Some(&index), _) => (index, true),
+      (None, Some(&index)) => (index, false),
+      (None, None) => return (None, false),
+    };
+    let transfer = &mut self.transfers[index];
+    let repeated = transfer.record(datagram, from_client, now);
+    (Some(transfer.id), repeated)
+  }
+
+  fn report(&self) {
+    if self.print_datagrams && self.datagrams > 0 {
+      println!();
+    }
+    println!("{} datagrama(s), {} transferência(s)", self.datagrams, self.transfers.len());
+    for transfer in &self.transfers {
+      transfer.report();
+    }
+  }
+}
+
+// Descrição de um datagrama: tipo, número de sequência, checksum e, nos erros, a mensagem.
+fn describe(datagram: &Datagram) -> String {
+  let checksum = |valid: bool| if valid { "ok" } else { "INVÁLIDO" };
+  match datagram {
+    Datagram::Text { command, argument } => format!("{} {}", command, argument).trim_end().to_string(),
+    Datagram::Header(Some(meta)) => format!(
+      "HEADER seq=0 packets={} size={} chunk={} compression={} sha256={}",
+      meta.total_packets,
+      meta.size,
+      meta.chunk_size,
+      meta.compression.as_str(),
+      meta.sha256_hex()
+    ),
+    Datagram::Header(None) => "HEADER seq=0 (metadados inválidos)".to_string(),
+    Datagram::Data { seq_number, payload, checksum_valid } => {
+      format!("DATA seq={} payload={} checksum={}", seq_number, payload.len(), checksum(*checksum_valid))
+    },
+    Datagram::EndOfTransmission => "EOT".to_string(),
+    Datagram::Error { code, message, checksum_valid } => {
+      format!("ERROR {} \"{}\" checksum={}", code, message, checksum(*checksum_valid))
+    },
+    Datagram::Unknown => "desconhecido".to_string(),
+  }
+}
+
+// Tamanho dos pacotes de um upload, como o servidor o interpreta (`?chunk=`).
+fn upload_chunk_size(target: &str) -> usize {
+  query_param(target, "chunk")
+    .and_then(|value| value.parse::<usize>().ok())
+    .map(|value| value.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE))
+    .unwrap_or(CHUNK_SIZE)
+}
+
+// Escuta passiva até Ctrl+C ou até `count` datagramas.
+#[cfg(target_os = "linux")]
+fn listen(port: u16, count: Option<u64>, inspector: &mut Inspector) -> io::Result<()> {
+  use rawsocket_udp::capture::Sniffer;
+
+  let mut sniffer = Sniffer::open(port).map_err(|e| match e.kind() {
+    io::ErrorKind::PermissionDenied => io::Error::new(e.kind(), "a escuta passiva requer root ou CAP_NET_RAW"),
+    _ => e,
+  })?;
+  interrupt::install();
+  eprintln!("escutando a porta {} (Ctrl+C encerra)", port);
+  while count.is_none_or(|count| inspector.datagrams < count) {
+    match sniffer.next_datagram() {
+      Ok(datagram) => inspector.inspect(&datagram),
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+        if interrupt::requested() {
+          break;
+        }
+      },
+      Err(e) => return Err(e),
+    }
+  }
+  // Sem isso, pacotes perdidos pela própria escuta apareceriam como lacunas das transferências.
+  let dropped = sniffer.dropped()?;
+  if dropped > 0 {
+    eprintln!("{} datagrama(s) descartados pelo kernel durante a escuta; as lacunas podem não ser reais", dropped);
+  }
+  Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn listen(_port: u16, _count: Option<u64>, _inspector: &mut Inspector) -> io::Result<()> {
+  Err(io::Error::new(io::ErrorKind::Unsupported, "a escuta passiva só está disponível no Linux"))
+}
+
+// Ctrl+C (ou SIGTERM) encerra a escuta e imprime o resumo. O tratador é instalado sem SA_RESTART,
+// para que a leitura bloqueada no socket volte com EINTR.
+#[cfg(target_os = "linux")]
+mod interrupt {
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::{mem, ptr};
+
+  static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+  extern "C" fn on_signal(_signal: libc::c_int) {
+    REQUESTED.store(true, Ordering::Relaxed);
+  }
+
+  pub fn install() {
+    unsafe {
+      let mut action: libc::sigaction = mem::zeroed();
+      action.sa_sigaction = on_signal as *const () as libc::sighandler_t;
+      libc::sigemptyset(&mut action.sa_mask);
+      libc::sigaction(libc::SIGINT, &action, ptr::null_mut());
+      libc::sigaction(libc::SIGTERM, &action, ptr::null_mut());
+    }
+  }
+
+  pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+  }
+}
+
+// Primeiro argumento que não é uma flag nem o valor de uma.
+fn capture_path(args: &[String]) -> Option<&str> {
+  args
+    .iter()
+    .enumerate()
+    .find(|(idx, arg)| !arg.starts_with("--") && (*idx == 0 || !VALUE_FLAGS.contains(&args[idx - 1].as_str())))
+    .map(|(_, arg)| arg.as_str())
+}
+
+fn parse_flag<T: FromStr>(value: &str, flag: &str) -> io::Result<T> {
+  value.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{} inválido", flag)))
+}
+
+// Valor do argumento que segue a flag informada, por exemplo `--port 8083`.
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+  args.iter().position(|arg| arg == flag).and_then(|idx| args.get(idx + 1)).map(String::as_str)
+}
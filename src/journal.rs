@@ -0,0 +1,308 @@
+// Diário das sessões de retransmissão, para que um servidor reiniciado continue atendendo os
+// RETRANSMIT das transferências em andamento. Cada sessão tem um arquivo próprio, só de acréscimo,
+// com um registro JSON por linha: o primeiro identifica o conteúdo entregue (caminho pedido,
+// tamanho, mtime, SHA-256 e total de pacotes) e os seguintes acrescentam os intervalos de pacotes
+// que o cliente já confirmou. Os dados em si não são gravados: na recuperação os pacotes são
+// gerados de novo a partir da origem e a sessão é descartada se o conteúdo tiver mudado.
+//
+// Uma linha incompleta no fim do arquivo (queda no meio de uma escrita) é ignorada, e cada
+// registro é sincronizado com o disco antes de a resposta ser enviada. O diretório também é
+// sincronizado depois de criar ou apagar um arquivo, para que a entrada dele no diretório sobreviva
+// a uma queda.
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+// Extensão dos arquivos do diário, um por sessão (`<sessão>.journal`).
+const EXTENSION: &str = "journal";
+// Sessões mais antigas que isso não são recuperadas: o cliente já desistiu da transferência.
+const MAX_RECOVERY_AGE: Duration = Duration::from_secs(10 * 60);
+
+// Conteúdo entregue pela sessão, que determina como regenerar os pacotes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentKind {
+  // Arquivo da origem (GET).
+  File,
+  // Listagem de diretório em JSON (LIST).
+  Listing,
+}
+
+// Registro de abertura de uma sessão.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionRecord {
+  pub session: u64,
+  pub client: SocketAddr,
+  pub kind: ContentKind,
+  // Caminho como enviado pelo cliente, com a query string (tamanho de bloco, compressão, hash).
+  pub target: String,
+  pub size: u64,
+  pub mtime: u64,
+  pub sha256: String,
+  // Pacotes da resposta, incluindo o de cabeçalho.
+  pub packets: u32,
+}
+
+// Sessão lida do diário: o registro de abertura e os intervalos confirmados (inclusivos).
+#[derive(Clone, Debug)]
+pub struct RecoveredSession {
+  pub record: SessionRecord,
+  pub acked: Vec<(u32, u32)>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "lowercase")]
+enum Entry {
+  Open(SessionRecord),
+  Acked { ranges: Vec<(u32, u32)> },
+}
+
+// Diretório do diário, mantido aberto para ser sincronizado.
+struct Directory {
+  path: PathBuf,
+  // `None` onde diretórios não podem ser abertos como arquivos (Windows).
+  handle: Option<File>,
+}
+
+// Diário das sessões de um `Handler`. O padrão é o diário desligado, que não grava nem recupera nada.
+#[derive(Default)]
+pub struct Journal {
+  dir: Option<Directory>,
+}
+
+// Diretório padrão do diário, no diretório temporário do sistema.
+pub fn default_dir() -> PathBuf {
+  std::env::temp_dir().join("rawsocket-udp-journal")
+}
+
+impl Journal {
+  // Liga o diário no diretório informado, criando-o se preciso.
+  pub fn open(dir: &Path) -> io::Result<Journal> {
+    fs::create_dir_all(dir)?;
+    let handle = File::open(dir).ok();
+    Ok(Journal { dir: Some(Directory { path: dir.to_path_buf(), handle }) })
+  }
+
+  // Abre o arquivo de uma nova sessão. Falhas são registradas e não impedem a resposta: a sessão
+  // apenas não sobrevive a um reinício.
+  pub(crate) fn begin(&self, record: &SessionRecord) {
+    if let Some(path) = self.session_path(record.session) {
+      let result =
+        File::create(&path).and_then(|file| append(file, &Entry::Open(record.clone()))).and_then(|()| self.sync_dir());
+      if let Err(e) = result {
+        warn!(session = record.session, error = %e, "failed to write session journal");
+      }
+    }
+  }
+
+  // Acrescenta intervalos de pacotes confirmados pelo cliente.
+  pub(crate) fn acked(&self, session: u64, ranges: &[(u32, u32)]) {
+    if ranges.is_empty() {
+      return;
+    }
+    if let Some(path) = self.session_path(session) {
+      let result = OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .and_then(|file| append(file, &Entry::Acked { ranges: ranges.to_vec() }));
+      if let Err(e) = result {
+        warn!(session, error = %e, "failed to append to session journal");
+      }
+    }
+  }
+
+  // Encerra a sessão, apagando o seu arquivo.
+  pub(crate) fn end(&self, session: u64) {
+    if let Some(path) = self.session_path(session) {
+      match fs::remove_file(&path).and_then(|()| self.sync_dir()) {
+        Ok(()) => {},
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(e) => warn!(session, error = %e, "failed to delete session journal"),
+      }
+    }
+  }
+
+  // Lê as sessões deixadas por uma execução anterior. Arquivos ilegíveis ou antigos demais são
+  // apagados; de cada cliente fica apenas a sessão mais recente.
+  pub(crate) fn recover(&self) -> Vec<RecoveredSession> {
+    let dir = match &self.dir {
+      Some(dir) => &dir.path,
+      None => return Vec::new(),
+    };
+    let entries = match fs::read_dir(dir) {
+      Ok(entries) => entries,
+      Err(e) => {
+        warn!(dir = %dir.display(), error = %e, "failed to read journal directory");
+        return Vec::new();
+      },
+    };
+
+    let mut latest: HashMap<SocketAddr, RecoveredSession> = HashMap::new();
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+      if path.extension().and_then(|extension| extension.to_str()) != Some(EXTENSION) {
+        continue;
+      }
+      let recent = fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| SystemTime::now().duration_since(modified).unwrap_or_default() < MAX_RECOVERY_AGE);
+      let session = match read_session(&path) {
+        Ok(Some(session)) if recent => session,
+        result => {
+          if let Err(e) = result {
+            debug!(path = %path.display(), error = %e, "discarding unreadable session journal");
+          }
+          let _ = fs::remove_file(&path);
+          continue;
+        },
+      };
+      match latest.get(&session.record.client) {
+        Some(newer) if newer.record.session > session.record.session => self.end(session.record.session),
+        _ => {
+          if let Some(older) = latest.insert(session.record.client, session) {
+            self.end(older.record.session);
+          }
+        },
+      }
+    }
+    latest.into_values().collect()
+  }
+
+  fn session_path(&self, session: u64) -> Option<PathBuf> {
+    self.dir.as_ref().map(|dir| dir.path.join(format!("{}.{}", session, EXTENSION)))
+  }
+
+  // Sincroniza o diretório do diário, gravando as entradas de arquivos criados ou apagados.
+  fn sync_dir(&self) -> io::Result<()> {
+    match self.dir.as_ref().and_then(|dir| dir.handle.as_ref()) {
+      Some(handle) => handle.sync_all(),
+      None => Ok(()),
+    }
+  }
+}
+
+fn read_session(path: &Path) -> io::Result<Option<RecoveredSession>> {
+  let mut lines = BufReader::new(File::open(path)?).lines();
+  let record = match lines.next().transpose()?.map(|line| serde_json::from_str::<Entry>(&line)) {
+    Some(Ok(Entry::Open(record))) => record,
+    _ => return Ok(None),
+  };
+  let mut acked = Vec::new();
+  for line in lines {
+    // Uma linha que não pode ser lida só pode ser a última, interrompida por uma queda.
+    match serde_json::from_str::<Entry>(&line?) {
+      Ok(Entry::Acked { ranges }) => acked.extend(ranges),
+      _ => break,
+    }
+  }
+  Ok(Some(RecoveredSession { record, acked }))
+}
+
+// Grava o registro em uma única escrita e espera ele chegar ao disco.
+fn append(mut file: File, entry: &Entry) -> io::Result<()> {
+  let mut line = serde_json::to_vec(entry)?;
+  line.push(b'\n');
+  file.write_all(&line)?;
+  file.sync_data()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::process;
+
+  use super::*;
+
+  // Diretório exclusivo do teste, vazio.
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rsudp-journal-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+  }
+
+  fn record(session: u64, client: &str) -> SessionRecord {
+    SessionRecord {
+      session,
+      client: client.parse().unwrap(),
+      kind: ContentKind::File,
+      target: "/docs/a.bin?chunk=1400".to_string(),
+      size: 10_000,
+      mtime: 1_700_000_000,
+      sha256: "ab".repeat(32),
+      packets: 9,
+    }
+  }
+
+  #[test]
+  fn recover_keeps_the_latest_session_of_each_client() {
+    let dir = temp_dir("latest");
+    let journal = Journal::open(&dir).unwrap();
+    journal.begin(&record(1, "10.0.0.1:4000"));
+    journal.acked(1, &[(2, 5)]);
+    journal.begin(&record(2, "10.0.0.2:4000"));
+    journal.begin(&record(3, "10.0.0.1:4000"));
+    journal.acked(3, &[(1, 1)]);
+    journal.acked(3, &[]);
+    journal.acked(3, &[(4, 6), (8, 8)]);
+
+    let mut recovered = Journal::open(&dir).unwrap().recover();
+    recovered.sort_by_key(|session| session.record.session);
+    let sessions: Vec<(u64, Vec<(u32, u32)>)> = recovered.into_iter().map(|session| (session.record.session, session.acked)).collect();
+    assert_eq!(sessions, vec![(2, vec![]), (3, vec![(1, 1), (4, 6), (8, 8)])]);
+    // A sessão substituída do mesmo cliente é apagada.
+    assert!(!dir.join("1.journal").exists());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn recover_ignores_an_interrupted_last_line() {
+    let dir = temp_dir("interrupted");
+    let journal = Journal::open(&dir).unwrap();
+    journal.begin(&record(7, "10.0.0.1:4000"));
+    journal.acked(7, &[(1, 3)]);
+    OpenOptions::new().append(true).open(dir.join("7.journal")).unwrap().write_all(b"{\"record\":\"acked\",\"ran").unwrap();
+
+    let recovered = journal.recover();
+    assert_eq!(recovered.len(), 1);
+    assert_eq!(recovered[0].record.target, "/docs/a.bin?chunk=1400");
+    assert_eq!(recovered[0].acked, vec![(1, 3)]);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn recover_discards_unreadable_files() {
+    let dir = temp_dir("unreadable");
+    let journal = Journal::open(&dir).unwrap();
+    fs::write(dir.join("5.journal"), b"isto nao e json\n").unwrap();
+    fs::write(dir.join("notas.txt"), b"outro arquivo").unwrap();
+
+    assert!(journal.recover().is_empty());
+    assert!(!dir.join("5.journal").exists());
+    assert!(dir.join("notas.txt").exists());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn end_removes_the_session() {
+    let dir = temp_dir("end");
+    let journal = Journal::open(&dir).unwrap();
+    journal.begin(&record(4, "10.0.0.1:4000"));
+    assert!(dir.join("4.journal").exists());
+    journal.end(4);
+    journal.end(4);
+    assert!(journal.recover().is_empty());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn disabled_journal_records_nothing() {
+    let journal = Journal::default();
+    journal.begin(&record(1, "10.0.0.1:4000"));
+    journal.acked(1, &[(1, 2)]);
+    assert!(journal.recover().is_empty());
+  }
+}
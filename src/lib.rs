@@ -1,3 +1,33 @@
+#[macro_use]
+extern crate lazy_static;
+
+pub mod protocol;
+pub mod error;
+pub mod handler;
+pub mod source;
+pub mod service;
+pub mod upload;
+pub mod listing;
+pub mod delta;
+pub mod compression;
+pub mod batch;
+pub mod pmtud;
+pub mod transfer;
+pub mod logging;
+pub mod metrics;
+pub mod capture;
+pub mod journal;
+pub mod limits;
+pub mod shaping;
+pub mod acl;
+pub mod auth;
+pub mod multicast;
+pub mod discovery;
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+pub mod workers;
+#[cfg(feature = "tokio")]
+pub mod async_server;
+
 use sha2::Sha256;
 use digest::Digest;
 use std::fs::File;
@@ -15,7 +45,7 @@ pub fn calculate_hash(mut file: &File) -> String {
         hasher.update(&buffer[..bytes_read]);
       }
       Err(err) => {
-        eprintln!("Error reading file: {}", err);
+        tracing::warn!(error = %err, "error reading file");
         break;
       }
     }
@@ -27,7 +57,7 @@ pub fn calculate_hash(mut file: &File) -> String {
     .map(|byte| format!("{:02x}", byte))
     .collect();
 
-  println!("SHA-256 Hash: {}", hash_hex_string);
+  tracing::debug!(sha256 = %hash_hex_string, "file hashed");
 
   hash_hex_string
 }
@@ -0,0 +1,235 @@
+// Limites das sessões do servidor. Uma sessão é a resposta de um GET ou LIST guardada para
+// retransmissão, ou um upload (PUT, DELTA). Ela termina quando o cliente inicia outra
+// transferência do mesmo endereço, depois de `idle_timeout` sem atividade ou, mesmo ativa, depois
+// de `max_lifetime`; uploads concluídos deixam de contar para os limites, mas continuam guardados
+// até expirar para responder a um fim de transmissão repetido.
+//
+// Como o cliente não avisa quando um download termina, uma sessão concluída só se distingue de uma
+// em andamento pelo silêncio. Uma nova sessão que ultrapassaria um limite (sessões do mesmo IP,
+// sessões no total ou bytes guardados em memória) toma primeiro o lugar das sessões mais
+// antigas que estão quietas há pelo menos `EVICTION_GRACE`; sem sessões quietas suficientes, o
+// cliente recebe BUSY. Os limites valem para todas as sessões de um `Handler` e das cópias dele.
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// Silêncio a partir do qual uma sessão pode ser encerrada para dar lugar a outra: o dobro do tempo
+// que o cliente espera por pacotes atrasados antes de pedir retransmissão.
+pub const EVICTION_GRACE: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionLimits {
+  // Sessões sem atividade (requisições, retransmissões, pacotes de upload) por esse tempo expiram.
+  pub idle_timeout: Duration,
+  // Duração máxima de uma sessão, mesmo com atividade.
+  pub max_lifetime: Duration,
+  // Sessões simultâneas no total e de um mesmo endereço IP.
+  pub max_sessions: usize,
+  pub max_sessions_per_peer: usize,
+  // Bytes guardados em memória, somando todas as sessões: pacotes para retransmissão e, nos
+  // uploads, o mapa de pacotes recebidos e as assinaturas de um DELTA. Os dados de um PUT são
+  // gravados em arquivos temporários e não entram nessa conta.
+  pub max_buffered_bytes: usize,
+  // Maior tamanho anunciado por um PUT ou DELTA; maiores recebem TOO_LARGE antes de qualquer
+  // alocação.
+  pub max_upload_size: u64,
+}
+
+impl Default for SessionLimits {
+  fn default() -> SessionLimits {
+    SessionLimits {
+      idle_timeout: Duration::from_secs(30),
+      max_lifetime: Duration::from_secs(60 * 60),
+      max_sessions: 1024,
+      max_sessions_per_peer: 64,
+      max_buffered_bytes: 1024 * 1024 * 1024,
+      max_upload_size: 16 * 1024 * 1024 * 1024,
+    }
+  }
+}
+
+impl SessionLimits {
+  // Motivo da expiração de uma sessão, ou `None` se ela continua válida.
+  pub(crate) fn expired(&self, created: Instant, last_activity: Instant, now: Instant) -> Option<&'static str> {
+    if now.saturating_duration_since(last_activity) > self.idle_timeout {
+      Some("idle")
+    } else if now.saturating_duration_since(created) > self.max_lifetime {
+      Some("lifetime")
+    } else {
+      None
+    }
+  }
+}
+
+// Sessão existente, como vista na admissão de uma nova.
+pub(crate) struct Occupant<K> {
+  pub key: K,
+  pub peer: IpAddr,
+  pub last_activity: Instant,
+  // Bytes guardados para retransmissão.
+  pub bytes: usize,
+}
+
+// Limite que impediu a nova sessão.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Refusal {
+  PeerSessions,
+  Sessions,
+  Memory,
+}
+
+impl Refusal {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Refusal::PeerSessions => "peer_sessions",
+      Refusal::Sessions => "sessions",
+      Refusal::Memory => "memory",
+    }
+  }
+
+  // Mensagem do erro BUSY enviado ao cliente.
+  pub fn message(&self) -> &'static str {
+    match self {
+      Refusal::PeerSessions => "Limite de sessões por cliente atingido",
+      Refusal::Sessions => "Limite de sessões do servidor atingido",
+      Refusal::Memory => "Memória para retransmissão esgotada",
+    }
+  }
+}
+
+// Sessões que precisam ser encerradas para admitir uma nova sessão de `peer` com `bytes` guardados
+// para retransmissão. As candidatas são as quietas, da mais antiga para a mais recente; se elas não
+// bastarem, devolve o limite que impede a admissão.
+pub(crate) fn make_room<K: Copy>(
+  limits: &SessionLimits,
+  mut occupants: Vec<Occupant<K>>,
+  peer: IpAddr,
+  bytes: usize,
+  now: Instant,
+) -> Result<Vec<K>, Refusal> {
+  occupants.sort_by_key(|occupant| occupant.last_activity);
+  let quiet: Vec<bool> = occupants
+    .iter()
+    .map(|occupant| now.saturating_duration_since(occupant.last_activity) >= EVICTION_GRACE)
+    .collect();
+  let mut kept = vec![true; occupants.len()];
+
+  let peer_over = |kept: &[bool]| {
+    let sessions = occupants.iter().zip(kept).filter(|(occupant, kept)| **kept && occupant.peer == peer).count();
+    sessions >= limits.max_sessions_per_peer
+  };
+  if !evict_until(&occupants, &quiet, &mut kept, |occupant| occupant.peer == peer, peer_over) {
+    return Err(Refusal::PeerSessions);
+  }
+  let total_over = |kept: &[bool]| kept.iter().filter(|kept| **kept).count() >= limits.max_sessions;
+  if !evict_until(&occupants, &quiet, &mut kept, |_| true, total_over) {
+    return Err(Refusal::Sessions);
+  }
+  let memory_over = |kept: &[bool]| {
+    let buffered: usize = occupants.iter().zip(kept).filter(|(_, kept)| **kept).map(|(occupant, _)| occupant.bytes).sum();
+    buffered.saturating_add(bytes) > limits.max_buffered_bytes
+  };
+  if !evict_until(&occupants, &quiet, &mut kept, |occupant| occupant.bytes > 0, memory_over) {
+    return Err(Refusal::Memory);
+  }
+  Ok(occupants.iter().zip(kept).filter(|(_, kept)| !kept).map(|(occupant, _)| occupant.key).collect())
+}
+
+// Marca como encerradas, em ordem, as sessões quietas elegíveis até o limite deixar de ser
+// ultrapassado. Devolve se conseguiu.
+fn evict_until<K>(
+  occupants: &[Occupant<K>],
+  quiet: &[bool],
+  kept: &mut [bool],
+  eligible: impl Fn(&Occupant<K>) -> bool,
+  over: impl Fn(&[bool]) -> bool,
+) -> bool {
+  for index in 0..occupants.len() {
+    if !over(kept) {
+      return true;
+    }
+    if kept[index] && quiet[index] && eligible(&occupants[index]) {
+      kept[index] = false;
+    }
+  }
+  !over(kept)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn limits(max_sessions: usize, max_sessions_per_peer: usize, max_buffered_bytes: usize) -> SessionLimits {
+    SessionLimits { max_sessions, max_sessions_per_peer, max_buffered_bytes, ..SessionLimits::default() }
+  }
+
+  fn peer(last: u8) -> IpAddr {
+    IpAddr::from([10, 0, 0, last])
+  }
+
+  // Instante de referência à frente do relógio, para que `now - idle` exista mesmo logo depois do boot.
+  fn later() -> Instant {
+    Instant::now() + Duration::from_secs(3600)
+  }
+
+  // Sessão `key` de `peer`, quieta há `idle` segundos.
+  fn occupant(key: u32, peer: IpAddr, idle: u64, bytes: usize, now: Instant) -> Occupant<u32> {
+    Occupant { key, peer, last_activity: now - Duration::from_secs(idle), bytes }
+  }
+
+  #[test]
+  fn admits_without_eviction_below_the_limits() {
+    let now = later();
+    let occupants = vec![occupant(1, peer(1), 60, 100, now)];
+    assert_eq!(make_room(&limits(2, 2, 1000), occupants, peer(2), 100, now), Ok(vec![]));
+  }
+
+  #[test]
+  fn evicts_the_oldest_quiet_sessions_first() {
+    let now = later();
+    let occupants = vec![
+      occupant(1, peer(1), 20, 0, now),
+      occupant(2, peer(2), 50, 0, now),
+      occupant(3, peer(3), 1, 0, now),
+      occupant(4, peer(4), 30, 0, now),
+    ];
+    assert_eq!(make_room(&limits(3, 8, usize::MAX), occupants, peer(9), 0, now), Ok(vec![2, 4]));
+  }
+
+  #[test]
+  fn active_sessions_are_never_evicted() {
+    let now = later();
+    let quiet_for = EVICTION_GRACE.as_secs() - 1;
+    let occupants = vec![occupant(1, peer(1), quiet_for, 0, now), occupant(2, peer(2), 0, 0, now)];
+    assert_eq!(make_room(&limits(2, 8, usize::MAX), occupants, peer(3), 0, now), Err(Refusal::Sessions));
+  }
+
+  #[test]
+  fn per_peer_limit_evicts_only_that_peer() {
+    let now = later();
+    let occupants = vec![
+      occupant(1, peer(2), 90, 0, now),
+      occupant(2, peer(1), 60, 0, now),
+      occupant(3, peer(1), 30, 0, now),
+    ];
+    assert_eq!(make_room(&limits(8, 2, usize::MAX), occupants, peer(1), 0, now), Ok(vec![2]));
+
+    let busy = vec![occupant(1, peer(1), 0, 0, now), occupant(2, peer(1), 0, 0, now)];
+    assert_eq!(make_room(&limits(8, 2, usize::MAX), busy, peer(1), 0, now), Err(Refusal::PeerSessions));
+  }
+
+  #[test]
+  fn memory_limit_evicts_sessions_holding_bytes() {
+    let now = later();
+    let occupants = vec![
+      occupant(1, peer(1), 90, 0, now),
+      occupant(2, peer(2), 60, 400, now),
+      occupant(3, peer(3), 30, 400, now),
+    ];
+    // Só a sessão 2 precisa sair; a 1, mais antiga, não guarda bytes.
+    assert_eq!(make_room(&limits(8, 8, 1000), occupants, peer(4), 500, now), Ok(vec![2]));
+
+    let active = vec![occupant(1, peer(1), 0, 900, now)];
+    assert_eq!(make_room(&limits(8, 8, 1000), active, peer(2), 500, now), Err(Refusal::Memory));
+    assert_eq!(make_room::<u32>(&limits(8, 8, 1000), Vec::new(), peer(2), 1001, now), Err(Refusal::Memory));
+  }
+}
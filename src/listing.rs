@@ -0,0 +1,142 @@
+// Listagem de diretórios (LIST). O servidor serializa as entradas em JSON e entrega o resultado
+// com o mesmo mecanismo de pacotes numerados e retransmissão usado para arquivos, já que uma
+// listagem pode não caber em um único datagrama.
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::calculate_hash;
+
+// Tipo de uma entrada do diretório.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+  File,
+  Dir,
+  Symlink,
+  Other,
+}
+
+// Uma entrada da listagem.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DirEntry {
+  pub name: String,
+  pub kind: EntryKind,
+  pub size: u64,
+  // Última modificação, em segundos desde a época Unix.
+  pub mtime: u64,
+  // SHA-256 do conteúdo, apenas para arquivos e quando pedido (`?hash=1`).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sha256: Option<String>,
+}
+
+// Lista o diretório, ordenado por nome. Arquivos ocultos (incluindo os temporários de upload) ficam de fora.
+pub fn list_directory(dir: &Path, with_hash: bool) -> io::Result<Vec<DirEntry>> {
+  let mut entries = Vec::new();
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let name = entry.file_name().to_string_lossy().into_owned();
+    if name.starts_with('.') {
+      continue;
+    }
+
+    let file_type = entry.file_type()?;
+    let kind = if file_type.is_symlink() {
+      EntryKind::Symlink
+    } else if file_type.is_dir() {
+      EntryKind::Dir
+    } else if file_type.is_file() {
+      EntryKind::File
+    } else {
+      EntryKind::Other
+    };
+    let metadata = entry.metadata()?;
+    let mtime = mtime_secs(&metadata);
+    let sha256 = if with_hash && kind == EntryKind::File {
+      Some(calculate_hash(&File::open(entry.path())?))
+    } else {
+      None
+    };
+
+    entries.push(DirEntry { name, kind, size: metadata.len(), mtime, sha256 });
+  }
+  entries.sort_by(|a, b| a.name.cmp(&b.name));
+  Ok(entries)
+}
+
+// Última modificação em segundos desde a época Unix, ou 0 quando o sistema não a informa.
+pub fn mtime_secs(metadata: &Metadata) -> u64 {
+  metadata
+    .modified()
+    .ok()
+    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+    .map(|elapsed| elapsed.as_secs())
+    .unwrap_or(0)
+}
+
+pub fn encode(entries: &[DirEntry]) -> io::Result<Vec<u8>> {
+  Ok(serde_json::to_vec(entries)?)
+}
+
+pub fn decode(data: &[u8]) -> io::Result<Vec<DirEntry>> {
+  Ok(serde_json::from_slice(data)?)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, process};
+
+  use super::*;
+
+  // SHA-256 de "abc".
+  const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+  fn entry(name: &str, kind: EntryKind, sha256: Option<&str>) -> DirEntry {
+    DirEntry { name: name.to_string(), kind, size: 3, mtime: 1_700_000_000, sha256: sha256.map(str::to_string) }
+  }
+
+  #[test]
+  fn entries_survive_encoding() {
+    let entries = vec![entry("a.txt", EntryKind::File, Some(ABC_SHA256)), entry("docs", EntryKind::Dir, None)];
+    let decoded = decode(&encode(&entries).unwrap()).unwrap();
+    assert_eq!(decoded.len(), 2);
+    assert_eq!((decoded[0].name.as_str(), decoded[0].kind, decoded[0].sha256.as_deref()), ("a.txt", EntryKind::File, Some(ABC_SHA256)));
+    assert_eq!((decoded[1].name.as_str(), decoded[1].kind, decoded[1].sha256.as_deref()), ("docs", EntryKind::Dir, None));
+    assert_eq!((decoded[1].size, decoded[1].mtime), (3, 1_700_000_000));
+  }
+
+  #[test]
+  fn hash_is_omitted_when_absent() {
+    let json = String::from_utf8(encode(&[entry("docs", EntryKind::Dir, None)]).unwrap()).unwrap();
+    assert_eq!(json, r#"[{"name":"docs","kind":"dir","size":3,"mtime":1700000000}]"#);
+    assert!(decode(b"{").is_err());
+    assert!(decode(br#"[{"name":"x","kind":"pipe","size":0,"mtime":0}]"#).is_err());
+  }
+
+  #[test]
+  fn lists_a_directory_sorted_without_hidden_entries() {
+    let dir = env::temp_dir().join(format!("rsudp-listing-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("b.txt"), "abc").unwrap();
+    fs::write(dir.join("a.txt"), "").unwrap();
+    fs::write(dir.join(".upload.tmp"), "x").unwrap();
+
+    let entries = list_directory(&dir, false).unwrap();
+    let names: Vec<(&str, EntryKind)> = entries.iter().map(|entry| (entry.name.as_str(), entry.kind)).collect();
+    assert_eq!(names, [("a.txt", EntryKind::File), ("b.txt", EntryKind::File), ("sub", EntryKind::Dir)]);
+    assert_eq!(entries[1].size, 3);
+    assert!(entries[1].mtime > 0);
+    assert!(entries.iter().all(|entry| entry.sha256.is_none()));
+
+    let hashed = list_directory(&dir, true).unwrap();
+    assert_eq!(hashed[1].sha256.as_deref(), Some(ABC_SHA256));
+    // Diretórios não têm hash.
+    assert_eq!(hashed[2].sha256, None);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(list_directory(&dir, false).unwrap_err().kind(), io::ErrorKind::NotFound);
+  }
+}
@@ -0,0 +1,66 @@
+// Configuração dos logs dos binários. A biblioteca registra eventos e spans com `tracing` (sessão,
+// requisição, pacotes), e cada binário instala aqui o subscriber que os escreve na saída de erro,
+// em texto para humanos ou em JSON (um objeto por linha). Eventos abaixo do nível configurado são
+// descartados antes de formatar os campos, o que deixa os logs por pacote (DEBUG/TRACE) sem custo
+// quando desativados.
+use std::io;
+
+use tracing_subscriber::EnvFilter;
+
+// Formato das linhas de log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+  Human,
+  Json,
+}
+
+impl LogFormat {
+  pub fn parse(value: &str) -> Option<LogFormat> {
+    match value {
+      "human" | "text" => Some(LogFormat::Human),
+      "json" => Some(LogFormat::Json),
+      _ => None,
+    }
+  }
+}
+
+// Instala o subscriber global. O filtro segue a sintaxe de `RUST_LOG` (por exemplo `debug` ou
+// `info,rawsocket_udp::handler=trace`); sem filtro explícito, vale `RUST_LOG` e depois o nível
+// padrão informado.
+pub fn init(format: LogFormat, filter: Option<&str>, default_level: &str) -> io::Result<()> {
+  let filter = match filter {
+    Some(filter) => EnvFilter::try_new(filter),
+    None => EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(default_level)),
+  }
+  .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("filtro de log inválido: {}", e)))?;
+
+  let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(io::stderr);
+  let installed = match format {
+    LogFormat::Human => builder.try_init(),
+    LogFormat::Json => builder.json().with_current_span(true).with_span_list(false).try_init(),
+  };
+  installed.map_err(|e| io::Error::other(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_formats() {
+    assert_eq!(LogFormat::parse("human"), Some(LogFormat::Human));
+    assert_eq!(LogFormat::parse("text"), Some(LogFormat::Human));
+    assert_eq!(LogFormat::parse("json"), Some(LogFormat::Json));
+    assert_eq!(LogFormat::parse("JSON"), None);
+    assert_eq!(LogFormat::parse(""), None);
+  }
+
+  #[test]
+  fn subscriber_is_installed_once() {
+    let invalid = init(LogFormat::Human, Some("info,rawsocket_udp=barulhento"), "info").unwrap_err();
+    assert_eq!(invalid.kind(), io::ErrorKind::InvalidInput);
+    // O filtro `off` não deixa passar nada dos outros testes.
+    init(LogFormat::Json, Some("off"), "info").unwrap();
+    assert!(init(LogFormat::Human, None, "info").is_err());
+  }
+}
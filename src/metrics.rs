@@ -0,0 +1,441 @@
+// Métricas do servidor no formato de texto do Prometheus: requisições por comando, bytes e pacotes
+// enviados, retransmissões atendidas, erros por código, sessões ativas e, por sessão, vazão e RTT.
+// `Metrics::serve` expõe o texto em um listener TCP local (`GET /metrics`) para ser coletado.
+//
+// Uma sessão é uma transferência de um cliente: a resposta de um GET/LIST com suas retransmissões,
+// ou um upload (PUT/DELTA) até a confirmação. Ela termina quando o cliente inicia outra
+// transferência, quando o upload é concluído ou depois de `SESSION_IDLE_TIMEOUT` sem atividade.
+// A vazão é medida do recebimento da requisição até o envio da última resposta (ou até a
+// confirmação do upload). O RTT é estimado pelo intervalo entre uma resposta que pede uma ação do
+// cliente (PUT-READY, RETRANSMIT, o fim de transmissão de um GET) e o próximo datagrama da mesma
+// sessão.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+use crate::protocol::ErrorCode;
+
+// Sessões sem atividade por mais que isso deixam de contar como ativas.
+pub const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Tamanho máximo lido da linha de requisição HTTP.
+const MAX_REQUEST_LINE: u64 = 8192;
+
+// Comandos contados em `rsudp_requests_total`; outros entram como OTHER, para que o cliente não
+// possa criar séries arbitrárias.
+const COMMANDS: [&str; 8] = ["GET", "STAT", "LIST", "PUT", "DELTA", "PROBE", "RETRANSMIT", "DISCOVER"];
+
+// Limites dos buckets, em bytes por segundo e em segundos.
+const THROUGHPUT_BUCKETS: [f64; 8] = [1e5, 1e6, 1e7, 5e7, 1e8, 5e8, 1e9, 1e10];
+const RTT_BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+const DURATION_BUCKETS: [f64; 9] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+// Papel de uma resposta no ciclo de vida da sessão, informado por `Handler::handle`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SessionEvent {
+  // Resposta que inicia uma transferência (GET/LIST entregue, PUT-READY).
+  Start,
+  // Resposta dentro da transferência em andamento (retransmissões, pacotes de upload).
+  Continue,
+  // Upload confirmado ou recusado: a sessão termina.
+  Finish,
+  // Resposta fora de uma transferência (STAT, PROBE, erros).
+  None,
+}
+
+// O que o tratamento de um datagrama informa às métricas.
+pub(crate) struct Handled<'a> {
+  // Comando da requisição; `None` para pacotes de dados de upload.
+  pub command: Option<&'a str>,
+  pub error: Option<ErrorCode>,
+  // Pacotes reenviados em resposta a um RETRANSMIT.
+  pub retransmitted: usize,
+  pub event: SessionEvent,
+  pub reply_expected: bool,
+  // Recebimento do datagrama.
+  pub started: Instant,
+}
+
+struct Histogram {
+  bounds: &'static [f64],
+  counts: Vec<u64>,
+  sum: f64,
+  count: u64,
+}
+
+impl Histogram {
+  fn new(bounds: &'static [f64]) -> Histogram {
+    Histogram { bounds, counts: vec![0; bounds.len()], sum: 0.0, count: 0 }
+  }
+
+  fn observe(&mut self, value: f64) {
+    for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+      if value <= *bound {
+        *count += 1;
+      }
+    }
+    self.sum += value;
+    self.count += 1;
+  }
+
+  fn write(&self, out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+    for (bound, count) in self.bounds.iter().zip(&self.counts) {
+      let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+    }
+    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+    let _ = writeln!(out, "{}_sum {}\n{}_count {}", name, self.sum, name, self.count);
+  }
+}
+
+// Transferência em andamento de um cliente.
+struct Session {
+  started: Instant,
+  last_activity: Instant,
+  // Fim do envio da última resposta.
+  last_sent: Option<Instant>,
+  // Bytes enviados e, nos uploads, recebidos.
+  bytes: u64,
+  // A resposta em tratamento pede uma ação do cliente; a espera começa quando ela é enviada.
+  reply_expected: bool,
+  // Momento em que o servidor passou a esperar a ação do cliente, para a medida do RTT.
+  awaiting_reply: Option<Instant>,
+}
+
+struct Histograms {
+  throughput: Histogram,
+  rtt: Histogram,
+  request_duration: Histogram,
+}
+
+pub struct Metrics {
+  requests: Mutex<BTreeMap<&'static str, u64>>,
+  errors: Mutex<BTreeMap<u16, u64>>,
+  bytes_sent: AtomicU64,
+  packets_sent: AtomicU64,
+  bytes_received: AtomicU64,
+  retransmitted: AtomicU64,
+  sessions_total: AtomicU64,
+  sessions: Mutex<HashMap<SocketAddr, Session>>,
+  histograms: Mutex<Histograms>,
+}
+
+impl Default for Metrics {
+  fn default() -> Metrics {
+    Metrics {
+      requests: Mutex::new(BTreeMap::new()),
+      errors: Mutex::new(BTreeMap::new()),
+      bytes_sent: AtomicU64::new(0),
+      packets_sent: AtomicU64::new(0),
+      bytes_received: AtomicU64::new(0),
+      retransmitted: AtomicU64::new(0),
+      sessions_total: AtomicU64::new(0),
+      sessions: Mutex::new(HashMap::new()),
+      histograms: Mutex::new(Histograms {
+        throughput: Histogram::new(&THROUGHPUT_BUCKETS),
+        rtt: Histogram::new(&RTT_BUCKETS),
+        request_duration: Histogram::new(&DURATION_BUCKETS),
+      }),
+    }
+  }
+}
+
+impl Metrics {
+  pub fn new() -> Metrics {
+    Metrics::default()
+  }
+
+  // Datagrama recebido de um cliente: fecha a espera do RTT da sessão dele, se houver.
+  // `continues_session` indica que o datagrama pertence à transferência em andamento.
+  pub(crate) fn received(&self, client_address: SocketAddr, bytes: usize, continues_session: bool) {
+    self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    let mut sessions = self.sessions.lock().unwrap();
+    if let Some(session) = sessions.get_mut(&client_address) {
+      let now = Instant::now();
+      if let (Some(since), true) = (session.awaiting_reply.take(), continues_session) {
+        self.histograms.lock().unwrap().rtt.observe(now.duration_since(since).as_secs_f64());
+      }
+      if continues_session {
+        session.bytes += bytes as u64;
+      }
+      session.last_activity = now;
+    }
+  }
+
+  // Requisição tratada: contadores por comando e código de erro, duração do tratamento e ciclo de
+  // vida da sessão do cliente. `reply_expected` indica que a resposta pede uma ação do cliente.
+  pub(crate) fn handled(&self, client_address: SocketAddr, handled: Handled) {
+    let Handled { command, error, retransmitted, event, reply_expected, started } = handled;
+    if let Some(command) = command {
+      let label = COMMANDS.iter().find(|known| **known == command).copied().unwrap_or("OTHER");
+      *self.requests.lock().unwrap().entry(label).or_insert(0) += 1;
+    }
+    if let Some(code) = error {
+      *self.errors.lock().unwrap().entry(code.as_u16()).or_insert(0) += 1;
+    }
+    self.retransmitted.fetch_add(retransmitted as u64, Ordering::Relaxed);
+    if command.is_some() {
+      self.histograms.lock().unwrap().request_duration.observe(started.elapsed().as_secs_f64());
+    }
+
+    let mut sessions = self.sessions.lock().unwrap();
+    match event {
+      SessionEvent::Start => {
+        if let Some(previous) = sessions.remove(&client_address) {
+          self.close(previous);
+        }
+        // Cada transferência nova encerra as inativas, para que a tabela não cresça com clientes
+        // que sumiram mesmo sem ninguém consultar as métricas.
+        self.expire(&mut sessions);
+        self.sessions_total.fetch_add(1, Ordering::Relaxed);
+        sessions.insert(
+          client_address,
+          Session { started, last_activity: Instant::now(), last_sent: None, bytes: 0, reply_expected, awaiting_reply: None },
+        );
+      },
+      SessionEvent::Finish => {
+        if let Some(session) = sessions.remove(&client_address) {
+          self.close(session);
+        }
+      },
+      SessionEvent::Continue => {
+        if let Some(session) = sessions.get_mut(&client_address) {
+          session.reply_expected = reply_expected;
+        }
+      },
+      SessionEvent::None => {},
+    }
+  }
+
+  // Resposta enviada ao cliente.
+  pub(crate) fn sent(&self, client_address: SocketAddr, datagrams: &[Vec<u8>]) {
+    let bytes: u64 = datagrams.iter().map(|datagram| datagram.len() as u64).sum();
+    self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+    self.packets_sent.fetch_add(datagrams.len() as u64, Ordering::Relaxed);
+    if let Some(session) = self.sessions.lock().unwrap().get_mut(&client_address) {
+      let now = Instant::now();
+      session.bytes += bytes;
+      session.last_sent = Some(now);
+      session.last_activity = now;
+      if std::mem::take(&mut session.reply_expected) {
+        session.awaiting_reply = Some(now);
+      }
+    }
+  }
+
+  // Sessões com atividade recente. As inativas são encerradas aqui e a cada sessão iniciada.
+  pub fn active_sessions(&self) -> usize {
+    let mut sessions = self.sessions.lock().unwrap();
+    self.expire(&mut sessions);
+    sessions.len()
+  }
+
+  fn expire(&self, sessions: &mut HashMap<SocketAddr, Session>) {
+    let expired: Vec<SocketAddr> = sessions
+      .iter()
+      .filter(|(_, session)| session.last_activity.elapsed() > SESSION_IDLE_TIMEOUT)
+      .map(|(address, _)| *address)
+      .collect();
+    for address in expired {
+      if let Some(session) = sessions.remove(&address) {
+        self.close(session);
+      }
+    }
+  }
+
+  fn close(&self, session: Session) {
+    let end = session.last_sent.unwrap_or(session.last_activity);
+    let elapsed = end.duration_since(session.started).as_secs_f64();
+    if session.bytes > 0 && elapsed > 0.0 {
+      self.histograms.lock().unwrap().throughput.observe(session.bytes as f64 / elapsed);
+    }
+  }
+
+  // Texto no formato de exposição do Prometheus (versão 0.0.4).
+  pub fn render(&self) -> String {
+    let active = self.active_sessions();
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# HELP rsudp_requests_total Requests handled, by command.\n# TYPE rsudp_requests_total counter");
+    for (command, count) in self.requests.lock().unwrap().iter() {
+      let _ = writeln!(out, "rsudp_requests_total{{command=\"{}\"}} {}", command, count);
+    }
+    let _ = writeln!(out, "# HELP rsudp_errors_total Error responses sent, by code.\n# TYPE rsudp_errors_total counter");
+    for (code, count) in self.errors.lock().unwrap().iter() {
+      let _ = writeln!(out, "rsudp_errors_total{{code=\"{}\"}} {}", code, count);
+    }
+    let counters = [
+      ("rsudp_sent_bytes_total", "Bytes sent in response datagrams.", &self.bytes_sent),
+      ("rsudp_sent_packets_total", "Response datagrams sent.", &self.packets_sent),
+      ("rsudp_received_bytes_total", "Bytes received from clients.", &self.bytes_received),
+      ("rsudp_retransmitted_packets_total", "Packets sent again in answer to RETRANSMIT.", &self.retransmitted),
+      ("rsudp_sessions_total", "Transfers started.", &self.sessions_total),
+    ];
+    for (name, help, value) in counters {
+      let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value.load(Ordering::Relaxed));
+    }
+    let _ = writeln!(
+      out,
+      "# HELP rsudp_active_sessions Transfers with recent activity.\n# TYPE rsudp_active_sessions gauge\nrsudp_active_sessions {}",
+      active
+    );
+
+    let histograms = self.histograms.lock().unwrap();
+    histograms.throughput.write(&mut out, "rsudp_session_throughput_bytes_per_second", "Throughput of finished transfers.");
+    histograms.rtt.write(&mut out, "rsudp_session_rtt_seconds", "Time between a response that needs a client action and the client's next datagram.");
+    histograms.request_duration.write(&mut out, "rsudp_request_duration_seconds", "Time spent handling a request, before sending.");
+    out
+  }
+
+  // Atende `GET /metrics` no endereço informado, em uma thread própria que vive até o fim do
+  // processo. Devolve o endereço efetivo do listener.
+  pub fn serve(self: Arc<Self>, address: SocketAddr) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(address)?;
+    let local_address = listener.local_addr()?;
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        let result = stream.and_then(|stream| self.respond(stream));
+        if let Err(e) = result {
+          debug!(error = %e, "metrics request failed");
+        }
+      }
+      warn!("metrics listener stopped");
+    });
+    Ok(local_address)
+  }
+
+  fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request_line = String::new();
+    // A linha de requisição é limitada, para que um cliente não faça o servidor acumular uma linha
+    // sem fim.
+    BufReader::new((&stream).take(MAX_REQUEST_LINE)).read_line(&mut request_line)?;
+    let (status, body) = match request_line.split_whitespace().nth(1) {
+      Some("/metrics") if request_line.starts_with("GET ") => ("200 OK", self.render()),
+      _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    write!(
+      stream,
+      "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+      status,
+      body.len(),
+      body
+    )?;
+    stream.flush()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::handler::Handler;
+  use crate::source::MemorySource;
+
+  fn client(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+  }
+
+  fn handler(metrics: &Arc<Metrics>) -> Handler {
+    Handler::new(Arc::new(MemorySource::new().with_file("a.bin", vec![1; 5000]))).with_metrics(Arc::clone(metrics))
+  }
+
+  #[test]
+  fn histogram_buckets_are_cumulative() {
+    let mut histogram = Histogram::new(&[1.0, 10.0]);
+    for value in [0.5, 5.0, 50.0] {
+      histogram.observe(value);
+    }
+    let mut out = String::new();
+    histogram.write(&mut out, "h", "ajuda");
+    assert!(out.contains("h_bucket{le=\"1\"} 1\nh_bucket{le=\"10\"} 2\nh_bucket{le=\"+Inf\"} 3\n"), "{}", out);
+    assert!(out.contains("h_sum 55.5\nh_count 3\n"), "{}", out);
+  }
+
+  #[test]
+  fn counts_requests_errors_and_sessions() {
+    let metrics = Arc::new(Metrics::new());
+    let handler = handler(&metrics);
+    let response = handler.handle(b"GET /a.bin?chunk=1000", client(4000));
+    metrics.sent(client(4000), &response);
+    handler.handle(b"RETRANSMIT 2", client(4000));
+    handler.handle(b"GET /missing.bin", client(4001));
+    handler.handle(b"STAT /a.bin", client(4002));
+    handler.handle(b"GET /", client(4002));
+    assert_eq!(metrics.active_sessions(), 1);
+
+    let text = metrics.render();
+    for line in [
+      "rsudp_requests_total{command=\"GET\"} 3",
+      "rsudp_requests_total{command=\"RETRANSMIT\"} 1",
+      "rsudp_requests_total{command=\"STAT\"} 1",
+      "rsudp_errors_total{code=\"400\"} 1",
+      "rsudp_errors_total{code=\"404\"} 1",
+      "rsudp_retransmitted_packets_total 1",
+      "rsudp_sessions_total 1",
+      "rsudp_active_sessions 1",
+    ] {
+      assert!(text.lines().any(|rendered| rendered == line), "{} ausente em:\n{}", line, text);
+    }
+    let sent: u64 = response.iter().map(|datagram| datagram.len() as u64).sum();
+    assert!(text.contains(&format!("rsudp_sent_bytes_total {}\n", sent)));
+    assert!(text.contains(&format!("rsudp_sent_packets_total {}\n", response.len())));
+  }
+
+  #[test]
+  fn unknown_commands_share_one_series() {
+    let metrics = Metrics::new();
+    for command in ["FOO", "BAR"] {
+      let handled = Handled {
+        command: Some(command),
+        error: Some(ErrorCode::BadRequest),
+        retransmitted: 0,
+        event: SessionEvent::None,
+        reply_expected: false,
+        started: Instant::now(),
+      };
+      metrics.handled(client(4000), handled);
+    }
+    let text = metrics.render();
+    assert!(text.contains("rsudp_requests_total{command=\"OTHER\"} 2\n"), "{}", text);
+    assert!(!text.contains("FOO"));
+  }
+
+  #[test]
+  fn a_new_transfer_closes_the_previous_session() {
+    let metrics = Arc::new(Metrics::new());
+    let handler = handler(&metrics);
+    for _ in 0..2 {
+      let response = handler.handle(b"GET /a.bin", client(4000));
+      metrics.sent(client(4000), &response);
+    }
+    let text = metrics.render();
+    assert!(text.contains("rsudp_sessions_total 2\n"));
+    assert!(text.contains("rsudp_active_sessions 1\n"));
+    assert!(text.contains("rsudp_session_throughput_bytes_per_second_count 1\n"), "{}", text);
+  }
+
+  #[test]
+  fn serves_the_metrics_over_http() {
+    let metrics = Arc::new(Metrics::new());
+    let address = Arc::clone(&metrics).serve(client(0)).unwrap();
+    let fetch = |request: &str| {
+      let mut stream = TcpStream::connect(address).unwrap();
+      stream.write_all(request.as_bytes()).unwrap();
+      let mut response = String::new();
+      stream.read_to_string(&mut response).unwrap();
+      response
+    };
+    let response = fetch("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("rsudp_active_sessions 0\n"));
+    assert!(fetch("GET /other HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    assert!(fetch("POST /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404 Not Found\r\n"));
+  }
+}
@@ -0,0 +1,632 @@
+// Distribuição de um arquivo para muitas máquinas com multicast IPv4: o servidor transmite o arquivo
+// uma única vez para um grupo e cada receptor que entrou no grupo monta a sua cópia.
+//
+// O tráfego do grupo usa os mesmos pacotes de um GET: o cabeçalho (seq 0, repetido a cada
+// `ANNOUNCE_INTERVAL` pacotes para quem entra no meio da transmissão), os pacotes de dados e o fim
+// de transmissão ao final de cada passada. Com FEC, cada grupo de `k` pacotes de dados é seguido de
+// um pacote de paridade (seq `PARITY_SEQ_BASE` + índice do grupo) com o XOR dos seus payloads, que
+// permite a um receptor reconstruir sozinho um pacote perdido no grupo.
+//
+// O que a paridade não cobre é pedido ao servidor por unicast com `NACK 3-7,12`. Os receptores
+// esperam um atraso aleatório antes de enviar o NACK; o servidor junta os pedidos recebidos em
+// `REPAIR_DELAY`, anuncia no grupo `REPAIR 3-7,12` e retransmite os pacotes no grupo. Quem ouve o
+// anúncio deixa de pedir os pacotes já anunciados (supressão de NACKs), de modo que uma perda comum
+// a vários receptores gera poucos pedidos. O servidor encerra depois de `linger` sem NACKs.
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use digest::Digest;
+use sha2::Sha256;
+use socket2::{Domain, Protocol, Socket, Type};
+use tracing::{debug, info, warn};
+
+use crate::batch::{BatchOptions, BatchSender};
+use crate::capture::{self, Direction};
+use crate::compression::Compression;
+use crate::error::ClientError;
+use crate::protocol::{chunk_size_for_mtu, Datagram, FileMeta, UdpPacket, HEADER_LEN, MAX_CHUNK_SIZE, MIN_CHUNK_SIZE};
+use crate::shaping::{self, RateLimit, Shaper, ShapingConfig};
+
+// Grupo e porta usados quando nenhum outro é informado (escopo administrativo local, RFC 2365).
+pub const DEFAULT_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 0, 83), 9083);
+// Números de sequência dos pacotes de paridade: `PARITY_SEQ_BASE` + índice do grupo.
+pub const PARITY_SEQ_BASE: u32 = 0x8000_0000;
+// Bytes da paridade antes do XOR: o `k` configurado e os pacotes de dados do grupo, menos que `k`
+// no último grupo.
+const PARITY_OVERHEAD: usize = 4;
+// Pacotes de dados entre duas repetições do cabeçalho.
+const ANNOUNCE_INTERVAL: usize = 256;
+// Tempo em que o servidor junta os NACKs antes de uma rodada de reparo.
+const REPAIR_DELAY: Duration = Duration::from_millis(10);
+// Rodadas de reparo no máximo, para que receptores com perdas sem fim não prendam o servidor.
+const MAX_REPAIR_ROUNDS: u32 = 64;
+// Silêncio do grupo depois do qual um receptor com pacotes faltando pede o reparo.
+const NACK_SILENCE: Duration = Duration::from_millis(300);
+// Maior atraso aleatório antes de um NACK, dentro do qual um anúncio de reparo o suprime.
+const NACK_BACKOFF: Duration = Duration::from_millis(50);
+// Tamanho máximo de uma mensagem NACK ou REPAIR.
+const MAX_RANGES_LEN: usize = 1200;
+// Maior arquivo aceito por padrão por um receptor.
+pub const DEFAULT_MAX_SIZE: u64 = 4 << 30;
+// Buffer de recepção dos receptores, para absorver as rajadas do grupo.
+const RECV_BUFFER: usize = 4 << 20;
+
+// Configuração do envio para o grupo.
+#[derive(Clone, Debug)]
+pub struct SenderConfig {
+  pub group: SocketAddrV4,
+  // Interface de saída do tráfego do grupo (`0.0.0.0` deixa a escolha para a tabela de rotas).
+  pub interface: Ipv4Addr,
+  pub ttl: u32,
+  pub chunk_size: usize,
+  // Pacotes de dados por pacote de paridade; `None` desliga a FEC.
+  pub fec: Option<usize>,
+  // Taxa de envio para o grupo; `None` envia o mais rápido possível.
+  pub rate: Option<RateLimit>,
+  // Espera por NACKs depois da última transmissão; zero encerra logo após a primeira passada.
+  pub linger: Duration,
+}
+
+impl Default for SenderConfig {
+  fn default() -> SenderConfig {
+    SenderConfig {
+      group: DEFAULT_GROUP,
+      interface: Ipv4Addr::UNSPECIFIED,
+      ttl: 1,
+      chunk_size: chunk_size_for_mtu(1500, false),
+      fec: None,
+      rate: None,
+      linger: Duration::from_secs(2),
+    }
+  }
+}
+
+// Resumo de uma distribuição.
+#[derive(Clone, Debug, Default)]
+pub struct SendReport {
+  // Datagramas enviados ao grupo, incluindo cabeçalhos, paridade e reparos.
+  pub datagrams: u64,
+  pub bytes: u64,
+  pub parity_packets: u64,
+  pub nacks: u64,
+  pub repair_rounds: u32,
+  pub repaired_packets: u64,
+  pub elapsed: Duration,
+}
+
+pub struct Sender {
+  socket: UdpSocket,
+  config: SenderConfig,
+  shaper: Arc<Shaper>,
+  batch: BatchSender,
+}
+
+impl Sender {
+  pub fn new(config: SenderConfig, batch: BatchOptions) -> io::Result<Sender> {
+    let max_chunk = if config.fec.is_some() { MAX_CHUNK_SIZE - PARITY_OVERHEAD } else { MAX_CHUNK_SIZE };
+    if !(MIN_CHUNK_SIZE..=max_chunk).contains(&config.chunk_size) {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "Tamanho de bloco fora dos limites"));
+    }
+    if config.fec.is_some_and(|k| !(1..=u16::MAX as usize).contains(&k)) {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "Grupo de FEC inválido"));
+    }
+    if !config.group.ip().is_multicast() {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "Endereço não é de um grupo multicast"));
+    }
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_multicast_if_v4(&config.interface)?;
+    socket.set_multicast_ttl_v4(config.ttl)?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.bind(&SocketAddr::from((config.interface, 0)).into())?;
+    let shaper = Arc::new(Shaper::new(ShapingConfig { global: config.rate, ..ShapingConfig::default() }));
+    Ok(Sender { socket: socket.into(), config, shaper, batch: BatchSender::new(batch) })
+  }
+
+  pub fn local_addr(&self) -> io::Result<SocketAddr> {
+    self.socket.local_addr()
+  }
+
+  // Transmite o arquivo ao grupo e atende os NACKs até `linger` sem pedidos.
+  pub fn send(&self, data: &[u8], mtime: u64) -> io::Result<SendReport> {
+    let started = Instant::now();
+    let group = SocketAddr::V4(self.config.group);
+    let src_port = self.local_addr()?.port();
+    let packets: Vec<Vec<u8>> = UdpPacket::prepare_packets(src_port, group.port(), data.to_vec(), self.config.chunk_size, mtime)
+      .iter()
+      .map(UdpPacket::serialize)
+      .collect();
+    let end_of_transmission = UdpPacket::end_of_transmission(src_port, group.port()).serialize();
+    let mut report = SendReport::default();
+
+    // Primeira passada: cabeçalho repetido, dados, paridade de cada grupo completo e fim de transmissão.
+    let mut datagrams = vec![packets[0].clone()];
+    let mut parity = Parity::default();
+    for (index, packet) in packets[1..].iter().enumerate() {
+      if index > 0 && index % ANNOUNCE_INTERVAL == 0 {
+        datagrams.push(packets[0].clone());
+      }
+      datagrams.push(packet.clone());
+      if let Some(k) = self.config.fec {
+        parity.add(&packet[HEADER_LEN..]);
+        if parity.len == k || index + 2 == packets.len() {
+          datagrams.push(parity.take(k, PARITY_SEQ_BASE + (index / k) as u32, src_port, group.port()));
+          report.parity_packets += 1;
+        }
+      }
+    }
+    datagrams.push(end_of_transmission.clone());
+    info!(
+      %group,
+      size = data.len(),
+      packets = packets.len(),
+      parity = report.parity_packets,
+      "multicast transmission started"
+    );
+    self.transmit(&datagrams, &mut report)?;
+
+    // Rodadas de reparo até `linger` sem NACKs.
+    let mut deadline = Instant::now() + self.config.linger;
+    let mut buf = [0u8; MAX_RANGES_LEN + 16];
+    while report.repair_rounds < MAX_REPAIR_ROUNDS {
+      let mut requested = BTreeSet::new();
+      let mut repair_at = None;
+      loop {
+        let wait_until = repair_at.unwrap_or(deadline);
+        let now = Instant::now();
+        if now >= wait_until {
+          break;
+        }
+        self.socket.set_read_timeout(Some(wait_until - now))?;
+        let (len, origin) = match self.socket.recv_from(&mut buf) {
+          Ok(received) => received,
+          Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+          Err(e) => return Err(e),
+        };
+        capture::record_socket(&self.socket, origin, Direction::Received, [&buf[..len]]);
+        if let Datagram::Text { command: "NACK", argument } = Datagram::decode(&buf[..len]) {
+          let sequences = decode_ranges(argument, packets.len() as u32 - 1);
+          debug!(client = %origin, requested = sequences.len(), "NACK received");
+          report.nacks += 1;
+          requested.extend(sequences);
+          repair_at.get_or_insert_with(|| Instant::now() + REPAIR_DELAY);
+        }
+      }
+      if requested.is_empty() {
+        break;
+      }
+
+      let sequences: Vec<u32> = requested.into_iter().collect();
+      let mut datagrams: Vec<Vec<u8>> =
+        encode_ranges(&sequences).into_iter().map(|ranges| format!("REPAIR {}", ranges).into_bytes()).collect();
+      datagrams.push(packets[0].clone());
+      datagrams.extend(sequences.iter().map(|&seq| packets[seq as usize].clone()));
+      datagrams.push(end_of_transmission.clone());
+      report.repair_rounds += 1;
+      report.repaired_packets += sequences.len() as u64;
+      debug!(round = report.repair_rounds, packets = sequences.len(), "multicast repair round");
+      self.transmit(&datagrams, &mut report)?;
+      deadline = Instant::now() + self.config.linger;
+    }
+    if report.repair_rounds == MAX_REPAIR_ROUNDS {
+      warn!(rounds = MAX_REPAIR_ROUNDS, "repair round limit reached, giving up on pending NACKs");
+    }
+
+    report.elapsed = started.elapsed();
+    info!(
+      %group,
+      datagrams = report.datagrams,
+      bytes = report.bytes,
+      nacks = report.nacks,
+      repair_rounds = report.repair_rounds,
+      repaired = report.repaired_packets,
+      elapsed_ms = report.elapsed.as_millis() as u64,
+      "multicast transmission finished"
+    );
+    Ok(report)
+  }
+
+  fn transmit(&self, datagrams: &[Vec<u8>], report: &mut SendReport) -> io::Result<()> {
+    let group = SocketAddr::V4(self.config.group);
+    let ticket = self.shaper.ticket(group);
+    shaping::send_all(&ticket, &self.batch, &self.socket, datagrams, group)?;
+    capture::record_socket(&self.socket, group, Direction::Sent, datagrams.iter().map(Vec::as_slice));
+    report.datagrams += datagrams.len() as u64;
+    report.bytes += datagrams.iter().map(|datagram| datagram.len() as u64).sum::<u64>();
+    Ok(())
+  }
+}
+
+// XOR acumulado dos payloads de um grupo de FEC.
+#[derive(Default)]
+struct Parity {
+  len: usize,
+  xor: Vec<u8>,
+}
+
+impl Parity {
+  fn add(&mut self, payload: &[u8]) {
+    xor_into(&mut self.xor, payload);
+    self.len += 1;
+  }
+
+  // Pacote de paridade do grupo acumulado: `k` e a quantidade de pacotes do grupo (2 bytes cada)
+  // seguidos do XOR.
+  fn take(&mut self, k: usize, seq_number: u32, src_port: u16, dst_port: u16) -> Vec<u8> {
+    let mut data = (k as u16).to_be_bytes().to_vec();
+    data.extend_from_slice(&(self.len as u16).to_be_bytes());
+    data.append(&mut self.xor);
+    self.len = 0;
+    UdpPacket::data_packet(seq_number, src_port, dst_port, data).serialize()
+  }
+}
+
+fn xor_into(target: &mut Vec<u8>, payload: &[u8]) {
+  if target.len() < payload.len() {
+    target.resize(payload.len(), 0);
+  }
+  target.iter_mut().zip(payload).for_each(|(byte, other)| *byte ^= other);
+}
+
+// Configuração de um receptor.
+#[derive(Clone, Debug)]
+pub struct ReceiverConfig {
+  pub group: SocketAddrV4,
+  // Interface em que o receptor entra no grupo (`0.0.0.0` deixa a escolha para o sistema).
+  pub interface: Ipv4Addr,
+  // Tempo sem nenhum datagrama do grupo depois do qual a recepção falha.
+  pub timeout: Duration,
+  // Pede ao servidor, por unicast, os pacotes que a FEC não recuperou.
+  pub nack: bool,
+  // Maior arquivo aceito; anúncios maiores são ignorados, pois o arquivo é montado em memória.
+  pub max_size: u64,
+}
+
+impl Default for ReceiverConfig {
+  fn default() -> ReceiverConfig {
+    ReceiverConfig {
+      group: DEFAULT_GROUP,
+      interface: Ipv4Addr::UNSPECIFIED,
+      timeout: Duration::from_secs(10),
+      nack: true,
+      max_size: DEFAULT_MAX_SIZE,
+    }
+  }
+}
+
+// Arquivo recebido do grupo.
+#[derive(Clone, Debug)]
+pub struct Received {
+  pub meta: FileMeta,
+  pub data: Vec<u8>,
+  // Endereço de origem do tráfego do grupo, para onde foram os NACKs.
+  pub sender: SocketAddr,
+  // Pacotes de dados distintos recebidos, sem contar os reconstruídos pela FEC.
+  pub packets: u64,
+  pub fec_recovered: u64,
+  pub nacks_sent: u64,
+  // Pacotes que deixaram de ser pedidos porque o servidor já havia anunciado o seu reparo.
+  pub nacks_suppressed: u64,
+  pub elapsed: Duration,
+}
+
+pub struct Receiver {
+  socket: UdpSocket,
+  config: ReceiverConfig,
+}
+
+impl Receiver {
+  // Entra no grupo. Vários receptores podem compartilhar a porta na mesma máquina.
+  pub fn join(config: ReceiverConfig) -> io::Result<Receiver> {
+    if !config.group.ip().is_multicast() {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "Endereço não é de um grupo multicast"));
+    }
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    socket.set_reuse_port(true)?;
+    if let Err(e) = socket.set_recv_buffer_size(RECV_BUFFER) {
+      warn!(error = %e, "could not enlarge receive buffer");
+    }
+    socket.bind(&SocketAddr::V4(config.group).into())?;
+    socket.join_multicast_v4(config.group.ip(), &config.interface)?;
+    Ok(Receiver { socket: socket.into(), config })
+  }
+
+  // Recebe o próximo arquivo transmitido ao grupo, pedindo os reparos necessários.
+  pub fn receive(&self) -> Result<Received, ClientError> {
+    let started = Instant::now();
+    let mut state: Option<Reception> = None;
+    let mut last_datagram = Instant::now();
+    let mut buf = vec![0u8; 65_536];
+
+    loop {
+      let now = Instant::now();
+      if now.duration_since(last_datagram) >= self.config.timeout {
+        if let Some(reception) = &state {
+          warn!(missing = reception.missing(), "multicast reception timed out");
+        }
+        return Err(ClientError::Timeout);
+      }
+      if let Some(reception) = state.as_mut().filter(|_| self.config.nack) {
+        // Sem tráfego por um tempo, pede o que falta mesmo sem ter visto o fim de transmissão.
+        let quiet_since = reception.last_nack.map_or(last_datagram, |sent| sent.max(last_datagram));
+        if reception.nack_at.is_none() && reception.missing() > 0 && now.duration_since(quiet_since) >= NACK_SILENCE {
+          reception.nack_at = Some(now + backoff());
+        }
+        if reception.nack_at.is_some_and(|at| now >= at) {
+          reception.nack_at = None;
+          reception.last_nack = Some(now);
+          self.send_nacks(reception)?;
+        }
+      }
+
+      let wake = state.as_ref().and_then(|reception| reception.nack_at).map_or(NACK_SILENCE, |at| at.saturating_duration_since(now));
+      self.socket.set_read_timeout(Some(wake.clamp(Duration::from_millis(1), NACK_SILENCE)))?;
+      let (len, origin) = match self.socket.recv_from(&mut buf) {
+        Ok(received) => received,
+        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+        Err(e) => return Err(e.into()),
+      };
+      capture::record_socket(&self.socket, origin, Direction::Received, [&buf[..len]]);
+      if state.as_ref().is_some_and(|reception| reception.sender != origin) {
+        continue;
+      }
+      last_datagram = Instant::now();
+
+      match Datagram::decode(&buf[..len]) {
+        Datagram::Header(Some(meta)) if state.is_none() => {
+          if let Err(reason) = self.check_header(&meta) {
+            warn!(sender = %origin, reason, "multicast header ignored");
+            continue;
+          }
+          info!(sender = %origin, size = meta.size, packets = meta.total_packets, sha256 = %meta.sha256_hex(), "multicast transfer announced");
+          state = Some(Reception::new(meta, origin));
+        },
+        Datagram::Data { seq_number, payload, checksum_valid: true } => {
+          if let Some(reception) = state.as_mut() {
+            if seq_number >= PARITY_SEQ_BASE {
+              reception.add_parity(seq_number - PARITY_SEQ_BASE, payload);
+            } else {
+              reception.add_data(seq_number, payload);
+            }
+          }
+        },
+        Datagram::Text { command: "REPAIR", argument } => {
+          if let Some(reception) = state.as_mut() {
+            let total = reception.meta.total_packets - 1;
+            reception.announced.extend(decode_ranges(argument, total));
+          }
+        },
+        Datagram::EndOfTransmission => {
+          if let Some(reception) = state.as_mut() {
+            // Uma passada ou rodada terminou: os anúncios dela não valem mais para a próxima.
+            reception.announced.clear();
+            if self.config.nack && reception.missing() > 0 && reception.nack_at.is_none() {
+              reception.nack_at = Some(Instant::now() + backoff());
+            }
+          }
+        },
+        _ => {},
+      }
+
+      if let Some(reception) = state.take_if(|reception| reception.missing() == 0) {
+        return reception.finish(started);
+      }
+    }
+  }
+
+  // Confere um cabeçalho antes de reservar memória para o arquivo: o grupo é aberto a qualquer
+  // remetente, e os metadados precisam ser coerentes entre si e caber no limite configurado.
+  fn check_header(&self, meta: &FileMeta) -> Result<(), &'static str> {
+    let chunk_size = meta.chunk_size as usize;
+    if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+      return Err("tamanho de bloco fora dos limites");
+    }
+    if meta.compression != Compression::None {
+      return Err("compressão não é usada no multicast");
+    }
+    if meta.size > self.config.max_size {
+      return Err("arquivo maior que o limite do receptor");
+    }
+    if u64::from(meta.total_packets) != meta.size.div_ceil(chunk_size as u64) + 1 {
+      return Err("total de pacotes não confere com o tamanho");
+    }
+    Ok(())
+  }
+
+  // Envia ao servidor os pacotes que faltam, menos os de reparo já anunciado.
+  fn send_nacks(&self, reception: &mut Reception) -> io::Result<()> {
+    let missing = reception.missing_sequences();
+    let wanted: Vec<u32> = missing.iter().copied().filter(|seq| !reception.announced.contains(seq)).collect();
+    reception.nacks_suppressed += (missing.len() - wanted.len()) as u64;
+    // Um anúncio suprime só este pedido: se a rodada anunciada se perder, o próximo pede tudo.
+    reception.announced.clear();
+    for ranges in encode_ranges(&wanted) {
+      let datagram = format!("NACK {}", ranges).into_bytes();
+      self.socket.send_to(&datagram, reception.sender)?;
+      capture::record_socket(&self.socket, reception.sender, Direction::Sent, [datagram.as_slice()]);
+      reception.nacks_sent += 1;
+    }
+    debug!(missing = missing.len(), requested = wanted.len(), "NACK sent");
+    Ok(())
+  }
+}
+
+// Estado de um arquivo sendo recebido do grupo.
+struct Reception {
+  meta: FileMeta,
+  sender: SocketAddr,
+  // Payload de cada pacote de dados, pelo índice (seq - 1).
+  chunks: Vec<Option<Vec<u8>>>,
+  received: usize,
+  // `k` da FEC, conhecido a partir do primeiro pacote de paridade.
+  fec_k: Option<usize>,
+  // Paridade de cada grupo de FEC: a quantidade de pacotes de dados do grupo e o XOR.
+  parity: HashMap<u32, (usize, Vec<u8>)>,
+  // Pacotes cujo reparo o servidor anunciou desde o último NACK.
+  announced: HashSet<u32>,
+  nack_at: Option<Instant>,
+  last_nack: Option<Instant>,
+  packets: u64,
+  fec_recovered: u64,
+  nacks_sent: u64,
+  nacks_suppressed: u64,
+}
+
+impl Reception {
+  fn new(meta: FileMeta, sender: SocketAddr) -> Reception {
+    let chunks = vec![None; meta.total_packets.saturating_sub(1) as usize];
+    Reception {
+      meta,
+      sender,
+      chunks,
+      received: 0,
+      fec_k: None,
+      parity: HashMap::new(),
+      announced: HashSet::new(),
+      nack_at: None,
+      last_nack: None,
+      packets: 0,
+      fec_recovered: 0,
+      nacks_sent: 0,
+      nacks_suppressed: 0,
+    }
+  }
+
+  fn missing(&self) -> usize {
+    self.chunks.len() - self.received
+  }
+
+  fn missing_sequences(&self) -> Vec<u32> {
+    self.chunks.iter().enumerate().filter(|(_, chunk)| chunk.is_none()).map(|(index, _)| index as u32 + 1).collect()
+  }
+
+  fn add_data(&mut self, seq_number: u32, payload: &[u8]) {
+    let index = seq_number as usize;
+    if index == 0 || index > self.chunks.len() || self.chunks[index - 1].is_some() {
+      return;
+    }
+    self.chunks[index - 1] = Some(payload.to_vec());
+    self.received += 1;
+    self.packets += 1;
+    if let Some(k) = self.fec_k {
+      self.recover(((index - 1) / k) as u32);
+    }
+  }
+
+  fn add_parity(&mut self, group: u32, payload: &[u8]) {
+    if payload.len() < PARITY_OVERHEAD || self.parity.contains_key(&group) {
+      return;
+    }
+    let k = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+    let count = u16::from_be_bytes([payload[2], payload[3]]) as usize;
+    if k == 0 || count == 0 || count > k || self.fec_k.is_some_and(|known| known != k) {
+      return;
+    }
+    self.fec_k = Some(k);
+    self.parity.insert(group, (count, payload[PARITY_OVERHEAD..].to_vec()));
+    self.recover(group);
+  }
+
+  // Reconstrói o pacote que falta em um grupo de FEC, se for o único.
+  fn recover(&mut self, group: u32) {
+    let (Some(k), Some((count, xor))) = (self.fec_k, self.parity.get(&group)) else { return };
+    let first = group as usize * k;
+    let last = (first + count).min(self.chunks.len());
+    if first >= last {
+      return;
+    }
+    let mut missing = (first..last).filter(|&index| self.chunks[index].is_none());
+    let (Some(index), None) = (missing.next(), missing.next()) else { return };
+    let mut data = xor.clone();
+    for chunk in self.chunks[first..last].iter().flatten() {
+      xor_into(&mut data, chunk);
+    }
+    let chunk_size = self.meta.chunk_size as usize;
+    let len = if index + 1 == self.chunks.len() { self.meta.size as usize - index * chunk_size } else { chunk_size };
+    data.resize(len, 0);
+    debug!(seq = index + 1, group, "packet recovered by FEC");
+    self.chunks[index] = Some(data);
+    self.received += 1;
+    self.fec_recovered += 1;
+  }
+
+  fn finish(self, started: Instant) -> Result<Received, ClientError> {
+    let data: Vec<u8> = self.chunks.into_iter().flatten().flatten().collect();
+    let sha256: [u8; 32] = Sha256::digest(&data).into();
+    if sha256 != self.meta.sha256 {
+      let obtained: String = sha256.iter().map(|byte| format!("{:02x}", byte)).collect();
+      return Err(ClientError::InvalidResponse(format!(
+        "SHA-256 não confere: esperado {}, obtido {}",
+        self.meta.sha256_hex(),
+        obtained
+      )));
+    }
+    Ok(Received {
+      meta: self.meta,
+      data,
+      sender: self.sender,
+      packets: self.packets,
+      fec_recovered: self.fec_recovered,
+      nacks_sent: self.nacks_sent,
+      nacks_suppressed: self.nacks_suppressed,
+      elapsed: started.elapsed(),
+    })
+  }
+}
+
+// Atraso aleatório antes de um NACK, entre zero e `NACK_BACKOFF`.
+fn backoff() -> Duration {
+  let seed = RandomState::new().hash_one((process::id(), Instant::now()));
+  NACK_BACKOFF.mul_f64((seed % 1000) as f64 / 1000.0)
+}
+
+// Números de sequência crescentes como intervalos (`3-7,12`), em mensagens de até
+// `MAX_RANGES_LEN` bytes.
+fn encode_ranges(sorted_sequences: &[u32]) -> Vec<String> {
+  let mut messages = Vec::new();
+  let mut current = String::new();
+  let mut index = 0;
+  while index < sorted_sequences.len() {
+    let first = sorted_sequences[index];
+    let mut last = first;
+    while index + 1 < sorted_sequences.len() && sorted_sequences[index + 1] == last + 1 {
+      index += 1;
+      last = sorted_sequences[index];
+    }
+    index += 1;
+    let range = if first == last { first.to_string() } else { format!("{}-{}", first, last) };
+    if !current.is_empty() && current.len() + 1 + range.len() > MAX_RANGES_LEN {
+      messages.push(std::mem::take(&mut current));
+    }
+    if !current.is_empty() {
+      current.push(',');
+    }
+    current.push_str(&range);
+  }
+  if !current.is_empty() {
+    messages.push(current);
+  }
+  messages
+}
+
+// Lê os intervalos de um NACK ou REPAIR, descartando números fora de 1..=`max_seq`.
+fn decode_ranges(argument: &str, max_seq: u32) -> Vec<u32> {
+  let mut sequences = Vec::new();
+  for range in argument.split(',') {
+    let (first, last) = match range.split_once('-') {
+      Some((first, last)) => (first.parse::<u32>(), last.parse::<u32>()),
+      None => (range.parse::<u32>(), range.parse::<u32>()),
+    };
+    if let (Ok(first), Ok(last)) = (first, last) {
+      sequences.extend(first.max(1)..=last.min(max_seq));
+    }
+  }
+  sequences
+}
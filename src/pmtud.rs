@@ -0,0 +1,222 @@
+// Descoberta do MTU do caminho na camada de pacotes, no estilo do DPLPMTUD (RFC 8899): o cliente
+// envia sondas `PROBE <tamanho>` com o bit DF ligado e o servidor confirma as que chegaram com um
+// `PROBE-ACK <tamanho>` do mesmo tamanho, também com DF, de modo que a sonda só é confirmada se
+// atravessar o caminho nos dois sentidos (a confirmação não é maior que o pedido, então não serve
+// para amplificar tráfego). Uma busca binária entre o BASE_PLPMTU e o máximo configurado encontra o
+// maior datagrama que atravessa o caminho sem fragmentação.
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+#[cfg(target_os = "linux")]
+use std::os::fd::RawFd;
+#[cfg(target_os = "linux")]
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+use tracing::info;
+#[cfg(target_os = "linux")]
+use tracing::warn;
+
+use crate::capture::{self, Direction};
+use crate::protocol::{IPV4_UDP_OVERHEAD, IPV6_UDP_OVERHEAD};
+
+// Menor PLPMTU que todo caminho deve suportar (RFC 8899, seção 5.1.2).
+pub const BASE_PLPMTU: usize = 1200;
+// Maior MTU sondado por padrão (jumbo frames).
+pub const MAX_PLPMTU: usize = 9000;
+// Tentativas por tamanho antes de considerar a sonda perdida (MAX_PROBES da RFC).
+const MAX_PROBES: usize = 3;
+const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+
+#[cfg(target_os = "linux")]
+lazy_static! {
+  // O bit DF é uma opção do socket, não do datagrama: os envios dos servidores seguram a leitura e
+  // a confirmação de uma sonda segura a escrita enquanto o DF fica forçado, para que nenhum outro
+  // envio pelo mesmo socket saia sem poder ser fragmentado.
+  static ref SEND_LOCK: RwLock<()> = RwLock::new(());
+}
+
+// Descobre o MTU do caminho até o servidor, o menor entre os dois sentidos, entre BASE_PLPMTU e
+// `max_mtu`.
+pub fn discover_path_mtu(socket: &UdpSocket, server: SocketAddr, max_mtu: usize) -> io::Result<usize> {
+  let overhead = if server.is_ipv6() { IPV6_UDP_OVERHEAD } else { IPV4_UDP_OVERHEAD };
+  set_dont_fragment(socket, server.is_ipv6())?;
+
+  let previous_timeout = socket.read_timeout()?;
+  socket.set_read_timeout(Some(PROBE_TIMEOUT))?;
+  let result = search(socket, server, BASE_PLPMTU - overhead, max_mtu.max(BASE_PLPMTU) - overhead);
+  socket.set_read_timeout(previous_timeout)?;
+
+  let payload = result?;
+  info!(mtu = payload + overhead, "path MTU discovered");
+  Ok(payload + overhead)
+}
+
+// Busca binária pelo maior payload UDP confirmado pelo servidor.
+fn search(socket: &UdpSocket, server: SocketAddr, base: usize, max: usize) -> io::Result<usize> {
+  if !probe(socket, server, base)? {
+    return Err(io::Error::new(
+      io::ErrorKind::TimedOut,
+      "servidor não confirmou nem a sonda de tamanho base",
+    ));
+  }
+
+  let (mut low, mut high) = (base, max);
+  while low < high {
+    let size = (low + high).div_ceil(2);
+    if probe(socket, server, size)? {
+      low = size;
+    } else {
+      high = size - 1;
+    }
+  }
+  Ok(low)
+}
+
+// Envia uma sonda de `size` bytes e espera a confirmação correspondente, do mesmo tamanho.
+fn probe(socket: &UdpSocket, server: SocketAddr, size: usize) -> io::Result<bool> {
+  let mut datagram = format!("PROBE {}", size).into_bytes();
+  datagram.resize(size, 0);
+  let expected = probe_ack(size);
+  let mut buf = vec![0u8; size + 1];
+
+  for _ in 0..MAX_PROBES {
+    match socket.send_to(&datagram, server) {
+      Ok(_) => capture::record_socket(socket, server, Direction::Sent, [datagram.as_slice()]),
+      // Maior que o MTU da interface local: o kernel recusa o envio com DF ligado.
+      Err(e) if is_message_too_long(&e) => return Ok(false),
+      Err(e) => return Err(e),
+    }
+
+    loop {
+      match socket.recv_from(&mut buf) {
+        Ok((len, origin)) => {
+          capture::record_socket(socket, origin, Direction::Received, [&buf[..len]]);
+          if origin == server && buf[..len] == *expected {
+            return Ok(true);
+          }
+          // Confirmações atrasadas de sondas anteriores são ignoradas.
+        },
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => break,
+        Err(e) => return Err(e),
+      }
+    }
+  }
+  Ok(false)
+}
+
+// Confirmação de uma sonda de `size` bytes, completada com zeros até o mesmo tamanho.
+pub(crate) fn probe_ack(size: usize) -> Vec<u8> {
+  let mut ack = format!("PROBE-ACK {}", size).into_bytes();
+  ack.resize(size.max(ack.len()), 0);
+  ack
+}
+
+pub(crate) fn is_probe_ack(datagram: &[u8]) -> bool {
+  datagram.starts_with(b"PROBE-ACK ")
+}
+
+// Autoriza um envio do servidor enquanto nenhuma confirmação de sonda está forçando o DF.
+#[cfg(target_os = "linux")]
+pub(crate) fn sending() -> RwLockReadGuard<'static, ()> {
+  SEND_LOCK.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Força o DF no socket do servidor até o guarda ser descartado, quando o modo anterior é restaurado.
+#[cfg(target_os = "linux")]
+pub(crate) struct DontFragment {
+  fd: RawFd,
+  ipv6: bool,
+  previous: libc::c_int,
+  _exclusive: RwLockWriteGuard<'static, ()>,
+}
+
+#[cfg(target_os = "linux")]
+impl DontFragment {
+  pub(crate) fn force(fd: RawFd, ipv6: bool) -> io::Result<DontFragment> {
+    let exclusive = SEND_LOCK.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let previous = mtu_discover(fd, ipv6)?;
+    set_mtu_discover(fd, ipv6, probe_mode(ipv6))?;
+    Ok(DontFragment { fd, ipv6, previous, _exclusive: exclusive })
+  }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for DontFragment {
+  fn drop(&mut self) {
+    if let Err(e) = set_mtu_discover(self.fd, self.ipv6, self.previous) {
+      warn!(error = %e, "could not restore path MTU discovery mode");
+    }
+  }
+}
+
+#[cfg(target_os = "linux")]
+fn is_message_too_long(e: &io::Error) -> bool {
+  e.raw_os_error() == Some(libc::EMSGSIZE)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_message_too_long(_e: &io::Error) -> bool {
+  false
+}
+
+// Liga o bit DF e ignora o PMTU em cache do kernel, para que cada sonda teste de fato o caminho.
+#[cfg(target_os = "linux")]
+fn set_dont_fragment(socket: &UdpSocket, ipv6: bool) -> io::Result<()> {
+  use std::os::fd::AsRawFd;
+
+  set_mtu_discover(socket.as_raw_fd(), ipv6, probe_mode(ipv6))
+}
+
+#[cfg(target_os = "linux")]
+fn probe_mode(ipv6: bool) -> libc::c_int {
+  if ipv6 {
+    libc::IPV6_PMTUDISC_PROBE
+  } else {
+    libc::IP_PMTUDISC_PROBE
+  }
+}
+
+#[cfg(target_os = "linux")]
+fn mtu_discover_option(ipv6: bool) -> (libc::c_int, libc::c_int) {
+  if ipv6 {
+    (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER)
+  } else {
+    (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER)
+  }
+}
+
+#[cfg(target_os = "linux")]
+fn mtu_discover(fd: RawFd, ipv6: bool) -> io::Result<libc::c_int> {
+  let (level, option) = mtu_discover_option(ipv6);
+  let mut value: libc::c_int = 0;
+  let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+  let result = unsafe { libc::getsockopt(fd, level, option, &mut value as *mut libc::c_int as *mut libc::c_void, &mut len) };
+  if result < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(value)
+}
+
+#[cfg(target_os = "linux")]
+fn set_mtu_discover(fd: RawFd, ipv6: bool, value: libc::c_int) -> io::Result<()> {
+  let (level, option) = mtu_discover_option(ipv6);
+  let result = unsafe {
+    libc::setsockopt(
+      fd,
+      level,
+      option,
+      &value as *const libc::c_int as *const libc::c_void,
+      std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+    )
+  };
+  if result < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+// Sem controle do bit DF, as sondas ainda detectam caminhos que descartam datagramas grandes.
+#[cfg(not(target_os = "linux"))]
+fn set_dont_fragment(_socket: &UdpSocket, _ipv6: bool) -> io::Result<()> {
+  Ok(())
+}
@@ -0,0 +1,402 @@
+use std::fmt;
+
+use digest::Digest;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::compression::Compression;
+
+// Constante para indicar o número de sequência de fim de transmissão.
+pub const END_OF_TRANSMISSION_SEQ_NUM: u32 = u32::MAX;
+// Número de sequência reservado para pacotes de erro (código e mensagem).
+pub const ERROR_SEQ_NUM: u32 = u32::MAX - 1;
+// Versão do protocolo, anunciada na descoberta de servidores (ver `discovery`).
+pub const PROTOCOL_VERSION: u32 = 1;
+// Porta padrão em que o servidor escuta.
+pub const SERVER_PORT: u16 = 8083;
+// Tamanho do cabeçalho: seq_number (4), src_port (2), dst_port (2), length (2), checksum (2).
+pub const HEADER_LEN: usize = 12;
+// Quantidade de dados por pacote quando o cliente não negocia outro tamanho.
+pub const CHUNK_SIZE: usize = 1472;
+// Limites aceitos na negociação do tamanho de bloco (`?chunk=` no GET).
+pub const MIN_CHUNK_SIZE: usize = 512;
+pub const MAX_CHUNK_SIZE: usize = MAX_UDP_PAYLOAD - HEADER_LEN;
+// Maior payload UDP sobre IPv4.
+pub const MAX_UDP_PAYLOAD: usize = 65_507;
+// Cabeçalhos IP e UDP somados a cada datagrama.
+pub const IPV4_UDP_OVERHEAD: usize = 28;
+pub const IPV6_UDP_OVERHEAD: usize = 48;
+
+// Estrutura que representa um pacote UDP.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UdpPacket {
+  pub seq_number: u32,
+  pub src_port: u16,
+  pub dst_port: u16,
+  pub length: u16,
+  pub checksum: u16,
+  pub data: Vec<u8>,
+}
+
+// Implementação de métodos para a estrutura UdpPacket.
+impl UdpPacket {
+  // Construtor para UdpPacket.
+  pub fn new(seq_number: u32, src_port: u16, dst_port: u16, data: Vec<u8>, length: u16, checksum: u16) -> UdpPacket {
+    UdpPacket {
+      seq_number,
+      src_port,
+      dst_port,
+      length,
+      checksum,
+      data,
+    }
+  }
+
+  // Método para serializar um pacote UDP em bytes.
+  pub fn serialize(&self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + self.data.len());
+    bytes.extend_from_slice(&self.seq_number.to_be_bytes());
+    bytes.extend_from_slice(&self.src_port.to_be_bytes());
+    bytes.extend_from_slice(&self.dst_port.to_be_bytes());
+    bytes.extend_from_slice(&self.length.to_be_bytes());
+    bytes.extend_from_slice(&self.checksum.to_be_bytes());
+    bytes.extend_from_slice(&self.data);
+
+    bytes
+  }
+
+  // Método para preparar pacotes a partir de dados brutos, com `chunk_size` bytes de dados por pacote.
+  // `mtime` (segundos desde a época Unix) vai nos metadados do pacote de cabeçalho.
+  pub fn prepare_packets(src_port: u16, dst_port: u16, data: Vec<u8>, chunk_size: usize, mtime: u64) -> Vec<UdpPacket> {
+    let mut packets = Vec::new();
+
+    // Primeiro pacote com os metadados: total de pacotes, tamanho de bloco, tamanho, mtime e hash
+    packets.push(FileMeta::new(&data, chunk_size, mtime).header_packet(src_port, dst_port));
+
+    // Demais pacotes com os dados
+    for (index, chunk) in data.chunks(chunk_size).enumerate() {
+      let seq_number = index as u32 + 1; // Começando de 1 porque 0 é o cabeçalho
+      packets.push(UdpPacket::data_packet(seq_number, src_port, dst_port, chunk.to_vec()));
+    }
+    packets
+  }
+
+  // Pacote de dados, com o checksum calculado sobre o conteúdo.
+  pub fn data_packet(seq_number: u32, src_port: u16, dst_port: u16, data: Vec<u8>) -> UdpPacket {
+    let checksum = calculate_checksum(&data);
+    let length = data.len() as u16 + 8;
+    UdpPacket::new(seq_number, src_port, dst_port, data, length, checksum)
+  }
+
+  // Pacote que sinaliza o fim de uma transmissão.
+  pub fn end_of_transmission(src_port: u16, dst_port: u16) -> UdpPacket {
+    UdpPacket::new(END_OF_TRANSMISSION_SEQ_NUM, src_port, dst_port, Vec::new(), 8, 0)
+  }
+
+  // Pacote de erro: código numérico (2 bytes) seguido de uma mensagem opcional em UTF-8.
+  pub fn error(src_port: u16, dst_port: u16, code: ErrorCode, message: &str) -> UdpPacket {
+    let mut data = code.as_u16().to_be_bytes().to_vec();
+    data.extend_from_slice(message.as_bytes());
+    let checksum = calculate_checksum(&data);
+    let length = data.len() as u16 + 8;
+    UdpPacket::new(ERROR_SEQ_NUM, src_port, dst_port, data, length, checksum)
+  }
+}
+
+// Códigos de erro enviados pelo servidor. Os valores seguem os status HTTP equivalentes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+  BadRequest,
+  // Credenciais ausentes, inválidas, fora da janela de tempo ou repetidas (ver `auth`).
+  Unauthorized,
+  Forbidden,
+  NotFound,
+  // O destino de um PUT já existe e a política de sobrescrita não permite substituí-lo.
+  AlreadyExists,
+  // O upload anunciado é maior que o permitido pelo servidor.
+  TooLarge,
+  // O conteúdo recebido não confere com o SHA-256 anunciado.
+  IntegrityFailure,
+  Internal,
+  // O servidor atingiu um limite (sessões, memória) e recusou a requisição.
+  Busy,
+  // A requisição depende de uma sessão (transferência) que o servidor não conhece ou já descartou.
+  SessionUnknown,
+  // Código não reconhecido por esta versão.
+  Other(u16),
+}
+
+impl ErrorCode {
+  pub fn as_u16(&self) -> u16 {
+    match self {
+      ErrorCode::BadRequest => 400,
+      ErrorCode::Unauthorized => 401,
+      ErrorCode::Forbidden => 403,
+      ErrorCode::NotFound => 404,
+      ErrorCode::AlreadyExists => 409,
+      ErrorCode::SessionUnknown => 410,
+      ErrorCode::TooLarge => 413,
+      ErrorCode::IntegrityFailure => 422,
+      ErrorCode::Internal => 500,
+      ErrorCode::Busy => 503,
+      ErrorCode::Other(code) => *code,
+    }
+  }
+
+  pub fn from_u16(code: u16) -> ErrorCode {
+    match code {
+      400 => ErrorCode::BadRequest,
+      401 => ErrorCode::Unauthorized,
+      403 => ErrorCode::Forbidden,
+      404 => ErrorCode::NotFound,
+      409 => ErrorCode::AlreadyExists,
+      410 => ErrorCode::SessionUnknown,
+      413 => ErrorCode::TooLarge,
+      422 => ErrorCode::IntegrityFailure,
+      500 => ErrorCode::Internal,
+      503 => ErrorCode::Busy,
+      other => ErrorCode::Other(other),
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      ErrorCode::BadRequest => "BAD_REQUEST",
+      ErrorCode::Unauthorized => "UNAUTHORIZED",
+      ErrorCode::Forbidden => "FORBIDDEN",
+      ErrorCode::NotFound => "NOT_FOUND",
+      ErrorCode::AlreadyExists => "ALREADY_EXISTS",
+      ErrorCode::TooLarge => "TOO_LARGE",
+      ErrorCode::IntegrityFailure => "INTEGRITY_FAILURE",
+      ErrorCode::Internal => "INTERNAL",
+      ErrorCode::Busy => "BUSY",
+      ErrorCode::SessionUnknown => "SESSION_UNKNOWN",
+      ErrorCode::Other(_) => "UNKNOWN",
+    }
+  }
+}
+
+impl fmt::Display for ErrorCode {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} {}", self.as_u16(), self.name())
+  }
+}
+
+// Comandos das mensagens de texto: requisições do cliente e respostas do servidor.
+pub const TEXT_COMMANDS: [&str; 14] = [
+  "GET", "STAT", "LIST", "PUT", "DELTA", "PROBE", "RETRANSMIT", "PUT-READY", "PUT-OK", "PROBE-ACK", "NACK", "REPAIR",
+  "DISCOVER", "SERVER",
+];
+
+// Um datagrama do protocolo, decodificado sem depender do estado da transferência. Mensagens de
+// texto são reconhecidas pelo comando inicial; os demais datagramas são pacotes binários
+// identificados pelo número de sequência.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Datagram<'a> {
+  // Mensagem de texto, sem os zeros de preenchimento (sondas PROBE).
+  Text { command: &'a str, argument: &'a str },
+  // Pacote de cabeçalho (seq 0); `None` quando os metadados não puderam ser lidos.
+  Header(Option<FileMeta>),
+  Data { seq_number: u32, payload: &'a [u8], checksum_valid: bool },
+  EndOfTransmission,
+  Error { code: ErrorCode, message: String, checksum_valid: bool },
+  // Curto demais para um cabeçalho ou com formato desconhecido.
+  Unknown,
+}
+
+impl<'a> Datagram<'a> {
+  pub fn decode(datagram: &'a [u8]) -> Datagram<'a> {
+    if let Some(text) = decode_text(datagram) {
+      return text;
+    }
+    if datagram.len() < HEADER_LEN {
+      return Datagram::Unknown;
+    }
+    let seq_number = u32::from_be_bytes([datagram[0], datagram[1], datagram[2], datagram[3]]);
+    let payload = &datagram[HEADER_LEN..];
+    let checksum_valid = calculate_checksum(payload) == u16::from_be_bytes([datagram[10], datagram[11]]);
+    match seq_number {
+      END_OF_TRANSMISSION_SEQ_NUM => Datagram::EndOfTransmission,
+      ERROR_SEQ_NUM if payload.len() >= 2 => Datagram::Error {
+        code: ErrorCode::from_u16(u16::from_be_bytes([payload[0], payload[1]])),
+        message: String::from_utf8_lossy(&payload[2..]).into_owned(),
+        checksum_valid,
+      },
+      ERROR_SEQ_NUM => Datagram::Unknown,
+      // O cabeçalho não leva checksum; os metadados trazem o SHA-256 do conteúdo.
+      0 => Datagram::Header(FileMeta::decode(payload)),
+      _ => Datagram::Data { seq_number, payload, checksum_valid },
+    }
+  }
+}
+
+fn decode_text(datagram: &[u8]) -> Option<Datagram<'_>> {
+  let end = datagram.iter().rposition(|&byte| byte != 0).map_or(0, |last| last + 1);
+  let text = std::str::from_utf8(&datagram[..end]).ok()?;
+  let (command, argument) = text.split_once(' ').unwrap_or((text, ""));
+  TEXT_COMMANDS.contains(&command).then_some(Datagram::Text { command, argument })
+}
+
+// Lê um pacote de erro. Devolve `None` se o datagrama não for um erro ou estiver corrompido.
+pub fn decode_error(datagram: &[u8]) -> Option<(ErrorCode, String)> {
+  if datagram.len() < HEADER_LEN + 2 || datagram[..4] != ERROR_SEQ_NUM.to_be_bytes() {
+    return None;
+  }
+  let data = &datagram[HEADER_LEN..];
+  if calculate_checksum(data) != u16::from_be_bytes([datagram[10], datagram[11]]) {
+    return None;
+  }
+  let code = ErrorCode::from_u16(u16::from_be_bytes([data[0], data[1]]));
+  Some((code, String::from_utf8_lossy(&data[2..]).into_owned()))
+}
+
+// Metadados de um arquivo, enviados no pacote de cabeçalho (seq 0) de uma transferência e como
+// resposta a um STAT. Os dois primeiros campos mantêm a posição do cabeçalho original.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileMeta {
+  // Pacotes da transferência, incluindo o de cabeçalho.
+  pub total_packets: u32,
+  pub chunk_size: u32,
+  pub size: u64,
+  // Última modificação, em segundos desde a época Unix (0 quando não se aplica).
+  pub mtime: u64,
+  pub sha256: [u8; 32],
+  // Compressão dos pacotes de dados; tamanho e hash se referem ao conteúdo original.
+  pub compression: Compression,
+}
+
+impl FileMeta {
+  pub const ENCODED_LEN: usize = 57;
+
+  pub fn new(data: &[u8], chunk_size: usize, mtime: u64) -> FileMeta {
+    FileMeta {
+      total_packets: data.len().div_ceil(chunk_size) as u32 + 1, // +1 para incluir o pacote de cabeçalho
+      chunk_size: chunk_size as u32,
+      size: data.len() as u64,
+      mtime,
+      sha256: Sha256::digest(data).into(),
+      compression: Compression::None,
+    }
+  }
+
+  pub fn encode(&self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(FileMeta::ENCODED_LEN);
+    bytes.extend_from_slice(&self.total_packets.to_be_bytes());
+    bytes.extend_from_slice(&self.chunk_size.to_be_bytes());
+    bytes.extend_from_slice(&self.size.to_be_bytes());
+    bytes.extend_from_slice(&self.mtime.to_be_bytes());
+    bytes.extend_from_slice(&self.sha256);
+    bytes.push(self.compression.id());
+    bytes
+  }
+
+  pub fn decode(data: &[u8]) -> Option<FileMeta> {
+    if data.len() < FileMeta::ENCODED_LEN {
+      return None;
+    }
+    let u32_at = |i: usize| u32::from_be_bytes(data[i..i + 4].try_into().unwrap());
+    let u64_at = |i: usize| u64::from_be_bytes(data[i..i + 8].try_into().unwrap());
+    Some(FileMeta {
+      total_packets: u32_at(0),
+      chunk_size: u32_at(4),
+      size: u64_at(8),
+      mtime: u64_at(16),
+      sha256: data[24..56].try_into().unwrap(),
+      compression: Compression::from_id(data[56])?,
+    })
+  }
+
+  // Pacote de cabeçalho (seq 0) com estes metadados.
+  pub fn header_packet(&self, src_port: u16, dst_port: u16) -> UdpPacket {
+    let data = self.encode();
+    let length = data.len() as u16 + 8;
+    UdpPacket::new(0, src_port, dst_port, data, length, 0)
+  }
+
+  pub fn sha256_hex(&self) -> String {
+    self.sha256.iter().map(|byte| format!("{:02x}", byte)).collect()
+  }
+}
+
+// Tamanho de bloco que cabe, sem fragmentação, em um caminho com o MTU informado.
+pub fn chunk_size_for_mtu(mtu: usize, ipv6: bool) -> usize {
+  let overhead = if ipv6 { IPV6_UDP_OVERHEAD } else { IPV4_UDP_OVERHEAD };
+  mtu.saturating_sub(overhead + HEADER_LEN).clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)
+}
+
+// Função para calcular o checksum de um bloco de dados.
+pub fn calculate_checksum(data: &[u8]) -> u16 {
+  let sum: u32 = data
+    .chunks(2)
+    .fold(0, |acc, chunk| {
+      let word = chunk
+        .iter()
+        .enumerate()
+        .fold(0u16, |word_acc, (i, &byte)| word_acc | ((byte as u16) << ((1 - i) * 8)));
+      acc + word as u32
+    });
+
+  let wrapped_sum = (sum & 0xFFFF) + (sum >> 16);
+  let wrapped_sum = (wrapped_sum & 0xFFFF) + (wrapped_sum >> 16);
+  !wrapped_sum as u16
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const KNOWN_CODES: [ErrorCode; 10] = [
+    ErrorCode::BadRequest,
+    ErrorCode::Unauthorized,
+    ErrorCode::Forbidden,
+    ErrorCode::NotFound,
+    ErrorCode::AlreadyExists,
+    ErrorCode::TooLarge,
+    ErrorCode::IntegrityFailure,
+    ErrorCode::Internal,
+    ErrorCode::Busy,
+    ErrorCode::SessionUnknown,
+  ];
+
+  #[test]
+  fn error_codes_survive_the_wire_format() {
+    for code in KNOWN_CODES {
+      assert_eq!(ErrorCode::from_u16(code.as_u16()), code);
+      assert_ne!(code.name(), "UNKNOWN");
+    }
+    assert_eq!(ErrorCode::from_u16(418), ErrorCode::Other(418));
+    assert_eq!(ErrorCode::Other(418).as_u16(), 418);
+    assert_eq!(ErrorCode::NotFound.to_string(), "404 NOT_FOUND");
+    assert_eq!(ErrorCode::Other(418).to_string(), "418 UNKNOWN");
+  }
+
+  #[test]
+  fn error_packets_carry_code_and_message() {
+    let datagram = UdpPacket::error(SERVER_PORT, 4000, ErrorCode::Busy, "Servidor ocupado").serialize();
+    assert_eq!(decode_error(&datagram), Some((ErrorCode::Busy, "Servidor ocupado".to_string())));
+    match Datagram::decode(&datagram) {
+      Datagram::Error { code, message, checksum_valid } => {
+        assert_eq!((code, message.as_str(), checksum_valid), (ErrorCode::Busy, "Servidor ocupado", true));
+      },
+      other => panic!("esperava um erro, obtido {:?}", other),
+    }
+
+    // Um erro corrompido não é confundido com uma resposta válida.
+    let mut corrupted = datagram.clone();
+    *corrupted.last_mut().unwrap() ^= 0xff;
+    assert_eq!(decode_error(&corrupted), None);
+    assert_eq!(decode_error(&datagram[..HEADER_LEN + 1]), None);
+    let data = UdpPacket::data_packet(1, SERVER_PORT, 4000, vec![0; 16]).serialize();
+    assert_eq!(decode_error(&data), None);
+  }
+
+  #[test]
+  fn decodes_text_and_control_datagrams() {
+    let mut probe = b"PROBE 1200".to_vec();
+    probe.resize(1200, 0);
+    assert_eq!(Datagram::decode(&probe), Datagram::Text { command: "PROBE", argument: "1200" });
+    assert_eq!(Datagram::decode(b"RETRANSMIT 1,2"), Datagram::Text { command: "RETRANSMIT", argument: "1,2" });
+    assert_eq!(Datagram::decode(&UdpPacket::end_of_transmission(SERVER_PORT, 4000).serialize()), Datagram::EndOfTransmission);
+    assert_eq!(Datagram::decode(b"GE"), Datagram::Unknown);
+  }
+}
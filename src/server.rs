@@ -1,273 +1,264 @@
-use std::{env, fs};
-use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Read};
-use std::net::{SocketAddr, UdpSocket};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::thread;
+use std::env;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use tracing::info;
+
+use rawsocket_udp::acl::Acl;
+use rawsocket_udp::auth::{Authenticator, KeySet};
+use rawsocket_udp::batch::BatchOptions;
+use rawsocket_udp::capture;
+use rawsocket_udp::discovery::DiscoveryConfig;
+use rawsocket_udp::handler::Handler;
+use rawsocket_udp::journal;
+use rawsocket_udp::limits::SessionLimits;
+use rawsocket_udp::logging::{self, LogFormat};
+use rawsocket_udp::multicast::{Sender, SenderConfig};
+use rawsocket_udp::service::Server;
+use rawsocket_udp::shaping::{RateLimit, ShapingConfig};
+use rawsocket_udp::source::{files_dir, validate_path, FileSource, FsSource};
 
-use serde::{Deserialize, Serialize};
-
-// Constante para indicar o número de sequência de fim de transmissão.
-const END_OF_TRANSMISSION_SEQ_NUM: u32 = u32::MAX;
-const TMP_PATH: &str = "D:\\Desktop\\TI\\projetos\\rawsocket-udp-rust\\src\\packets.tmp";
-
-// Macro para uso de variáveis estáticas.
-#[macro_use]
-extern crate lazy_static;
-
-// Estrutura que representa um pacote UDP.
-#[derive(Clone, Serialize, Deserialize)]
-struct UdpPacket {
-  seq_number: u32,
-  src_port: u16,
-  dst_port: u16,
-  length: u16,
-  checksum: u16,
-  data: Vec<u8>,
-}
-
-// Armazenamento estático para pacotes, usando um Mutex para acesso seguro entre threads.
-lazy_static! {
-  static ref PACKETS_STORAGE: Mutex<HashMap<u32, UdpPacket>> = Mutex::new(HashMap::new());
-}
-
-fn save_packets_to_file(packets: &HashMap<u32, UdpPacket>) -> io::Result<()> {
-  let file = File::create(TMP_PATH).expect("error creating temporary file");
-  let writer = BufWriter::new(file);
-  serde_json::to_writer(writer, packets)?;
-  Ok(())
-}
-
-fn load_packets_from_file(filepath: &str) -> io::Result<HashMap<u32, UdpPacket>> {
-  let file = File::open(filepath)?;
-  let reader = BufReader::new(file);
-  let packets = serde_json::from_reader(reader)?;
-  Ok(packets)
-}
+// Função principal que configura e executa o servidor UDP.
+fn main() -> io::Result<()> {
+  let args: Vec<String> = env::args().skip(1).collect();
+  init_logging(&args)?;
+  // `--pcap arquivo`: grava todos os datagramas enviados e recebidos em um arquivo pcap.
+  if let Some(path) = flag_value(&args, "--pcap") {
+    capture::start(Path::new(path))?;
+    info!(path, "capturing traffic");
+  }
+  let batch = BatchOptions { gso: args.iter().any(|arg| arg == "--gso"), gro: false };
+  if let Some(path) = flag_value(&args, "--multicast") {
+    return run_multicast(&args, path, batch);
+  }
 
-// Implementação de métodos para a estrutura UdpPacket.
-impl UdpPacket {
-  // Construtor para UdpPacket.
-  fn new(seq_number: u32, src_port: u16, dst_port: u16, data: Vec<u8>, length: u16, checksum: u16) -> UdpPacket {
-    UdpPacket {
-      seq_number,
-      src_port,
-      dst_port,
-      length,
-      checksum,
-      data,
+  // `--journal dir`: diário das sessões de retransmissão, recuperadas ao reiniciar o servidor.
+  let journal_dir = flag_value(&args, "--journal").map_or_else(journal::default_dir, PathBuf::from);
+  // Arquivos servidos a partir de src/files, relativo ao executável (origem padrão do builder).
+  let mut builder = Server::builder().batch(batch).journal(journal_dir).limits(session_limits(&args)?).shaping(shaping(&args)?);
+  // `--auth-keys arquivo`: exige token ou requisição assinada com uma das chaves (formato descrito
+  // em `auth`); `--auth-window SECS` é a tolerância do horário das assinaturas e `--allow-anonymous`
+  // aceita também clientes sem credenciais.
+  if let Some(path) = flag_value(&args, "--auth-keys") {
+    let keys = KeySet::load(Path::new(path))?;
+    info!(keys = keys.len(), "client authentication enabled");
+    let mut authenticator = Authenticator::new(keys).allow_anonymous(args.iter().any(|arg| arg == "--allow-anonymous"));
+    if let Some(secs) = parsed_flag::<u64>(&args, "--auth-window")? {
+      authenticator = authenticator.window(Duration::from_secs(secs));
     }
+    builder = builder.authenticate(authenticator);
   }
-
-  // Método para serializar um pacote UDP em bytes.
-  fn serialize(&self) -> Vec<u8> {
-    let mut bytes = Vec::new();
-    bytes.extend_from_slice(&self.seq_number.to_be_bytes());
-    bytes.extend_from_slice(&self.src_port.to_be_bytes());
-    bytes.extend_from_slice(&self.dst_port.to_be_bytes());
-    bytes.extend_from_slice(&self.length.to_be_bytes());
-    bytes.extend_from_slice(&self.checksum.to_be_bytes());
-    bytes.extend(self.data.clone());
-
-    bytes
+  // `--acl arquivo`: regras de acesso aos caminhos servidos (formato descrito em `acl`).
+  if let Some(path) = flag_value(&args, "--acl") {
+    builder = builder.acl(Acl::load(Path::new(path))?);
+  }
+  if !args.iter().any(|arg| arg == "--no-discovery") {
+    builder = builder.discovery(discovery(&args)?);
+  }
+  // `--metrics 127.0.0.1:9183`: expõe as métricas no formato do Prometheus.
+  if let Some(address) = flag_value(&args, "--metrics") {
+    let address: SocketAddr = address
+      .parse()
+      .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "--metrics inválido"))?;
+    builder = builder.metrics(address);
   }
 
-  // Método para preparar pacotes a partir de dados brutos.
-fn prepare_packets(src_port: u16, dst_port: u16, data: Vec<u8>) -> Vec<UdpPacket> {
-    let total_packets = (data.len() as f32 / 1472.0).ceil() as u32 + 1; // +1 para incluir o pacote de cabeçalho
-    let mut packets = Vec::new();
+  if args.iter().any(|arg| arg == "--async") {
+    return run_async(&args, batch, builder.into_handler()?);
+  }
+  if let Some(workers) = flag_value(&args, "--workers") {
+    let workers = workers
+      .parse()
+      .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "--workers inválido"))?;
+    return run_workers(workers, batch, builder.into_handler()?);
+  }
 
-    // Primeiro pacote com o total de pacotes
-    packets.push(UdpPacket::new(0, src_port, dst_port, total_packets.to_be_bytes().to_vec(), 8, 0));
+  let server = builder.build()?;
+  info!(address = %server.local_addr()?, "listening");
+  server.run()
+}
 
-    // Demais pacotes com os dados
-    for (index, chunk) in data.chunks(1472).enumerate() {
-        let seq_number = index as u32 + 1; // Começando de 1 porque 0 é o cabeçalho
-        let checksum = UdpPacket::calculate_checksum(chunk);
-        let length = chunk.len() as u16 + 8;
-        packets.push(UdpPacket::new(seq_number, src_port, dst_port, chunk.to_vec(), length, checksum));
-    }
-    packets
+// Logs na saída de erro: `--log-format human|json` e `--log-level` com um filtro no formato de
+// RUST_LOG (por exemplo `debug` para ver cada pacote retransmitido). O padrão é `info`, uma linha
+// por requisição.
+fn init_logging(args: &[String]) -> io::Result<()> {
+  let format = match flag_value(args, "--log-format") {
+    Some(value) => LogFormat::parse(value)
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--log-format inválido"))?,
+    None => LogFormat::Human,
+  };
+  logging::init(format, flag_value(args, "--log-level"), "info")
 }
-  // Método para calcular o checksum de um bloco de dados.
-fn calculate_checksum(data: &[u8]) -> u16 {
-    let sum: u32 = data
-      .chunks(2)
-      .fold(0, |acc, chunk| {
-        let word = chunk
-          .iter()
-          .enumerate()
-          .fold(0u16, |word_acc, (i, &byte)| word_acc | ((byte as u16) << ((1 - i) * 8)));
-        acc + word as u32
-      });
 
-    let wrapped_sum = (sum & 0xFFFF) + (sum >> 16);
-    let wrapped_sum = (wrapped_sum & 0xFFFF) + (wrapped_sum >> 16);
-    !wrapped_sum as u16
+// Limites das sessões: `--idle-timeout` e `--max-lifetime` em segundos, `--session-limit` e
+// `--peer-session-limit` (sessões simultâneas no total e por IP), `--memory-limit` em MiB guardados
+// em memória e `--max-upload` em MiB. Sessões além dos limites recebem BUSY; uploads maiores
+// que o máximo, TOO_LARGE.
+fn session_limits(args: &[String]) -> io::Result<SessionLimits> {
+  let mut limits = SessionLimits::default();
+  if let Some(secs) = parsed_flag::<u64>(args, "--idle-timeout")? {
+    limits.idle_timeout = Duration::from_secs(secs);
+  }
+  if let Some(secs) = parsed_flag::<u64>(args, "--max-lifetime")? {
+    limits.max_lifetime = Duration::from_secs(secs);
+  }
+  if let Some(sessions) = parsed_flag(args, "--session-limit")? {
+    limits.max_sessions = sessions;
+  }
+  if let Some(sessions) = parsed_flag(args, "--peer-session-limit")? {
+    limits.max_sessions_per_peer = sessions;
   }
+  if let Some(mib) = parsed_flag::<usize>(args, "--memory-limit")? {
+    limits.max_buffered_bytes = mib.saturating_mul(1024 * 1024);
+  }
+  if let Some(mib) = parsed_flag::<u64>(args, "--max-upload")? {
+    limits.max_upload_size = mib.saturating_mul(1024 * 1024);
+  }
+  Ok(limits)
 }
 
-// Função principal que configura e executa o servidor UDP.
-fn main() -> io::Result<()> {
-  let socket = UdpSocket::bind("0.0.0.0:8083")?;
-  println!("Escutando em 127.0.0.1:8083...");
-  let packets_storage = Arc::new(Mutex::new(load_packets_from_file(TMP_PATH).unwrap_or_else(|_| HashMap::new())));
-
-  *PACKETS_STORAGE.lock().unwrap() = load_packets_from_file(TMP_PATH).unwrap_or_else(|_| HashMap::new());
-
-  loop {
-    let mut buf = [0u8; 2048];
-    let (size, client_address) = socket.recv_from(&mut buf)?;
-    let request = std::str::from_utf8(&buf[..size]).unwrap_or_default().to_string();
-    let socket_clone = socket.try_clone()?;
-    let storage_clone = Arc::clone(&packets_storage);
-
-    thread::spawn(move || {
-      handle_client_request(socket_clone, client_address, request, storage_clone);
-    });  
+// Descoberta na rede local, ligada por padrão (`--no-discovery` a desliga): `--name` é o nome
+// anunciado (por padrão, o nome da máquina), `--advertise ENDEREÇO:PORTA` o endereço que os clientes
+// devem usar, quando não é a origem das respostas, e `--interface IP` a interface em que o servidor
+// entra no grupo de descoberta.
+fn discovery(args: &[String]) -> io::Result<DiscoveryConfig> {
+  let name = match flag_value(args, "--name") {
+    Some(name) => name.to_string(),
+    None => fs::read_to_string("/proc/sys/kernel/hostname")
+      .map(|name| name.trim().to_string())
+      .ok()
+      .or_else(|| env::var("HOSTNAME").ok())
+      .filter(|name| !name.is_empty())
+      .unwrap_or_else(|| "rawsocket-udp".to_string()),
+  };
+  let mut config = DiscoveryConfig::new(name);
+  config.advertise = parsed_flag(args, "--advertise")?;
+  if let Some(interface) = parsed_flag(args, "--interface")? {
+    config.interface = interface;
   }
+  Ok(config)
 }
 
-fn handle_client_request(socket: UdpSocket, client_address: SocketAddr, request: String, storage: Arc<Mutex<HashMap<u32, UdpPacket>>>) {
-  println!("Request: {}", request);
-  if request.starts_with("GET /") {
-      handle_get_request(&socket, &request, client_address, storage);
-  } else if request.starts_with("RETRANSMIT ") {
-      println!("Handling retransmission request.");
-      handle_retransmission_request(&socket, &request, client_address, storage);
-  } else {
-      println!("Invalid request: {}", request);
+// Limites de taxa de envio em bytes por segundo, com sufixo K, M ou G: `--rate-limit` para todo o
+// servidor, `--peer-rate-limit` para cada IP, `--subnet-rate-limit 10.0.0.0/8=50M`, repetível, para
+// cada sub-rede e `--identity-rate-limit` para cada chave de `--auth-keys`.
+fn shaping(args: &[String]) -> io::Result<ShapingConfig> {
+  let rate = |flag: &str, value: &str| {
+    RateLimit::parse(value).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} inválido", flag)))
+  };
+  let mut config = ShapingConfig::default();
+  if let Some(value) = flag_value(args, "--rate-limit") {
+    config.global = Some(rate("--rate-limit", value)?);
+  }
+  if let Some(value) = flag_value(args, "--peer-rate-limit") {
+    config.per_peer = Some(rate("--peer-rate-limit", value)?);
+  }
+  if let Some(value) = flag_value(args, "--identity-rate-limit") {
+    config.per_identity = Some(rate("--identity-rate-limit", value)?);
   }
+  for value in flag_values(args, "--subnet-rate-limit") {
+    let (subnet, limit) = value
+      .split_once('=')
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--subnet-rate-limit inválido"))?;
+    config.subnets.push((subnet.parse()?, rate("--subnet-rate-limit", limit)?));
+  }
+  Ok(config)
 }
 
-fn handle_get_request(socket: &UdpSocket, request: &str, client_address: SocketAddr, storage: Arc<Mutex<HashMap<u32, UdpPacket>>>) -> io::Result<()> {
-  // Dividindo a requisição em partes para análise
-  let parts: Vec<&str> = request.split_whitespace().collect();
-  if parts.len() < 2 {
-      return send_error_message(socket, "Requisição mal formatada", client_address);
+// Modo multicast: `--multicast caminho` transmite um arquivo servido uma vez para o grupo
+// `--group 239.255.0.83:9083` e encerra depois de atender os NACKs dos receptores. `--interface IP`
+// escolhe a interface de saída, `--ttl N` o alcance, `--fec K` envia um pacote de paridade a cada K
+// pacotes de dados, `--rate` limita a taxa (como `--rate-limit`), `--linger SECS` é a espera por
+// NACKs depois da última transmissão e `--chunk N` o tamanho de bloco.
+fn run_multicast(args: &[String], path: &str, batch: BatchOptions) -> io::Result<()> {
+  let mut config = SenderConfig::default();
+  if let Some(group) = parsed_flag(args, "--group")? {
+    config.group = group;
   }
-  let path = parts[1];
-
-  // Identificando a partir de qual pacote a transmissão deve começar, se especificado
-  let mut start_packet = 0;
-  if let Some(query_idx) = path.find("?start=") {
-      if let Ok(num) = path[query_idx + 7..].parse::<u32>() {
-          start_packet = num;
-      }
+  if let Some(interface) = parsed_flag(args, "--interface")? {
+    config.interface = interface;
   }
-
-  // Extraindo o nome do arquivo da URL, considerando que pode haver uma query string
-  let filename = if let Some(idx) = path.find('?') {
-      &path[1..idx]
-  } else {
-      &path[1..]
-  };
-
-  // Verificando se o nome do arquivo não está vazio
-  if filename.is_empty() {
-      return send_error_message(socket, "Nome do arquivo não especificado", client_address);
+  if let Some(ttl) = parsed_flag(args, "--ttl")? {
+    config.ttl = ttl;
   }
-
-  match get_file_data(filename) {
-      Ok(data) => {
-          let packets = UdpPacket::prepare_packets(8083, client_address.port(), data);
-          for packet in packets {
-              send_packet(&socket, packet, client_address)?;
-          }
-          send_end_of_transmission_packet(&socket, client_address)?;
-      },
-      Err(e) if e.kind() == io::ErrorKind::NotFound => {
-          println!("File not found: {}", filename);
-          send_error_message(&socket, "Arquivo não encontrado", client_address)?;
-      },
-      Err(e) => {
-          println!("Error reading file: {}", e);
-          send_error_message(&socket, &format!("Erro ao ler o arquivo: {}", e), client_address)?;
-      }
+  config.fec = parsed_flag(args, "--fec")?;
+  if let Some(value) = flag_value(args, "--rate") {
+    config.rate = Some(RateLimit::parse(value).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--rate inválido"))?);
+  }
+  if let Some(secs) = parsed_flag::<f64>(args, "--linger")? {
+    config.linger = Duration::try_from_secs_f64(secs).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "--linger inválido"))?;
   }
+  if let Some(chunk_size) = parsed_flag(args, "--chunk")? {
+    config.chunk_size = chunk_size;
+  }
+
+  let source = FsSource::new(files_dir()?);
+  let file = source.read(validate_path(path.trim_start_matches('/'))?)?;
+  let sender = Sender::new(config, batch)?;
+  info!(path, address = %sender.local_addr()?, "multicast sender ready");
+  sender.send(&file.data, file.mtime)?;
   Ok(())
 }
 
+// Modo multi-core: vários workers com sockets na mesma porta (SO_REUSEPORT).
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn run_workers(workers: usize, batch: BatchOptions, handler: Handler) -> io::Result<()> {
+  use rawsocket_udp::workers::{self, WorkerConfig};
 
-// Funções auxiliares para enviar pacotes, tratar requisições de retransmissão e acessar dados do arquivo.
-fn get_packet_for_sequence(seq_number: u32) -> Option<UdpPacket> {
-  let packets = PACKETS_STORAGE.lock().unwrap();
-  packets.get(&seq_number).cloned()
+  let address = SocketAddr::from(([0, 0, 0, 0], 8083));
+  info!(%address, workers, "listening");
+  workers::run(address, WorkerConfig { workers, batch }, handler)
 }
 
-fn send_packet(socket: &UdpSocket, packet: UdpPacket, destination: SocketAddr) -> io::Result<()> {
-  let packet_bytes = packet.serialize();
-  socket.send_to(&packet_bytes, destination)?;
-
-  let mut packets = PACKETS_STORAGE.lock().unwrap();
-  packets.insert(packet.seq_number, packet);
-  save_packets_to_file(&packets).expect("error creating tmp files");
-  Ok(())
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn run_workers(_workers: usize, _batch: BatchOptions, _handler: Handler) -> io::Result<()> {
+  Err(io::Error::new(io::ErrorKind::Unsupported, "SO_REUSEPORT não está disponível nesta plataforma"))
 }
 
-fn send_end_of_transmission_packet(socket: &UdpSocket, destination: SocketAddr) -> io::Result<()> {
-  let end_packet = UdpPacket::new(
-    END_OF_TRANSMISSION_SEQ_NUM,
-    8083,
-    destination.port(),
-    Vec::new(),
-    8,
-    0,
-  );
+// Modo assíncrono: sessões atendidas por tarefas do tokio, com limite de concorrência.
+#[cfg(feature = "tokio")]
+fn run_async(args: &[String], batch: BatchOptions, handler: Handler) -> io::Result<()> {
+  use rawsocket_udp::async_server::{self, AsyncServerConfig};
 
-  let packet_bytes = end_packet.serialize();
-  socket.send_to(&packet_bytes, destination)?;
-  
-  if let Err(e) = fs::remove_file(TMP_PATH) {
-    println!("Failed to delete temporary file: {}", e);
+  let mut config = AsyncServerConfig { batch, ..AsyncServerConfig::default() };
+  if let Some(max_sessions) = flag_value(args, "--max-sessions") {
+    config.max_sessions = max_sessions
+      .parse()
+      .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "--max-sessions inválido"))?;
   }
-  Ok(())
-}
 
-fn get_file_data(filename: &str) -> io::Result<Vec<u8>> {
-    let exe_path = env::current_exe()?;
-    let exe_dir = exe_path.parent().ok_or(io::Error::new(io::ErrorKind::Other, "Failed to get executable directory"))?;
-    let files_dir = exe_dir.join("../../src/files");
-    let path = files_dir.join(filename);
-    let mut file = match File::open(&path) {
-        Ok(file) => file,
-        Err(_) => return Err(io::Error::new(io::ErrorKind::NotFound, "File not found"))
-    };
-
-    let mut data = Vec::new();
-    file.read_to_end(&mut data)?;
-    Ok(data)
+  let runtime = tokio::runtime::Runtime::new()?;
+  runtime.block_on(async {
+    let socket = tokio::net::UdpSocket::bind("0.0.0.0:8083").await?;
+    info!(address = %socket.local_addr()?, max_sessions = config.max_sessions, "listening (async)");
+    async_server::run(socket, config, handler).await
+  })
 }
 
-fn handle_retransmission_request(socket: &UdpSocket, request: &str, client_address: SocketAddr, storage: Arc<Mutex<HashMap<u32, UdpPacket>>>) -> io::Result<()> {
-  let sequences: Vec<u32> = request.trim_start_matches("RETRANSMIT ")
-                                  .split(',')
-                                  .filter_map(|s| s.parse::<u32>().ok())
-                                  .collect();
-
-  let mut retransmitted_any = false;
+#[cfg(not(feature = "tokio"))]
+fn run_async(_args: &[String], _batch: BatchOptions, _handler: Handler) -> io::Result<()> {
+  Err(io::Error::new(
+    io::ErrorKind::Unsupported,
+    "modo assíncrono requer a feature `tokio` (cargo run --features tokio --bin server -- --async)",
+  ))
+}
 
-  for seq_number in sequences {
-      println!("Verificando sequência de pacotes: {}", seq_number);
-      if let Some(packet) = get_packet_for_sequence(seq_number) {
-          println!("Retransmitindo pacote para número de sequência: {}", seq_number);
-          send_packet(socket, packet, client_address)?;
-          retransmitted_any = true;
-      } else {
-          println!("Nenhum pacote encontrado para número de sequência: {}", seq_number);
-      }
-  }
-  
-  if retransmitted_any {
-    send_end_of_transmission_packet(socket, client_address)?;
-  }
+fn parsed_flag<T: FromStr>(args: &[String], flag: &str) -> io::Result<Option<T>> {
+  flag_value(args, flag)
+    .map(|value| value.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{} inválido", flag))))
+    .transpose()
+}
 
-  Ok(())
+// Valor do argumento que segue a flag informada, por exemplo `--max-sessions 64`.
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+  args.iter().position(|arg| arg == flag).and_then(|idx| args.get(idx + 1)).map(String::as_str)
 }
 
-fn send_error_message(socket: &UdpSocket, message: &str, destination: SocketAddr) -> io::Result<()> {
-  let error_message = format!("ERROR: {}", message);
-  socket.send_to(error_message.as_bytes(), destination)?;
-  Ok(())
-}
\ No newline at end of file
+// Valores de todas as ocorrências de uma flag repetível.
+fn flag_values<'a>(args: &'a [String], flag: &'a str) -> impl Iterator<Item = &'a str> {
+  args.windows(2).filter(move |pair| pair[0] == flag).map(|pair| pair[1].as_str())
+}
@@ -0,0 +1,331 @@
+// Servidor embutível: `Server::builder()` escolhe o endereço, a origem dos arquivos, os ganchos de
+// autorização e log, o endereço das métricas e o diário de sessões, e `run` atende as requisições
+// com uma thread por datagrama, como o binário `server`. `spawn` roda o servidor em segundo plano e
+// devolve um `ServerHandle` para encerrá-lo, o que permite usá-lo dentro de outros processos e em
+// testes.
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::acl::Acl;
+use crate::auth::Authenticator;
+use crate::batch::{BatchOptions, BatchSender};
+use crate::capture::{self, Direction};
+use crate::discovery::{self, DiscoveryConfig};
+use crate::handler::{Authorizer, Handler, Identifier, Request, RequestLog, RequestLogger};
+use crate::journal::Journal;
+use crate::limits::SessionLimits;
+use crate::metrics::Metrics;
+use crate::shaping::{self, ShapingConfig};
+use crate::protocol::{MAX_UDP_PAYLOAD, SERVER_PORT};
+use crate::source::{files_dir, FileSource, FsSource};
+
+// Intervalo entre verificações do pedido de encerramento enquanto o socket espera por datagramas.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(200);
+// Threads que tratam as requisições em `Server::run`, por núcleo. Como cada uma envia a resposta
+// respeitando os limites de taxa, há mais threads que núcleos.
+const THREADS_PER_CORE: usize = 4;
+// Requisições recebidas que podem aguardar uma thread livre; além disso, são descartadas.
+const REQUEST_QUEUE_LEN: usize = 1024;
+
+// Requisição recebida, à espera de uma thread.
+type Received = (SocketAddr, Vec<u8>);
+
+pub struct ServerBuilder {
+  address: SocketAddr,
+  source: Option<Arc<dyn FileSource>>,
+  authenticator: Option<Authenticator>,
+  acl: Option<Acl>,
+  authorizer: Option<Authorizer>,
+  identifier: Option<Identifier>,
+  logger: Option<RequestLogger>,
+  batch: BatchOptions,
+  metrics: Option<SocketAddr>,
+  journal: Option<PathBuf>,
+  limits: SessionLimits,
+  shaping: ShapingConfig,
+  discovery: Option<DiscoveryConfig>,
+}
+
+impl ServerBuilder {
+  // Endereço do socket; por padrão, 0.0.0.0:8083.
+  pub fn bind(mut self, address: SocketAddr) -> ServerBuilder {
+    self.address = address;
+    self
+  }
+
+  // Origem dos arquivos servidos; por padrão, o diretório `src/files` relativo ao executável.
+  pub fn source(self, source: impl FileSource + 'static) -> ServerBuilder {
+    self.shared_source(Arc::new(source))
+  }
+
+  // Como `source`, para uma origem que também é usada fora do servidor.
+  pub fn shared_source(mut self, source: Arc<dyn FileSource>) -> ServerBuilder {
+    self.source = Some(source);
+    self
+  }
+
+  // Exige credenciais (token ou requisição assinada com HMAC) em cada GET, STAT, LIST, PUT e DELTA
+  // (ver `auth`); a chave usada passa a ser a identidade do cliente.
+  pub fn authenticate(mut self, authenticator: Authenticator) -> ServerBuilder {
+    self.authenticator = Some(authenticator);
+    self
+  }
+
+  // Lista de controle de acesso consultada antes de cada GET, STAT, LIST, PUT e DELTA (ver `acl`);
+  // por padrão, todos os caminhos ficam acessíveis.
+  pub fn acl(mut self, acl: Acl) -> ServerBuilder {
+    self.acl = Some(acl);
+    self
+  }
+
+  // Gancho consultado antes de cada GET, STAT, LIST, PUT e DELTA; o `Err` recusa a requisição
+  // com um erro FORBIDDEN contendo o motivo.
+  pub fn authorize(mut self, authorizer: impl Fn(&Request) -> Result<(), String> + Send + Sync + 'static) -> ServerBuilder {
+    self.authorizer = Some(Arc::new(authorizer));
+    self
+  }
+
+  // Gancho que devolve a identidade do cliente em cada GET, STAT, LIST, PUT e DELTA, para quando a
+  // autenticação é feita fora do servidor; a identidade vale para a ACL e para o limite de taxa por
+  // identidade. Com `authenticate`, a chave autenticada tem precedência.
+  pub fn identify(mut self, identifier: impl Fn(&Request) -> Option<String> + Send + Sync + 'static) -> ServerBuilder {
+    self.identifier = Some(Arc::new(identifier));
+    self
+  }
+
+  // Gancho chamado depois de cada requisição tratada.
+  pub fn on_request(mut self, logger: impl Fn(&RequestLog) + Send + Sync + 'static) -> ServerBuilder {
+    self.logger = Some(Arc::new(logger));
+    self
+  }
+
+  // Opções de envio em lote (sendmmsg/GSO).
+  pub fn batch(mut self, batch: BatchOptions) -> ServerBuilder {
+    self.batch = batch;
+    self
+  }
+
+  // Endereço do listener TCP que expõe as métricas no formato do Prometheus (`GET /metrics`);
+  // por padrão, as métricas não são coletadas nem expostas.
+  pub fn metrics(mut self, address: SocketAddr) -> ServerBuilder {
+    self.metrics = Some(address);
+    self
+  }
+
+  // Diretório do diário de sessões de retransmissão (ver `journal`); as sessões gravadas por uma
+  // execução anterior são recuperadas ao criar o tratamento. Por padrão não há diário e as sessões
+  // se perdem quando o processo termina.
+  pub fn journal(mut self, dir: impl Into<PathBuf>) -> ServerBuilder {
+    self.journal = Some(dir.into());
+    self
+  }
+
+  // Expiração das sessões e limites de sessões simultâneas e de memória (ver `limits`).
+  pub fn limits(mut self, limits: SessionLimits) -> ServerBuilder {
+    self.limits = limits;
+    self
+  }
+
+  // Limites de taxa de envio, global, por cliente, por sub-rede e por identidade (ver `shaping`).
+  pub fn shaping(mut self, shaping: ShapingConfig) -> ServerBuilder {
+    self.shaping = shaping;
+    self
+  }
+
+  // Responde aos pedidos de descoberta de clientes na rede local com o nome, a versão do protocolo e
+  // as capacidades (ver `discovery`); por padrão, o servidor não responde a eles.
+  pub fn discovery(mut self, discovery: DiscoveryConfig) -> ServerBuilder {
+    self.discovery = Some(discovery);
+    self
+  }
+
+  // Tratamento configurado, para uso com os outros modos de servidor (`workers`, `async_server`).
+  // Inicia o listener de métricas e recupera as sessões do diário, se configurados.
+  pub fn into_handler(self) -> io::Result<Handler> {
+    let source = match self.source {
+      Some(source) => source,
+      None => Arc::new(FsSource::new(files_dir()?)),
+    };
+    let mut handler = Handler::new(source).with_shaping(self.shaping).with_limits(self.limits);
+    if let Some(authenticator) = self.authenticator {
+      handler = handler.with_authenticator(Arc::new(authenticator));
+    }
+    if let Some(acl) = self.acl {
+      info!(rules = acl.rules().len(), "access control list enabled");
+      handler = handler.with_acl(Arc::new(acl));
+    }
+    if let Some(authorizer) = self.authorizer {
+      handler = handler.with_authorizer(authorizer);
+    }
+    if let Some(identifier) = self.identifier {
+      handler = handler.with_identifier(identifier);
+    }
+    if let Some(logger) = self.logger {
+      handler = handler.with_logger(logger);
+    }
+    if let Some(discovery) = self.discovery {
+      info!(name = %discovery.name, "discovery enabled");
+      handler = handler.with_discovery(discovery);
+    }
+    if let Some(address) = self.metrics {
+      let metrics = Arc::new(Metrics::new());
+      handler = handler.with_metrics(Arc::clone(&metrics));
+      let address = metrics.serve(address)?;
+      info!(%address, "serving metrics");
+    }
+    if let Some(dir) = self.journal {
+      handler = handler.with_journal(Journal::open(&dir)?);
+      info!(dir = %dir.display(), "session journal enabled");
+      handler.recover_sessions();
+    }
+    Ok(handler)
+  }
+
+  // Liga o socket e devolve o servidor, ainda sem atender requisições.
+  pub fn build(self) -> io::Result<Server> {
+    let socket = UdpSocket::bind(self.address)?;
+    let sender = Arc::new(BatchSender::new(self.batch));
+    let handler = self.into_handler()?;
+    if let Some(discovery) = handler.discovery() {
+      discovery::join_group(&socket, discovery.interface);
+    }
+    Ok(Server { socket, handler, sender, stop: Arc::new(AtomicBool::new(false)) })
+  }
+}
+
+pub struct Server {
+  socket: UdpSocket,
+  handler: Handler,
+  sender: Arc<BatchSender>,
+  stop: Arc<AtomicBool>,
+}
+
+impl Server {
+  pub fn builder() -> ServerBuilder {
+    ServerBuilder {
+      address: SocketAddr::from(([0, 0, 0, 0], SERVER_PORT)),
+      source: None,
+      authenticator: None,
+      acl: None,
+      authorizer: None,
+      identifier: None,
+      logger: None,
+      batch: BatchOptions::default(),
+      metrics: None,
+      journal: None,
+      limits: SessionLimits::default(),
+      shaping: ShapingConfig::default(),
+      discovery: None,
+    }
+  }
+
+  // Endereço efetivo do socket (útil quando o builder pediu a porta 0).
+  pub fn local_addr(&self) -> io::Result<SocketAddr> {
+    self.socket.local_addr()
+  }
+
+  pub fn handler(&self) -> &Handler {
+    &self.handler
+  }
+
+  // Atende requisições até `ServerHandle::shutdown` ser chamado (ou para sempre, se o servidor
+  // não foi iniciado com `spawn`). As requisições são tratadas por um número fixo de threads; com
+  // todas ocupadas e a fila cheia, os datagramas recebidos são descartados e o cliente repete o
+  // pedido depois do timeout.
+  pub fn run(&self) -> io::Result<()> {
+    self.socket.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))?;
+    let local_address = self.socket.local_addr()?;
+    let (requests, queue) = mpsc::sync_channel::<Received>(REQUEST_QUEUE_LEN);
+    let queue = Arc::new(Mutex::new(queue));
+    let threads = thread::available_parallelism().map_or(1, |n| n.get()) * THREADS_PER_CORE;
+    let pool = (0..threads)
+      .map(|_| {
+        let socket = self.socket.try_clone()?;
+        let handler = self.handler.clone();
+        let sender = Arc::clone(&self.sender);
+        let queue = Arc::clone(&queue);
+        Ok(thread::spawn(move || serve_requests(&queue, &socket, &handler, &sender)))
+      })
+      .collect::<io::Result<Vec<_>>>()?;
+
+    let mut buf = vec![0u8; MAX_UDP_PAYLOAD];
+    while !self.stop.load(Ordering::Relaxed) {
+      let (size, client_address) = match self.socket.recv_from(&mut buf) {
+        Ok(received) => received,
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => continue,
+        Err(e) => return Err(e),
+      };
+      let request = buf[..size].to_vec();
+      capture::record(local_address, client_address, Direction::Received, &request);
+      match requests.try_send((client_address, request)) {
+        Ok(()) => {},
+        Err(TrySendError::Full(_)) => warn!(peer = %client_address, "request queue full, dropping request"),
+        Err(TrySendError::Disconnected(_)) => return Err(io::Error::other("threads de tratamento encerradas")),
+      }
+    }
+    // Sem a fila, as threads terminam depois de atender o que já foi recebido.
+    drop(requests);
+    for thread in pool {
+      if thread.join().is_err() {
+        warn!("request thread panicked");
+      }
+    }
+    Ok(())
+  }
+
+  // Roda o servidor em uma thread própria.
+  pub fn spawn(self) -> io::Result<ServerHandle> {
+    let address = self.local_addr()?;
+    let stop = Arc::clone(&self.stop);
+    let thread = thread::spawn(move || self.run());
+    Ok(ServerHandle { address, stop, thread })
+  }
+}
+
+// Laço de uma thread de `Server::run`: trata as requisições da fila e envia as respostas.
+fn serve_requests(queue: &Mutex<Receiver<Received>>, socket: &UdpSocket, handler: &Handler, sender: &BatchSender) {
+  let local_address = socket.local_addr().unwrap_or(SocketAddr::from(([0, 0, 0, 0], 0)));
+  loop {
+    let next = queue.lock().unwrap().recv();
+    let (client_address, request) = match next {
+      Ok(request) => request,
+      Err(_) => return,
+    };
+    let datagrams = handler.handle(&request, client_address);
+    let ticket = handler.shaper().ticket(client_address);
+    match shaping::send_all(&ticket, sender, socket, &datagrams, client_address) {
+      Ok(()) => {
+        capture::record_all(local_address, client_address, Direction::Sent, datagrams.iter().map(Vec::as_slice));
+        handler.sent(client_address, &datagrams);
+      },
+      Err(e) => warn!(peer = %client_address, error = %e, "failed to send response"),
+    }
+  }
+}
+
+// Servidor rodando em segundo plano.
+pub struct ServerHandle {
+  address: SocketAddr,
+  stop: Arc<AtomicBool>,
+  thread: JoinHandle<io::Result<()>>,
+}
+
+impl ServerHandle {
+  pub fn local_addr(&self) -> SocketAddr {
+    self.address
+  }
+
+  // Para de atender novas requisições e espera o laço do servidor terminar.
+  pub fn shutdown(self) -> io::Result<()> {
+    self.stop.store(true, Ordering::Relaxed);
+    self.thread.join().map_err(|_| io::Error::other("thread do servidor terminou com pânico"))?
+  }
+}
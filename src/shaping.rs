@@ -0,0 +1,489 @@
+// Limites de taxa de envio do servidor (token bucket) e divisão justa do limite global entre as
+// respostas sendo enviadas ao mesmo tempo.
+//
+// Cada datagrama enviado consome bytes dos buckets que valem para o cliente: o global, o do seu IP,
+// o da primeira sub-rede configurada que o contém e o da sua identidade (atribuída pelo gancho
+// `ServerBuilder::identify`). Um bucket acumula até `burst` bytes e pode ficar devendo: um envio
+// maior que o saldo passa assim que o saldo cobre `burst`, e os seguintes esperam a dívida ser paga.
+//
+// As respostas são enviadas em blocos de até `BATCH_SIZE` datagramas e no máximo `burst` bytes.
+// Com limite global, os envios que esperam por ele formam uma fila circular: só o primeiro da fila
+// pode consumir o bucket global e, depois de enviar um bloco, volta para o fim. Assim um download
+// grande alterna bloco a bloco com os menores em vez de ocupar todo o limite. Um envio barrado pelo
+// limite do próprio cliente sai da fila para não atrasar os demais.
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::batch::{BatchSender, BATCH_SIZE};
+use crate::protocol::MAX_UDP_PAYLOAD;
+
+// Espera entre consultas de um envio que aguarda a sua vez na fila do limite global.
+const FAIR_POLL_INTERVAL: Duration = Duration::from_millis(1);
+// Intervalo entre as limpezas dos buckets de clientes e identidades sem uso.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+// Identidades de clientes sem requisições por esse tempo são esquecidas.
+const IDENTITY_TTL: Duration = Duration::from_secs(10 * 60);
+
+// Taxa em bytes por segundo, com rajadas de até `burst` bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+  pub bytes_per_sec: u64,
+  pub burst: u64,
+}
+
+impl RateLimit {
+  // Limite com a rajada padrão: 50 ms da taxa, no mínimo um datagrama do maior tamanho.
+  pub fn new(bytes_per_sec: u64) -> RateLimit {
+    RateLimit { bytes_per_sec, burst: (bytes_per_sec / 20).max(MAX_UDP_PAYLOAD as u64) }
+  }
+
+  // Lê uma taxa em bytes por segundo com sufixo opcional K, M ou G (múltiplos de 1024), como `10M`.
+  pub fn parse(value: &str) -> Option<RateLimit> {
+    let (digits, multiplier) = match value.chars().last()?.to_ascii_uppercase() {
+      'K' => (&value[..value.len() - 1], 1 << 10),
+      'M' => (&value[..value.len() - 1], 1 << 20),
+      'G' => (&value[..value.len() - 1], 1 << 30),
+      _ => (value, 1),
+    };
+    let bytes_per_sec = digits.parse::<u64>().ok()?.checked_mul(multiplier)?;
+    (bytes_per_sec > 0).then(|| RateLimit::new(bytes_per_sec))
+  }
+}
+
+// Bloco de endereços IP no formato CIDR (`10.0.0.0/8`, `2001:db8::/32`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Subnet {
+  network: IpAddr,
+  prefix: u8,
+}
+
+impl Subnet {
+  // Um servidor ligado a `[::]` vê clientes IPv4 como `::ffff:a.b.c.d`; eles são comparados como
+  // IPv4 com blocos IPv4, e blocos IPv6 comparam endereços IPv4 na forma mapeada.
+  pub fn contains(&self, address: IpAddr) -> bool {
+    let address = match (self.network, address) {
+      (IpAddr::V4(_), IpAddr::V6(v6)) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+      (IpAddr::V6(_), IpAddr::V4(v4)) => IpAddr::V6(v4.to_ipv6_mapped()),
+      _ => address,
+    };
+    match (self.network, address) {
+      (IpAddr::V4(network), IpAddr::V4(address)) => {
+        let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+        u32::from(network) & mask == u32::from(address) & mask
+      },
+      (IpAddr::V6(network), IpAddr::V6(address)) => {
+        let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+        u128::from(network) & mask == u128::from(address) & mask
+      },
+      _ => false,
+    }
+  }
+}
+
+impl FromStr for Subnet {
+  type Err = io::Error;
+
+  fn from_str(value: &str) -> io::Result<Subnet> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("sub-rede inválida: {}", value));
+    let (network, prefix) = value.split_once('/').ok_or_else(invalid)?;
+    let network: IpAddr = network.parse().map_err(|_| invalid())?;
+    let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+    let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+    if prefix > max_prefix {
+      return Err(invalid());
+    }
+    Ok(Subnet { network, prefix })
+  }
+}
+
+impl fmt::Display for Subnet {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}/{}", self.network, self.prefix)
+  }
+}
+
+// Limites configurados; `None` (ou lista vazia) não limita.
+#[derive(Clone, Debug, Default)]
+pub struct ShapingConfig {
+  // Todo o tráfego enviado pelo servidor.
+  pub global: Option<RateLimit>,
+  // Cada endereço IP de origem.
+  pub per_peer: Option<RateLimit>,
+  // Cada sub-rede listada, compartilhado pelos clientes dela; vale a primeira que contém o cliente.
+  pub subnets: Vec<(Subnet, RateLimit)>,
+  // Cada identidade autenticada.
+  pub per_identity: Option<RateLimit>,
+}
+
+impl ShapingConfig {
+  fn is_unlimited(&self) -> bool {
+    self.global.is_none() && self.per_peer.is_none() && self.subnets.is_empty() && self.per_identity.is_none()
+  }
+
+  // Maior bloco enviado de uma vez: a menor rajada entre os limites.
+  fn quantum(&self) -> Option<u64> {
+    let subnets = self.subnets.iter().map(|(_, limit)| limit);
+    self.global.iter().chain(&self.per_peer).chain(subnets).chain(&self.per_identity).map(|limit| limit.burst).min()
+  }
+}
+
+struct Bucket {
+  limit: RateLimit,
+  tokens: f64,
+  updated: Instant,
+}
+
+impl Bucket {
+  fn new(limit: RateLimit, now: Instant) -> Bucket {
+    Bucket { limit, tokens: limit.burst as f64, updated: now }
+  }
+
+  fn refill(&mut self, now: Instant) {
+    let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * self.limit.bytes_per_sec as f64).min(self.limit.burst as f64);
+    self.updated = now;
+  }
+
+  // Espera até o saldo permitir enviar `bytes`.
+  fn delay(&self, bytes: usize) -> Duration {
+    let needed = (bytes as f64).min(self.limit.burst as f64);
+    if self.tokens >= needed {
+      Duration::ZERO
+    } else {
+      Duration::from_secs_f64((needed - self.tokens) / self.limit.bytes_per_sec as f64)
+    }
+  }
+
+  fn take(&mut self, bytes: usize) {
+    self.tokens -= bytes as f64;
+  }
+
+  fn is_full(&self) -> bool {
+    self.tokens >= self.limit.burst as f64
+  }
+}
+
+// Identidade atribuída às requisições de um cliente.
+struct ClientIdentity {
+  identity: String,
+  seen: Instant,
+}
+
+struct State {
+  global: Option<Bucket>,
+  peers: HashMap<IpAddr, Bucket>,
+  // Na ordem de `ShapingConfig::subnets`.
+  subnets: Vec<Bucket>,
+  identities: HashMap<String, Bucket>,
+  clients: HashMap<SocketAddr, ClientIdentity>,
+  // Envios esperando a vez de consumir o bucket global.
+  queue: VecDeque<u64>,
+  next_ticket: u64,
+  last_prune: Instant,
+}
+
+// Limitador compartilhado pelas threads e tarefas que enviam respostas.
+pub struct Shaper {
+  config: ShapingConfig,
+  state: Mutex<State>,
+}
+
+impl Shaper {
+  pub fn new(config: ShapingConfig) -> Shaper {
+    let now = Instant::now();
+    let state = State {
+      global: config.global.map(|limit| Bucket::new(limit, now)),
+      peers: HashMap::new(),
+      subnets: config.subnets.iter().map(|(_, limit)| Bucket::new(*limit, now)).collect(),
+      identities: HashMap::new(),
+      clients: HashMap::new(),
+      queue: VecDeque::new(),
+      next_ticket: 0,
+      last_prune: now,
+    };
+    Shaper { config, state: Mutex::new(state) }
+  }
+
+  pub fn config(&self) -> &ShapingConfig {
+    &self.config
+  }
+
+  // Associa as próximas respostas ao cliente à identidade informada.
+  pub fn identify(&self, client_address: SocketAddr, identity: String) {
+    if self.config.per_identity.is_none() {
+      return;
+    }
+    let seen = Instant::now();
+    self.state.lock().unwrap().clients.insert(client_address, ClientIdentity { identity, seen });
+  }
+
+  // Permissão para enviar uma resposta ao cliente, consultada antes de cada bloco.
+  pub fn ticket(self: &Arc<Shaper>, client_address: SocketAddr) -> Ticket {
+    if self.config.is_unlimited() {
+      return Ticket { shaper: Arc::clone(self), id: 0, client_address, identity: None };
+    }
+    let mut state = self.state.lock().unwrap();
+    state.next_ticket += 1;
+    let now = Instant::now();
+    let identity = state.clients.get_mut(&client_address).map(|client| {
+      client.seen = now;
+      client.identity.clone()
+    });
+    Ticket { shaper: Arc::clone(self), id: state.next_ticket, client_address, identity }
+  }
+
+  // Divide a resposta nos blocos enviados de uma vez.
+  fn chunks<'d>(&self, datagrams: &'d [Vec<u8>]) -> Vec<&'d [Vec<u8>]> {
+    let quantum = match self.config.quantum() {
+      Some(quantum) => quantum as usize,
+      None => return datagrams.chunks(BATCH_SIZE.max(1)).collect(),
+    };
+    let mut chunks = Vec::new();
+    let mut rest = datagrams;
+    while !rest.is_empty() {
+      let mut len = 1;
+      let mut bytes = rest[0].len();
+      while len < rest.len().min(BATCH_SIZE) && bytes + rest[len].len() <= quantum {
+        bytes += rest[len].len();
+        len += 1;
+      }
+      let (chunk, remaining) = rest.split_at(len);
+      chunks.push(chunk);
+      rest = remaining;
+    }
+    chunks
+  }
+
+  fn poll(&self, ticket: &Ticket, bytes: usize) -> Option<Duration> {
+    if self.config.is_unlimited() {
+      return None;
+    }
+    let now = Instant::now();
+    let mut guard = self.state.lock().unwrap();
+    let state = &mut *guard;
+    if now.saturating_duration_since(state.last_prune) >= PRUNE_INTERVAL {
+      state.prune(now);
+    }
+
+    // Buckets próprios do cliente, criados no primeiro envio.
+    let peer = ticket.client_address.ip();
+    let mut local: Vec<&mut Bucket> = Vec::new();
+    if let Some(limit) = self.config.per_peer {
+      local.push(state.peers.entry(peer).or_insert_with(|| Bucket::new(limit, now)));
+    }
+    if let Some(index) = self.config.subnets.iter().position(|(subnet, _)| subnet.contains(peer)) {
+      local.push(&mut state.subnets[index]);
+    }
+    if let (Some(limit), Some(identity)) = (self.config.per_identity, &ticket.identity) {
+      local.push(state.identities.entry(identity.clone()).or_insert_with(|| Bucket::new(limit, now)));
+    }
+    local.iter_mut().for_each(|bucket| bucket.refill(now));
+    let local_delay = local.iter().map(|bucket| bucket.delay(bytes)).max().unwrap_or_default();
+    if !local_delay.is_zero() {
+      state.queue.retain(|id| *id != ticket.id);
+      return Some(local_delay);
+    }
+
+    if let Some(global) = &mut state.global {
+      if !state.queue.contains(&ticket.id) {
+        state.queue.push_back(ticket.id);
+      }
+      if state.queue.front() != Some(&ticket.id) {
+        return Some(FAIR_POLL_INTERVAL);
+      }
+      global.refill(now);
+      let delay = global.delay(bytes);
+      if !delay.is_zero() {
+        return Some(delay);
+      }
+      global.take(bytes);
+      state.queue.rotate_left(1);
+    }
+    local.iter_mut().for_each(|bucket| bucket.take(bytes));
+    None
+  }
+}
+
+impl State {
+  // Esquece os buckets cheios (sem envios recentes) e as identidades antigas.
+  fn prune(&mut self, now: Instant) {
+    self.peers.retain(|_, bucket| {
+      bucket.refill(now);
+      !bucket.is_full()
+    });
+    self.identities.retain(|_, bucket| {
+      bucket.refill(now);
+      !bucket.is_full()
+    });
+    self.clients.retain(|_, client| now.saturating_duration_since(client.seen) < IDENTITY_TTL);
+    self.last_prune = now;
+  }
+}
+
+// Envio de uma resposta em andamento; sai da fila do limite global ao ser descartado.
+pub struct Ticket {
+  shaper: Arc<Shaper>,
+  id: u64,
+  client_address: SocketAddr,
+  identity: Option<String>,
+}
+
+impl Ticket {
+  pub fn chunks<'d>(&self, datagrams: &'d [Vec<u8>]) -> Vec<&'d [Vec<u8>]> {
+    self.shaper.chunks(datagrams)
+  }
+
+  // Reserva `bytes` nos buckets do cliente ou devolve quanto esperar antes de tentar de novo.
+  pub fn poll(&self, bytes: usize) -> Option<Duration> {
+    self.shaper.poll(self, bytes)
+  }
+
+  // Bloqueia a thread até poder enviar `bytes`.
+  pub fn wait(&self, bytes: usize) {
+    while let Some(delay) = self.poll(bytes) {
+      thread::sleep(delay);
+    }
+  }
+}
+
+impl Drop for Ticket {
+  fn drop(&mut self) {
+    if self.id != 0 {
+      self.shaper.state.lock().unwrap().queue.retain(|id| *id != self.id);
+    }
+  }
+}
+
+// Bytes de um bloco de datagramas.
+pub fn chunk_len(chunk: &[Vec<u8>]) -> usize {
+  chunk.iter().map(Vec::len).sum()
+}
+
+// Envia a resposta por um socket bloqueante respeitando os limites.
+pub fn send_all(ticket: &Ticket, sender: &BatchSender, socket: &UdpSocket, datagrams: &[Vec<u8>], destination: SocketAddr) -> io::Result<()> {
+  for chunk in ticket.chunks(datagrams) {
+    ticket.wait(chunk_len(chunk));
+    sender.send_all(socket, chunk, destination)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn subnet(value: &str) -> Subnet {
+    value.parse().unwrap()
+  }
+
+  fn ip(value: &str) -> IpAddr {
+    value.parse().unwrap()
+  }
+
+  fn peer(value: &str) -> SocketAddr {
+    SocketAddr::new(ip(value), 4000)
+  }
+
+  #[test]
+  fn parses_rates_with_suffixes() {
+    assert_eq!(RateLimit::parse("10M").map(|limit| limit.bytes_per_sec), Some(10 << 20));
+    assert_eq!(RateLimit::parse("5k").map(|limit| limit.bytes_per_sec), Some(5 << 10));
+    assert_eq!(RateLimit::parse("2G").map(|limit| limit.bytes_per_sec), Some(2 << 30));
+    assert_eq!(RateLimit::parse("1500").map(|limit| limit.bytes_per_sec), Some(1500));
+    for invalid in ["0", "0M", "", "x", "M", "-5", "1.5M", "99999999999999G"] {
+      assert_eq!(RateLimit::parse(invalid), None, "{}", invalid);
+    }
+  }
+
+  #[test]
+  fn burst_covers_at_least_one_datagram() {
+    assert_eq!(RateLimit::new(1000).burst, MAX_UDP_PAYLOAD as u64);
+    assert_eq!(RateLimit::new(100 << 20).burst, (100 << 20) / 20);
+  }
+
+  #[test]
+  fn parses_subnets() {
+    assert_eq!(subnet("10.0.0.0/8").to_string(), "10.0.0.0/8");
+    assert_eq!(subnet("2001:db8::/32").to_string(), "2001:db8::/32");
+    assert_eq!(subnet("::/128").to_string(), "::/128");
+    for invalid in ["10.0.0.0", "10.0.0.0/33", "::/129", "10.0.0/8", "10.0.0.0/x", "/8"] {
+      assert!(invalid.parse::<Subnet>().is_err(), "{}", invalid);
+    }
+  }
+
+  #[test]
+  fn subnets_contain_their_addresses() {
+    let private = subnet("10.0.0.0/8");
+    assert!(private.contains(ip("10.1.2.3")));
+    assert!(!private.contains(ip("11.0.0.1")));
+    assert!(!private.contains(ip("2001:db8::1")));
+
+    let host = subnet("192.168.1.7/32");
+    assert!(host.contains(ip("192.168.1.7")));
+    assert!(!host.contains(ip("192.168.1.8")));
+
+    let everything = subnet("0.0.0.0/0");
+    assert!(everything.contains(ip("203.0.113.9")));
+    assert!(subnet("::/0").contains(ip("2001:db8::1")));
+
+    let documentation = subnet("2001:db8::/32");
+    assert!(documentation.contains(ip("2001:db8:ffff::1")));
+    assert!(!documentation.contains(ip("2001:db9::1")));
+    assert!(!documentation.contains(ip("10.1.2.3")));
+  }
+
+  #[test]
+  fn subnets_match_mapped_addresses() {
+    // Cliente IPv4 visto por um servidor ligado a `[::]`.
+    assert!(subnet("10.0.0.0/8").contains(ip("::ffff:10.1.2.3")));
+    assert!(!subnet("10.0.0.0/8").contains(ip("::ffff:11.1.2.3")));
+    // Endereço IPv4 comparado com um bloco IPv6 na forma mapeada.
+    assert!(subnet("::ffff:0:0/96").contains(ip("10.1.2.3")));
+    assert!(!subnet("2001:db8::/32").contains(ip("10.1.2.3")));
+  }
+
+  #[test]
+  fn per_peer_limit_delays_only_that_peer() {
+    let limit = RateLimit { bytes_per_sec: 1000, burst: 2000 };
+    let shaper = Arc::new(Shaper::new(ShapingConfig { per_peer: Some(limit), ..ShapingConfig::default() }));
+    let busy = shaper.ticket(peer("10.0.0.1"));
+    assert_eq!(busy.poll(2000), None);
+    let delay = busy.poll(1000).expect("o saldo do cliente acabou");
+    assert!(delay > Duration::from_millis(500) && delay <= Duration::from_secs(1), "{:?}", delay);
+    assert_eq!(shaper.ticket(peer("10.0.0.2")).poll(2000), None);
+  }
+
+  #[test]
+  fn subnet_limit_is_shared_by_its_clients() {
+    let limit = RateLimit { bytes_per_sec: 1000, burst: 2000 };
+    let config = ShapingConfig { subnets: vec![(subnet("10.0.0.0/8"), limit)], ..ShapingConfig::default() };
+    let shaper = Arc::new(Shaper::new(config));
+    assert_eq!(shaper.ticket(peer("10.0.0.1")).poll(2000), None);
+    assert!(shaper.ticket(peer("10.0.0.2")).poll(1000).is_some());
+    assert!(shaper.ticket(peer("::ffff:10.0.0.3")).poll(1000).is_some());
+    assert_eq!(shaper.ticket(peer("192.168.0.1")).poll(2000), None);
+  }
+
+  #[test]
+  fn global_limit_takes_turns_between_responses() {
+    let limit = RateLimit { bytes_per_sec: 1 << 30, burst: 1 << 20 };
+    let shaper = Arc::new(Shaper::new(ShapingConfig { global: Some(limit), ..ShapingConfig::default() }));
+    let first = shaper.ticket(peer("10.0.0.1"));
+    let second = shaper.ticket(peer("10.0.0.2"));
+    assert_eq!(first.poll(1000), None);
+    // Depois de enviar, cada um volta para o fim da fila e espera a vez do outro.
+    assert_eq!(second.poll(1000), Some(FAIR_POLL_INTERVAL));
+    assert_eq!(first.poll(1000), None);
+    assert_eq!(second.poll(1000), None);
+    assert_eq!(second.poll(1000), Some(FAIR_POLL_INTERVAL));
+    // Uma resposta terminada sai da fila.
+    drop(first);
+    assert_eq!(second.poll(1000), None);
+  }
+}
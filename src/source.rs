@@ -0,0 +1,225 @@
+// Origem dos arquivos servidos. O tratamento de requisições só enxerga caminhos relativos
+// separados por `/` (sem barra inicial; "" é a raiz) e acessa o conteúdo por meio de `FileSource`,
+// o que permite servir um diretório do disco (`FsSource`), arquivos em memória (`MemorySource`)
+// ou qualquer outro armazenamento.
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::env;
+
+use digest::Digest;
+use sha2::Sha256;
+
+use crate::listing::{self, DirEntry, EntryKind};
+
+// Conteúdo de um arquivo e sua última modificação, em segundos desde a época Unix.
+#[derive(Clone, Debug)]
+pub struct SourceFile {
+  pub data: Vec<u8>,
+  pub mtime: u64,
+}
+
+pub trait FileSource: Send + Sync {
+  // Conteúdo do arquivo; `io::ErrorKind::NotFound` quando ele não existe.
+  fn read(&self, path: &str) -> io::Result<SourceFile>;
+
+  // Tipo da entrada; `io::ErrorKind::NotFound` quando ela não existe.
+  fn kind(&self, path: &str) -> io::Result<EntryKind>;
+
+  // Entradas do diretório, ordenadas por nome e sem as ocultas. O SHA-256 dos arquivos só é
+  // calculado quando pedido.
+  fn list(&self, dir: &str, with_hash: bool) -> io::Result<Vec<DirEntry>>;
+
+  // Publica o arquivo recebido por um PUT, substituindo o existente. Origens somente leitura
+  // recusam o upload.
+  fn write(&self, _path: &str, _data: &mut dyn Read) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::PermissionDenied, "Origem somente leitura"))
+  }
+}
+
+// Recusa caminhos absolutos e componentes `..` ou `.`, que sairiam do conjunto servido.
+pub fn validate_path(path: &str) -> io::Result<&str> {
+  if !Path::new(path).components().all(|component| matches!(component, Component::Normal(_))) {
+    return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Caminho fora do diretório servido"));
+  }
+  Ok(path)
+}
+
+// Diretório servido por padrão, relativo ao executável.
+pub fn files_dir() -> io::Result<PathBuf> {
+  let exe_path = env::current_exe()?;
+  let exe_dir = exe_path.parent().ok_or(io::Error::other("Failed to get executable directory"))?;
+  Ok(exe_dir.join("../../src/files"))
+}
+
+// Arquivos de um diretório do disco.
+#[derive(Clone, Debug)]
+pub struct FsSource {
+  root: PathBuf,
+}
+
+impl FsSource {
+  pub fn new(root: impl Into<PathBuf>) -> FsSource {
+    FsSource { root: root.into() }
+  }
+
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+
+  fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+    Ok(self.root.join(validate_path(path)?))
+  }
+}
+
+// Contador para nomes únicos dos arquivos temporários de publicação.
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+
+impl FileSource for FsSource {
+  fn read(&self, path: &str) -> io::Result<SourceFile> {
+    let not_found = || io::Error::new(io::ErrorKind::NotFound, "File not found");
+    let mut file = File::open(self.resolve(path).map_err(|_| not_found())?).map_err(|_| not_found())?;
+    let mtime = listing::mtime_secs(&file.metadata()?);
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(SourceFile { data, mtime })
+  }
+
+  fn kind(&self, path: &str) -> io::Result<EntryKind> {
+    let metadata = fs::metadata(self.resolve(path)?)?;
+    Ok(if metadata.is_dir() {
+      EntryKind::Dir
+    } else if metadata.is_file() {
+      EntryKind::File
+    } else {
+      EntryKind::Other
+    })
+  }
+
+  fn list(&self, dir: &str, with_hash: bool) -> io::Result<Vec<DirEntry>> {
+    listing::list_directory(&self.resolve(dir)?, with_hash)
+  }
+
+  // Grava ao lado do destino, com um nome oculto, e só então o substitui (rename).
+  fn write(&self, path: &str, data: &mut dyn Read) -> io::Result<()> {
+    let target = self.resolve(path)?;
+    let parent = target.parent().ok_or(io::Error::other("Destino sem diretório pai"))?;
+    let name = target.file_name().ok_or(io::Error::other("Destino sem nome de arquivo"))?;
+    fs::create_dir_all(parent)?;
+    let temp = parent.join(format!(".{}.{}.publish", name.to_string_lossy(), NEXT_TEMP.fetch_add(1, Ordering::Relaxed)));
+
+    let written = File::create(&temp).and_then(|mut file| {
+      io::copy(data, &mut file)?;
+      file.flush()?;
+      file.sync_all()
+    });
+    match written.and_then(|_| fs::rename(&temp, &target)) {
+      Ok(()) => Ok(()),
+      Err(e) => {
+        let _ = fs::remove_file(&temp);
+        Err(e)
+      },
+    }
+  }
+}
+
+// Arquivos mantidos em memória, indexados pelo caminho. Diretórios existem implicitamente como
+// prefixos dos caminhos. Aceita uploads.
+#[derive(Debug, Default)]
+pub struct MemorySource {
+  files: RwLock<BTreeMap<String, SourceFile>>,
+}
+
+impl MemorySource {
+  pub fn new() -> MemorySource {
+    MemorySource::default()
+  }
+
+  // Adiciona o arquivo, como em `insert`, e devolve a origem; útil para montá-la em uma expressão.
+  pub fn with_file(self, path: &str, data: impl Into<Vec<u8>>) -> MemorySource {
+    self.insert(path, data);
+    self
+  }
+
+  // Adiciona ou substitui um arquivo, com a hora atual como última modificação.
+  pub fn insert(&self, path: &str, data: impl Into<Vec<u8>>) {
+    let file = SourceFile { data: data.into(), mtime: now_secs() };
+    self.files.write().unwrap().insert(path.trim_matches('/').to_string(), file);
+  }
+
+  pub fn get(&self, path: &str) -> Option<Vec<u8>> {
+    self.files.read().unwrap().get(path.trim_matches('/')).map(|file| file.data.clone())
+  }
+
+  pub fn remove(&self, path: &str) -> bool {
+    self.files.write().unwrap().remove(path.trim_matches('/')).is_some()
+  }
+}
+
+impl FileSource for MemorySource {
+  fn read(&self, path: &str) -> io::Result<SourceFile> {
+    self
+      .files
+      .read()
+      .unwrap()
+      .get(path)
+      .cloned()
+      .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found"))
+  }
+
+  fn kind(&self, path: &str) -> io::Result<EntryKind> {
+    let files = self.files.read().unwrap();
+    if files.contains_key(path) {
+      return Ok(EntryKind::File);
+    }
+    let prefix = format!("{}/", path);
+    if path.is_empty() || files.range(prefix.clone()..).next().is_some_and(|(key, _)| key.starts_with(&prefix)) {
+      return Ok(EntryKind::Dir);
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "File not found"))
+  }
+
+  fn list(&self, dir: &str, with_hash: bool) -> io::Result<Vec<DirEntry>> {
+    if self.kind(dir)? != EntryKind::Dir {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "O caminho não é um diretório"));
+    }
+    let prefix = if dir.is_empty() { String::new() } else { format!("{}/", dir) };
+    let files = self.files.read().unwrap();
+    let mut entries: Vec<DirEntry> = Vec::new();
+    for (key, file) in files.range(prefix.clone()..).take_while(|(key, _)| key.starts_with(&prefix)) {
+      let rest = &key[prefix.len()..];
+      let entry = match rest.split_once('/') {
+        // Subdiretório: os arquivos abaixo dele têm chaves contíguas, então basta olhar a última entrada.
+        Some((name, _)) if entries.last().is_some_and(|last| last.name == name) => continue,
+        Some((name, _)) => DirEntry { name: name.to_string(), kind: EntryKind::Dir, size: 0, mtime: 0, sha256: None },
+        None => DirEntry {
+          name: rest.to_string(),
+          kind: EntryKind::File,
+          size: file.data.len() as u64,
+          mtime: file.mtime,
+          sha256: with_hash.then(|| Sha256::digest(&file.data).iter().map(|byte| format!("{:02x}", byte)).collect()),
+        },
+      };
+      if !entry.name.starts_with('.') {
+        entries.push(entry);
+      }
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+  }
+
+  fn write(&self, path: &str, data: &mut dyn Read) -> io::Result<()> {
+    let mut content = Vec::new();
+    data.read_to_end(&mut content)?;
+    self.files.write().unwrap().insert(path.to_string(), SourceFile { data: content, mtime: now_secs() });
+    Ok(())
+  }
+}
+
+fn now_secs() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
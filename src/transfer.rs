@@ -0,0 +1,990 @@
+// API de cliente. `Client` fala o protocolo com um servidor: baixa arquivos (GET) com progresso,
+// cancelamento e destino configurável, lista diretórios (LIST), consulta metadados (STAT), envia
+// arquivos (PUT), atualiza cópias locais por diferença (DELTA) e espelha árvores remotas. O binário
+// `client` é uma interface de linha de comando sobre este módulo.
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use digest::Digest;
+use sha2::Sha256;
+use socket2::SockRef;
+use tracing::{debug, debug_span, info, trace, warn};
+
+use crate::auth::{self, Credentials};
+use crate::batch::{BatchOptions, BatchReceiver, BatchSender};
+use crate::calculate_hash;
+use crate::capture::{self, Direction};
+use crate::compression::{self, Compression};
+use crate::delta::{self, Delta};
+use crate::error::ClientError;
+use crate::listing::{self, DirEntry, EntryKind};
+use crate::protocol::{Datagram, FileMeta, UdpPacket, CHUNK_SIZE, HEADER_LEN, MAX_CHUNK_SIZE, MIN_CHUNK_SIZE, SERVER_PORT};
+use crate::upload::OverwritePolicy;
+
+// Tamanho pedido ao kernel para o buffer de recepção do socket (limitado por net.core.rmem_max).
+const RECV_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+// Intervalo entre verificações de cancelamento enquanto a recepção espera por datagramas.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Números de sequência por pedido de retransmissão.
+const MAX_PACKETS_PER_REQUEST: usize = 10;
+// Downloads simultâneos do espelhamento por padrão.
+pub const DEFAULT_MIRROR_PARALLELISM: usize = 4;
+
+// Configuração do cliente, compartilhada por todas as operações.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientConfig {
+  // Tamanho de bloco pedido ao servidor (`?chunk=`); `None` usa o padrão do servidor. Valores fora
+  // de `MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE` são ajustados por `Client::new`, como o servidor faria.
+  pub chunk_size: Option<usize>,
+  // Compressão pedida nos downloads (`?compress=`).
+  pub compression: Compression,
+  pub batch: BatchOptions,
+  // Silêncio do servidor que conta como uma tentativa perdida.
+  pub timeout: Duration,
+  // Tentativas sem resposta antes de desistir com `ClientError::Timeout`.
+  pub max_attempts: u32,
+}
+
+impl Default for ClientConfig {
+  fn default() -> ClientConfig {
+    ClientConfig {
+      chunk_size: None,
+      compression: Compression::None,
+      batch: BatchOptions::default(),
+      timeout: Duration::from_secs(5),
+      max_attempts: 5,
+    }
+  }
+}
+
+// Permite cancelar um download em andamento a partir de outra thread.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+  pub fn new() -> CancelToken {
+    CancelToken::default()
+  }
+
+  pub fn cancel(&self) {
+    self.0.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::Relaxed)
+  }
+}
+
+// Andamento de um download, informado a cada lote de datagramas recebido.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+  // Pacotes de dados recebidos e o total anunciado pelo cabeçalho (`None` até ele chegar).
+  pub packets: u32,
+  pub total_packets: Option<u32>,
+  // Bytes de dados recebidos, comprimidos se houver compressão, e o tamanho do arquivo.
+  pub bytes: u64,
+  pub size: Option<u64>,
+  // Pacotes pedidos novamente com RETRANSMIT até agora.
+  pub retransmitted: u32,
+  // Tempo desde o envio da requisição.
+  pub elapsed: Duration,
+}
+
+impl Progress {
+  // Fração dos pacotes já recebida, entre 0 e 1.
+  pub fn fraction(&self) -> Option<f64> {
+    self.total_packets.map(|total| if total == 0 { 1.0 } else { self.packets as f64 / total as f64 })
+  }
+
+  // Bytes recebidos por segundo.
+  pub fn throughput(&self) -> f64 {
+    per_second(self.bytes, self.elapsed)
+  }
+
+  // Tempo restante estimado a partir da fração recebida e do tempo decorrido.
+  pub fn eta(&self) -> Option<Duration> {
+    match self.fraction() {
+      Some(fraction) if fraction > 0.0 => Some(self.elapsed.mul_f64((1.0 - fraction) / fraction)),
+      _ => None,
+    }
+  }
+
+  // Fração dos pacotes que precisou ser retransmitida.
+  pub fn loss_rate(&self) -> Option<f64> {
+    self.total_packets.filter(|&total| total > 0).map(|total| self.retransmitted as f64 / total as f64)
+  }
+}
+
+// Para onde vão os dados de um download. Em todos os casos o hash é conferido antes da entrega.
+pub enum Destination<'a> {
+  // Mantidos em memória, em `Download::data`.
+  Memory,
+  // Gravados no arquivo, criando os diretórios que faltarem.
+  Path(PathBuf),
+  Writer(&'a mut dyn Write),
+}
+
+// Função chamada com o andamento de um download.
+pub type ProgressCallback<'a> = Box<dyn FnMut(&Progress) + 'a>;
+
+// Opções de um download.
+pub struct GetOptions<'a> {
+  pub destination: Destination<'a>,
+  // Chamado a cada lote de datagramas recebido.
+  pub progress: Option<ProgressCallback<'a>>,
+  pub cancel: Option<CancelToken>,
+  // Pacotes descartados na primeira chegada, para simular perda e exercitar a retransmissão.
+  pub simulated_loss: HashSet<u32>,
+}
+
+impl Default for GetOptions<'_> {
+  fn default() -> Self {
+    GetOptions { destination: Destination::Memory, progress: None, cancel: None, simulated_loss: HashSet::new() }
+  }
+}
+
+impl<'a> GetOptions<'a> {
+  pub fn destination(mut self, destination: Destination<'a>) -> GetOptions<'a> {
+    self.destination = destination;
+    self
+  }
+
+  pub fn on_progress(mut self, callback: impl FnMut(&Progress) + 'a) -> GetOptions<'a> {
+    self.progress = Some(Box::new(callback));
+    self
+  }
+
+  pub fn cancel_with(mut self, token: CancelToken) -> GetOptions<'a> {
+    self.cancel = Some(token);
+    self
+  }
+
+  pub fn simulate_loss(mut self, packets: HashSet<u32>) -> GetOptions<'a> {
+    self.simulated_loss = packets;
+    self
+  }
+}
+
+// Resultado de um download concluído e verificado.
+#[derive(Debug)]
+pub struct Download {
+  pub path: String,
+  // Metadados anunciados pelo servidor no pacote de cabeçalho.
+  pub meta: FileMeta,
+  // Pacotes de dados e bytes que atravessaram a rede (comprimidos, se houver compressão).
+  pub packets: u32,
+  pub wire_bytes: u64,
+  // Pacotes pedidos novamente com RETRANSMIT.
+  pub retransmitted: u32,
+  pub elapsed: Duration,
+  // Conteúdo, quando o destino é `Destination::Memory`.
+  pub data: Option<Vec<u8>>,
+}
+
+impl Download {
+  // Bytes por segundo que atravessaram a rede.
+  pub fn throughput(&self) -> f64 {
+    per_second(self.wire_bytes, self.elapsed)
+  }
+
+  // Fração dos pacotes de dados que precisou ser retransmitida.
+  pub fn loss_rate(&self) -> f64 {
+    if self.packets == 0 { 0.0 } else { self.retransmitted as f64 / self.packets as f64 }
+  }
+}
+
+// Resultado de uma atualização por diferença: tamanho do arquivo reconstruído, bytes enviados como
+// literais e tamanho do delta recebido.
+#[derive(Clone, Copy, Debug)]
+pub struct DeltaStats {
+  pub size: usize,
+  pub literal: usize,
+  pub transferred: usize,
+}
+
+// Opções do espelhamento.
+#[derive(Clone, Copy, Debug)]
+pub struct MirrorOptions {
+  // Downloads simultâneos.
+  pub parallel: usize,
+  // Atualiza por diferença os arquivos que já existem localmente.
+  pub delta: bool,
+}
+
+impl Default for MirrorOptions {
+  fn default() -> MirrorOptions {
+    MirrorOptions { parallel: DEFAULT_MIRROR_PARALLELISM, delta: false }
+  }
+}
+
+// Resultado do espelhamento: arquivos baixados, mantidos por já estarem atualizados e os caminhos
+// remotos (arquivos ou diretórios) que falharam.
+#[derive(Debug, Default)]
+pub struct MirrorReport {
+  pub downloaded: usize,
+  pub skipped: usize,
+  pub failed: Vec<(String, ClientError)>,
+}
+
+// Um arquivo a baixar no espelhamento.
+struct MirrorJob {
+  remote: String,
+  local: PathBuf,
+  sha256: Option<String>,
+  // Existe uma cópia local desatualizada: baixa só as diferenças.
+  delta: bool,
+}
+
+// Pacotes de uma resposta de vários pacotes, indexados pelo número de sequência (o 0 é o cabeçalho).
+struct Fetched {
+  packets: HashMap<u32, Vec<u8>>,
+  // Total anunciado pelo cabeçalho, incluindo ele (0 enquanto ele não chega).
+  announced: u32,
+  // Número do último pacote de dados, conhecido quando a resposta está completa.
+  count: u32,
+  data_packets: u32,
+  data_bytes: u64,
+  retransmitted: u32,
+  started: Instant,
+}
+
+impl Fetched {
+  fn new() -> Fetched {
+    Fetched {
+      packets: HashMap::new(),
+      announced: 0,
+      count: 0,
+      data_packets: 0,
+      data_bytes: 0,
+      retransmitted: 0,
+      started: Instant::now(),
+    }
+  }
+
+  // Guarda o pacote se ele ainda não tinha chegado.
+  fn insert(&mut self, seq_number: u32, data: &[u8]) -> bool {
+    if self.packets.contains_key(&seq_number) {
+      return false;
+    }
+    if seq_number != 0 {
+      self.data_packets += 1;
+      self.data_bytes += data.len() as u64;
+    }
+    self.packets.insert(seq_number, data.to_vec());
+    true
+  }
+
+  fn meta(&self) -> Option<FileMeta> {
+    self.packets.get(&0).and_then(|header| FileMeta::decode(header))
+  }
+
+  fn progress(&self) -> Progress {
+    let meta = self.meta();
+    Progress {
+      packets: self.data_packets,
+      total_packets: meta.as_ref().map(|meta| meta.total_packets.saturating_sub(1)),
+      bytes: self.data_bytes,
+      size: meta.map(|meta| meta.size),
+      retransmitted: self.retransmitted,
+      elapsed: self.started.elapsed(),
+    }
+  }
+
+  // Junta, em ordem, os dados dos pacotes 1..=count, descomprimindo-os se o cabeçalho indicar compressão.
+  fn assemble(&self) -> io::Result<Vec<u8>> {
+    let payloads = (1..=self.count).filter_map(|i| self.packets.get(&i));
+    match self.meta() {
+      Some(meta) if meta.compression != Compression::None => {
+        // Cada payload descomprime em no máximo um bloco; o tamanho anunciado, vindo da rede, não
+        // basta para reservar memória.
+        let capacity = meta.size.min(u64::from(self.count) * u64::from(meta.chunk_size));
+        let mut data = Vec::with_capacity(capacity as usize);
+        for payload in payloads {
+          data.extend(compression::decompress_payload(payload, meta.chunk_size as usize)?);
+        }
+        Ok(data)
+      },
+      _ => Ok(payloads.flatten().copied().collect()),
+    }
+  }
+}
+
+pub struct Client {
+  server: SocketAddr,
+  config: ClientConfig,
+  credentials: Option<Credentials>,
+}
+
+impl Client {
+  pub fn new(server: SocketAddr, mut config: ClientConfig) -> Client {
+    config.chunk_size = config.chunk_size.map(|chunk_size| chunk_size.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE));
+    Client { server, config, credentials: None }
+  }
+
+  // Credenciais apresentadas em cada requisição que acessa arquivos (ver `auth`).
+  pub fn with_credentials(mut self, credentials: Credentials) -> Client {
+    self.credentials = Some(credentials);
+    self
+  }
+
+  pub fn server(&self) -> SocketAddr {
+    self.server
+  }
+
+  pub fn config(&self) -> &ClientConfig {
+    &self.config
+  }
+
+  // Baixa um arquivo para a memória.
+  pub fn get(&self, path: &str) -> Result<Download, ClientError> {
+    self.get_with(path, GetOptions::default())
+  }
+
+  // Baixa um arquivo, pedindo retransmissão do que faltar, e confere o SHA-256 anunciado no
+  // cabeçalho antes de entregar os dados ao destino.
+  pub fn get_with(&self, path: &str, mut options: GetOptions) -> Result<Download, ClientError> {
+    let started = Instant::now();
+    let path = path.trim_start_matches('/');
+    let socket = self.socket()?;
+    let request = self.transfer_request("GET", path, vec!["start=1".to_string()]);
+    let fetched = self.fetch(&socket, &request, &mut options)?;
+    let meta = fetched.meta().ok_or_else(|| invalid_response("resposta sem cabeçalho"))?;
+    let data = fetched.assemble().map_err(|e| invalid_response(e.to_string()))?;
+
+    let sha256: [u8; 32] = Sha256::digest(&data).into();
+    if sha256 != meta.sha256 {
+      return Err(invalid_response(format!(
+        "SHA-256 não confere: esperado {}, obtido {}",
+        meta.sha256_hex(),
+        hex(&sha256)
+      )));
+    }
+
+    let data = match options.destination {
+      Destination::Memory => Some(data),
+      Destination::Path(target) => {
+        if let Some(parent) = target.parent() {
+          fs::create_dir_all(parent)?;
+        }
+        fs::write(target, &data)?;
+        None
+      },
+      Destination::Writer(writer) => {
+        writer.write_all(&data)?;
+        writer.flush()?;
+        None
+      },
+    };
+    Ok(Download {
+      path: path.to_string(),
+      meta,
+      packets: fetched.count,
+      wire_bytes: fetched.data_bytes,
+      retransmitted: fetched.retransmitted,
+      elapsed: started.elapsed(),
+      data,
+    })
+  }
+
+  // Lista um diretório remoto (LIST), com o SHA-256 dos arquivos se `with_hash`.
+  pub fn list(&self, dir: &str, with_hash: bool) -> Result<Vec<DirEntry>, ClientError> {
+    let params = if with_hash { vec!["hash=1".to_string()] } else { Vec::new() };
+    let request = self.transfer_request("LIST", dir, params);
+    let fetched = self.fetch(&self.socket()?, &request, &mut GetOptions::default())?;
+    fetched
+      .assemble()
+      .and_then(|data| listing::decode(&data))
+      .map_err(|e| invalid_response(e.to_string()))
+  }
+
+  // Consulta os metadados de um arquivo remoto (STAT), repetindo o pedido até o servidor responder.
+  pub fn stat(&self, path: &str) -> Result<FileMeta, ClientError> {
+    let mut request = format!("STAT /{}", path.trim_start_matches('/'));
+    if let Some(chunk_size) = self.config.chunk_size {
+      request.push_str(&format!("?chunk={}", chunk_size));
+    }
+    let request = self.authenticated(request);
+
+    let socket = self.socket()?;
+    for attempt in 1..=self.config.max_attempts {
+      self.send(&socket, request.as_bytes())?;
+      match self.receive_datagram(&socket)? {
+        Some(reply) => {
+          if let Some(err) = ClientError::from_datagram(&reply) {
+            return Err(err);
+          }
+          return reply
+            .get(HEADER_LEN..)
+            .and_then(FileMeta::decode)
+            .ok_or_else(|| invalid_response("resposta de STAT sem metadados"));
+        },
+        None => debug!(attempt, max_attempts = self.config.max_attempts, "timeout waiting for STAT reply"),
+      }
+    }
+    Err(ClientError::Timeout)
+  }
+
+  // Envia um arquivo local ao servidor (PUT). O servidor acompanha as lacunas e pede, com
+  // RETRANSMIT, os pacotes que não chegaram; ao final confere o SHA-256 e responde PUT-OK.
+  pub fn put(&self, local: &Path, remote: &str, overwrite: OverwritePolicy) -> Result<(), ClientError> {
+    let data = fs::read(local)?;
+    let chunk_size = self.config.chunk_size.unwrap_or(CHUNK_SIZE);
+    let request = format!(
+      "PUT /{}?size={}&sha256={}&chunk={}&overwrite={}",
+      remote.trim_start_matches('/'),
+      data.len(),
+      sha256_hex(&data),
+      chunk_size,
+      overwrite.as_str()
+    );
+    let request = self.authenticated(request);
+    self.send_upload(&self.socket()?, &request, data, chunk_size)
+  }
+
+  // Atualiza `local` por diferença: envia as assinaturas dos blocos da cópia local (DELTA), pede o
+  // delta (`GET ?delta=1`), reconstrói o arquivo e confere o hash antes de substituir a cópia. Sem
+  // cópia local, o delta traz o arquivo inteiro.
+  pub fn delta(&self, remote: &str, local: &Path) -> Result<DeltaStats, ClientError> {
+    let remote = remote.trim_start_matches('/');
+    let basis = match fs::read(local) {
+      Ok(basis) => basis,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+      Err(e) => return Err(e.into()),
+    };
+
+    let block_size = delta::block_size_for(basis.len());
+    let blocks = delta::signatures(&basis, block_size);
+    let signatures = delta::encode_signatures(&blocks);
+    let upload_chunk = self.config.chunk_size.unwrap_or(CHUNK_SIZE);
+    let request = format!(
+      "DELTA /{}?size={}&sha256={}&block={}&chunk={}",
+      remote,
+      signatures.len(),
+      sha256_hex(&signatures),
+      block_size,
+      upload_chunk
+    );
+    let request = self.authenticated(request);
+    debug!(blocks = blocks.len(), bytes = signatures.len(), "sending block signatures");
+    let socket = self.socket()?;
+    self.send_upload(&socket, &request, signatures, upload_chunk)?;
+
+    let params = vec!["start=1".to_string(), "delta=1".to_string()];
+    let request = self.transfer_request("GET", remote, params);
+    let fetched = self.fetch(&socket, &request, &mut GetOptions::default())?;
+    let invalid = |e: io::Error| invalid_response(e.to_string());
+    let delta = Delta::decode(&fetched.assemble().map_err(invalid)?).map_err(invalid)?;
+    let data = delta.apply(&basis, block_size).map_err(invalid)?;
+
+    // Grava ao lado do destino, confere o hash do que foi gravado e só então substitui a cópia local.
+    if let Some(parent) = local.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    let name = local.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let temp = local.with_file_name(format!(".{}.delta", name));
+    fs::write(&temp, &data)?;
+    let (expected, sha256) = (hex(&delta.sha256), calculate_hash(&File::open(&temp)?));
+    if sha256 != expected {
+      let _ = fs::remove_file(&temp);
+      return Err(invalid_response(format!("SHA-256 não confere: esperado {}, obtido {}", expected, sha256)));
+    }
+    fs::rename(&temp, local)?;
+    Ok(DeltaStats { size: data.len(), literal: delta.literal_len(), transferred: fetched.data_bytes as usize })
+  }
+
+  // Espelha um diretório remoto em `local_root`: percorre a árvore remota, recria os diretórios e
+  // baixa os arquivos com até `options.parallel` transferências simultâneas. Arquivos locais com o
+  // mesmo tamanho e hash são mantidos; com `options.delta`, os que mudaram são atualizados por
+  // diferença. Falhas em arquivos ou diretórios isolados ficam no relatório; só erros de E/S locais
+  // interrompem o espelhamento.
+  pub fn mirror(&self, remote_root: &str, local_root: &Path, options: MirrorOptions) -> Result<MirrorReport, ClientError> {
+    let mut report = MirrorReport::default();
+    let mut jobs = Vec::new();
+    let mut pending_dirs = vec![(remote_root.trim_matches('/').to_string(), local_root.to_path_buf())];
+    while let Some((remote_dir, local_dir)) = pending_dirs.pop() {
+      let entries = match self.list(&remote_dir, true) {
+        Ok(entries) => entries,
+        Err(ClientError::Io(e)) => return Err(e.into()),
+        Err(err) => {
+          report.failed.push((remote_dir, err));
+          continue;
+        },
+      };
+      fs::create_dir_all(&local_dir)?;
+      for entry in entries {
+        // Nomes vindos do servidor não podem sair do diretório local.
+        if !Path::new(&entry.name).components().all(|c| matches!(c, Component::Normal(_)))
+          || entry.name.contains(['/', '\\'])
+        {
+          warn!(name = ?entry.name, "skipping unsafe entry name");
+          continue;
+        }
+        let remote = if remote_dir.is_empty() { entry.name.clone() } else { format!("{}/{}", remote_dir, entry.name) };
+        let local = local_dir.join(&entry.name);
+        match entry.kind {
+          EntryKind::Dir => pending_dirs.push((remote, local)),
+          EntryKind::File if is_up_to_date(&local, entry.size, entry.sha256.as_deref()) => report.skipped += 1,
+          EntryKind::File => {
+            let delta = options.delta && local.is_file();
+            jobs.push(MirrorJob { remote, local, sha256: entry.sha256, delta });
+          },
+          _ => debug!(path = %remote, "skipping entry, not a regular file"),
+        }
+      }
+    }
+
+    let total = jobs.len();
+    info!(files = total, up_to_date = report.skipped, "mirror planned");
+    let jobs = Mutex::new(jobs);
+    let failures = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+      for _ in 0..options.parallel.max(1).min(total) {
+        scope.spawn(|| loop {
+          let job = match jobs.lock().unwrap().pop() {
+            Some(job) => job,
+            None => break,
+          };
+          if let Err(err) = self.mirror_file(&job) {
+            failures.lock().unwrap().push((job.remote, err));
+          }
+        });
+      }
+    });
+
+    let failures = failures.into_inner().unwrap();
+    report.downloaded = total - failures.len();
+    report.failed.extend(failures);
+    Ok(report)
+  }
+
+  // Baixa um arquivo do espelhamento e confere o hash anunciado na listagem.
+  fn mirror_file(&self, job: &MirrorJob) -> Result<(), ClientError> {
+    if job.delta {
+      // O delta já confere o hash do arquivo reconstruído.
+      return self.delta(&job.remote, &job.local).map(|_| ());
+    }
+    let options = GetOptions::default().destination(Destination::Path(job.local.clone()));
+    let download = self.get_with(&job.remote, options)?;
+    match &job.sha256 {
+      Some(expected) if *expected != download.meta.sha256_hex() => Err(invalid_response(format!(
+        "SHA-256 não confere: esperado {}, obtido {}",
+        expected,
+        download.meta.sha256_hex()
+      ))),
+      _ => Ok(()),
+    }
+  }
+
+  // Socket novo para cada operação, de modo que respostas atrasadas de uma operação não se
+  // confundam com as da seguinte: porta aleatória e buffer de recepção ampliado. O tempo limite de
+  // leitura curto permite verificar o cancelamento; o tempo limite configurado é contado à parte.
+  fn socket(&self) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(if self.server.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" })?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    SockRef::from(&socket).set_recv_buffer_size(RECV_BUFFER_SIZE)?;
+    Ok(socket)
+  }
+
+  // Monta uma requisição de transferência (GET ou LIST) com os parâmetros negociados.
+  fn transfer_request(&self, command: &str, path: &str, mut params: Vec<String>) -> String {
+    if let Some(chunk_size) = self.config.chunk_size {
+      params.push(format!("chunk={}", chunk_size));
+    }
+    if self.config.compression != Compression::None {
+      params.push(format!("compress={}", self.config.compression.as_str()));
+    }
+    let path = path.trim_start_matches('/');
+    if params.is_empty() {
+      self.authenticated(format!("{} /{}", command, path))
+    } else {
+      self.authenticated(format!("{} /{}?{}", command, path, params.join("&")))
+    }
+  }
+
+  fn authenticated(&self, request: String) -> String {
+    match &self.credentials {
+      Some(credentials) => credentials.apply(&request),
+      None => request,
+    }
+  }
+
+  // Busca uma resposta de vários pacotes (arquivo, delta ou listagem), pedindo retransmissão do que
+  // faltar. Devolve o erro enviado pelo servidor ou `ClientError::Timeout` se a transferência não
+  // terminou dentro do número máximo de tentativas.
+  fn fetch(&self, socket: &UdpSocket, request: &str, options: &mut GetOptions) -> Result<Fetched, ClientError> {
+    let span = debug_span!("fetch", server = %self.server, request = %auth::strip_credentials(request));
+    let _entered = span.enter();
+    // Recepção em lote (recvmmsg, e GRO se pedido) com buffers do tamanho de um pacote; o tamanho
+    // pedido é só uma estimativa até o cabeçalho informar o bloco usado pelo servidor.
+    let buffer_size = self.config.chunk_size.unwrap_or(CHUNK_SIZE) + HEADER_LEN;
+    let mut receiver = BatchReceiver::new(socket, self.config.batch, buffer_size);
+    let mut fetched = Fetched::new();
+    let mut is_retransmitting = false; // Estado para controlar a retransmissão
+    let mut pending_requests = 1; // Respostas (cada uma terminada por fim de transmissão) aguardadas na rodada
+    let mut attempts = 0;
+
+    while attempts < self.config.max_attempts {
+      if !is_retransmitting {
+        pending_requests = 1;
+        match self.send(socket, request.as_bytes()) {
+          Ok(_) => debug!("request sent"),
+          Err(e) => {
+            debug!(error = %e, "failed to send request");
+            attempts += 1;
+            continue;
+          },
+        }
+      }
+
+      if self.receive_response(socket, &mut receiver, pending_requests, &mut fetched, options)? == 0 {
+        // Nada chegou nesta rodada: a requisição ou a resposta inteira se perdeu.
+        debug!(attempt = attempts + 1, "no packets received, retrying");
+        attempts += 1;
+        is_retransmitting = !fetched.packets.is_empty();
+        if !is_retransmitting {
+          continue;
+        }
+      }
+
+      // O total vem do cabeçalho, que só chega na primeira resposta (ou numa retransmissão do
+      // pacote 0); sem ele, o maior número de sequência recebido é a melhor estimativa.
+      let expected_packets = if fetched.announced > 0 {
+        fetched.announced
+      } else {
+        fetched.packets.keys().max().map_or(0, |max| max + 1)
+      };
+      let missing_packets: Vec<u32> = (0..expected_packets).filter(|seq| !fetched.packets.contains_key(seq)).collect();
+      if missing_packets.is_empty() {
+        fetched.count = expected_packets - 1;
+        return Ok(fetched);
+      }
+      debug!(missing = missing_packets.len(), total = expected_packets - 1, "missing packets detected");
+      pending_requests = self.request_retransmission(socket, &missing_packets)?;
+      fetched.retransmitted += missing_packets.len() as u32;
+      is_retransmitting = true;
+    }
+
+    debug!(attempts = self.config.max_attempts, "transfer failed, giving up");
+    Err(ClientError::Timeout)
+  }
+
+  // Recebe datagramas até chegar o fim de transmissão de cada uma das `pending_requests` requisições
+  // (o GET ou cada RETRANSMIT) ou até o servidor ficar em silêncio pelo tempo limite. Devolve
+  // quantos pacotes novos chegaram.
+  fn receive_response(
+    &self,
+    socket: &UdpSocket,
+    receiver: &mut BatchReceiver,
+    pending_requests: usize,
+    fetched: &mut Fetched,
+    options: &mut GetOptions,
+  ) -> Result<usize, ClientError> {
+    let mut new_packets = 0;
+    let mut finished_requests = 0;
+    let mut last_activity = Instant::now();
+    let mut chunk_size = None;
+
+    while finished_requests < pending_requests {
+      if options.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+        return Err(ClientError::Cancelled);
+      }
+      match receiver.recv(socket) {
+        Ok(_) => {
+          last_activity = Instant::now();
+          if capture::enabled() {
+            for (buf, origin) in receiver.datagrams() {
+              capture::record_socket(socket, origin, Direction::Received, [buf]);
+            }
+          }
+          // O lote inteiro é processado mesmo depois de um fim de transmissão, pois ele pode
+          // conter pacotes de outras respostas.
+          for (buf, origin) in receiver.datagrams() {
+            if origin != self.server {
+              continue;
+            }
+            match Datagram::decode(buf) {
+              Datagram::EndOfTransmission => finished_requests += 1,
+              // Erro tipado do servidor; um pacote de erro corrompido é ignorado.
+              Datagram::Error { code, message, checksum_valid: true } => return Err(ClientError::Server { code, message }),
+              // Cabeçalho: guardado como pacote 0, pois indica o total e a compressão dos demais.
+              Datagram::Header(Some(meta)) => {
+                fetched.announced = meta.total_packets;
+                chunk_size = Some((meta.chunk_size as usize).min(MAX_CHUNK_SIZE));
+                if fetched.insert(0, &buf[HEADER_LEN..]) {
+                  new_packets += 1;
+                }
+              },
+              Datagram::Data { seq_number, .. } if options.simulated_loss.remove(&seq_number) => {
+                debug!(seq = seq_number, "packet dropped to simulate loss")
+              },
+              Datagram::Data { seq_number, checksum_valid: false, .. } => debug!(seq = seq_number, "checksum mismatch"),
+              Datagram::Data { seq_number, payload, .. } if fetched.insert(seq_number, payload) => new_packets += 1,
+              _ => {},
+            }
+          }
+          // Pacotes maiores que o buffer chegam truncados, falham na soma de verificação e são pedidos
+          // de novo.
+          if let Some(chunk_size) = chunk_size.take() {
+            receiver.reserve(chunk_size + HEADER_LEN);
+          }
+          if let Some(progress) = options.progress.as_mut() {
+            progress(&fetched.progress());
+          }
+        },
+        Err(ref e) if is_timeout(e) => {
+          if last_activity.elapsed() >= self.config.timeout {
+            break;
+          }
+        },
+        Err(e) => return Err(e.into()),
+      }
+    }
+    Ok(new_packets)
+  }
+
+  // Pede retransmissão dos pacotes faltantes, em várias mensagens se a lista for grande. Devolve
+  // quantas requisições foram enviadas, cada uma respondida com um fim de transmissão.
+  fn request_retransmission(&self, socket: &UdpSocket, missing_packets: &[u32]) -> io::Result<usize> {
+    let mut requests = 0;
+    for chunk in missing_packets.chunks(MAX_PACKETS_PER_REQUEST) {
+      let request = format!(
+        "RETRANSMIT {}",
+        chunk.iter().map(|num| num.to_string()).collect::<Vec<_>>().join(",")
+      );
+      trace!(%request, "requesting retransmission");
+      self.send(socket, request.as_bytes())?;
+      requests += 1;
+    }
+    Ok(requests)
+  }
+
+  // Envia dados com o mecanismo do PUT: negocia com `request` (PUT ou DELTA), envia os pacotes com o
+  // tamanho de bloco confirmado no PUT-READY e atende aos pedidos de retransmissão até o servidor
+  // confirmar com PUT-OK. Pedidos de retransmissão repetidos, sem progresso, contam como tentativas.
+  fn send_upload(&self, socket: &UdpSocket, request: &str, data: Vec<u8>, chunk_size: usize) -> Result<(), ClientError> {
+    let span = debug_span!("upload", server = %self.server, request = %auth::strip_credentials(request));
+    let _entered = span.enter();
+    let max_attempts = self.config.max_attempts;
+    // Negociação: repete o pedido até o servidor responder.
+    let mut reply = None;
+    for attempt in 1..=max_attempts {
+      self.send(socket, request.as_bytes())?;
+      reply = self.receive_reply(socket)?;
+      if reply.is_some() {
+        break;
+      }
+      debug!(attempt, max_attempts, "timeout waiting for PUT-READY");
+    }
+    let reply = reply.ok_or(ClientError::Timeout)??;
+    if reply.starts_with("PUT-OK") {
+      debug!("server already has identical content");
+      return Ok(());
+    }
+    if !reply.starts_with("PUT-READY") {
+      return Err(ClientError::InvalidResponse(reply));
+    }
+    // Servidores antigos não informam o bloco aceito.
+    let chunk_size = match reply.split_whitespace().nth(2) {
+      Some(accepted) => accepted.parse::<usize>().map_err(|_| ClientError::InvalidResponse(reply.clone()))?,
+      None => chunk_size,
+    };
+    if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+      return Err(ClientError::InvalidResponse(reply));
+    }
+
+    let sender = BatchSender::new(self.config.batch);
+    let local_port = socket.local_addr()?.port();
+    // O pacote 0 (cabeçalho) não é enviado: tamanho e hash já foram anunciados no pedido.
+    let datagrams: Vec<Vec<u8>> = UdpPacket::prepare_packets(local_port, SERVER_PORT, data, chunk_size, 0)
+      .iter()
+      .skip(1)
+      .map(UdpPacket::serialize)
+      .collect();
+    let end = UdpPacket::end_of_transmission(local_port, SERVER_PORT).serialize();
+    debug!(packets = datagrams.len(), chunk_size, "sending upload");
+    self.send_all(socket, &sender, &datagrams)?;
+    self.send(socket, &end)?;
+
+    let mut attempts = 0;
+    let mut last_request = None;
+    loop {
+      match self.receive_reply(socket)?.transpose()? {
+        Some(reply) if reply.starts_with("PUT-OK") => {
+          debug!(%reply, "server confirmed upload");
+          return Ok(());
+        },
+        Some(reply) if reply.starts_with("RETRANSMIT ") => {
+          let resend: Vec<Vec<u8>> = reply
+            .trim_start_matches("RETRANSMIT ")
+            .split(',')
+            .filter_map(|seq| seq.parse::<usize>().ok())
+            .filter_map(|seq| datagrams.get(seq.wrapping_sub(1)).cloned())
+            .collect();
+          // O servidor pede sempre as primeiras lacunas; o mesmo pedido de novo indica que nada do
+          // último reenvio foi aceito.
+          if last_request.as_ref() == Some(&reply) {
+            attempts += 1;
+            if attempts >= max_attempts {
+              return Err(ClientError::Timeout);
+            }
+          } else {
+            attempts = 0;
+          }
+          debug!(packets = resend.len(), attempts, "server requested packets again");
+          self.send_all(socket, &sender, &resend)?;
+          self.send(socket, &end)?;
+          last_request = Some(reply);
+        },
+        // Respostas atrasadas de rodadas anteriores (por exemplo, um PUT-READY repetido).
+        Some(_) => continue,
+        None => {
+          attempts += 1;
+          if attempts >= max_attempts {
+            return Err(ClientError::Timeout);
+          }
+          debug!(attempts, max_attempts, "timeout waiting for server, resending end of transmission");
+          self.send(socket, &end)?;
+        },
+      }
+    }
+  }
+
+  // Envia um datagrama ao servidor, registrando-o na captura.
+  fn send(&self, socket: &UdpSocket, datagram: &[u8]) -> io::Result<()> {
+    socket.send_to(datagram, self.server)?;
+    capture::record_socket(socket, self.server, Direction::Sent, [datagram]);
+    Ok(())
+  }
+
+  fn send_all(&self, socket: &UdpSocket, sender: &BatchSender, datagrams: &[Vec<u8>]) -> io::Result<()> {
+    sender.send_all(socket, datagrams, self.server)?;
+    capture::record_socket(socket, self.server, Direction::Sent, datagrams.iter().map(Vec::as_slice));
+    Ok(())
+  }
+
+  // Recebe uma resposta de texto do servidor, ou o erro que ele enviou no lugar dela. Devolve
+  // `None` quando o tempo limite expira.
+  fn receive_reply(&self, socket: &UdpSocket) -> io::Result<Option<Result<String, ClientError>>> {
+    Ok(self.receive_datagram(socket)?.map(|reply| match ClientError::from_datagram(&reply) {
+      Some(err) => Err(err),
+      None => Ok(String::from_utf8_lossy(&reply).into_owned()),
+    }))
+  }
+
+  // Recebe um datagrama do servidor, ignorando outras origens. Devolve `None` quando o tempo limite expira.
+  fn receive_datagram(&self, socket: &UdpSocket) -> io::Result<Option<Vec<u8>>> {
+    let deadline = Instant::now() + self.config.timeout;
+    let mut buf = [0u8; 2048];
+    loop {
+      match socket.recv_from(&mut buf) {
+        Ok((size, origin)) => {
+          capture::record_socket(socket, origin, Direction::Received, [&buf[..size]]);
+          if origin == self.server {
+            return Ok(Some(buf[..size].to_vec()));
+          }
+        },
+        Err(ref e) if is_timeout(e) => {
+          if Instant::now() >= deadline {
+            return Ok(None);
+          }
+        },
+        Err(e) => return Err(e),
+      }
+    }
+  }
+}
+
+// Verifica se o arquivo local já tem o tamanho e o hash do remoto.
+fn is_up_to_date(local: &Path, size: u64, sha256: Option<&str>) -> bool {
+  let file = match File::open(local) {
+    Ok(file) => file,
+    Err(_) => return false,
+  };
+  match (file.metadata(), sha256) {
+    (Ok(metadata), Some(sha256)) => metadata.len() == size && file_sha256_hex(file).is_ok_and(|hash| hash == sha256),
+    _ => false,
+  }
+}
+
+fn file_sha256_hex(mut file: File) -> io::Result<String> {
+  let mut hasher = Sha256::new();
+  io::copy(&mut file, &mut hasher)?;
+  Ok(hex(&hasher.finalize()))
+}
+
+fn per_second(bytes: u64, elapsed: Duration) -> f64 {
+  let secs = elapsed.as_secs_f64();
+  if secs > 0.0 { bytes as f64 / secs } else { 0.0 }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+  e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+fn invalid_response(reason: impl Into<String>) -> ClientError {
+  ClientError::InvalidResponse(reason.into())
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+  hex(&Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn progress(packets: u32, total_packets: Option<u32>, retransmitted: u32) -> Progress {
+    Progress { packets, total_packets, bytes: packets as u64 * 1000, size: None, retransmitted, elapsed: Duration::from_secs(2) }
+  }
+
+  #[test]
+  fn progress_estimates_from_the_received_fraction() {
+    let halfway = progress(50, Some(100), 5);
+    assert_eq!(halfway.fraction(), Some(0.5));
+    assert_eq!(halfway.eta(), Some(Duration::from_secs(2)));
+    assert_eq!(halfway.throughput(), 25_000.0);
+    assert_eq!(halfway.loss_rate(), Some(0.05));
+
+    let done = progress(100, Some(100), 0);
+    assert_eq!((done.fraction(), done.eta()), (Some(1.0), Some(Duration::ZERO)));
+  }
+
+  #[test]
+  fn progress_before_the_header_has_no_estimates() {
+    let early = progress(3, None, 0);
+    assert_eq!((early.fraction(), early.eta(), early.loss_rate()), (None, None, None));
+    let nothing = progress(0, Some(100), 0);
+    assert_eq!(nothing.eta(), None);
+    // Arquivo vazio: completo assim que o cabeçalho chega.
+    let empty = progress(0, Some(0), 0);
+    assert_eq!((empty.fraction(), empty.loss_rate()), (Some(1.0), None));
+    assert_eq!(Progress { elapsed: Duration::ZERO, ..early }.throughput(), 0.0);
+  }
+
+  #[test]
+  fn download_statistics() {
+    let meta = FileMeta::new(&[0; 4000], 1000, 0);
+    let download = Download {
+      path: "a.bin".to_string(),
+      meta,
+      packets: 4,
+      wire_bytes: 4000,
+      retransmitted: 1,
+      elapsed: Duration::from_millis(500),
+      data: None,
+    };
+    assert_eq!((download.throughput(), download.loss_rate()), (8000.0, 0.25));
+    assert_eq!(Download { packets: 0, ..download }.loss_rate(), 0.0);
+  }
+}
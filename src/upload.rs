@@ -0,0 +1,607 @@
+// Upload de arquivos (PUT). Aqui os papéis se invertem: o cliente envia os pacotes de dados e o
+// servidor acompanha as lacunas, pede retransmissões e, ao final, confere o SHA-256 antes de
+// publicá-lo na origem dos arquivos servidos (`FileSource::write`). Os dados são recebidos em um
+// arquivo temporário no diretório temporário do sistema.
+//
+// Troca de mensagens:
+//   cliente -> `PUT /caminho?size=N&sha256=HEX[&chunk=C][&overwrite=never|always|if-different]`
+//   servidor -> `PUT-READY <pacotes> <bloco>` (ou `PUT-OK <sha256>` se nada precisa ser enviado), com
+//               o tamanho de bloco aceito, que pode diferir do pedido
+//   cliente -> pacotes de dados 1..=pacotes, com o bloco aceito, e o pacote de fim de transmissão
+//   servidor -> `RETRANSMIT a,b,c` enquanto faltarem pacotes, depois `PUT-OK <sha256>` ou um pacote de erro
+//
+// O mesmo mecanismo transporta as assinaturas de blocos da transferência por diferença:
+//   cliente -> `DELTA /caminho?size=N&sha256=HEX&block=B[&chunk=C]`, seguido dos pacotes de assinaturas
+//   servidor -> `PUT-READY`, `RETRANSMIT` e `PUT-OK` como acima; as assinaturas ficam guardadas para
+//               o `GET /caminho?delta=1` seguinte, que responde com o delta (ver `delta`).
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+use std::{env, process};
+
+use digest::Digest;
+use sha2::Sha256;
+use tracing::{debug, debug_span, info, trace, warn, Span};
+
+use crate::calculate_hash;
+use crate::delta::{self, BlockSignature, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
+use crate::handler::{error_message, io_error_code, next_session_id, query_param, request_path, requested_chunk_size, Handler, SessionKey};
+use crate::limits::SessionLimits;
+use crate::listing::EntryKind;
+use crate::protocol::{calculate_checksum, ErrorCode, END_OF_TRANSMISSION_SEQ_NUM, HEADER_LEN};
+use crate::source::{validate_path, FileSource};
+
+// Quantidade máxima de números de sequência pedidos em cada RETRANSMIT enviado ao cliente.
+const MAX_SEQS_PER_RETRANSMIT: usize = 128;
+
+// O que fazer quando o destino do upload já existe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverwritePolicy {
+  // Recusa o upload.
+  Never,
+  // Substitui o arquivo existente.
+  Always,
+  // Substitui apenas se o conteúdo for diferente; se for igual, responde PUT-OK sem transferir nada.
+  IfDifferent,
+}
+
+impl OverwritePolicy {
+  pub fn parse(value: &str) -> Option<OverwritePolicy> {
+    match value {
+      "never" => Some(OverwritePolicy::Never),
+      "always" => Some(OverwritePolicy::Always),
+      "if-different" => Some(OverwritePolicy::IfDifferent),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      OverwritePolicy::Never => "never",
+      OverwritePolicy::Always => "always",
+      OverwritePolicy::IfDifferent => "if-different",
+    }
+  }
+}
+
+// O que está sendo enviado pelo cliente.
+enum UploadKind {
+  // Um arquivo, publicado no destino ao final.
+  File(OverwritePolicy),
+  // Assinaturas dos blocos da cópia local do cliente, para um delta do destino. Ficam em
+  // `signatures` depois de recebidas.
+  Signatures { block_size: usize, signatures: Vec<BlockSignature> },
+}
+
+// Estado de um upload em andamento, um por cliente.
+pub(crate) struct Upload {
+  // Identificador da sessão nos logs.
+  session: u64,
+  // Caminho do destino na origem dos arquivos.
+  target: String,
+  temp: PathBuf,
+  file: Option<File>,
+  size: u64,
+  chunk_size: usize,
+  received: Vec<bool>,
+  remaining: usize,
+  sha256: String,
+  kind: UploadKind,
+  // Resposta final, guardada para ser reenviada se o cliente repetir o fim de transmissão.
+  outcome: Option<Vec<u8>>,
+  created: Instant,
+  last_activity: Instant,
+}
+
+// Uploads de um `Handler`, por cliente.
+pub(crate) type Uploads = Mutex<HashMap<SocketAddr, Upload>>;
+
+// Trata o `PUT`: valida os parâmetros, aplica a política de sobrescrita e prepara o arquivo temporário.
+pub(crate) fn handle_put_request(handler: &Handler, request: &str, client_address: SocketAddr) -> Vec<Vec<u8>> {
+  let source = handler.source();
+  let path = match request.split_whitespace().nth(1) {
+    Some(path) => path,
+    None => return vec![error_message(ErrorCode::BadRequest, "Requisição mal formatada", client_address)],
+  };
+  let filename = request_path(path);
+  let size = query_param(path, "size").and_then(|value| value.parse::<u64>().ok());
+  let sha256 = query_param(path, "sha256").filter(|hash| hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()));
+  let (size, sha256) = match (size, sha256) {
+    (Some(size), Some(sha256)) if !filename.is_empty() => (size, sha256.to_ascii_lowercase()),
+    _ => return vec![error_message(ErrorCode::BadRequest, "PUT requer caminho, size e sha256", client_address)],
+  };
+  let overwrite = match query_param(path, "overwrite").map(OverwritePolicy::parse) {
+    None => OverwritePolicy::Never,
+    Some(Some(policy)) => policy,
+    Some(None) => return vec![error_message(ErrorCode::BadRequest, "Política de sobrescrita inválida", client_address)],
+  };
+  let chunk_size = requested_chunk_size(path);
+
+  let target = match validate_path(filename) {
+    Ok(target) => target.to_string(),
+    Err(e) => return vec![error_message(ErrorCode::Forbidden, &e.to_string(), client_address)],
+  };
+  if let Err(too_large) = check_size(size, handler.limits(), client_address) {
+    return vec![too_large];
+  }
+
+  if let Err(busy) = handler.admit_session(SessionKey::Upload(client_address), footprint(size, chunk_size, false)) {
+    return vec![busy];
+  }
+  let mut uploads = handler.uploads().lock().unwrap();
+  // PUT repetido (o PUT-READY se perdeu): mantém o que já foi recebido.
+  if let Some(upload) = uploads.get_mut(&client_address) {
+    if upload.outcome.is_none() && upload.target == target && upload.sha256 == sha256 && upload.size == size {
+      upload.last_activity = Instant::now();
+      return vec![ready_message(upload)];
+    }
+  }
+  if let Some(previous) = uploads.remove(&client_address) {
+    discard(previous);
+  }
+
+  match source.kind(&target) {
+    Ok(EntryKind::Dir) => return vec![error_message(ErrorCode::BadRequest, "O destino é um diretório", client_address)],
+    Ok(_) => match overwrite {
+      OverwritePolicy::Never => return vec![error_message(ErrorCode::AlreadyExists, "Arquivo já existe", client_address)],
+      OverwritePolicy::IfDifferent if same_content(source, &target, size, &sha256) => {
+        info!("upload skipped, content is identical");
+        return vec![ok_message(&sha256)];
+      },
+      _ => {},
+    },
+    Err(_) => {},
+  }
+
+  let upload = match start_upload(target, size, chunk_size, sha256, UploadKind::File(overwrite)) {
+    Ok(upload) => upload,
+    Err(e) => {
+      warn!(error = %e, "failed to prepare upload");
+      return vec![error_message(ErrorCode::Internal, &format!("Erro ao preparar o upload: {}", e), client_address)];
+    }
+  };
+  Span::current().record("session", upload.session);
+  info!(bytes = size, packets = upload.received.len(), "upload started");
+  let response = ready_message(&upload);
+  uploads.insert(client_address, upload);
+  vec![response]
+}
+
+// Trata o `DELTA`: prepara o recebimento das assinaturas da cópia local do cliente.
+pub(crate) fn handle_delta_request(handler: &Handler, request: &str, client_address: SocketAddr) -> Vec<Vec<u8>> {
+  let source = handler.source();
+  let path = match request.split_whitespace().nth(1) {
+    Some(path) => path,
+    None => return vec![error_message(ErrorCode::BadRequest, "Requisição mal formatada", client_address)],
+  };
+  let filename = request_path(path);
+  let size = query_param(path, "size").and_then(|value| value.parse::<u64>().ok());
+  let sha256 = query_param(path, "sha256").filter(|hash| hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()));
+  let block_size = query_param(path, "block")
+    .and_then(|value| value.parse::<usize>().ok())
+    .filter(|block_size| (MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(block_size));
+  let (size, sha256, block_size) = match (size, sha256, block_size) {
+    (Some(size), Some(sha256), Some(block_size)) if !filename.is_empty() => (size, sha256.to_ascii_lowercase(), block_size),
+    _ => return vec![error_message(ErrorCode::BadRequest, "DELTA requer caminho, size, sha256 e block", client_address)],
+  };
+
+  let target = match validate_path(filename) {
+    Ok(target) => target.to_string(),
+    Err(e) => return vec![error_message(ErrorCode::Forbidden, &e.to_string(), client_address)],
+  };
+  if !source.kind(&target).is_ok_and(|kind| kind == EntryKind::File) {
+    return vec![error_message(ErrorCode::NotFound, "Arquivo não encontrado", client_address)];
+  }
+  if let Err(too_large) = check_size(size, handler.limits(), client_address) {
+    return vec![too_large];
+  }
+
+  let chunk_size = requested_chunk_size(path);
+  if let Err(busy) = handler.admit_session(SessionKey::Upload(client_address), footprint(size, chunk_size, true)) {
+    return vec![busy];
+  }
+  let mut uploads = handler.uploads().lock().unwrap();
+  if let Some(upload) = uploads.get_mut(&client_address) {
+    if upload.outcome.is_none() && upload.target == target && upload.sha256 == sha256 && upload.size == size {
+      upload.last_activity = Instant::now();
+      return vec![ready_message(upload)];
+    }
+  }
+  if let Some(previous) = uploads.remove(&client_address) {
+    discard(previous);
+  }
+
+  let kind = UploadKind::Signatures { block_size, signatures: Vec::new() };
+  let upload = match start_upload(target, size, chunk_size, sha256, kind) {
+    Ok(upload) => upload,
+    Err(e) => {
+      warn!(error = %e, "failed to prepare delta");
+      return vec![error_message(ErrorCode::Internal, &format!("Erro ao preparar o delta: {}", e), client_address)];
+    }
+  };
+  Span::current().record("session", upload.session);
+  info!(bytes = size, block_size, "receiving delta signatures");
+  let response = ready_message(&upload);
+  uploads.insert(client_address, upload);
+  vec![response]
+}
+
+// Assinaturas recebidas do cliente para `target`, com o tamanho de bloco usado por ele.
+pub(crate) fn delta_basis(uploads: &Uploads, client_address: SocketAddr, target: &str) -> Option<(usize, Vec<BlockSignature>)> {
+  let uploads = uploads.lock().unwrap();
+  let upload = uploads.get(&client_address).filter(|upload| upload.target == target)?;
+  match (&upload.kind, &upload.outcome) {
+    (UploadKind::Signatures { block_size, signatures }, Some(outcome)) if outcome.starts_with(b"PUT-OK") => {
+      Some((*block_size, signatures.clone()))
+    },
+    _ => None,
+  }
+}
+
+// Trata um datagrama que pertence ao upload em andamento do cliente. Devolve `None` quando o
+// cliente não tem upload ativo ou o datagrama não é um pacote de dados (por exemplo, um novo PUT).
+pub(crate) fn handle_upload_packet(handler: &Handler, datagram: &[u8], client_address: SocketAddr) -> Option<Vec<Vec<u8>>> {
+  if datagram.len() < HEADER_LEN {
+    return None;
+  }
+  let mut uploads = handler.uploads().lock().unwrap();
+  let upload = uploads.get_mut(&client_address)?;
+  let seq_number = u32::from_be_bytes([datagram[0], datagram[1], datagram[2], datagram[3]]);
+  upload.last_activity = Instant::now();
+  let span = debug_span!("upload", session = upload.session, peer = %client_address);
+  let _entered = span.enter();
+
+  if seq_number == END_OF_TRANSMISSION_SEQ_NUM {
+    return Some(vec![finish_or_request_missing(handler.source(), upload, client_address)]);
+  }
+  if seq_number == 0 || seq_number as usize > upload.received.len() {
+    return None;
+  }
+  if upload.outcome.is_some() || upload.received[seq_number as usize - 1] {
+    // Duplicata ou pacote atrasado de um upload já concluído.
+    trace!(seq = seq_number, "duplicate upload packet");
+    return Some(Vec::new());
+  }
+
+  let received_checksum = u16::from_be_bytes([datagram[10], datagram[11]]);
+  let data = &datagram[HEADER_LEN..];
+  if calculate_checksum(data) != received_checksum || data.len() != expected_len(upload, seq_number) {
+    debug!(seq = seq_number, bytes = data.len(), "upload packet discarded: bad checksum or length");
+    return Some(Vec::new());
+  }
+
+  let offset = (seq_number as u64 - 1) * upload.chunk_size as u64;
+  let written = upload.file.as_mut().map(|file| {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(data)
+  });
+  match written {
+    Some(Ok(())) => {
+      trace!(seq = seq_number, bytes = data.len(), "upload packet stored");
+      upload.received[seq_number as usize - 1] = true;
+      upload.remaining -= 1;
+      Some(Vec::new())
+    },
+    Some(Err(e)) => {
+      warn!(seq = seq_number, error = %e, "failed to write upload packet");
+      Some(vec![fail(upload, &format!("Erro ao gravar o arquivo: {}", e), client_address)])
+    },
+    None => Some(Vec::new()),
+  }
+}
+
+// Recusa uploads maiores que `SessionLimits::max_upload_size`, antes de criar o arquivo temporário e
+// o mapa de pacotes recebidos.
+fn check_size(size: u64, limits: &SessionLimits, client_address: SocketAddr) -> Result<(), Vec<u8>> {
+  let max_size = limits.max_upload_size;
+  if size > max_size {
+    info!(bytes = size, max_size, "upload refused, too large");
+    return Err(error_message(ErrorCode::TooLarge, &format!("Upload maior que o limite de {} bytes", max_size), client_address));
+  }
+  Ok(())
+}
+
+// Memória ocupada por um upload de `size` bytes, contada no limite de bytes guardados: o mapa dos
+// pacotes recebidos e, no DELTA, as assinaturas, que ficam em memória até o GET do delta. Os dados de
+// um PUT vão para o arquivo temporário.
+fn footprint(size: u64, chunk_size: usize, signatures: bool) -> usize {
+  let packets = usize::try_from(size.div_ceil(chunk_size as u64)).unwrap_or(usize::MAX);
+  if signatures {
+    packets.saturating_add(usize::try_from(size).unwrap_or(usize::MAX))
+  } else {
+    packets
+  }
+}
+
+fn start_upload(target: String, size: u64, chunk_size: usize, sha256: String, kind: UploadKind) -> io::Result<Upload> {
+  let extension = match kind {
+    UploadKind::File(_) => "part",
+    UploadKind::Signatures { .. } => "sig",
+  };
+  let session = next_session_id();
+  let temp = env::temp_dir().join(format!("rawsocket-udp-{}-{}.{}", process::id(), session, extension));
+  let file = File::create(&temp)?;
+  file.set_len(size)?;
+
+  let packets = size.div_ceil(chunk_size as u64) as usize;
+  let now = Instant::now();
+  Ok(Upload {
+    session,
+    target,
+    temp,
+    file: Some(file),
+    size,
+    chunk_size,
+    received: vec![false; packets],
+    remaining: packets,
+    sha256,
+    kind,
+    outcome: None,
+    created: now,
+    last_activity: now,
+  })
+}
+
+// Fim de transmissão do cliente: pede o que falta ou, com tudo recebido, publica o arquivo.
+fn finish_or_request_missing(source: &dyn FileSource, upload: &mut Upload, client_address: SocketAddr) -> Vec<u8> {
+  if let Some(outcome) = &upload.outcome {
+    return outcome.clone();
+  }
+
+  if upload.remaining > 0 {
+    let missing: Vec<String> = upload
+      .received
+      .iter()
+      .enumerate()
+      .filter(|(_, received)| !**received)
+      .take(MAX_SEQS_PER_RETRANSMIT)
+      .map(|(index, _)| (index + 1).to_string())
+      .collect();
+    debug!(missing = upload.remaining, requested = missing.len(), "upload incomplete, requesting retransmission");
+    return format!("RETRANSMIT {}", missing.join(",")).into_bytes();
+  }
+
+  let outcome = match publish(source, upload) {
+    Ok(()) => {
+      info!(session = upload.session, bytes = upload.size, "upload completed");
+      ok_message(&upload.sha256)
+    },
+    Err(e) => {
+      warn!(session = upload.session, error = %e, "failed to complete upload");
+      let _ = fs::remove_file(&upload.temp);
+      error_message(io_error_code(&e), &e.to_string(), client_address)
+    }
+  };
+  upload.outcome = Some(outcome.clone());
+  outcome
+}
+
+// Confere o hash do arquivo temporário e o publica na origem ou, no caso de assinaturas, as carrega.
+fn publish(source: &dyn FileSource, upload: &mut Upload) -> io::Result<()> {
+  if let Some(file) = upload.file.take() {
+    file.sync_all()?;
+  }
+  let hash = calculate_hash(&File::open(&upload.temp)?);
+  if hash != upload.sha256 {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "Hash SHA-256 não confere"));
+  }
+  match &mut upload.kind {
+    UploadKind::File(OverwritePolicy::Never) if source.kind(&upload.target).is_ok() => {
+      Err(io::Error::new(io::ErrorKind::AlreadyExists, "Arquivo já existe"))
+    },
+    UploadKind::File(_) => {
+      let published = File::open(&upload.temp).and_then(|mut file| source.write(&upload.target, &mut file));
+      let _ = fs::remove_file(&upload.temp);
+      published
+    },
+    UploadKind::Signatures { signatures, .. } => {
+      let data = fs::read(&upload.temp);
+      let _ = fs::remove_file(&upload.temp);
+      *signatures = delta::decode_signatures(&data?)?;
+      Ok(())
+    },
+  }
+}
+
+fn fail(upload: &mut Upload, message: &str, client_address: SocketAddr) -> Vec<u8> {
+  upload.file = None;
+  let _ = fs::remove_file(&upload.temp);
+  let outcome = error_message(ErrorCode::Internal, message, client_address);
+  upload.outcome = Some(outcome.clone());
+  outcome
+}
+
+// Uploads em andamento, com a última atividade, para os limites de sessões. Os concluídos só
+// aguardam um fim de transmissão repetido e não contam.
+pub(crate) fn active_sessions(uploads: &Uploads) -> Vec<(SocketAddr, Instant, usize)> {
+  let uploads = uploads.lock().unwrap();
+  uploads
+    .iter()
+    .filter(|(_, upload)| upload.outcome.is_none())
+    .map(|(address, upload)| {
+      let signatures = matches!(upload.kind, UploadKind::Signatures { .. });
+      (*address, upload.last_activity, footprint(upload.size, upload.chunk_size, signatures))
+    })
+    .collect()
+}
+
+// Encerra o upload do cliente para dar lugar a outra sessão.
+pub(crate) fn evict(uploads: &Uploads, client_address: SocketAddr) {
+  if let Some(upload) = uploads.lock().unwrap().remove(&client_address) {
+    info!(session = upload.session, peer = %client_address, "quiet upload evicted to make room");
+    discard(upload);
+  }
+}
+
+// Descarta os uploads expirados, concluídos ou não.
+pub(crate) fn expire_sessions(uploads: &Uploads, limits: &SessionLimits, now: Instant) {
+  let mut uploads = uploads.lock().unwrap();
+  let expired: Vec<(SocketAddr, &'static str)> = uploads
+    .iter()
+    .filter_map(|(address, upload)| limits.expired(upload.created, upload.last_activity, now).map(|reason| (*address, reason)))
+    .collect();
+  for (address, reason) in expired {
+    if let Some(upload) = uploads.remove(&address) {
+      if upload.outcome.is_none() {
+        info!(session = upload.session, peer = %address, reason, "upload expired");
+      }
+      discard(upload);
+    }
+  }
+}
+
+fn discard(upload: Upload) {
+  if upload.outcome.is_none() {
+    drop(upload.file);
+    let _ = fs::remove_file(&upload.temp);
+  }
+}
+
+fn same_content(source: &dyn FileSource, target: &str, size: u64, sha256: &str) -> bool {
+  match source.read(target) {
+    Ok(file) => {
+      let hash: String = Sha256::digest(&file.data).iter().map(|byte| format!("{:02x}", byte)).collect();
+      file.data.len() as u64 == size && hash == sha256
+    },
+    Err(_) => false,
+  }
+}
+
+// Tamanho esperado dos dados de um pacote: cheio, exceto possivelmente o último.
+fn expected_len(upload: &Upload, seq_number: u32) -> usize {
+  let offset = (seq_number as u64 - 1) * upload.chunk_size as u64;
+  (upload.size - offset).min(upload.chunk_size as u64) as usize
+}
+
+fn ready_message(upload: &Upload) -> Vec<u8> {
+  format!("PUT-READY {} {}", upload.received.len(), upload.chunk_size).into_bytes()
+}
+
+fn ok_message(sha256: &str) -> Vec<u8> {
+  format!("PUT-OK {}", sha256).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use super::*;
+  use crate::protocol::{decode_error, UdpPacket, SERVER_PORT};
+  use crate::source::MemorySource;
+
+  const CHUNK: usize = 512;
+
+  fn client() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 4000))
+  }
+
+  fn sample(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 13 % 251) as u8).collect()
+  }
+
+  fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+  }
+
+  fn put_request(path: &str, data: &[u8], extra: &str) -> Vec<u8> {
+    format!("PUT /{}?size={}&sha256={}&chunk={}{}", path, data.len(), sha256_hex(data), CHUNK, extra).into_bytes()
+  }
+
+  fn packet(data: &[u8], seq: u32) -> Vec<u8> {
+    let chunk = data.chunks(CHUNK).nth(seq as usize - 1).unwrap().to_vec();
+    UdpPacket::data_packet(seq, 4000, SERVER_PORT, chunk).serialize()
+  }
+
+  fn end_of_transmission() -> Vec<u8> {
+    UdpPacket::end_of_transmission(4000, SERVER_PORT).serialize()
+  }
+
+  fn error_code(response: &[Vec<u8>]) -> Option<ErrorCode> {
+    response.first().and_then(|datagram| decode_error(datagram)).map(|(code, _)| code)
+  }
+
+  #[test]
+  fn overwrite_policies_round_trip() {
+    for policy in [OverwritePolicy::Never, OverwritePolicy::Always, OverwritePolicy::IfDifferent] {
+      assert_eq!(OverwritePolicy::parse(policy.as_str()), Some(policy));
+    }
+    assert_eq!(OverwritePolicy::parse("sempre"), None);
+  }
+
+  #[test]
+  fn put_requests_missing_packets_then_publishes() {
+    let source = Arc::new(MemorySource::new());
+    let handler = Handler::new(source.clone());
+    let data = sample(2000);
+    assert_eq!(handler.handle(&put_request("up/a.bin", &data, ""), client()), vec![b"PUT-READY 4 512".to_vec()]);
+
+    // Os pacotes 2 e 4 se perdem; um pacote corrompido é descartado.
+    handler.handle(&packet(&data, 1), client());
+    let mut corrupted = packet(&data, 3);
+    corrupted[HEADER_LEN] ^= 0xff;
+    handler.handle(&corrupted, client());
+    assert_eq!(handler.handle(&end_of_transmission(), client()), vec![b"RETRANSMIT 2,3,4".to_vec()]);
+    for seq in 2..=4 {
+      handler.handle(&packet(&data, seq), client());
+    }
+    let ok = format!("PUT-OK {}", sha256_hex(&data)).into_bytes();
+    assert_eq!(handler.handle(&end_of_transmission(), client()), vec![ok.clone()]);
+    assert_eq!(source.get("up/a.bin"), Some(data));
+    // Um fim de transmissão repetido recebe a mesma resposta.
+    assert_eq!(handler.handle(&end_of_transmission(), client()), vec![ok]);
+  }
+
+  #[test]
+  fn put_with_wrong_hash_is_not_published() {
+    let source = Arc::new(MemorySource::new());
+    let handler = Handler::new(source.clone());
+    let data = sample(1000);
+    let request = format!("PUT /a.bin?size=1000&sha256={}&chunk={}", sha256_hex(b"outro"), CHUNK);
+    handler.handle(request.as_bytes(), client());
+    for seq in 1..=2 {
+      handler.handle(&packet(&data, seq), client());
+    }
+    assert_eq!(error_code(&handler.handle(&end_of_transmission(), client())), Some(ErrorCode::IntegrityFailure));
+    assert_eq!(source.get("a.bin"), None);
+  }
+
+  #[test]
+  fn put_is_refused_before_any_transfer() {
+    let existing = sample(700);
+    let source = Arc::new(MemorySource::new().with_file("a.bin", existing.clone()).with_file("dir/b.bin", "b"));
+    let limits = SessionLimits { max_upload_size: 1500, ..SessionLimits::default() };
+    let handler = Handler::new(source).with_limits(limits);
+    let data = sample(1000);
+
+    let cases: [(Vec<u8>, ErrorCode); 7] = [
+      (b"PUT /c.bin?size=10".to_vec(), ErrorCode::BadRequest),
+      (put_request("c.bin", &data, "&overwrite=sempre"), ErrorCode::BadRequest),
+      (put_request("../c.bin", &data, ""), ErrorCode::Forbidden),
+      (put_request("c.bin", &sample(1501), ""), ErrorCode::TooLarge),
+      (put_request("a.bin", &data, ""), ErrorCode::AlreadyExists),
+      (put_request("a.bin", &data, "&overwrite=never"), ErrorCode::AlreadyExists),
+      (put_request("dir", &data, "&overwrite=always"), ErrorCode::BadRequest),
+    ];
+    for (request, expected) in cases {
+      assert_eq!(error_code(&handler.handle(&request, client())), Some(expected), "{}", String::from_utf8_lossy(&request));
+    }
+
+    // Conteúdo idêntico: nada a enviar.
+    let response = handler.handle(&put_request("a.bin", &existing, "&overwrite=if-different"), client());
+    assert_eq!(response, vec![format!("PUT-OK {}", sha256_hex(&existing)).into_bytes()]);
+    let response = handler.handle(&put_request("a.bin", &data, "&overwrite=if-different"), client());
+    assert_eq!(response, vec![b"PUT-READY 2 512".to_vec()]);
+  }
+
+  #[test]
+  fn footprint_counts_packets_and_signatures() {
+    assert_eq!(footprint(0, 512, false), 0);
+    assert_eq!(footprint(1025, 512, false), 3);
+    assert_eq!(footprint(1025, 512, true), 1028);
+    assert_eq!(footprint(u64::MAX, 512, true), usize::MAX);
+  }
+}
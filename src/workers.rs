@@ -0,0 +1,239 @@
+// Servidor multi-core: N workers, cada um com seu próprio socket ligado à mesma porta via
+// SO_REUSEPORT. O kernel distribui os datagramas entre os sockets, mas quem atende um cliente é
+// sempre o worker dono da sessão (escolhido pelo hash do endereço do cliente), de modo que o GET e
+// as retransmissões de um mesmo cliente são tratados em ordem pelo mesmo worker.
+//
+// As filas entre as threads são limitadas: quando a fila de requisições de um worker está cheia, o
+// datagrama é descartado (o cliente repete o pedido depois do timeout). As respostas são enviadas
+// por uma thread própria de cada worker, que intercala as respostas de clientes diferentes bloco a
+// bloco, de modo que um cliente com limite de taxa não atrasa o tratamento nem o envio aos demais.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tracing::{error, warn};
+
+use crate::batch::{BatchOptions, BatchSender};
+use crate::capture::{self, Direction};
+use crate::discovery;
+use crate::handler::Handler;
+use crate::protocol::MAX_UDP_PAYLOAD;
+use crate::shaping::{self, Ticket};
+
+// Requisições que podem ficar enfileiradas para um worker.
+const REQUEST_QUEUE_LEN: usize = 1024;
+// Respostas de um worker que podem estar aguardando envio.
+const RESPONSE_QUEUE_LEN: usize = 64;
+
+// Configuração do servidor com workers.
+#[derive(Clone, Debug)]
+pub struct WorkerConfig {
+  // Número de workers (e de sockets ligados à porta).
+  pub workers: usize,
+  // Opções de envio em lote (sendmmsg/GSO).
+  pub batch: BatchOptions,
+}
+
+impl Default for WorkerConfig {
+  fn default() -> Self {
+    WorkerConfig {
+      workers: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+      batch: BatchOptions::default(),
+    }
+  }
+}
+
+// Requisição encaminhada ao worker dono da sessão.
+type Request = (SocketAddr, Vec<u8>);
+// Resposta entregue à thread de envio do worker.
+type Response = (SocketAddr, Vec<Vec<u8>>);
+
+// Sobe os workers e bloqueia enquanto eles estiverem rodando.
+pub fn run(address: SocketAddr, config: WorkerConfig, handler: Handler) -> io::Result<()> {
+  let workers = config.workers.max(1);
+  let sockets = (0..workers)
+    .map(|_| bind_reuse_port(address))
+    .collect::<io::Result<Vec<_>>>()?;
+  // Um socket no grupo basta: o sistema entrega os datagramas do grupo a todos os da porta.
+  if let Some(discovery) = handler.discovery() {
+    discovery::join_group(&sockets[0], discovery.interface);
+  }
+
+  let (senders, receivers): (Vec<SyncSender<Request>>, Vec<Receiver<Request>>) =
+    (0..workers).map(|_| mpsc::sync_channel(REQUEST_QUEUE_LEN)).unzip();
+  let batch_sender = Arc::new(BatchSender::new(config.batch));
+  let mut threads = Vec::with_capacity(workers * 3);
+
+  for (index, (socket, requests)) in sockets.iter().zip(receivers).enumerate() {
+    // Thread de recepção: lê o socket do worker e encaminha cada requisição ao dono da sessão.
+    let recv_socket = socket.try_clone()?;
+    let senders = senders.clone();
+    threads.push(thread::spawn(move || receive_loop(index, recv_socket, senders)));
+
+    // Thread do worker: atende, em ordem, as sessões que pertencem a ele.
+    let (responses, pending) = mpsc::sync_channel(RESPONSE_QUEUE_LEN);
+    let worker_handler = handler.clone();
+    threads.push(thread::spawn(move || worker_loop(requests, responses, worker_handler)));
+
+    // Thread de envio: envia as respostas do worker respeitando os limites de taxa.
+    let send_socket = socket.try_clone()?;
+    let batch_sender = Arc::clone(&batch_sender);
+    let handler = handler.clone();
+    threads.push(thread::spawn(move || send_loop(index, send_socket, pending, batch_sender, handler)));
+  }
+  drop(senders);
+
+  for thread in threads {
+    if thread.join().is_err() {
+      error!("worker panicked");
+    }
+  }
+  Ok(())
+}
+
+// Worker responsável pela sessão de um cliente.
+pub fn session_owner(client_address: &SocketAddr, workers: usize) -> usize {
+  let mut hasher = DefaultHasher::new();
+  client_address.hash(&mut hasher);
+  (hasher.finish() % workers as u64) as usize
+}
+
+fn bind_reuse_port(address: SocketAddr) -> io::Result<UdpSocket> {
+  let socket = Socket::new(Domain::for_address(address), Type::DGRAM, Some(Protocol::UDP))?;
+  socket.set_reuse_port(true)?;
+  socket.bind(&address.into())?;
+  Ok(socket.into())
+}
+
+fn receive_loop(index: usize, socket: UdpSocket, senders: Vec<SyncSender<Request>>) {
+  let mut buf = vec![0u8; MAX_UDP_PAYLOAD];
+  let local_address = socket.local_addr().unwrap_or(SocketAddr::from(([0, 0, 0, 0], 0)));
+  loop {
+    let (size, client_address) = match socket.recv_from(&mut buf) {
+      Ok(received) => received,
+      Err(e) => {
+        warn!(worker = index, error = %e, "receive failed");
+        continue;
+      }
+    };
+    let request = buf[..size].to_vec();
+    capture::record(local_address, client_address, Direction::Received, &request);
+    let owner = session_owner(&client_address, senders.len());
+    match senders[owner].try_send((client_address, request)) {
+      Ok(()) => {},
+      Err(TrySendError::Full(_)) => warn!(worker = owner, peer = %client_address, "worker queue full, dropping request"),
+      Err(TrySendError::Disconnected(_)) => warn!(worker = owner, peer = %client_address, "worker stopped, dropping request"),
+    }
+  }
+}
+
+fn worker_loop(requests: Receiver<Request>, responses: SyncSender<Response>, handler: Handler) {
+  for (client_address, request) in requests {
+    let datagrams = handler.handle(&request, client_address);
+    if datagrams.is_empty() {
+      continue;
+    }
+    // Com a fila de envio cheia, o worker espera; a fila de requisições enche e a recepção passa a
+    // descartar datagramas.
+    if responses.send((client_address, datagrams)).is_err() {
+      break;
+    }
+  }
+}
+
+// Resposta em envio: os datagramas ainda não enviados começam em `sent`. O ticket do limite de taxa
+// é obtido quando a resposta chega à vez do cliente, depois das anteriores a ele.
+struct Pending {
+  client_address: SocketAddr,
+  datagrams: Vec<Vec<u8>>,
+  sent: usize,
+  ticket: Option<Ticket>,
+}
+
+// Envia as respostas do worker bloco a bloco. A cada volta, cada cliente com respostas pendentes
+// envia um bloco da mais antiga delas, se os limites permitirem; quando nenhum pode enviar, a thread
+// espera pelo menor atraso pedido ou por uma nova resposta.
+fn send_loop(index: usize, socket: UdpSocket, responses: Receiver<Response>, batch_sender: Arc<BatchSender>, handler: Handler) {
+  let local_address = socket.local_addr().unwrap_or(SocketAddr::from(([0, 0, 0, 0], 0)));
+  let mut pending: VecDeque<Pending> = VecDeque::new();
+  let mut disconnected = false;
+  loop {
+    if pending.is_empty() {
+      if disconnected {
+        return;
+      }
+      match responses.recv() {
+        Ok(response) => pending.push_back(Pending::new(response)),
+        Err(_) => return,
+      }
+    }
+    while !disconnected && pending.len() < RESPONSE_QUEUE_LEN {
+      match responses.try_recv() {
+        Ok(response) => pending.push_back(Pending::new(response)),
+        Err(TryRecvError::Empty) => break,
+        Err(TryRecvError::Disconnected) => disconnected = true,
+      }
+    }
+
+    let mut progressed = false;
+    let mut delay: Option<Duration> = None;
+    let mut served = HashSet::new();
+    let mut position = 0;
+    while position < pending.len() {
+      let response = &mut pending[position];
+      // Respostas ao mesmo cliente saem na ordem em que foram produzidas.
+      if !served.insert(response.client_address) {
+        position += 1;
+        continue;
+      }
+      let client_address = response.client_address;
+      let ticket = response.ticket.get_or_insert_with(|| handler.shaper().ticket(client_address));
+      let chunk = ticket.chunks(&response.datagrams[response.sent..])[0];
+      if let Some(wait) = ticket.poll(shaping::chunk_len(chunk)) {
+        delay = Some(delay.map_or(wait, |delay| delay.min(wait)));
+        position += 1;
+        continue;
+      }
+      progressed = true;
+      if let Err(e) = batch_sender.send_all(&socket, chunk, client_address) {
+        warn!(worker = index, peer = %client_address, error = %e, "failed to send response");
+        pending.remove(position);
+        continue;
+      }
+      response.sent += chunk.len();
+      if response.sent < response.datagrams.len() {
+        position += 1;
+        continue;
+      }
+      if let Some(Pending { datagrams, .. }) = pending.remove(position) {
+        capture::record_all(local_address, client_address, Direction::Sent, datagrams.iter().map(Vec::as_slice));
+        handler.sent(client_address, &datagrams);
+      }
+    }
+
+    if let (false, Some(delay)) = (progressed, delay) {
+      if disconnected || pending.len() >= RESPONSE_QUEUE_LEN {
+        thread::sleep(delay);
+        continue;
+      }
+      match responses.recv_timeout(delay) {
+        Ok(response) => pending.push_back(Pending::new(response)),
+        Err(RecvTimeoutError::Timeout) => {},
+        Err(RecvTimeoutError::Disconnected) => disconnected = true,
+      }
+    }
+  }
+}
+
+impl Pending {
+  fn new((client_address, datagrams): Response) -> Pending {
+    Pending { client_address, datagrams, sent: 0, ticket: None }
+  }
+}
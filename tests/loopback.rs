@@ -0,0 +1,411 @@
+// Testes de ponta a ponta no loopback: um `Server` com arquivos em memória (`MemorySource`) e o
+// `Client` da biblioteca, cobrindo GET, com e sem perda simulada, PUT e os demais comandos.
+// Execute com `cargo test --test loopback`.
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rawsocket_udp::auth::{Authenticator, Credentials, KeySet};
+use rawsocket_udp::compression::Compression;
+use rawsocket_udp::discovery::{self, DiscoveryConfig, DiscoveryOptions, DISCOVER_LEN};
+use rawsocket_udp::error::ClientError;
+use rawsocket_udp::limits::SessionLimits;
+use rawsocket_udp::listing::EntryKind;
+use rawsocket_udp::pmtud;
+use rawsocket_udp::protocol::{ErrorCode, FileMeta};
+use rawsocket_udp::service::{Server, ServerHandle};
+use rawsocket_udp::source::MemorySource;
+use rawsocket_udp::transfer::{CancelToken, Client, ClientConfig, Destination, GetOptions, MirrorOptions};
+use rawsocket_udp::upload::OverwritePolicy;
+
+// Conteúdo com várias dezenas de pacotes e um último pacote parcial.
+fn sample(len: usize) -> Vec<u8> {
+  (0..len).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+fn start(source: Arc<MemorySource>) -> ServerHandle {
+  let address = SocketAddr::from(([127, 0, 0, 1], 0));
+  Server::builder().bind(address).shared_source(source).build().and_then(Server::spawn).expect("servidor não iniciou")
+}
+
+fn client(server: &ServerHandle, chunk_size: Option<usize>) -> Client {
+  let config = ClientConfig { chunk_size, timeout: Duration::from_millis(500), ..ClientConfig::default() };
+  Client::new(server.local_addr(), config)
+}
+
+// Arquivo local com o conteúdo, em um caminho exclusivo deste teste.
+fn local_file(name: &str, data: &[u8]) -> PathBuf {
+  let path = env::temp_dir().join(format!("rsudp-loopback-{}-{}", process::id(), name));
+  fs::write(&path, data).expect("arquivo local não foi gravado");
+  path
+}
+
+#[test]
+fn get_returns_the_file() {
+  let data = sample(100_000);
+  let source = Arc::new(MemorySource::new().with_file("docs/report.bin", data.clone()));
+  let server = start(source);
+
+  let download = client(&server, None).get("docs/report.bin").expect("GET falhou");
+  assert_eq!(download.data.as_deref(), Some(data.as_slice()));
+  assert_eq!(download.meta.size, data.len() as u64);
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn get_retransmits_lost_packets() {
+  let data = sample(50_000);
+  let source = Arc::new(MemorySource::new().with_file("lossy.bin", data.clone()));
+  let server = start(source);
+
+  // Perde o primeiro pacote de dados, um do meio e o último, parcial.
+  let packets = data.len().div_ceil(1000) as u32;
+  let lost: HashSet<u32> = [1, 10, packets].into_iter().collect();
+  let download = client(&server, Some(1000))
+    .get_with("lossy.bin", GetOptions::default().simulate_loss(lost))
+    .expect("GET com perda falhou");
+  assert_eq!(download.data.as_deref(), Some(data.as_slice()));
+  assert!(download.retransmitted >= 3);
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn get_missing_file_is_not_found() {
+  let server = start(Arc::new(MemorySource::new()));
+
+  match client(&server, None).get("missing.bin") {
+    Err(ClientError::Server { code, .. }) => assert_eq!(code, ErrorCode::NotFound),
+    other => panic!("esperava NOT_FOUND, obtido {:?}", other.map(|download| download.path)),
+  }
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn put_stores_the_file() {
+  let data = sample(80_000);
+  let source = Arc::new(MemorySource::new());
+  let server = start(Arc::clone(&source));
+  let local = local_file("put.bin", &data);
+
+  client(&server, Some(700)).put(&local, "uploads/put.bin", OverwritePolicy::Never).expect("PUT falhou");
+  assert_eq!(source.get("uploads/put.bin"), Some(data));
+  fs::remove_file(local).unwrap();
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn put_respects_the_overwrite_policy() {
+  let source = Arc::new(MemorySource::new().with_file("existing.bin", b"original".to_vec()));
+  let server = start(Arc::clone(&source));
+  let local = local_file("overwrite.bin", b"replacement");
+  let client = client(&server, None);
+
+  match client.put(&local, "existing.bin", OverwritePolicy::Never) {
+    Err(ClientError::Server { code, .. }) => assert_eq!(code, ErrorCode::AlreadyExists),
+    other => panic!("esperava ALREADY_EXISTS, obtido {:?}", other.is_ok()),
+  }
+  assert_eq!(source.get("existing.bin"), Some(b"original".to_vec()));
+
+  client.put(&local, "existing.bin", OverwritePolicy::Always).expect("PUT com sobrescrita falhou");
+  assert_eq!(source.get("existing.bin"), Some(b"replacement".to_vec()));
+  fs::remove_file(local).unwrap();
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn delta_updates_the_local_copy() {
+  // Blocos distintos, para que só o trecho alterado vá como literal.
+  let mut state: u32 = 0x9e37_79b9;
+  let basis: Vec<u8> = (0..200_000)
+    .map(|_| {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      state as u8
+    })
+    .collect();
+  let mut data = basis.clone();
+  data[100_000..100_100].fill(0);
+  let source = Arc::new(MemorySource::new().with_file("delta.bin", data.clone()));
+  let server = start(source);
+  let local = local_file("delta.bin", &basis);
+
+  let stats = client(&server, None).delta("delta.bin", &local).expect("DELTA falhou");
+  assert_eq!(fs::read(&local).unwrap(), data);
+  assert_eq!(stats.size, data.len());
+  assert!(stats.literal < data.len() / 10, "literais demais: {}", stats.literal);
+  fs::remove_file(local).unwrap();
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn compressed_get_returns_the_file() {
+  let data: Vec<u8> = b"conteudo que se repete ".iter().copied().cycle().take(120_000).collect();
+  let source = Arc::new(MemorySource::new().with_file("text.log", data.clone()));
+  let server = start(source);
+
+  for compression in [Compression::Zstd, Compression::Deflate] {
+    let config = ClientConfig { compression, timeout: Duration::from_millis(500), ..ClientConfig::default() };
+    let download = Client::new(server.local_addr(), config).get("text.log").expect("GET comprimido falhou");
+    assert_eq!(download.data.as_deref(), Some(data.as_slice()));
+  }
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn authenticated_get_requires_valid_credentials() {
+  let data = sample(30_000);
+  let source = Arc::new(MemorySource::new().with_file("private.bin", data.clone()));
+  let mut keys = KeySet::default();
+  keys.insert("ci-agent", "s3gredo");
+  let address = SocketAddr::from(([127, 0, 0, 1], 0));
+  let server = Server::builder()
+    .bind(address)
+    .shared_source(source)
+    .authenticate(Authenticator::new(keys))
+    .build()
+    .and_then(Server::spawn)
+    .expect("servidor não iniciou");
+
+  for credentials in [None, Some("ci-agent:errado"), Some("outra:s3gredo")] {
+    let client = client(&server, None);
+    let client = match credentials {
+      Some(key) => client.with_credentials(Credentials::parse_key(key).unwrap()),
+      None => client,
+    };
+    match client.get("private.bin") {
+      Err(ClientError::Server { code, .. }) => assert_eq!(code, ErrorCode::Unauthorized),
+      other => panic!("esperava UNAUTHORIZED com {:?}, obtido {:?}", credentials, other.map(|download| download.path)),
+    }
+  }
+
+  // As retransmissões pertencem à transferência já autenticada.
+  let lost: HashSet<u32> = [2, 5].into_iter().collect();
+  let download = client(&server, Some(1000))
+    .with_credentials(Credentials::parse_key("ci-agent:s3gredo").unwrap())
+    .get_with("private.bin", GetOptions::default().simulate_loss(lost))
+    .expect("GET autenticado falhou");
+  assert_eq!(download.data.as_deref(), Some(data.as_slice()));
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn session_limit_answers_busy() {
+  let source = Arc::new(MemorySource::new().with_file("a.bin", sample(5_000)));
+  let limits = SessionLimits { max_sessions: 1, ..SessionLimits::default() };
+  let address = SocketAddr::from(([127, 0, 0, 1], 0));
+  let server = Server::builder()
+    .bind(address)
+    .shared_source(source)
+    .limits(limits)
+    .build()
+    .and_then(Server::spawn)
+    .expect("servidor não iniciou");
+
+  // A sessão do primeiro cliente continua guardada para retransmissão e não está quieta.
+  client(&server, None).get("a.bin").expect("primeiro GET falhou");
+  match client(&server, None).get("a.bin") {
+    Err(ClientError::Server { code, .. }) => assert_eq!(code, ErrorCode::Busy),
+    other => panic!("esperava BUSY, obtido {:?}", other.map(|download| download.path)),
+  }
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn list_returns_the_directory() {
+  let source = MemorySource::new()
+    .with_file("docs/b.txt", "abc")
+    .with_file("docs/a.bin", sample(10))
+    .with_file("docs/old/c.txt", "c")
+    .with_file("docs/.hidden", "x")
+    .with_file("other.txt", "o");
+  let server = start(Arc::new(source));
+
+  let entries = client(&server, None).list("docs", true).expect("LIST falhou");
+  let names: Vec<(&str, EntryKind)> = entries.iter().map(|entry| (entry.name.as_str(), entry.kind)).collect();
+  assert_eq!(names, [("a.bin", EntryKind::File), ("b.txt", EntryKind::File), ("old", EntryKind::Dir)]);
+  assert_eq!(entries[1].size, 3);
+  assert_eq!(entries[1].sha256.as_deref(), Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+
+  let root = client(&server, None).list("", false).expect("LIST da raiz falhou");
+  assert_eq!(root.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>(), ["docs", "other.txt"]);
+  assert!(root.iter().all(|entry| entry.sha256.is_none()));
+
+  match client(&server, None).list("missing", false) {
+    Err(ClientError::Server { code, .. }) => assert_eq!(code, ErrorCode::NotFound),
+    other => panic!("esperava NOT_FOUND, obtido {:?}", other.map(|entries| entries.len())),
+  }
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn stat_returns_the_metadata() {
+  let data = sample(10_500);
+  let source = Arc::new(MemorySource::new().with_file("docs/report.bin", data.clone()));
+  let server = start(source);
+
+  let meta = client(&server, Some(1000)).stat("docs/report.bin").expect("STAT falhou");
+  let expected = FileMeta::new(&data, 1000, meta.mtime);
+  assert_eq!(meta, expected);
+  assert_eq!((meta.size, meta.total_packets), (10_500, 12));
+  assert!(meta.mtime > 0);
+
+  for (path, expected) in [("missing.bin", ErrorCode::NotFound), ("docs", ErrorCode::BadRequest), ("../etc/passwd", ErrorCode::Forbidden)] {
+    match client(&server, None).stat(path) {
+      Err(ClientError::Server { code, .. }) => assert_eq!(code, expected, "{}", path),
+      other => panic!("esperava {} para {}, obtido {:?}", expected, path, other),
+    }
+  }
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn probes_find_the_path_mtu() {
+  let server = start(Arc::new(MemorySource::new()));
+  let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+
+  // O MTU do loopback é maior que o máximo sondado.
+  assert_eq!(pmtud::discover_path_mtu(&socket, server.local_addr(), 9000).unwrap(), 9000);
+  assert_eq!(pmtud::discover_path_mtu(&socket, server.local_addr(), 1500).unwrap(), 1500);
+
+  // A confirmação tem o tamanho da sonda; sondas que não têm o tamanho anunciado ficam sem resposta.
+  socket.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+  let mut buf = [0u8; 4096];
+  let mut probe = b"PROBE 1400".to_vec();
+  probe.resize(1400, 0);
+  socket.send_to(&probe, server.local_addr()).unwrap();
+  let (len, _) = socket.recv_from(&mut buf).expect("sonda não confirmada");
+  assert_eq!(len, 1400);
+  assert!(buf.starts_with(b"PROBE-ACK 1400\0"));
+  for unanswered in [&b"PROBE 1400"[..], &probe[..1399], b"PROBE 5"] {
+    socket.send_to(unanswered, server.local_addr()).unwrap();
+    assert!(socket.recv_from(&mut buf).is_err(), "{:?}", String::from_utf8_lossy(&unanswered[..unanswered.len().min(10)]));
+  }
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn discover_finds_the_server() {
+  let address = SocketAddr::from(([127, 0, 0, 1], 0));
+  let server = Server::builder()
+    .bind(address)
+    .shared_source(Arc::new(MemorySource::new()))
+    .discovery(DiscoveryConfig::new("loopback"))
+    .build()
+    .and_then(Server::spawn)
+    .expect("servidor não iniciou");
+  let silent = start(Arc::new(MemorySource::new()));
+
+  let options = DiscoveryOptions {
+    timeout: Duration::from_millis(400),
+    targets: vec![server.local_addr(), silent.local_addr()],
+    ..DiscoveryOptions::default()
+  };
+  let servers = discovery::discover(&options).expect("descoberta falhou");
+  // Só o servidor com a descoberta ligada responde.
+  assert_eq!(servers.len(), 1);
+  assert_eq!((servers[0].address, servers[0].name.as_str()), (server.local_addr(), "loopback"));
+  assert!(servers[0].compatible());
+
+  // Pedidos sem o preenchimento não são respondidos.
+  let socket = UdpSocket::bind(address).unwrap();
+  socket.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+  let mut buf = [0u8; 2048];
+  socket.send_to(b"DISCOVER", server.local_addr()).unwrap();
+  assert!(socket.recv_from(&mut buf).is_err());
+  let mut request = b"DISCOVER".to_vec();
+  request.resize(DISCOVER_LEN, 0);
+  socket.send_to(&request, server.local_addr()).unwrap();
+  let (len, _) = socket.recv_from(&mut buf).expect("descoberta sem resposta");
+  assert!(buf.starts_with(b"SERVER {") && len <= DISCOVER_LEN);
+  server.shutdown().unwrap();
+  silent.shutdown().unwrap();
+}
+
+#[test]
+fn mirror_copies_the_tree() {
+  let source = Arc::new(
+    MemorySource::new()
+      .with_file("site/index.html", "<html></html>")
+      .with_file("site/css/style.css", "body {}")
+      .with_file("site/img/logo.bin", sample(20_000))
+      .with_file("other/skip.txt", "fora"),
+  );
+  let server = start(Arc::clone(&source));
+  let local = env::temp_dir().join(format!("rsudp-loopback-{}-mirror", process::id()));
+  let _ = fs::remove_dir_all(&local);
+
+  let options = MirrorOptions { parallel: 2, delta: false };
+  let report = client(&server, None).mirror("site", &local, options).expect("espelhamento falhou");
+  assert_eq!((report.downloaded, report.skipped, report.failed.len()), (3, 0, 0));
+  assert_eq!(fs::read(local.join("img/logo.bin")).unwrap(), sample(20_000));
+  assert_eq!(fs::read_to_string(local.join("css/style.css")).unwrap(), "body {}");
+  assert!(!local.join("skip.txt").exists());
+
+  // Arquivos iguais são mantidos; os alterados no servidor são atualizados por diferença.
+  let mut changed = sample(20_000);
+  changed[10_000..10_010].copy_from_slice(b"atualizado");
+  source.insert("site/img/logo.bin", changed.clone());
+  let options = MirrorOptions { parallel: 2, delta: true };
+  let report = client(&server, None).mirror("site", &local, options).expect("segundo espelhamento falhou");
+  assert_eq!((report.downloaded, report.skipped, report.failed.len()), (1, 2, 0));
+  assert_eq!(fs::read(local.join("img/logo.bin")).unwrap(), changed);
+
+  let report = client(&server, None).mirror("missing", &local.join("missing"), options).expect("espelhamento falhou");
+  assert_eq!(report.failed.len(), 1);
+  assert_eq!(report.failed[0].1.code(), Some(ErrorCode::NotFound));
+  fs::remove_dir_all(&local).unwrap();
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn get_writes_to_the_requested_destination() {
+  let data = sample(40_000);
+  let server = start(Arc::new(MemorySource::new().with_file("a.bin", data.clone())));
+
+  let path = env::temp_dir().join(format!("rsudp-loopback-{}-destination", process::id())).join("sub/a.bin");
+  let download = client(&server, None)
+    .get_with("a.bin", GetOptions::default().destination(Destination::Path(path.clone())))
+    .expect("GET para arquivo falhou");
+  assert!(download.data.is_none());
+  assert_eq!(fs::read(&path).unwrap(), data);
+  fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+
+  let mut buffer = Vec::new();
+  let download = client(&server, None)
+    .get_with("a.bin", GetOptions::default().destination(Destination::Writer(&mut buffer)))
+    .expect("GET para writer falhou");
+  assert_eq!((download.meta.size, download.packets), (40_000, download.meta.total_packets - 1));
+  assert_eq!(buffer, data);
+  server.shutdown().unwrap();
+}
+
+#[test]
+fn get_reports_progress_and_can_be_cancelled() {
+  let data = sample(200_000);
+  let server = start(Arc::new(MemorySource::new().with_file("big.bin", data.clone())));
+
+  let mut updates = Vec::new();
+  let download = client(&server, Some(1000))
+    .get_with("big.bin", GetOptions::default().on_progress(|progress| updates.push(*progress)))
+    .expect("GET com progresso falhou");
+  assert_eq!(download.data.as_deref(), Some(data.as_slice()));
+  let last = updates.last().expect("nenhum progresso informado");
+  assert_eq!((last.packets, last.total_packets, last.size), (200, Some(200), Some(200_000)));
+  assert!(updates.windows(2).all(|pair| pair[0].packets <= pair[1].packets));
+
+  // Cancelado já no primeiro lote recebido.
+  let token = CancelToken::new();
+  let canceller = token.clone();
+  let options = GetOptions::default().cancel_with(token).on_progress(move |_| canceller.cancel());
+  match client(&server, Some(1000)).get_with("big.bin", options) {
+    Err(ClientError::Cancelled) => {},
+    other => panic!("esperava o cancelamento, obtido {:?}", other.map(|download| download.path)),
+  }
+  server.shutdown().unwrap();
+}
@@ -0,0 +1,137 @@
+// Testes da distribuição multicast no loopback. Um relay entre dois grupos faz o papel da rede:
+// repassa o tráfego do `Sender` ao grupo dos receptores, descartando a primeira cópia de pacotes
+// escolhidos, e leva os NACKs dos receptores de volta ao `Sender`. Execute com
+// `cargo test --test multicast`.
+use std::collections::HashSet;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rawsocket_udp::batch::BatchOptions;
+use rawsocket_udp::multicast::{Receiver, ReceiverConfig, Sender, SenderConfig};
+use rawsocket_udp::protocol::Datagram;
+use socket2::{Domain, Protocol, Socket, Type};
+
+const LOOPBACK: Ipv4Addr = Ipv4Addr::LOCALHOST;
+const CHUNK_SIZE: usize = 1000;
+const FEC_K: usize = 4;
+// 11 pacotes de dados: dois grupos completos de FEC e um último grupo parcial de 3 pacotes, cujo
+// último pacote também é parcial.
+const FILE_SIZE: usize = 10 * CHUNK_SIZE + 500;
+const LAST_SEQ: u32 = FILE_SIZE.div_ceil(CHUNK_SIZE) as u32;
+
+fn sample() -> Vec<u8> {
+  (0..FILE_SIZE).map(|i| (i * 13 % 251) as u8).collect()
+}
+
+struct Relay {
+  stop: Arc<AtomicBool>,
+  thread: JoinHandle<io::Result<()>>,
+}
+
+impl Relay {
+  // Entra em `from` e repassa para `to`, descartando a primeira cópia de cada seq em `lost`. NACKs
+  // recebidos no socket de saída vão para `sender`.
+  fn start(from: SocketAddrV4, to: SocketAddrV4, sender: SocketAddr, lost: &[u32]) -> io::Result<Relay> {
+    let input = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    input.set_reuse_address(true)?;
+    input.bind(&SocketAddr::V4(from).into())?;
+    input.join_multicast_v4(from.ip(), &LOOPBACK)?;
+    let input: UdpSocket = input.into();
+    input.set_read_timeout(Some(Duration::from_millis(5)))?;
+
+    let output = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    output.set_multicast_if_v4(&LOOPBACK)?;
+    output.set_multicast_loop_v4(true)?;
+    output.bind(&SocketAddr::from((LOOPBACK, 0)).into())?;
+    let output: UdpSocket = output.into();
+    output.set_nonblocking(true)?;
+
+    let mut lost: HashSet<u32> = lost.iter().copied().collect();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::clone(&stop);
+    let thread = thread::spawn(move || {
+      let mut buf = vec![0u8; 65_536];
+      while !stopped.load(Ordering::Relaxed) {
+        match input.recv_from(&mut buf) {
+          Ok((len, _)) => {
+            let dropped = match Datagram::decode(&buf[..len]) {
+              Datagram::Data { seq_number, .. } => lost.remove(&seq_number),
+              _ => false,
+            };
+            if !dropped {
+              output.send_to(&buf[..len], to)?;
+            }
+          },
+          Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {},
+          Err(e) => return Err(e),
+        }
+        match output.recv_from(&mut buf) {
+          Ok((len, _)) => {
+            output.send_to(&buf[..len], sender)?;
+          },
+          Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+          Err(e) => return Err(e),
+        }
+      }
+      Ok(())
+    });
+    Ok(Relay { stop, thread })
+  }
+
+  fn stop(self) {
+    self.stop.store(true, Ordering::Relaxed);
+    self.thread.join().expect("relay terminou com pânico").expect("relay falhou");
+  }
+}
+
+fn sender(group: SocketAddrV4, linger: Duration) -> Sender {
+  let config = SenderConfig { group, interface: LOOPBACK, chunk_size: CHUNK_SIZE, fec: Some(FEC_K), linger, ..SenderConfig::default() };
+  Sender::new(config, BatchOptions::default()).expect("sender não foi criado")
+}
+
+fn receiver(group: SocketAddrV4, nack: bool) -> Receiver {
+  let config = ReceiverConfig { group, interface: LOOPBACK, timeout: Duration::from_secs(5), nack, ..ReceiverConfig::default() };
+  Receiver::join(config).expect("receptor não entrou no grupo")
+}
+
+#[test]
+fn fec_recovers_a_loss_in_the_last_partial_group() {
+  let (sent_to, relayed_to) = (SocketAddrV4::new(Ipv4Addr::new(239, 255, 0, 91), 19091), SocketAddrV4::new(Ipv4Addr::new(239, 255, 0, 92), 19092));
+  let sender = sender(sent_to, Duration::ZERO);
+  // Uma perda num grupo completo e outra no último pacote, do grupo parcial.
+  let relay = Relay::start(sent_to, relayed_to, sender.local_addr().unwrap(), &[2, LAST_SEQ]).unwrap();
+  let receiver = receiver(relayed_to, false);
+  let receiving = thread::spawn(move || receiver.receive());
+
+  let data = sample();
+  sender.send(&data, 0).unwrap();
+  let received = receiving.join().unwrap().expect("recepção falhou");
+  relay.stop();
+
+  assert_eq!(received.data, data);
+  assert_eq!(received.fec_recovered, 2);
+  assert_eq!(received.nacks_sent, 0);
+}
+
+#[test]
+fn nack_repairs_what_fec_cannot_recover() {
+  let (sent_to, relayed_to) = (SocketAddrV4::new(Ipv4Addr::new(239, 255, 0, 93), 19093), SocketAddrV4::new(Ipv4Addr::new(239, 255, 0, 94), 19094));
+  let sender = sender(sent_to, Duration::from_millis(500));
+  // Duas perdas no grupo parcial: a paridade não basta e o receptor pede o reparo.
+  let relay = Relay::start(sent_to, relayed_to, sender.local_addr().unwrap(), &[LAST_SEQ - 1, LAST_SEQ]).unwrap();
+  let receiver = receiver(relayed_to, true);
+  let receiving = thread::spawn(move || receiver.receive());
+
+  let data = sample();
+  let report = sender.send(&data, 0).unwrap();
+  let received = receiving.join().unwrap().expect("recepção falhou");
+  relay.stop();
+
+  assert_eq!(received.data, data);
+  assert!(received.nacks_sent >= 1);
+  assert!(report.repair_rounds >= 1);
+}